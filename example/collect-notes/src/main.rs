@@ -2,7 +2,9 @@ use std::path::PathBuf;
 use std::time::Duration;
 
 use anyhow::Result;
-use futures::stream::TryStreamExt;
+use chrono::Utc;
+use futures::stream::{BoxStream, StreamExt, TryStreamExt};
+use misskey::pager::PrefetchStream;
 use misskey::prelude::*;
 use misskey::HttpClient;
 use structopt::StructOpt;
@@ -14,6 +16,9 @@ struct Opt {
     output: PathBuf,
     #[structopt(short, long, parse(try_from_str = Url::parse))]
     url: Url,
+    /// Collect only the notes in the last DAYS days, fetching them day by day concurrently
+    #[structopt(short, long)]
+    days: Option<i64>,
     #[structopt(env = "API_TOKEN")]
     i: String,
 }
@@ -33,13 +38,26 @@ async fn main() -> Result<()> {
     let file = File::create(opt.output).await?;
     let mut writer = BufWriter::new(file);
 
-    // `notes` variable here is a stream to enumerate all local notes
-    let mut notes = client.local_notes(..);
-    // Having an interval of 10 seconds between requests; this may be too much of concern,
-    // so adjust it for the server you are targeting
-    notes.set_interval(Duration::from_secs(10));
-    // Fetch 100 notes at once
-    notes.set_page_size(100);
+    let fetch = |range| {
+        // `notes` variable here is a stream to enumerate local notes in `range`
+        let mut notes = client.local_notes(range);
+        // Having an interval of 10 seconds between requests; this may be too much of concern,
+        // so adjust it for the server you are targeting
+        notes.set_interval(Duration::from_secs(10));
+        // Fetch 100 notes at once
+        notes.set_page_size(100);
+        notes
+    };
+    let mut notes: BoxStream<Result<_, _>> = match opt.days {
+        // Fetch the notes in two days at the same time, and get them back in order
+        Some(days) => {
+            let since = Utc::now() - chrono::Duration::days(days);
+            let mut notes = PrefetchStream::new(since.., chrono::Duration::days(1), fetch);
+            notes.set_concurrency(2);
+            notes.boxed()
+        }
+        None => fetch((..).into()).boxed(),
+    };
 
     // Retrieve all notes until there are no more.
     while let Some(note) = notes.try_next().await? {
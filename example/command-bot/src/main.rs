@@ -0,0 +1,59 @@
+use std::time::Duration;
+
+use anyhow::Result;
+use misskey::bot::{Bot, Interval, Rest};
+use misskey::{HttpClient, WebSocketClient};
+use structopt::StructOpt;
+use url::Url;
+
+#[derive(StructOpt)]
+struct Opt {
+    #[structopt(short, long, parse(try_from_str = Url::parse))]
+    url: Url,
+    #[structopt(env = "API_TOKEN")]
+    i: String,
+}
+
+#[tokio::main]
+async fn main() -> Result<()> {
+    let opt = Opt::from_args();
+
+    // Create `HttpClient`.
+    let http_client = HttpClient::with_token(opt.url.clone(), opt.i.clone())?;
+
+    // Build `WebSocketClient` and connect to Misskey.
+    let ws_client = WebSocketClient::builder(opt.url)
+        .token(opt.i)
+        .connect()
+        .await?;
+
+    // Create a bot that works with `HttpClient`.
+    let mut bot = Bot::new(http_client);
+
+    // Register `ping` command, which takes no arguments.
+    // The bot dispatches mentions such as "@bot ping" to this handler, but not "@bot pinging?".
+    bot.command("ping", |ctx, ()| async move {
+        println!("got ping from @{}", ctx.author().username);
+
+        // Create a pong note as a reply to the mention
+        ctx.reply("pong").await?;
+        Ok(())
+    });
+
+    // Register `remind` command, which takes a period of time and the rest of the text,
+    // such as "@bot remind 1h30m take a break".
+    // The usage is shown in the reply when the arguments are invalid.
+    bot.command("remind", |ctx, (Interval(after), Rest(text))| async move {
+        tokio::time::sleep(after).await;
+        ctx.reply(text).await?;
+        Ok(())
+    })
+    .usage("<time> <text>")
+    .cooldown(Duration::from_secs(60));
+
+    // Respond to the commands from the main stream.
+    // the main stream is a channel that streams events about the connected account, such as mentions.
+    bot.run(&ws_client).await?;
+
+    Ok(())
+}
@@ -43,6 +43,7 @@ macro_rules! impl_offset_pagination {
 pub mod admin;
 pub mod announcements;
 pub mod antennas;
+pub mod ap;
 pub mod blocking;
 pub mod charts;
 pub mod clips;
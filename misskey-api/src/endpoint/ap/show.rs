@@ -0,0 +1,57 @@
+use crate::model::{note::Note, user::User};
+
+use serde::{Deserialize, Serialize};
+
+#[derive(Serialize, Debug, Clone)]
+#[serde(rename_all = "camelCase")]
+pub struct Request {
+    pub uri: String,
+}
+
+#[derive(Deserialize, Debug, Clone)]
+#[serde(tag = "type", content = "object")]
+pub enum Response {
+    User(Box<User>),
+    Note(Box<Note>),
+}
+
+impl misskey_core::Request for Request {
+    type Response = Response;
+    const ENDPOINT: &'static str = "ap/show";
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{Request, Response};
+    use crate::test::{ClientExt, TestClient};
+
+    use misskey_test::env;
+
+    #[tokio::test]
+    async fn request_with_note() {
+        let client = TestClient::new();
+        let note = client.create_note(Some("test"), None, None).await;
+        let uri = env::api_url().join(&format!("/notes/{}", note.id)).unwrap();
+
+        let response = client
+            .test(Request {
+                uri: uri.to_string(),
+            })
+            .await;
+        assert!(matches!(response, Response::Note(n) if n.id == note.id));
+    }
+
+    #[tokio::test]
+    async fn request_with_user() {
+        let client = TestClient::new();
+        let user = client.me().await;
+        let uri = env::api_url().join(&format!("/users/{}", user.id)).unwrap();
+
+        let response = client
+            .test(Request {
+                uri: uri.to_string(),
+            })
+            .await;
+        assert!(matches!(response, Response::User(u) if u.id == user.id));
+    }
+}
@@ -7,7 +7,6 @@ use crate::model::{channel::Channel, drive::DriveFile, id::Id, user::User};
 use chrono::{DateTime, Utc};
 use serde::{Deserialize, Serialize};
 use thiserror::Error;
-#[cfg(any(not(feature = "13-0-0"), feature = "13-2-4"))]
 use url::Url;
 
 #[derive(Serialize, Deserialize, Clone, PartialEq, Eq, Hash, Debug)]
@@ -180,6 +179,10 @@ pub struct Note {
     #[serde(default)]
     pub renote_id: Option<Id<Note>>,
     #[serde(default)]
+    pub uri: Option<String>,
+    #[serde(default)]
+    pub url: Option<Url>,
+    #[serde(default)]
     pub reply: Option<Box<Note>>,
     #[serde(default)]
     pub renote: Option<Box<Note>>,
@@ -2,7 +2,7 @@ use crate::model::note::Tag;
 
 use serde::{Deserialize, Serialize};
 
-#[derive(Serialize, Deserialize, Debug, Clone, Default)]
+#[derive(Serialize, Deserialize, PartialEq, Eq, Hash, Debug, Clone, Default)]
 #[serde(transparent)]
 pub struct Query<T>(pub Vec<Vec<T>>);
 
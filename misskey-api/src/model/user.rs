@@ -6,7 +6,9 @@ use std::fmt::{self, Display};
 
 #[cfg(feature = "12-48-0")]
 use crate::model::notification::NotificationType;
-use crate::model::{id::Id, note::Note, page::Page};
+#[cfg(feature = "13-0-0")]
+use crate::model::role::PoliciesSimple;
+use crate::model::{drive::DriveFile, id::Id, note::Note, page::Page};
 
 #[cfg(feature = "13-1-0")]
 use chrono::serde::ts_milliseconds;
@@ -183,6 +185,10 @@ pub struct User {
     #[cfg(not(feature = "12-42-0"))]
     #[cfg_attr(docsrs, doc(cfg(not(feature = "12-42-0"))))]
     pub banner_color: Option<String>,
+    #[serde(default)]
+    pub avatar_id: Option<Id<DriveFile>>,
+    #[serde(default)]
+    pub banner_id: Option<Id<DriveFile>>,
     #[cfg(not(feature = "13-0-0"))]
     #[cfg_attr(docsrs, doc(cfg(not(feature = "13-0-0"))))]
     pub emojis: Option<Vec<UserEmoji>>,
@@ -316,6 +322,10 @@ pub struct User {
     #[cfg(feature = "13-12-2")]
     #[cfg_attr(docsrs, doc(cfg(feature = "13-12-2")))]
     pub prevent_ai_learning: Option<bool>,
+    #[cfg(feature = "13-0-0")]
+    #[cfg_attr(docsrs, doc(cfg(feature = "13-0-0")))]
+    #[serde(default)]
+    pub policies: Option<PoliciesSimple>,
 }
 
 fn default_false() -> bool {
@@ -36,6 +36,8 @@ pub type ChannelStream<'a, T, E> = BoxStreamSink<
 >;
 /// Stream for the [`StreamingClient::broadcast`] method.
 pub type BroadcastStream<'a, T, E> = BoxStream<'a, Result<T, E>>;
+/// Stream for the [`StreamingClient::reconnections`] method.
+pub type ReconnectionStream = BoxStream<'static, ()>;
 
 /// Abstraction over API clients with streaming connections.
 pub trait StreamingClient {
@@ -58,6 +60,17 @@ pub trait StreamingClient {
     fn broadcast<E: BroadcastEvent>(
         &self,
     ) -> BoxFuture<Result<BroadcastStream<E, Self::Error>, Self::Error>>;
+
+    /// Returns a stream that yields an item each time the client re-establishes the connection
+    /// after it has been lost.
+    ///
+    /// Messages sent by the server while the connection was down are not delivered, so this can
+    /// be used to recover them by other means.
+    /// The default implementation returns [`None`], which means that the client does not
+    /// report reconnections.
+    fn reconnections(&self) -> Option<ReconnectionStream> {
+        None
+    }
 }
 
 impl<C: ?Sized> StreamingClient for &C
@@ -85,6 +98,10 @@ where
     ) -> BoxFuture<Result<BroadcastStream<E, Self::Error>, Self::Error>> {
         C::broadcast(self)
     }
+
+    fn reconnections(&self) -> Option<ReconnectionStream> {
+        C::reconnections(self)
+    }
 }
 
 impl<C: ?Sized> StreamingClient for &mut C
@@ -112,6 +129,10 @@ where
     ) -> BoxFuture<Result<BroadcastStream<E, Self::Error>, Self::Error>> {
         C::broadcast(self)
     }
+
+    fn reconnections(&self) -> Option<ReconnectionStream> {
+        C::reconnections(self)
+    }
 }
 
 impl<C: ?Sized> StreamingClient for Box<C>
@@ -139,6 +160,10 @@ where
     ) -> BoxFuture<Result<BroadcastStream<E, Self::Error>, Self::Error>> {
         C::broadcast(self)
     }
+
+    fn reconnections(&self) -> Option<ReconnectionStream> {
+        C::reconnections(self)
+    }
 }
 
 /// Request to connect to the channel.
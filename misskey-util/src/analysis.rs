@@ -0,0 +1,472 @@
+//! Extraction of mentions, hashtags, URLs and custom emojis from notes.
+//!
+//! [`NoteEntities::extract`] parses the text and the content warning of a note as MFM and
+//! collects the entities in them, including the ones in the renoted or quoted note.
+//! Each entity records the ID of the note it is found in.
+//!
+//! # Examples
+//!
+//! ```no_run
+//! # use misskey_util::ClientExt;
+//! # #[tokio::main]
+//! # async fn main() -> anyhow::Result<()> {
+//! # let client = misskey_test::test_client().await?;
+//! use misskey_util::analysis::NoteEntities;
+//!
+//! let note = client
+//!     .create_note("Hello, @ai! #misskey https://misskey-hub.net :wave:")
+//!     .await?;
+//! let entities = NoteEntities::extract(&note);
+//! assert_eq!(entities.mentions[0].acct, "@ai");
+//! assert_eq!(entities.hashtags[0].name, "misskey");
+//! assert_eq!(entities.urls[0].url.as_str(), "https://misskey-hub.net/");
+//! assert_eq!(entities.emojis[0].name, "wave");
+//!
+//! // look up the mentioned users
+//! for (mention, user) in entities.resolve_mentions(&client).await? {
+//!     println!("{} => {:?}", mention.acct, user.map(|user| user.id));
+//! }
+//! # Ok(())
+//! # }
+//! ```
+
+use std::collections::HashMap;
+
+use crate::mfm::{self, Node};
+use crate::{ClientExt, Error};
+
+use misskey_api::model::{id::Id, note::Note, user::User};
+use url::Url;
+
+/// A mention of a user, such as `@ai@misskey.io`.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Mention {
+    /// ID of the note the mention is found in.
+    pub note_id: Id<Note>,
+    /// Username of the mentioned user.
+    pub username: String,
+    /// Host of the mentioned user, or `None` for the users of the local instance.
+    ///
+    /// The mention without host in a note from a remote instance refers to the user of that
+    /// instance, so this is the host of the author in that case.
+    pub host: Option<String>,
+    /// The mention as it is written, such as `@ai`.
+    pub acct: String,
+}
+
+impl Mention {
+    /// Returns `true` if the mention refers to `user`.
+    ///
+    /// Usernames and hosts are compared case-insensitively.
+    pub fn refers_to(&self, user: &User) -> bool {
+        self.username.eq_ignore_ascii_case(&user.username)
+            && match (&self.host, &user.host) {
+                (Some(host), Some(user_host)) => host.eq_ignore_ascii_case(user_host),
+                (None, None) => true,
+                _ => false,
+            }
+    }
+}
+
+/// A hashtag, such as `#misskey`.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Hashtag {
+    /// ID of the note the hashtag is found in.
+    pub note_id: Id<Note>,
+    /// Name of the hashtag without `#`.
+    pub name: String,
+    /// Whether the hashtag is in [`tags`][`Note::tags`] of the note, i.e. it is registered
+    /// by the server.
+    pub registered: bool,
+}
+
+/// A URL, which is written as it is or as a link.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct NoteUrl {
+    /// ID of the note the URL is found in.
+    pub note_id: Id<Note>,
+    /// The URL.
+    pub url: Url,
+    /// The label of the link in plain text, or `None` if the URL is written as it is.
+    pub label: Option<String>,
+}
+
+/// A custom emoji, such as `:wave:`.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct CustomEmoji {
+    /// ID of the note the emoji is found in.
+    pub note_id: Id<Note>,
+    /// Shortcode of the emoji without colons.
+    pub name: String,
+    /// URL of the image of the emoji, or `None` if it is not included in the note.
+    pub url: Option<Url>,
+}
+
+/// Entities found in a note and the note it renotes or quotes.
+///
+/// The entities are listed in the order of appearance, from the content warning and the text
+/// of the note to the ones of the renoted note. The same entity appearing more than once in a
+/// note is listed only once.
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct NoteEntities {
+    /// Mentions of users.
+    pub mentions: Vec<Mention>,
+    /// Hashtags.
+    pub hashtags: Vec<Hashtag>,
+    /// URLs, including the ones of the links.
+    pub urls: Vec<NoteUrl>,
+    /// Custom emojis.
+    pub emojis: Vec<CustomEmoji>,
+    /// IDs of the mentioned users in [`mentions`][`Note::mentions`] of the notes.
+    pub mentioned_user_ids: Vec<Id<User>>,
+}
+
+impl NoteEntities {
+    /// Extracts the entities from `note`, including the renoted or quoted note.
+    pub fn extract(note: &Note) -> NoteEntities {
+        let mut entities = NoteEntities::default();
+        let mut next = Some(note);
+        while let Some(note) = next {
+            entities.extract_note(note);
+            next = note.renote.as_deref();
+        }
+        entities
+    }
+
+    fn extract_note(&mut self, note: &Note) {
+        let mut extractor = Extractor {
+            entities: self,
+            note,
+            emojis: mfm::note_emojis(note),
+        };
+        for text in note.cw.iter().chain(&note.text) {
+            extractor.extract_nodes(&mfm::parse(text));
+        }
+        for id in &note.mentions {
+            if !self.mentioned_user_ids.contains(id) {
+                self.mentioned_user_ids.push(*id);
+            }
+        }
+    }
+
+    /// Looks up the mentioned users.
+    ///
+    /// The mentions are matched with the users in [`mentions`][`Note::mentions`] of the notes,
+    /// which are fetched in a single request. The user is `None` if the mention is not
+    /// recognized by the server, e.g. the user does not exist.
+    pub async fn resolve_mentions<C: ClientExt>(
+        &self,
+        client: &C,
+    ) -> Result<Vec<(Mention, Option<User>)>, Error<C::Error>> {
+        let users = if self.mentioned_user_ids.is_empty() {
+            Vec::new()
+        } else {
+            client
+                .get_users(self.mentioned_user_ids.iter().copied())
+                .await?
+        };
+        let resolved = self
+            .mentions
+            .iter()
+            .map(|mention| {
+                let user = users
+                    .iter()
+                    .find(|user| mention.refers_to(user))
+                    .or_else(|| {
+                        // the host of the local instance may be written explicitly
+                        let mut candidates = users
+                            .iter()
+                            .filter(|user| mention.username.eq_ignore_ascii_case(&user.username));
+                        match (candidates.next(), candidates.next()) {
+                            (Some(user), None) => Some(user),
+                            _ => None,
+                        }
+                    });
+                (mention.clone(), user.cloned())
+            })
+            .collect();
+        Ok(resolved)
+    }
+}
+
+struct Extractor<'a> {
+    entities: &'a mut NoteEntities,
+    note: &'a Note,
+    emojis: HashMap<String, Url>,
+}
+
+impl Extractor<'_> {
+    fn extract_nodes(&mut self, nodes: &[Node]) {
+        for node in nodes {
+            self.extract_node(node);
+        }
+    }
+
+    fn extract_node(&mut self, node: &Node) {
+        let note_id = self.note.id;
+        match node {
+            Node::Mention {
+                username,
+                host,
+                acct,
+            } => {
+                let mention = Mention {
+                    note_id,
+                    username: username.clone(),
+                    host: host.clone().or_else(|| self.note.user.host.clone()),
+                    acct: acct.clone(),
+                };
+                push_unique(&mut self.entities.mentions, mention);
+            }
+            Node::Hashtag { hashtag } => {
+                let registered = self
+                    .note
+                    .tags
+                    .iter()
+                    .any(|tag| tag.0.to_lowercase() == hashtag.to_lowercase());
+                let hashtag = Hashtag {
+                    note_id,
+                    name: hashtag.clone(),
+                    registered,
+                };
+                push_unique(&mut self.entities.hashtags, hashtag);
+            }
+            Node::Url { url, .. } => self.push_url(url, None),
+            Node::Link { url, children, .. } => {
+                self.push_url(url, Some(mfm::nodes_to_plain_text(children)));
+                self.extract_nodes(children);
+            }
+            Node::EmojiCode { name } => {
+                let url = self.emojis.get(name).or_else(|| {
+                    let host = self.note.user.host.as_deref()?;
+                    self.emojis.get(&format!("{}@{}", name, host))
+                });
+                let emoji = CustomEmoji {
+                    note_id,
+                    name: name.clone(),
+                    url: url.cloned(),
+                };
+                push_unique(&mut self.entities.emojis, emoji);
+            }
+            node => self.extract_nodes(node.children()),
+        }
+    }
+
+    fn push_url(&mut self, url: &str, label: Option<String>) {
+        if let Ok(url) = Url::parse(url) {
+            let url = NoteUrl {
+                note_id: self.note.id,
+                url,
+                label,
+            };
+            push_unique(&mut self.entities.urls, url);
+        }
+    }
+}
+
+fn push_unique<T: PartialEq>(entities: &mut Vec<T>, entity: T) {
+    if !entities.contains(&entity) {
+        entities.push(entity);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{Mention, NoteEntities};
+    use crate::test_util::{id, note_json, user, user_json, MockClient};
+
+    use misskey_api::model::{note::Note, user::User};
+    use serde_json::{json, Value};
+    use url::Url;
+
+    fn note_by(n: u64, host: Option<&str>, text: &str) -> Value {
+        let mut note = note_json(n, text);
+        note["user"] = user_json(0, "author", host);
+        note
+    }
+
+    fn with_emoji(mut note: Value, name: &str, url: &str) -> Value {
+        #[cfg(not(feature = "13-0-0"))]
+        {
+            note["emojis"] = json!([{ "name": name, "url": url }]);
+        }
+        #[cfg(feature = "13-2-4")]
+        {
+            note["emojis"] = json!({ name: url });
+        }
+        #[cfg(all(feature = "13-0-0", not(feature = "13-2-4")))]
+        let _ = (name, url);
+        note
+    }
+
+    fn mention(note: u64, username: &str, host: Option<&str>, acct: &str) -> Mention {
+        Mention {
+            note_id: id(note),
+            username: username.to_owned(),
+            host: host.map(str::to_owned),
+            acct: acct.to_owned(),
+        }
+    }
+
+    #[test]
+    fn test_extract() {
+        let text = "Hi @ai and @bob@other.example! #Misskey #rust @ai\n\
+                    [a **label**](https://example.com/a) https://example.com/b :wave: :wave:";
+        let mut note = with_emoji(
+            note_by(1, None, text),
+            "wave",
+            "https://example.com/wave.png",
+        );
+        note["cw"] = json!(":blob: #cw");
+        note["tags"] = json!(["misskey"]);
+        note["mentions"] = json!([id::<User>(2), id::<User>(3)]);
+        let note: Note = serde_json::from_value(note).unwrap();
+
+        let entities = NoteEntities::extract(&note);
+        assert_eq!(
+            entities.mentions,
+            [
+                mention(1, "ai", None, "@ai"),
+                mention(1, "bob", Some("other.example"), "@bob@other.example"),
+            ]
+        );
+        let hashtags: Vec<_> = entities
+            .hashtags
+            .iter()
+            .map(|tag| (tag.name.as_str(), tag.registered))
+            .collect();
+        assert_eq!(
+            hashtags,
+            [("cw", false), ("Misskey", true), ("rust", false)]
+        );
+        let urls: Vec<_> = entities
+            .urls
+            .iter()
+            .map(|url| (url.url.as_str(), url.label.as_deref()))
+            .collect();
+        assert_eq!(
+            urls,
+            [
+                ("https://example.com/a", Some("a label")),
+                ("https://example.com/b", None),
+            ]
+        );
+        let emojis: Vec<_> = entities
+            .emojis
+            .iter()
+            .map(|emoji| (emoji.name.as_str(), emoji.url.as_ref().map(Url::as_str)))
+            .collect();
+        #[cfg(any(not(feature = "13-0-0"), feature = "13-2-4"))]
+        let wave = Some("https://example.com/wave.png");
+        #[cfg(all(feature = "13-0-0", not(feature = "13-2-4")))]
+        let wave = None;
+        assert_eq!(emojis, [("blob", None), ("wave", wave)]);
+        assert_eq!(entities.mentioned_user_ids, [id(2), id(3)]);
+    }
+
+    #[test]
+    fn test_extract_remote() {
+        // mentions without host in remote notes refer to the users of that instance
+        let note: Note =
+            serde_json::from_value(note_by(1, Some("remote.example"), "@ai @bob@misskey.test"))
+                .unwrap();
+        let entities = NoteEntities::extract(&note);
+        assert_eq!(
+            entities.mentions,
+            [
+                mention(1, "ai", Some("remote.example"), "@ai"),
+                mention(1, "bob", Some("misskey.test"), "@bob@misskey.test"),
+            ]
+        );
+    }
+
+    #[test]
+    fn test_extract_renote() {
+        let mut renote = note_by(2, None, "#misskey @ai https://example.com");
+        renote["mentions"] = json!([id::<User>(5)]);
+        let mut note = note_by(1, None, "#misskey @ai");
+        note["renote"] = renote;
+        note["renoteId"] = json!(id::<Note>(2));
+        note["mentions"] = json!([id::<User>(5)]);
+        let note: Note = serde_json::from_value(note).unwrap();
+
+        // the same entities in different notes are listed separately
+        let entities = NoteEntities::extract(&note);
+        let hashtags: Vec<_> = entities.hashtags.iter().map(|tag| tag.note_id).collect();
+        assert_eq!(hashtags, [id(1), id(2)]);
+        assert_eq!(
+            entities.mentions,
+            [mention(1, "ai", None, "@ai"), mention(2, "ai", None, "@ai")]
+        );
+        assert_eq!(entities.urls.len(), 1);
+        assert_eq!(entities.urls[0].note_id, id(2));
+        assert_eq!(entities.mentioned_user_ids, [id(5)]);
+    }
+
+    #[test]
+    fn test_extract_empty() {
+        let mut note = note_json(1, "");
+        note["text"] = Value::Null;
+        let note: Note = serde_json::from_value(note).unwrap();
+        assert_eq!(NoteEntities::extract(&note), NoteEntities::default());
+    }
+
+    #[test]
+    fn test_refers_to() {
+        let mention = mention(1, "Ai", Some("Misskey.io"), "@Ai@Misskey.io");
+        assert!(mention.refers_to(&user(1, "ai", Some("misskey.io"))));
+        assert!(!mention.refers_to(&user(1, "ai", None)));
+        assert!(!mention.refers_to(&user(1, "ai", Some("example.com"))));
+        assert!(!mention.refers_to(&user(1, "bob", Some("misskey.io"))));
+
+        let local = self::mention(1, "ai", None, "@ai");
+        assert!(local.refers_to(&user(1, "AI", None)));
+        assert!(!local.refers_to(&user(1, "ai", Some("misskey.io"))));
+    }
+
+    #[tokio::test]
+    async fn test_resolve_mentions() {
+        let client = MockClient::new(|endpoint, request| {
+            assert_eq!(endpoint, "users/show");
+            assert_eq!(request["userIds"], json!([id::<User>(2), id::<User>(3)]));
+            json!([
+                user_json(2, "ai", None),
+                user_json(3, "bob", Some("other.example"))
+            ])
+        });
+        let mut note = note_by(1, None, "@ai @AI@misskey.test @bob@other.example @ghost");
+        note["mentions"] = json!([id::<User>(2), id::<User>(3)]);
+        let note: Note = serde_json::from_value(note).unwrap();
+
+        let resolved = NoteEntities::extract(&note)
+            .resolve_mentions(&client)
+            .await
+            .unwrap();
+        let resolved: Vec<_> = resolved
+            .iter()
+            .map(|(mention, user)| (mention.acct.as_str(), user.as_ref().map(|user| user.id)))
+            .collect();
+        assert_eq!(
+            resolved,
+            [
+                ("@ai", Some(id(2))),
+                // the host of the local instance
+                ("@AI@misskey.test", Some(id(2))),
+                ("@bob@other.example", Some(id(3))),
+                ("@ghost", None),
+            ]
+        );
+    }
+
+    #[tokio::test]
+    async fn test_resolve_no_mentions() {
+        let client = MockClient::new(|endpoint, _| panic!("unexpected request to {}", endpoint));
+        let note: Note = serde_json::from_value(note_by(1, None, "@ghost")).unwrap();
+        let resolved = NoteEntities::extract(&note)
+            .resolve_mentions(&client)
+            .await
+            .unwrap();
+        assert_eq!(resolved.len(), 1);
+        assert!(resolved[0].1.is_none());
+    }
+}
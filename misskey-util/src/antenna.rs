@@ -0,0 +1,37 @@
+//! Utilities for antennas.
+//!
+//! The keywords of antennas are disjunctions of conjunctions, which can be written in the
+//! textual syntax of the web UI with [`AntennaQuery`]: spaces separate the keywords that must
+//! all be contained, lines or `|` separate the alternatives, and a leading `-` makes an exclude
+//! keyword.
+//! [`AntennaMatcher`] checks whether notes would be delivered to an antenna, so that the
+//! conditions can be tested on existing notes before creating it.
+//!
+//! # Examples
+//!
+//! ```no_run
+//! # #[tokio::main]
+//! # async fn main() -> anyhow::Result<()> {
+//! # let client = misskey_test::test_client().await?;
+//! use misskey_util::antenna::AntennaQuery;
+//! use misskey_util::ClientExt;
+//!
+//! let query: AntennaQuery = "misskey rust | misskey-rs".parse()?;
+//! let antenna = client
+//!     .build_antenna()
+//!     .name("misskey-rs")
+//!     .include(query.keywords)
+//!     .create()
+//!     .await?;
+//!
+//! // prints "misskey rust\nmisskey-rs"
+//! println!("{}", AntennaQuery::from_antenna(&antenna));
+//! # Ok(())
+//! # }
+//! ```
+
+mod matcher;
+mod query;
+
+pub use matcher::AntennaMatcher;
+pub use query::{AntennaQuery, ParseQueryError};
@@ -0,0 +1,379 @@
+use std::collections::HashSet;
+
+use misskey_api::model::{
+    antenna::{Antenna, AntennaSource},
+    id::Id,
+    note::{Note, Visibility},
+    query::Query,
+    user::User,
+};
+
+use super::query::{self, AntennaQuery};
+
+/// Evaluates notes against the conditions of an antenna locally.
+///
+/// The conditions are checked in the same way as Misskey does when delivering notes to
+/// antennas, so the antenna can be tried on existing notes before it is created.
+/// Since the relationships of the users are not known locally, the users followed by the owner
+/// of the antenna and the members of the user list have to be given with
+/// [`following`][`AntennaMatcher::following`] and
+/// [`list_members`][`AntennaMatcher::list_members`] to match notes with [`AntennaSource::List`]
+/// and followers-only notes, as well as [`AntennaSource::Home`] before Misskey 13.
+/// From Misskey 13, the server does not filter the notes for [`AntennaSource::Home`], and
+/// neither does this matcher.
+/// The users given in the form of `username@host` with the host of the instance match the local
+/// users only if the host is set with [`local_host`][`AntennaMatcher::local_host`].
+///
+/// # Examples
+///
+/// ```no_run
+/// # #[tokio::main]
+/// # async fn main() -> anyhow::Result<()> {
+/// # let client = misskey_test::test_client().await?;
+/// use futures::stream::{StreamExt, TryStreamExt};
+/// use misskey_util::antenna::{AntennaMatcher, AntennaQuery};
+/// use misskey_util::ClientExt;
+///
+/// let query: AntennaQuery = "misskey rust\n-spam".parse()?;
+/// let mut matcher = AntennaMatcher::new(query);
+/// matcher.case_sensitive(false).with_replies(false);
+///
+/// let notes: Vec<_> = client.local_notes(..).take(100).try_collect().await?;
+/// for note in notes.iter().filter(|note| matcher.matches(note)) {
+///     println!("{}", note.text.as_deref().unwrap_or_default());
+/// }
+/// # Ok(())
+/// # }
+/// ```
+#[derive(Debug, Clone)]
+pub struct AntennaMatcher {
+    query: AntennaQuery,
+    case_sensitive: bool,
+    with_file: bool,
+    with_replies: bool,
+    src: AntennaSource,
+    users: Vec<(String, Option<String>)>,
+    local_host: Option<String>,
+    owner: Option<Id<User>>,
+    following: HashSet<Id<User>>,
+    list_members: HashSet<Id<User>>,
+}
+
+impl AntennaMatcher {
+    /// Creates a matcher with the keywords, receiving notes from all users.
+    pub fn new(query: AntennaQuery) -> Self {
+        AntennaMatcher {
+            query,
+            case_sensitive: false,
+            with_file: false,
+            with_replies: false,
+            src: AntennaSource::All,
+            users: Vec::new(),
+            local_host: None,
+            owner: None,
+            following: HashSet::new(),
+            list_members: HashSet::new(),
+        }
+    }
+
+    /// Creates a matcher with the conditions of the antenna.
+    ///
+    /// The users followed by the owner and the members of the user list are not included.
+    pub fn from_antenna(antenna: &Antenna) -> Self {
+        let mut matcher = AntennaMatcher::new(AntennaQuery::from_antenna(antenna));
+        matcher
+            .case_sensitive(antenna.case_sensitive)
+            .with_file(antenna.with_file)
+            .with_replies(antenna.with_replies)
+            .src(antenna.src)
+            .users(&antenna.users);
+        matcher
+    }
+
+    /// Sets whether the keywords are case sensitive.
+    pub fn case_sensitive(&mut self, case_sensitive: bool) -> &mut Self {
+        self.case_sensitive = case_sensitive;
+        self
+    }
+
+    /// Sets whether only the notes with files are matched.
+    pub fn with_file(&mut self, with_file: bool) -> &mut Self {
+        self.with_file = with_file;
+        self
+    }
+
+    /// Sets whether the replies are matched.
+    pub fn with_replies(&mut self, with_replies: bool) -> &mut Self {
+        self.with_replies = with_replies;
+        self
+    }
+
+    /// Sets the source of the notes.
+    pub fn src(&mut self, src: AntennaSource) -> &mut Self {
+        self.src = src;
+        self
+    }
+
+    /// Sets the users to receive notes from for [`AntennaSource::Users`], in the form of
+    /// `username` for local users or `username@host` for remote users.
+    pub fn users(&mut self, users: impl IntoIterator<Item = impl AsRef<str>>) -> &mut Self {
+        self.users = users
+            .into_iter()
+            .map(|acct| {
+                let acct = acct.as_ref().trim_start_matches('@').to_lowercase();
+                match acct.split_once('@') {
+                    Some((username, host)) => (username.to_owned(), Some(host.to_owned())),
+                    None => (acct, None),
+                }
+            })
+            .collect();
+        self
+    }
+
+    /// Sets the host of the instance, so that the users given as `username@host` with this host
+    /// match the local users, as Misskey does.
+    pub fn local_host(&mut self, host: impl AsRef<str>) -> &mut Self {
+        self.local_host = Some(host.as_ref().to_lowercase());
+        self
+    }
+
+    /// Sets the owner of the antenna, whose notes are treated like the notes of the followed
+    /// users.
+    pub fn owner(&mut self, owner: Id<User>) -> &mut Self {
+        self.owner = Some(owner);
+        self
+    }
+
+    /// Sets the users followed by the owner of the antenna, used for followers-only notes and
+    /// [`AntennaSource::Home`] before Misskey 13.
+    pub fn following(&mut self, following: impl IntoIterator<Item = Id<User>>) -> &mut Self {
+        self.following = following.into_iter().collect();
+        self
+    }
+
+    /// Sets the members of the user list (or the user group) for [`AntennaSource::List`].
+    pub fn list_members(&mut self, members: impl IntoIterator<Item = Id<User>>) -> &mut Self {
+        self.list_members = members.into_iter().collect();
+        self
+    }
+
+    /// Returns `true` if the note would be delivered to the antenna.
+    pub fn matches(&self, note: &Note) -> bool {
+        let is_followed =
+            self.owner == Some(note.user_id) || self.following.contains(&note.user_id);
+        match note.visibility {
+            Visibility::Specified => return false,
+            Visibility::Followers if !is_followed => return false,
+            _ => {}
+        }
+        if !self.with_replies && note.reply_id.is_some() {
+            return false;
+        }
+
+        let from_source = match self.src {
+            AntennaSource::All => true,
+            // Misskey 13 leaves the home source unimplemented and delivers all notes
+            #[cfg(feature = "13-0-0")]
+            AntennaSource::Home => true,
+            #[cfg(not(feature = "13-0-0"))]
+            AntennaSource::Home => is_followed,
+            AntennaSource::Users => {
+                let username = note.user.username.to_lowercase();
+                let host = note.user.host.as_ref().map(|host| host.to_lowercase());
+                let host = self.full_host(host.as_deref());
+                self.users
+                    .iter()
+                    .any(|(u, h)| *u == username && self.full_host(h.as_deref()) == host)
+            }
+            AntennaSource::List => self.list_members.contains(&note.user_id),
+            #[cfg(all(feature = "12-10-0", not(feature = "13-7-0")))]
+            AntennaSource::Group => self.list_members.contains(&note.user_id),
+        };
+        if !from_source {
+            return false;
+        }
+
+        if !self.query.is_empty() {
+            if note.text.is_none() && note.cw.is_none() {
+                return false;
+            }
+            let text = format!(
+                "{}\n{}",
+                note.text.as_deref().unwrap_or_default(),
+                note.cw.as_deref().unwrap_or_default()
+            );
+            let text = self.normalize(&text);
+            if !query::is_empty(&self.query.keywords)
+                && !self.contains_any(&text, &self.query.keywords)
+            {
+                return false;
+            }
+            if self.contains_any(&text, &self.query.exclude_keywords) {
+                return false;
+            }
+        }
+
+        if self.with_file && note.file_ids.is_empty() {
+            return false;
+        }
+        true
+    }
+
+    /// Returns the host of the user, where `None` is the local host if it is known.
+    fn full_host<'a>(&'a self, host: Option<&'a str>) -> Option<&'a str> {
+        host.or(self.local_host.as_deref())
+    }
+
+    fn normalize(&self, text: &str) -> String {
+        if self.case_sensitive {
+            text.to_owned()
+        } else {
+            text.to_lowercase()
+        }
+    }
+
+    fn contains_any(&self, text: &str, query: &Query<String>) -> bool {
+        query.0.iter().any(|and| {
+            let mut keywords = and.iter().filter(|keyword| !keyword.is_empty()).peekable();
+            keywords.peek().is_some()
+                && keywords.all(|keyword| text.contains(&self.normalize(keyword)))
+        })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::AntennaMatcher;
+    use crate::test_util::{id, note_json, user_json};
+
+    use misskey_api::model::{antenna::AntennaSource, note::Note, user::User};
+    use serde_json::{json, Value};
+
+    fn note(user: u64, username: &str, host: Option<&str>, text: &str) -> Value {
+        let mut note = note_json(1, text);
+        note["userId"] = json!(id::<User>(user));
+        note["user"] = user_json(user, username, host);
+        note
+    }
+
+    fn parse(note: Value) -> Note {
+        serde_json::from_value(note).unwrap()
+    }
+
+    fn matcher(query: &str) -> AntennaMatcher {
+        AntennaMatcher::new(query.parse().unwrap())
+    }
+
+    #[test]
+    fn test_keywords() {
+        let matcher = matcher("misskey rust | misskey-rs\n-spam");
+        let matches = |text| matcher.matches(&parse(note(1, "a", None, text)));
+        assert!(matches("Rust and Misskey"));
+        assert!(matches("misskey-rs"));
+        assert!(!matches("misskey"));
+        assert!(!matches("misskey-rs spam"));
+
+        let mut with_cw = note(1, "a", None, "rust");
+        with_cw["cw"] = json!("misskey");
+        assert!(matcher.matches(&parse(with_cw)));
+        let mut without_text = note(1, "a", None, "");
+        without_text["text"] = Value::Null;
+        assert!(!matcher.matches(&parse(without_text.clone())));
+        // no keywords matches the notes without text
+        assert!(AntennaMatcher::new(Default::default()).matches(&parse(without_text)));
+    }
+
+    #[test]
+    fn test_exclude_keywords_only() {
+        let matcher = matcher("-spam -ham\n-egg");
+        let matches = |text| matcher.matches(&parse(note(1, "a", None, text)));
+        assert!(matches("spam"));
+        assert!(!matches("spam ham"));
+        assert!(!matches("egg"));
+    }
+
+    #[test]
+    fn test_case_sensitive() {
+        let mut matcher = matcher("Misskey");
+        matcher.case_sensitive(true);
+        assert!(matcher.matches(&parse(note(1, "a", None, "Misskey"))));
+        assert!(!matcher.matches(&parse(note(1, "a", None, "misskey"))));
+    }
+
+    #[test]
+    fn test_conditions() {
+        let mut matcher = matcher("");
+        let mut reply = note(1, "a", None, "reply");
+        reply["replyId"] = json!(id::<Note>(0));
+        let reply = parse(reply);
+        assert!(!matcher.matches(&reply));
+        matcher.with_replies(true);
+        assert!(matcher.matches(&reply));
+
+        let mut with_file = note(1, "a", None, "file");
+        with_file["fileIds"] = json!([id::<()>(0)]);
+        matcher.with_file(true);
+        assert!(matcher.matches(&parse(with_file)));
+        assert!(!matcher.matches(&parse(note(1, "a", None, "no file"))));
+    }
+
+    #[test]
+    fn test_visibility() {
+        let mut matcher = matcher("");
+        let mut specified = note(1, "a", None, "");
+        specified["visibility"] = json!("specified");
+        let mut followers = note(1, "a", None, "");
+        followers["visibility"] = json!("followers");
+        let (specified, followers) = (parse(specified), parse(followers));
+        assert!(!matcher.matches(&specified));
+        assert!(!matcher.matches(&followers));
+
+        matcher.following([id(1)]);
+        assert!(!matcher.matches(&specified));
+        assert!(matcher.matches(&followers));
+        matcher.following([]).owner(id(1));
+        assert!(matcher.matches(&followers));
+    }
+
+    #[test]
+    fn test_users() {
+        let mut matcher = matcher("");
+        matcher.src(AntennaSource::Users).users([
+            "@Alice",
+            "bob@Example.com",
+            "carol@misskey.test",
+        ]);
+        let matches = |matcher: &AntennaMatcher, username, host| {
+            matcher.matches(&parse(note(1, username, host, "")))
+        };
+        assert!(matches(&matcher, "alice", None));
+        assert!(!matches(&matcher, "alice", Some("example.com")));
+        assert!(matches(&matcher, "Bob", Some("example.com")));
+        assert!(!matches(&matcher, "bob", None));
+        assert!(!matches(&matcher, "carol", None));
+
+        matcher.local_host("Misskey.test");
+        assert!(matches(&matcher, "alice", None));
+        assert!(matches(&matcher, "alice", Some("misskey.test")));
+        assert!(matches(&matcher, "carol", None));
+        assert!(!matches(&matcher, "carol", Some("example.com")));
+    }
+
+    #[test]
+    fn test_list() {
+        let mut matcher = matcher("");
+        matcher.src(AntennaSource::List).list_members([id(2)]);
+        assert!(!matcher.matches(&parse(note(1, "a", None, ""))));
+        assert!(matcher.matches(&parse(note(2, "b", None, ""))));
+    }
+
+    #[test]
+    fn test_home() {
+        let mut matcher = matcher("");
+        matcher.src(AntennaSource::Home).following([id(2)]);
+        assert!(matcher.matches(&parse(note(2, "b", None, ""))));
+        // Misskey 13 does not filter the notes for the home source
+        let from_other = matcher.matches(&parse(note(1, "a", None, "")));
+        assert_eq!(from_other, cfg!(feature = "13-0-0"));
+    }
+}
@@ -0,0 +1,323 @@
+use std::fmt::{self, Display};
+use std::iter::Peekable;
+use std::str::{CharIndices, FromStr};
+
+use misskey_api::model::{antenna::Antenna, query::Query};
+
+/// Keywords and exclude keywords of an antenna in the textual syntax.
+///
+/// The syntax follows the convention of the keyword fields in the web UI: keywords separated by
+/// spaces must all be contained (AND), and lines or `|` separate the alternatives (OR).
+/// Keywords with a leading `-` go to the exclude keywords, and notes are excluded if they
+/// contain all the exclude keywords of any line.
+/// Keywords containing spaces, `|` or `"`, or starting with `-` can be written in double
+/// quotes, where `\"` and `\\` are the escapes of `"` and `\`.
+///
+/// Note that the exclusions are not tied to the keywords on the same line, since the antenna
+/// holds them as two separate queries. `cat -dog` excludes notes containing `dog` even if they
+/// match other lines.
+///
+/// # Examples
+///
+/// ```
+/// use misskey_util::antenna::AntennaQuery;
+///
+/// let query: AntennaQuery = "misskey rust | \"misskey-rs\"\n-spam".parse()?;
+/// assert_eq!(
+///     query.keywords.clone().into_vec(),
+///     vec![vec!["misskey", "rust"], vec!["misskey-rs"]]
+/// );
+/// assert_eq!(query.exclude_keywords.clone().into_vec(), vec![vec!["spam"]]);
+/// assert_eq!(query.to_string(), "misskey rust\nmisskey-rs\n-spam");
+/// # Ok::<(), misskey_util::antenna::ParseQueryError>(())
+/// ```
+#[derive(Debug, Clone, PartialEq, Eq, Default)]
+pub struct AntennaQuery {
+    /// The keywords, as a disjunction of conjunctions.
+    pub keywords: Query<String>,
+    /// The exclude keywords, as a disjunction of conjunctions.
+    pub exclude_keywords: Query<String>,
+}
+
+impl AntennaQuery {
+    /// Parses the textual syntax into [`AntennaQuery`].
+    pub fn parse(s: &str) -> Result<AntennaQuery, ParseQueryError> {
+        let mut keywords = Vec::new();
+        let mut exclude_keywords = Vec::new();
+        let mut include = Vec::new();
+        let mut exclude = Vec::new();
+
+        let mut chars = s.char_indices().peekable();
+        while let Some(&(start, c)) = chars.peek() {
+            if c == '\n' || c == '|' {
+                chars.next();
+                push_nonempty(&mut keywords, &mut include);
+                push_nonempty(&mut exclude_keywords, &mut exclude);
+                continue;
+            }
+            if c.is_whitespace() {
+                chars.next();
+                continue;
+            }
+
+            let negative = c == '-';
+            if negative {
+                chars.next();
+            }
+            let word = match chars.peek() {
+                Some(&(_, '"')) => {
+                    chars.next();
+                    read_quoted(&mut chars, start)?
+                }
+                Some(&(_, c)) if !is_delimiter(c) => read_plain(&mut chars),
+                _ => return Err(parse_error(start, "expected a keyword after `-`")),
+            };
+            if negative {
+                exclude.push(word);
+            } else {
+                include.push(word);
+            }
+        }
+        push_nonempty(&mut keywords, &mut include);
+        push_nonempty(&mut exclude_keywords, &mut exclude);
+
+        Ok(AntennaQuery {
+            keywords: Query::from_vec(keywords),
+            exclude_keywords: Query::from_vec(exclude_keywords),
+        })
+    }
+
+    /// Takes the keywords and exclude keywords of the antenna.
+    pub fn from_antenna(antenna: &Antenna) -> AntennaQuery {
+        AntennaQuery {
+            keywords: antenna.keywords.clone(),
+            #[cfg(feature = "12-19-0")]
+            exclude_keywords: antenna.exclude_keywords.clone(),
+            #[cfg(not(feature = "12-19-0"))]
+            exclude_keywords: Query::new(),
+        }
+    }
+
+    /// Returns `true` if there are neither keywords nor exclude keywords.
+    pub fn is_empty(&self) -> bool {
+        is_empty(&self.keywords) && is_empty(&self.exclude_keywords)
+    }
+}
+
+/// Returns `true` if the query has no non-empty keyword, which Misskey treats as no condition.
+pub(super) fn is_empty(query: &Query<String>) -> bool {
+    query
+        .0
+        .iter()
+        .all(|and| and.iter().all(|keyword| keyword.is_empty()))
+}
+
+fn push_nonempty(query: &mut Vec<Vec<String>>, words: &mut Vec<String>) {
+    if !words.is_empty() {
+        query.push(std::mem::take(words));
+    }
+}
+
+fn is_delimiter(c: char) -> bool {
+    c.is_whitespace() || c == '|'
+}
+
+fn read_plain(chars: &mut Peekable<CharIndices>) -> String {
+    let mut word = String::new();
+    while let Some(&(_, c)) = chars.peek() {
+        if is_delimiter(c) {
+            break;
+        }
+        word.push(c);
+        chars.next();
+    }
+    word
+}
+
+fn read_quoted(chars: &mut Peekable<CharIndices>, start: usize) -> Result<String, ParseQueryError> {
+    let mut word = String::new();
+    loop {
+        match chars.next() {
+            Some((_, '"')) => break,
+            Some((_, '\\')) => match chars.peek() {
+                Some(&(_, c)) if c == '"' || c == '\\' => {
+                    word.push(c);
+                    chars.next();
+                }
+                _ => word.push('\\'),
+            },
+            Some((_, c)) => word.push(c),
+            None => return Err(parse_error(start, "unterminated quote")),
+        }
+    }
+    match chars.peek() {
+        Some(&(i, c)) if !is_delimiter(c) => Err(parse_error(
+            i,
+            "expected a delimiter after the quoted keyword",
+        )),
+        _ if word.is_empty() => Err(parse_error(start, "empty keyword")),
+        _ => Ok(word),
+    }
+}
+
+impl FromStr for AntennaQuery {
+    type Err = ParseQueryError;
+
+    fn from_str(s: &str) -> Result<AntennaQuery, Self::Err> {
+        AntennaQuery::parse(s)
+    }
+}
+
+impl Display for AntennaQuery {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        let lines = self
+            .keywords
+            .0
+            .iter()
+            .map(|and| (false, and))
+            .chain(self.exclude_keywords.0.iter().map(|and| (true, and)));
+        let mut first = true;
+        for (negative, and) in lines {
+            let mut words = and.iter().filter(|keyword| !keyword.is_empty()).peekable();
+            if words.peek().is_none() {
+                continue;
+            }
+            if !first {
+                f.write_str("\n")?;
+            }
+            first = false;
+            for (i, word) in words.enumerate() {
+                if i > 0 {
+                    f.write_str(" ")?;
+                }
+                if negative {
+                    f.write_str("-")?;
+                }
+                write_word(f, word)?;
+            }
+        }
+        Ok(())
+    }
+}
+
+fn write_word(f: &mut fmt::Formatter, word: &str) -> fmt::Result {
+    let needs_quote = word.starts_with('-') || word.contains(|c| is_delimiter(c) || c == '"');
+    if !needs_quote {
+        return f.write_str(word);
+    }
+    f.write_str("\"")?;
+    for c in word.chars() {
+        if c == '"' || c == '\\' {
+            f.write_str("\\")?;
+        }
+        write!(f, "{}", c)?;
+    }
+    f.write_str("\"")
+}
+
+/// Error type for parsing [`AntennaQuery`].
+#[derive(Debug, Clone)]
+pub struct ParseQueryError {
+    position: usize,
+    message: String,
+}
+
+impl ParseQueryError {
+    /// Returns the byte offset in the input where the error occurred.
+    pub fn position(&self) -> usize {
+        self.position
+    }
+}
+
+impl std::error::Error for ParseQueryError {}
+
+impl Display for ParseQueryError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(
+            f,
+            "invalid antenna query at {}: {}",
+            self.position, self.message
+        )
+    }
+}
+
+fn parse_error(position: usize, message: impl Into<String>) -> ParseQueryError {
+    ParseQueryError {
+        position,
+        message: message.into(),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::AntennaQuery;
+
+    use misskey_api::model::query::Query;
+
+    fn query(lines: &[&[&str]]) -> Query<String> {
+        Query::from_vec(
+            lines
+                .iter()
+                .map(|and| and.iter().map(|s| s.to_string()).collect())
+                .collect(),
+        )
+    }
+
+    #[test]
+    fn test_parse() {
+        let parsed: AntennaQuery = "a b | c\n\n  d  \n-e -f\ng -h".parse().unwrap();
+        assert_eq!(
+            parsed.keywords,
+            query(&[&["a", "b"], &["c"], &["d"], &["g"]])
+        );
+        assert_eq!(parsed.exclude_keywords, query(&[&["e", "f"], &["h"]]));
+        assert_eq!(AntennaQuery::parse("").unwrap(), AntennaQuery::default());
+        assert!(AntennaQuery::parse(" \n | ").unwrap().is_empty());
+        // `-` in the middle of a keyword is kept
+        let parsed = AntennaQuery::parse("misskey-rs a-").unwrap();
+        assert_eq!(parsed.keywords, query(&[&["misskey-rs", "a-"]]));
+    }
+
+    #[test]
+    fn test_parse_quoted() {
+        let parsed = AntennaQuery::parse(r#""a b" "c|d" "-e" -"f g" "\"h\\" "i\j""#).unwrap();
+        assert_eq!(
+            parsed.keywords,
+            query(&[&["a b", "c|d", "-e", "\"h\\", "i\\j"]])
+        );
+        assert_eq!(parsed.exclude_keywords, query(&[&["f g"]]));
+    }
+
+    #[test]
+    fn test_parse_error() {
+        for (text, position) in [
+            ("a -", 2),
+            ("a - b", 2),
+            ("a -|b", 2),
+            ("\"a", 0),
+            ("b \"a\\\"", 2),
+            ("\"a\"b", 3),
+            ("a \"\"", 2),
+        ] {
+            let err = AntennaQuery::parse(text).unwrap_err();
+            assert_eq!(err.position(), position, "{:?}: {}", text, err);
+        }
+    }
+
+    #[test]
+    fn test_display() {
+        let parsed = AntennaQuery {
+            keywords: query(&[&["a", "b c"], &["-d", "e\"f\\"], &[""]]),
+            exclude_keywords: query(&[&["g|h", ""]]),
+        };
+        let text = parsed.to_string();
+        assert_eq!(text, "a \"b c\"\n\"-d\" \"e\\\"f\\\\\"\n-\"g|h\"");
+        assert_eq!(
+            AntennaQuery::parse(&text).unwrap(),
+            AntennaQuery {
+                keywords: query(&[&["a", "b c"], &["-d", "e\"f\\"]]),
+                exclude_keywords: query(&[&["g|h"]]),
+            }
+        );
+    }
+}
@@ -0,0 +1,763 @@
+//! Backup and restore of accounts.
+//!
+//! [`Archive::export`] collects the data of the account logged in with the client into an
+//! [`Archive`], which can be saved as JSON with [`serde_json`]. The files attached to the notes
+//! are only recorded as metadata by default; [`Archive::fetch_files`] downloads their contents
+//! into the archive with the HTTP client of your choice.
+//!
+//! [`Archive::restore`] recreates the user lists, antennas, clips, follows and pages on another
+//! account, which may be on another instance. The users and notes are looked up on the instance
+//! of the client by their usernames and URIs.
+//!
+//! # Examples
+//!
+//! ```no_run
+//! # #[tokio::main]
+//! # async fn main() -> anyhow::Result<()> {
+//! # let client = misskey_test::test_client().await?;
+//! # let new_client = misskey_test::test_client().await?;
+//! use misskey_util::archive::Archive;
+//!
+//! let archive = Archive::export(&client).await?;
+//! std::fs::write("archive.json", serde_json::to_vec(&archive)?)?;
+//!
+//! let archive: Archive = serde_json::from_slice(&std::fs::read("archive.json")?)?;
+//! let report = archive.restore(&new_client).await?;
+//! for user in report.unresolved_users {
+//!     println!("could not follow @{}", user.username);
+//! }
+//! # Ok(())
+//! # }
+//! ```
+
+use std::collections::HashMap;
+use std::future::Future;
+use std::io;
+
+use crate::{ClientExt, Error};
+
+use chrono::{DateTime, Utc};
+use futures::stream::TryStreamExt;
+use misskey_api::endpoint;
+#[cfg(feature = "13-0-0")]
+use misskey_api::model::flash::Flash;
+#[cfg(feature = "12-79-0")]
+use misskey_api::model::gallery::GalleryPost;
+#[cfg(feature = "12-67-0")]
+use misskey_api::model::registry::{RegistryKey, RegistryScope, RegistryValue};
+use misskey_api::model::{
+    antenna::{Antenna, AntennaSource},
+    clip::Clip,
+    drive::DriveFile,
+    id::Id,
+    note::Note,
+    page::Page,
+    user::User,
+    user_list::UserList,
+};
+use misskey_core::{model::ApiResult, Client};
+use serde::{Deserialize, Serialize};
+use url::Url;
+
+/// The version of the archive format written by [`Archive::export`].
+pub const ARCHIVE_VERSION: u32 = 1;
+
+/// Archive of an account.
+///
+/// The fields not supported by the version of Misskey are left empty, and are filled with
+/// the defaults when the archive exported with another version is read.
+#[derive(Serialize, Deserialize, Debug, Clone)]
+#[serde(rename_all = "camelCase")]
+pub struct Archive {
+    /// Version of the archive format, which is [`ARCHIVE_VERSION`] for the newly exported one.
+    pub version: u32,
+    /// The time the archive is exported.
+    pub exported_at: DateTime<Utc>,
+    /// URL of the instance the account belongs to.
+    pub origin: Url,
+    /// The profile of the account.
+    pub profile: User,
+    /// The notes posted by the account.
+    #[serde(default)]
+    pub notes: Vec<Note>,
+    /// The files attached to the notes.
+    #[serde(default)]
+    pub files: Vec<ArchivedFile>,
+    /// The users the account is following.
+    #[serde(default)]
+    pub following: Vec<User>,
+    /// The followers of the account.
+    #[serde(default)]
+    pub followers: Vec<User>,
+    /// The users muted by the account.
+    #[serde(default)]
+    pub mutes: Vec<User>,
+    /// The users blocked by the account.
+    #[serde(default)]
+    pub blocks: Vec<User>,
+    /// The user lists of the account.
+    #[serde(default)]
+    pub user_lists: Vec<ArchivedUserList>,
+    /// The antennas of the account.
+    #[serde(default)]
+    pub antennas: Vec<Antenna>,
+    /// The clips of the account.
+    #[serde(default)]
+    pub clips: Vec<ArchivedClip>,
+    /// The pages created by the account.
+    #[serde(default)]
+    pub pages: Vec<Page>,
+    /// The gallery posts created by the account.
+    #[cfg(feature = "12-79-0")]
+    #[cfg_attr(docsrs, doc(cfg(feature = "12-79-0")))]
+    #[serde(default)]
+    pub gallery_posts: Vec<GalleryPost>,
+    /// The Plays created by the account.
+    #[cfg(feature = "13-0-0")]
+    #[cfg_attr(docsrs, doc(cfg(feature = "13-0-0")))]
+    #[serde(default)]
+    pub plays: Vec<Flash>,
+    /// The registry of the account.
+    #[cfg(feature = "12-67-0")]
+    #[cfg_attr(docsrs, doc(cfg(feature = "12-67-0")))]
+    #[serde(default)]
+    pub registry: Vec<ArchivedRegistryScope>,
+}
+
+/// A file in [`Archive`].
+#[derive(Serialize, Deserialize, Debug, Clone)]
+#[serde(rename_all = "camelCase")]
+pub struct ArchivedFile {
+    /// The metadata of the file.
+    pub file: DriveFile,
+    /// The contents of the file, which are fetched with [`Archive::fetch_files`].
+    ///
+    /// This is serialized in Base64.
+    #[serde(default, with = "base64_contents")]
+    pub contents: Option<Vec<u8>>,
+}
+
+/// A user list in [`Archive`].
+#[derive(Serialize, Deserialize, Debug, Clone)]
+#[serde(rename_all = "camelCase")]
+pub struct ArchivedUserList {
+    /// The user list.
+    pub list: UserList,
+    /// The users in the list.
+    pub users: Vec<User>,
+}
+
+/// A clip in [`Archive`].
+#[derive(Serialize, Deserialize, Debug, Clone)]
+#[serde(rename_all = "camelCase")]
+pub struct ArchivedClip {
+    /// The clip.
+    pub clip: Clip,
+    /// The notes in the clip.
+    pub notes: Vec<Note>,
+}
+
+/// A scope of the registry in [`Archive`].
+#[cfg(feature = "12-67-0")]
+#[cfg_attr(docsrs, doc(cfg(feature = "12-67-0")))]
+#[derive(Serialize, Deserialize, Debug, Clone)]
+#[serde(rename_all = "camelCase")]
+pub struct ArchivedRegistryScope {
+    /// The scope.
+    pub scope: RegistryScope,
+    /// The values in the scope.
+    pub values: HashMap<RegistryKey, RegistryValue>,
+}
+
+/// The result of [`Archive::restore`].
+#[derive(Debug, Clone, Default)]
+pub struct RestoreReport {
+    /// The created user lists.
+    pub user_lists: Vec<UserList>,
+    /// The created antennas.
+    pub antennas: Vec<Antenna>,
+    /// The created clips.
+    pub clips: Vec<Clip>,
+    /// The newly followed users.
+    pub followed: Vec<User>,
+    /// The created pages.
+    pub pages: Vec<Page>,
+    /// The users in the archive that are not found on the instance.
+    pub unresolved_users: Vec<User>,
+    /// The notes in the clips that are not found on the instance, or all of them before
+    /// 12.57.0, where notes cannot be clipped.
+    pub unresolved_notes: Vec<Note>,
+    /// The antennas that cannot be recreated because their sources are not restored.
+    pub skipped_antennas: Vec<Antenna>,
+}
+
+impl Archive {
+    /// Exports the account logged in with `client`.
+    pub async fn export<C: ClientExt>(client: &C) -> Result<Archive, Error<C::Error>> {
+        let meta = client.meta().await?;
+        let origin =
+            Url::parse(&meta.uri).map_err(|e| io::Error::new(io::ErrorKind::InvalidData, e))?;
+        let profile = client.me().await?;
+
+        let notes: Vec<Note> = client.user_notes(&profile, ..).try_collect().await?;
+        let mut files: Vec<ArchivedFile> = Vec::new();
+        for file in notes.iter().flat_map(|note| &note.files) {
+            if files.iter().all(|f| f.file.id != file.id) {
+                files.push(ArchivedFile {
+                    file: file.clone(),
+                    contents: None,
+                });
+            }
+        }
+
+        let mut user_lists = Vec::new();
+        for list in client.user_lists().await? {
+            let users = if list.user_ids.is_empty() {
+                Vec::new()
+            } else {
+                client.get_users(list.user_ids.clone()).await?
+            };
+            user_lists.push(ArchivedUserList { list, users });
+        }
+
+        let mut clips = Vec::new();
+        for clip in client.clips().await? {
+            let notes = client.clip_notes(&clip).try_collect().await?;
+            clips.push(ArchivedClip { clip, notes });
+        }
+
+        #[cfg(feature = "12-67-0")]
+        let mut registry = Vec::new();
+        #[cfg(feature = "12-67-0")]
+        for scope in client.registry_scopes().await? {
+            let values = client.registry_get_all(scope.clone()).await?;
+            registry.push(ArchivedRegistryScope { scope, values });
+        }
+
+        Ok(Archive {
+            version: ARCHIVE_VERSION,
+            exported_at: Utc::now(),
+            origin,
+            following: client.following(&profile).try_collect().await?,
+            followers: client.followers(&profile).try_collect().await?,
+            profile,
+            notes,
+            files,
+            mutes: client.muting_users().try_collect().await?,
+            blocks: client.blocking_users().try_collect().await?,
+            user_lists,
+            antennas: client.antennas().await?,
+            clips,
+            pages: client.pages().try_collect().await?,
+            #[cfg(feature = "12-79-0")]
+            gallery_posts: client.gallery_posts().try_collect().await?,
+            #[cfg(feature = "13-0-0")]
+            plays: client.plays().try_collect().await?,
+            #[cfg(feature = "12-67-0")]
+            registry,
+        })
+    }
+
+    /// Fetches the contents of the files whose contents are not in the archive yet.
+    ///
+    /// `fetch` is called with each file and returns its contents, e.g. by downloading it from
+    /// [`url`][`DriveFile::url`] with an HTTP client.
+    pub async fn fetch_files<F, Fut, E>(&mut self, mut fetch: F) -> Result<(), E>
+    where
+        F: FnMut(&DriveFile) -> Fut,
+        Fut: Future<Output = Result<Vec<u8>, E>>,
+    {
+        for file in &mut self.files {
+            if file.contents.is_none() {
+                file.contents = Some(fetch(&file.file).await?);
+            }
+        }
+        Ok(())
+    }
+
+    /// Recreates the user lists, antennas, clips, follows and pages in the archive on the
+    /// account logged in with `client`.
+    ///
+    /// The users and notes that are not found on the instance are skipped and reported in
+    /// [`RestoreReport`]. Notes, files, mutes, blocks and the rest of the archive are not
+    /// restored. Archives of versions other than [`ARCHIVE_VERSION`] are rejected with
+    /// [`Error::Io`].
+    pub async fn restore<C: ClientExt>(
+        &self,
+        client: &C,
+    ) -> Result<RestoreReport, Error<C::Error>> {
+        if self.version != ARCHIVE_VERSION {
+            let message = format!("unsupported archive version {}", self.version);
+            return Err(io::Error::new(io::ErrorKind::InvalidData, message).into());
+        }
+        let meta = client.meta().await?;
+        let target =
+            Url::parse(&meta.uri).map_err(|e| io::Error::new(io::ErrorKind::InvalidData, e))?;
+        self.restore_on(client, target.host_str().unwrap_or_default())
+            .await
+    }
+
+    /// Restores the archive on the instance at `target_host`.
+    async fn restore_on<C: ClientExt>(
+        &self,
+        client: &C,
+        target_host: &str,
+    ) -> Result<RestoreReport, Error<C::Error>> {
+        let mut resolver = Resolver {
+            client,
+            origin_host: self.origin.host_str().unwrap_or_default().to_owned(),
+            target_host: target_host.to_owned(),
+            users: HashMap::new(),
+        };
+        let mut report = RestoreReport::default();
+
+        let mut list_ids: HashMap<Id<UserList>, UserList> = HashMap::new();
+        for archived in &self.user_lists {
+            let list = client.create_user_list(archived.list.name.clone()).await?;
+            for user in &archived.users {
+                match resolver.resolve_user(user).await? {
+                    Some(resolved) => client.push_to_user_list(&list, &resolved).await?,
+                    None => report.unresolved_users.push(user.clone()),
+                }
+            }
+            list_ids.insert(archived.list.id, list.clone());
+            report.user_lists.push(list);
+        }
+
+        for antenna in &self.antennas {
+            let mut builder = client.build_antenna();
+            builder
+                .name(antenna.name.clone())
+                .include(antenna.keywords.clone())
+                .case_sensitive(antenna.case_sensitive)
+                .exclude_replies(!antenna.with_replies)
+                .with_files_only(antenna.with_file)
+                .notify(antenna.notify);
+            #[cfg(feature = "12-19-0")]
+            builder.exclude(antenna.exclude_keywords.clone());
+            match antenna.src {
+                AntennaSource::All => builder.all(),
+                AntennaSource::Home => builder.home(),
+                AntennaSource::Users => {
+                    builder.users(antenna.users.iter().map(|acct| resolver.rehost_acct(acct)))
+                }
+                AntennaSource::List => {
+                    let list = antenna.user_list_id.and_then(|id| list_ids.get(&id));
+                    match list {
+                        Some(list) => builder.user_list(list),
+                        None => {
+                            report.skipped_antennas.push(antenna.clone());
+                            continue;
+                        }
+                    }
+                }
+                #[cfg(all(feature = "12-10-0", not(feature = "13-7-0")))]
+                AntennaSource::Group => {
+                    report.skipped_antennas.push(antenna.clone());
+                    continue;
+                }
+            };
+            report.antennas.push(builder.create().await?);
+        }
+
+        for archived in &self.clips {
+            #[cfg(not(feature = "12-57-0"))]
+            let clip = client.create_clip(archived.clip.name.clone()).await?;
+            #[cfg(feature = "12-57-0")]
+            let clip = {
+                let mut builder = client.build_clip();
+                builder
+                    .name(archived.clip.name.clone())
+                    .public(archived.clip.is_public);
+                if let Some(description) = &archived.clip.description {
+                    builder.description(description.clone());
+                }
+                builder.create().await?
+            };
+            // notes cannot be clipped before 12.57.0
+            #[cfg(not(feature = "12-57-0"))]
+            report
+                .unresolved_notes
+                .extend(archived.notes.iter().cloned());
+            #[cfg(feature = "12-57-0")]
+            for note in &archived.notes {
+                match resolver.resolve_note(&self.origin, note).await? {
+                    Some(resolved) => client.clip_note(&clip, &resolved).await?,
+                    None => report.unresolved_notes.push(note.clone()),
+                }
+            }
+            report.clips.push(clip);
+        }
+
+        for user in &self.following {
+            match resolver.resolve_user(user).await? {
+                Some(resolved) => {
+                    if !client.is_following(&resolved).await? {
+                        report.followed.push(client.follow(&resolved).await?);
+                    }
+                }
+                None => report.unresolved_users.push(user.clone()),
+            }
+        }
+
+        for page in &self.pages {
+            let mut builder = client.build_page();
+            builder
+                .title(page.title.clone())
+                .name(page.name.clone())
+                .content(page.content.clone())
+                .variables(page.variables.clone())
+                .font(page.font)
+                .align_center(page.align_center)
+                .hide_title_when_pinned(page.hide_title_when_pinned);
+            if let Some(summary) = &page.summary {
+                builder.summary(summary.clone());
+            }
+            #[cfg(feature = "12-31-0")]
+            builder.script(page.script.clone());
+            report.pages.push(builder.create().await?);
+        }
+
+        Ok(report)
+    }
+}
+
+/// Looks up the users and notes of the archive on the instance of the client.
+struct Resolver<'a, C> {
+    client: &'a C,
+    origin_host: String,
+    target_host: String,
+    users: HashMap<(String, Option<String>), Option<User>>,
+}
+
+impl<C: Client + Sync> Resolver<'_, C> {
+    /// Returns the host of the user seen from the target instance.
+    fn rehost(&self, host: Option<&str>) -> Option<String> {
+        let host = host.unwrap_or(&self.origin_host);
+        if host.eq_ignore_ascii_case(&self.target_host) {
+            None
+        } else {
+            Some(host.to_owned())
+        }
+    }
+
+    /// Rewrites the acct such as `@user@host` or `user` in the origin to the one in the target.
+    fn rehost_acct(&self, acct: &str) -> String {
+        let mut parts = acct.trim_start_matches('@').splitn(2, '@');
+        let username = parts.next().unwrap_or_default();
+        match self.rehost(parts.next()) {
+            Some(host) => format!("@{}@{}", username, host),
+            None => format!("@{}", username),
+        }
+    }
+
+    async fn resolve_user(&mut self, user: &User) -> Result<Option<User>, Error<C::Error>> {
+        let host = self.rehost(user.host.as_deref());
+        let key = (user.username.to_lowercase(), host.clone());
+        if let Some(resolved) = self.users.get(&key) {
+            return Ok(resolved.clone());
+        }
+        let request = endpoint::users::show::Request::WithUsername {
+            username: user.username.clone(),
+            host,
+        };
+        let resolved = match self.client.request(request).await.map_err(Error::Client)? {
+            ApiResult::Ok(user) => Some(user),
+            // the user does not exist or its instance cannot be reached
+            ApiResult::Err { error }
+                if error.code == "NO_SUCH_USER"
+                    || error.code == "FAILED_TO_RESOLVE_REMOTE_USER" =>
+            {
+                None
+            }
+            ApiResult::Err { error } => return Err(Error::Api(error)),
+        };
+        self.users.insert(key, resolved.clone());
+        Ok(resolved)
+    }
+
+    #[cfg(feature = "12-57-0")]
+    async fn resolve_note(
+        &self,
+        origin: &Url,
+        note: &Note,
+    ) -> Result<Option<Note>, Error<C::Error>> {
+        let uri = match &note.uri {
+            Some(uri) => uri.clone(),
+            None => origin
+                .join(&format!("/notes/{}", note.id))
+                .map_err(|e| io::Error::new(io::ErrorKind::InvalidData, e))?
+                .to_string(),
+        };
+        let request = endpoint::ap::show::Request { uri };
+        match self.client.request(request).await.map_err(Error::Client)? {
+            ApiResult::Ok(endpoint::ap::show::Response::Note(note)) => Ok(Some(*note)),
+            // the URI points to a user, not a note
+            ApiResult::Ok(endpoint::ap::show::Response::User(_)) => Ok(None),
+            // the note does not exist or its instance cannot be reached
+            ApiResult::Err { error }
+                if error.code == "NO_SUCH_OBJECT" || error.code == "REQUEST_FAILED" =>
+            {
+                Ok(None)
+            }
+            ApiResult::Err { error } => Err(Error::Api(error)),
+        }
+    }
+}
+
+mod base64_contents {
+    use serde::{Deserialize, Deserializer, Serializer};
+
+    pub fn serialize<S: Serializer>(
+        contents: &Option<Vec<u8>>,
+        serializer: S,
+    ) -> Result<S::Ok, S::Error> {
+        match contents {
+            Some(contents) => serializer.serialize_some(&base64::encode(contents)),
+            None => serializer.serialize_none(),
+        }
+    }
+
+    pub fn deserialize<'de, D: Deserializer<'de>>(
+        deserializer: D,
+    ) -> Result<Option<Vec<u8>>, D::Error> {
+        Option::<String>::deserialize(deserializer)?
+            .map(|encoded| base64::decode(encoded).map_err(serde::de::Error::custom))
+            .transpose()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{Archive, ArchivedClip, ArchivedFile, ARCHIVE_VERSION};
+    use crate::test_util::{api_error, drive_file_json, id, note, user, user_json, MockClient};
+    use crate::Error;
+
+    use misskey_api::model::clip::Clip;
+    use serde_json::{json, Value};
+
+    fn archive() -> Archive {
+        serde_json::from_value(json!({
+            "version": ARCHIVE_VERSION,
+            "exportedAt": "2000-01-01T00:00:00Z",
+            "origin": "https://origin.example/",
+            "profile": user_json(0, "user", None),
+        }))
+        .unwrap()
+    }
+
+    fn clip_json(n: u64, name: &str) -> Value {
+        #[allow(unused_mut)]
+        let mut clip = json!({
+            "id": id::<Clip>(n),
+            "createdAt": "2000-01-01T00:00:00Z",
+            "name": name,
+        });
+        #[cfg(feature = "12-57-0")]
+        {
+            clip["userId"] = json!(id::<()>(0));
+            clip["user"] = user_json(0, "user", None);
+            clip["description"] = Value::Null;
+            clip["isPublic"] = json!(false);
+        }
+        #[cfg(feature = "13-10-0")]
+        {
+            clip["favoritedCount"] = json!(0);
+        }
+        clip
+    }
+
+    fn relation_json(user_id: &Value) -> Value {
+        json!({
+            "id": user_id,
+            "isFollowing": false,
+            "hasPendingFollowRequestFromYou": false,
+            "hasPendingFollowRequestToYou": false,
+            "isFollowed": false,
+            "isBlocking": false,
+            "isBlocked": false,
+            "isMuted": false,
+            "isRenoteMuted": false,
+        })
+    }
+
+    #[test]
+    fn test_serialize_round_trip() {
+        let mut archive = archive();
+        archive
+            .following
+            .push(user(1, "alice", Some("remote.example")));
+        archive.files.push(ArchivedFile {
+            file: serde_json::from_value(drive_file_json(2, "a.png", "md5", None)).unwrap(),
+            contents: Some(b"\x00\xffcontents".to_vec()),
+        });
+        let value = serde_json::to_value(&archive).unwrap();
+        assert_eq!(value["files"][0]["contents"], "AP9jb250ZW50cw==");
+
+        let archive: Archive = serde_json::from_value(value).unwrap();
+        assert_eq!(archive.version, ARCHIVE_VERSION);
+        assert_eq!(archive.following[0].username, "alice");
+        assert_eq!(
+            archive.files[0].contents.as_deref(),
+            Some(&b"\x00\xffcontents"[..])
+        );
+    }
+
+    #[test]
+    fn test_deserialize_defaults() {
+        let archive = archive();
+        assert!(archive.notes.is_empty());
+        assert!(archive.following.is_empty());
+        assert!(archive.clips.is_empty());
+    }
+
+    #[test]
+    fn test_deserialize_invalid_contents() {
+        let mut value = serde_json::to_value(archive()).unwrap();
+        value["files"] = json!([{
+            "file": drive_file_json(2, "a.png", "md5", None),
+            "contents": "not base64!",
+        }]);
+        assert!(serde_json::from_value::<Archive>(value).is_err());
+    }
+
+    #[tokio::test]
+    async fn test_restore_unknown_version() {
+        let client = MockClient::new(|_, _| unreachable!());
+        let mut archive = archive();
+        archive.version = ARCHIVE_VERSION + 1;
+        let result = archive.restore(&client).await;
+        assert!(matches!(result, Err(Error::Io(_))));
+        assert!(client.requests().is_empty());
+    }
+
+    #[tokio::test]
+    async fn test_restore_report() {
+        let client = MockClient::new(|endpoint, request| match endpoint {
+            "users/show" => match request["username"].as_str() {
+                Some("alice") => {
+                    assert_eq!(request["host"], "remote.example");
+                    user_json(1, "alice", Some("remote.example"))
+                }
+                Some("bob") => {
+                    // the local user of the origin is now remote
+                    assert_eq!(request["host"], "origin.example");
+                    api_error("NO_SUCH_USER")
+                }
+                _ => unreachable!(),
+            },
+            "users/relation" => relation_json(&request["userId"]),
+            "following/create" => user_json(1, "alice", Some("remote.example")),
+            "clips/create" => clip_json(10, "clip"),
+            "ap/show" => api_error("NO_SUCH_OBJECT"),
+            _ => unreachable!("{}", endpoint),
+        });
+        let mut archive = archive();
+        archive
+            .following
+            .push(user(1, "alice", Some("remote.example")));
+        archive.following.push(user(2, "bob", None));
+        archive.following.push(user(2, "Bob", None));
+        archive.clips.push(ArchivedClip {
+            clip: serde_json::from_value(clip_json(3, "clip")).unwrap(),
+            notes: vec![note(4, "note")],
+        });
+
+        let report = archive.restore_on(&client, "target.example").await.unwrap();
+        assert_eq!(report.clips.len(), 1);
+        assert_eq!(report.followed.len(), 1);
+        assert_eq!(report.followed[0].username, "alice");
+        // the unresolved user is looked up once
+        let usernames: Vec<_> = report
+            .unresolved_users
+            .iter()
+            .map(|u| &u.username)
+            .collect();
+        assert_eq!(usernames, ["bob", "Bob"]);
+        assert_eq!(
+            client
+                .endpoints()
+                .iter()
+                .filter(|e| *e == "users/show")
+                .count(),
+            2
+        );
+        // the note is not found on the target, or cannot be clipped before 12.57.0
+        assert_eq!(report.unresolved_notes.len(), 1);
+        assert!(!client.endpoints().iter().any(|e| e == "clips/add-note"));
+    }
+
+    #[tokio::test]
+    async fn test_restore_api_error() {
+        let client = MockClient::new(|endpoint, _| match endpoint {
+            "users/show" => api_error("RATE_LIMIT_EXCEEDED"),
+            _ => unreachable!("{}", endpoint),
+        });
+        let mut archive = archive();
+        archive.following.push(user(1, "alice", None));
+        let result = archive.restore_on(&client, "target.example").await;
+        assert!(matches!(result, Err(Error::Api(e)) if e.code == "RATE_LIMIT_EXCEEDED"));
+    }
+
+    #[cfg(feature = "12-57-0")]
+    #[tokio::test]
+    async fn test_restore_clip_notes() {
+        let client = MockClient::new(|endpoint, request| match endpoint {
+            "clips/create" => clip_json(10, "clip"),
+            "ap/show" => match request["uri"].as_str() {
+                Some("https://origin.example/notes/found") => json!({
+                    "type": "Note",
+                    "object": crate::test_util::note_json(5, "found"),
+                }),
+                Some("https://origin.example/notes/user") => json!({
+                    "type": "User",
+                    "object": user_json(1, "alice", None),
+                }),
+                _ => api_error("NO_SUCH_OBJECT"),
+            },
+            "clips/add-note" => Value::Null,
+            _ => unreachable!("{}", endpoint),
+        });
+        let mut archive = archive();
+        let mut notes = vec![note(4, "found"), note(5, "user"), note(6, "missing")];
+        for note in &mut notes {
+            let path = note.text.clone().unwrap();
+            note.uri = Some(format!("https://origin.example/notes/{}", path));
+        }
+        archive.clips.push(ArchivedClip {
+            clip: serde_json::from_value(clip_json(3, "clip")).unwrap(),
+            notes,
+        });
+
+        let report = archive.restore_on(&client, "target.example").await.unwrap();
+        let unresolved: Vec<_> = report
+            .unresolved_notes
+            .iter()
+            .map(|n| n.text.as_deref().unwrap())
+            .collect();
+        assert_eq!(unresolved, ["user", "missing"]);
+        let (_, request) = client
+            .requests()
+            .into_iter()
+            .find(|(e, _)| e == "clips/add-note")
+            .unwrap();
+        assert_eq!(request["noteId"], json!(id::<()>(5)));
+    }
+
+    #[cfg(feature = "12-57-0")]
+    #[tokio::test]
+    async fn test_restore_clip_notes_api_error() {
+        let client = MockClient::new(|endpoint, _| match endpoint {
+            "clips/create" => clip_json(10, "clip"),
+            "ap/show" => api_error("INTERNAL_ERROR"),
+            _ => unreachable!("{}", endpoint),
+        });
+        let mut archive = archive();
+        archive.clips.push(ArchivedClip {
+            clip: serde_json::from_value(clip_json(3, "clip")).unwrap(),
+            notes: vec![note(4, "note")],
+        });
+        let result = archive.restore_on(&client, "target.example").await;
+        assert!(matches!(result, Err(Error::Api(e)) if e.code == "INTERNAL_ERROR"));
+    }
+}
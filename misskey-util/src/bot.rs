@@ -0,0 +1,542 @@
+//! A framework for bots that respond to commands in mentions.
+//!
+//! [`Bot`] watches the mentions and replies to the account on the main stream, and dispatches
+//! the notes that start with a command name (after the mentions) to the registered handlers,
+//! such as `@bot remind 10m stretch`.
+//! The words following the command name are parsed into the types the handler takes
+//! (see [`FromArgs`]), and the handler replies to the command through [`CommandContext`].
+//!
+//! # Examples
+//!
+//! ```no_run
+//! # use misskey_util::ClientExt;
+//! # #[tokio::main]
+//! # async fn main() -> anyhow::Result<()> {
+//! # let http_client = misskey_test::test_client().await?;
+//! # let ws_client = misskey_test::test_websocket_client(misskey_test::env::token()).await?;
+//! use std::time::Duration;
+//!
+//! use futures_timer::Delay;
+//! use misskey_util::bot::{Bot, Interval, Rest};
+//!
+//! let mut bot = Bot::new(http_client);
+//! bot.command("ping", |ctx, ()| async move {
+//!     ctx.reply("pong").await?;
+//!     Ok(())
+//! });
+//! bot.command("remind", |ctx, (Interval(after), Rest(text))| async move {
+//!     Delay::new(after).await;
+//!     ctx.reply(text).await?;
+//!     Ok(())
+//! })
+//! .usage("<time> <text>")
+//! .cooldown(Duration::from_secs(60));
+//!
+//! // Respond to the commands until the main stream ends.
+//! bot.run(&ws_client).await?;
+//! # Ok(())
+//! # }
+//! ```
+
+use std::fmt::{self, Debug, Display};
+use std::future::Future;
+use std::sync::{Arc, Mutex};
+use std::time::Duration;
+
+use crate::streaming::recent::RecentNotes;
+use crate::{ClientExt, Error, StreamingClientExt};
+
+use futures::{
+    future::{self, BoxFuture, Either, FutureExt},
+    stream::{FuturesUnordered, StreamExt},
+};
+#[cfg(feature = "13-7-0")]
+use misskey_api::model::role::Role;
+use misskey_api::model::{note::Note, user::User};
+use misskey_api::streaming::channel::main::MainStreamEvent;
+use misskey_api::EntityRef;
+use misskey_core::Client;
+
+mod access;
+mod args;
+mod context;
+
+#[cfg(feature = "13-7-0")]
+use access::RoleMembers;
+use access::{AccessList, Cooldowns};
+pub use args::{ArgError, Args, FromArgs, Interval, ParseIntervalError, Rest};
+pub use context::CommandContext;
+
+/// How long the members of the roles are cached by default.
+#[cfg(feature = "13-7-0")]
+const DEFAULT_ROLE_CACHE_TTL: Duration = Duration::from_secs(5 * 60);
+
+/// Possible errors from running [`Bot`].
+pub enum BotError<S, C> {
+    /// Errors from the main stream, where `S` is the error type of the streaming client.
+    Streaming(Error<S>),
+    /// Errors from the client the bot works with, where `C` is its error type.
+    ///
+    /// This includes the errors returned by the command handlers, unless they are handled by
+    /// [`Bot::on_error`].
+    Client(Error<C>),
+}
+
+impl<S: std::error::Error, C: std::error::Error> std::error::Error for BotError<S, C> {
+    fn source(&self) -> Option<&(dyn std::error::Error + 'static)> {
+        match self {
+            BotError::Streaming(err) => err.source(),
+            BotError::Client(err) => err.source(),
+        }
+    }
+}
+
+impl<S: std::error::Error, C: std::error::Error> Display for BotError<S, C> {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            BotError::Streaming(err) => Display::fmt(err, f),
+            BotError::Client(err) => write!(f, "failed to handle a command: {}", err),
+        }
+    }
+}
+
+impl<S: std::error::Error, C: std::error::Error> Debug for BotError<S, C> {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            BotError::Streaming(err) => f.debug_tuple("Streaming").field(&err).finish(),
+            BotError::Client(err) => f.debug_tuple("Client").field(&err).finish(),
+        }
+    }
+}
+
+type CommandFuture<C> = BoxFuture<'static, Result<(), Error<<C as Client>::Error>>>;
+type Handler<C> =
+    Box<dyn Fn(CommandContext<C>, &mut Args) -> Result<CommandFuture<C>, ArgError> + Send + Sync>;
+type ErrorHandler<C> = Box<dyn Fn(Error<<C as Client>::Error>) + Send + Sync>;
+
+/// A command registered to [`Bot`], which is returned by [`Bot::command`] to configure it.
+pub struct Command<C: Client> {
+    name: String,
+    aliases: Vec<String>,
+    usage: Option<String>,
+    cooldown: Option<Duration>,
+    access: AccessList,
+    handler: Handler<C>,
+}
+
+impl<C: Client> Debug for Command<C> {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        f.debug_struct("Command")
+            .field("name", &self.name)
+            .field("aliases", &self.aliases)
+            .field("usage", &self.usage)
+            .field("cooldown", &self.cooldown)
+            .field("access", &self.access)
+            .finish()
+    }
+}
+
+impl<C: Client> Command<C> {
+    /// Adds another name of the command.
+    ///
+    /// Like the command names, aliases are case-insensitive.
+    pub fn alias(&mut self, alias: impl Into<String>) -> &mut Self {
+        self.aliases.push(alias.into().to_lowercase());
+        self
+    }
+
+    /// Sets the description of the arguments, which is shown when the arguments are invalid.
+    pub fn usage(&mut self, usage: impl Into<String>) -> &mut Self {
+        self.usage.replace(usage.into());
+        self
+    }
+
+    /// Sets how long each user has to wait to use the command again, overriding
+    /// [`Bot::cooldown`].
+    ///
+    /// Commands used during the cooldown are ignored.
+    pub fn cooldown(&mut self, cooldown: Duration) -> &mut Self {
+        self.cooldown.replace(cooldown);
+        self
+    }
+
+    /// Allows the user to use the command.
+    ///
+    /// Once any user or role is allowed, the command is restricted to them.
+    pub fn allow_user(&mut self, user: impl EntityRef<User>) -> &mut Self {
+        self.access.allowed_users.insert(user.entity_ref());
+        self
+    }
+
+    /// Denies the user to use the command.
+    pub fn deny_user(&mut self, user: impl EntityRef<User>) -> &mut Self {
+        self.access.denied_users.insert(user.entity_ref());
+        self
+    }
+
+    /// Allows the users assigned the role to use the command.
+    ///
+    /// Once any user or role is allowed, the command is restricted to them.
+    /// The role has to be public, as its members are looked up with
+    /// [`public_role_users`][`ClientExt::public_role_users`].
+    #[cfg(feature = "13-7-0")]
+    #[cfg_attr(docsrs, doc(cfg(feature = "13-7-0")))]
+    pub fn allow_role(&mut self, role: impl EntityRef<Role>) -> &mut Self {
+        self.access.allowed_roles.insert(role.entity_ref());
+        self
+    }
+
+    /// Denies the users assigned the role to use the command.
+    ///
+    /// The role has to be public, as its members are looked up with
+    /// [`public_role_users`][`ClientExt::public_role_users`].
+    #[cfg(feature = "13-7-0")]
+    #[cfg_attr(docsrs, doc(cfg(feature = "13-7-0")))]
+    pub fn deny_role(&mut self, role: impl EntityRef<Role>) -> &mut Self {
+        self.access.denied_roles.insert(role.entity_ref());
+        self
+    }
+
+    fn matches(&self, name: &str) -> bool {
+        self.name == name || self.aliases.iter().any(|alias| alias == name)
+    }
+}
+
+/// A bot that responds to the commands in the mentions and replies to the account.
+///
+/// See the [module-level documentation][`crate::bot`] for an example.
+///
+/// A note is treated as a command if its text starts with a registered command name, after
+/// the mentions at the beginning. Notes from the bot account itself and, by default, from
+/// other bots (see [`ignore_bots`][`Bot::ignore_bots`]) are ignored.
+/// Each command is handled concurrently, so that a slow command does not block the others.
+pub struct Bot<C: Client> {
+    client: Arc<C>,
+    commands: Vec<Command<C>>,
+    cooldown: Duration,
+    access: AccessList,
+    ignore_bots: bool,
+    on_error: Option<ErrorHandler<C>>,
+    cooldowns: Cooldowns,
+    #[cfg(feature = "13-7-0")]
+    role_members: RoleMembers,
+    recent: Mutex<RecentNotes>,
+}
+
+impl<C: Client> Debug for Bot<C> {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        f.debug_struct("Bot")
+            .field("commands", &self.commands)
+            .field("cooldown", &self.cooldown)
+            .field("access", &self.access)
+            .field("ignore_bots", &self.ignore_bots)
+            .finish()
+    }
+}
+
+impl<C> Bot<C>
+where
+    C: ClientExt + Send + 'static,
+    C::Error: Send,
+{
+    /// Creates a bot that works with `client`, without any commands.
+    pub fn new(client: C) -> Self {
+        Bot {
+            client: Arc::new(client),
+            commands: Vec::new(),
+            cooldown: Duration::from_secs(0),
+            access: AccessList::default(),
+            ignore_bots: true,
+            on_error: None,
+            cooldowns: Cooldowns::default(),
+            #[cfg(feature = "13-7-0")]
+            role_members: RoleMembers::new(DEFAULT_ROLE_CACHE_TTL),
+            recent: Mutex::new(RecentNotes::new()),
+        }
+    }
+
+    /// Returns the client the bot works with.
+    pub fn client(&self) -> &C {
+        &self.client
+    }
+
+    /// Registers a command with `name`, replacing the existing one with the same name.
+    ///
+    /// `handler` receives the arguments parsed as `A`. If the arguments are invalid, the bot
+    /// replies with the error (and the [`usage`][`Command::usage`] of the command) instead of
+    /// calling the handler. Command names are case-insensitive.
+    pub fn command<A, F, Fut>(&mut self, name: impl Into<String>, handler: F) -> &mut Command<C>
+    where
+        A: FromArgs,
+        F: Fn(CommandContext<C>, A) -> Fut + Send + Sync + 'static,
+        Fut: Future<Output = Result<(), Error<C::Error>>> + Send + 'static,
+    {
+        let name = name.into().to_lowercase();
+        self.commands.retain(|command| command.name != name);
+        self.commands.push(Command {
+            name,
+            aliases: Vec::new(),
+            usage: None,
+            cooldown: None,
+            access: AccessList::default(),
+            handler: Box::new(move |ctx, args| {
+                let args = args.parse()?;
+                Ok(handler(ctx, args).boxed())
+            }),
+        });
+        self.commands.last_mut().unwrap()
+    }
+
+    /// Sets how long each user has to wait to use the same command again.
+    ///
+    /// Commands used during the cooldown are ignored. There is no cooldown by default.
+    pub fn cooldown(&mut self, cooldown: Duration) -> &mut Self {
+        self.cooldown = cooldown;
+        self
+    }
+
+    /// Sets whether to ignore the commands from bot accounts, which defaults to `true`.
+    pub fn ignore_bots(&mut self, ignore_bots: bool) -> &mut Self {
+        self.ignore_bots = ignore_bots;
+        self
+    }
+
+    /// Allows the user to use the commands.
+    ///
+    /// Once any user or role is allowed, all commands are restricted to them.
+    pub fn allow_user(&mut self, user: impl EntityRef<User>) -> &mut Self {
+        self.access.allowed_users.insert(user.entity_ref());
+        self
+    }
+
+    /// Denies the user to use the commands.
+    pub fn deny_user(&mut self, user: impl EntityRef<User>) -> &mut Self {
+        self.access.denied_users.insert(user.entity_ref());
+        self
+    }
+
+    /// Allows the users assigned the role to use the commands.
+    ///
+    /// Once any user or role is allowed, all commands are restricted to them.
+    /// The role has to be public, as its members are looked up with
+    /// [`public_role_users`][`ClientExt::public_role_users`].
+    #[cfg(feature = "13-7-0")]
+    #[cfg_attr(docsrs, doc(cfg(feature = "13-7-0")))]
+    pub fn allow_role(&mut self, role: impl EntityRef<Role>) -> &mut Self {
+        self.access.allowed_roles.insert(role.entity_ref());
+        self
+    }
+
+    /// Denies the users assigned the role to use the commands.
+    ///
+    /// The role has to be public, as its members are looked up with
+    /// [`public_role_users`][`ClientExt::public_role_users`].
+    #[cfg(feature = "13-7-0")]
+    #[cfg_attr(docsrs, doc(cfg(feature = "13-7-0")))]
+    pub fn deny_role(&mut self, role: impl EntityRef<Role>) -> &mut Self {
+        self.access.denied_roles.insert(role.entity_ref());
+        self
+    }
+
+    /// Sets how long the members of the roles are cached, which defaults to 5 minutes.
+    #[cfg(feature = "13-7-0")]
+    #[cfg_attr(docsrs, doc(cfg(feature = "13-7-0")))]
+    pub fn role_cache_ttl(&mut self, ttl: Duration) -> &mut Self {
+        self.role_members.set_ttl(ttl);
+        self
+    }
+
+    /// Sets the function that handles the errors from the command handlers.
+    ///
+    /// Without this, the first error stops [`run`][`Bot::run`].
+    pub fn on_error<F>(&mut self, on_error: F) -> &mut Self
+    where
+        F: Fn(Error<C::Error>) + Send + Sync + 'static,
+    {
+        self.on_error.replace(Box::new(on_error));
+        self
+    }
+
+    /// Responds to the commands delivered on the main stream of `streaming`, until the stream ends.
+    pub async fn run<S>(&self, streaming: &S) -> Result<(), BotError<S::Error, C::Error>>
+    where
+        S: StreamingClientExt,
+    {
+        let me = Arc::new(self.client.me().await.map_err(BotError::Client)?);
+        let mut events = streaming.main_stream().await.map_err(BotError::Streaming)?;
+        let mut running = FuturesUnordered::new();
+
+        loop {
+            let event = if running.is_empty() {
+                events.next().await
+            } else {
+                match future::select(events.next(), running.next()).await {
+                    Either::Left((event, _)) => event,
+                    Either::Right((result, _)) => {
+                        if let Some(Err(e)) = result {
+                            self.handle_error(e).map_err(BotError::Client)?;
+                        }
+                        continue;
+                    }
+                }
+            };
+
+            let note = match event {
+                Some(Ok(MainStreamEvent::Mention(note))) => note,
+                Some(Ok(MainStreamEvent::Reply(note))) => note,
+                Some(Ok(_)) => continue,
+                Some(Err(e)) => return Err(BotError::Streaming(e)),
+                None => break,
+            };
+            if let Some(future) = self.dispatch(&me, note) {
+                running.push(future);
+            }
+        }
+
+        while let Some(result) = running.next().await {
+            if let Err(e) = result {
+                self.handle_error(e).map_err(BotError::Client)?;
+            }
+        }
+        Ok(())
+    }
+
+    fn handle_error(&self, err: Error<C::Error>) -> Result<(), Error<C::Error>> {
+        match &self.on_error {
+            Some(on_error) => {
+                on_error(err);
+                Ok(())
+            }
+            None => Err(err),
+        }
+    }
+
+    fn dispatch(
+        &self,
+        me: &Arc<User>,
+        note: Note,
+    ) -> Option<BoxFuture<'_, Result<(), Error<C::Error>>>> {
+        if note.user_id == me.id || (self.ignore_bots && note.user.is_bot) {
+            return None;
+        }
+        // a reply to the bot that also mentions it is delivered twice
+        if !self
+            .recent
+            .lock()
+            .unwrap_or_else(|e| e.into_inner())
+            .insert(note.id)
+        {
+            return None;
+        }
+
+        let (name, args) = split_command(note.text.as_deref()?)?;
+        let command = self
+            .commands
+            .iter()
+            .find(|command| command.matches(&name))?;
+        let me = Arc::clone(me);
+
+        let future = async move {
+            if !self.is_allowed(command, &note).await? {
+                return Ok(());
+            }
+            let cooldown = command.cooldown.unwrap_or(self.cooldown);
+            if !self
+                .cooldowns
+                .try_use(note.user_id, &command.name, cooldown)
+            {
+                return Ok(());
+            }
+
+            let ctx = CommandContext {
+                client: Arc::clone(&self.client),
+                me,
+                note,
+                command: command.name.clone(),
+            };
+            let err = match (command.handler)(ctx.clone(), &mut Args::new(args)) {
+                Ok(future) => return future.await,
+                Err(err) => err,
+            };
+
+            let text = match &command.usage {
+                Some(usage) => format!("{}\nusage: {} {}", err, command.name, usage),
+                None => err.to_string(),
+            };
+            ctx.reply(text).await?;
+            Ok(())
+        };
+        Some(future.boxed())
+    }
+
+    async fn is_allowed(&self, command: &Command<C>, note: &Note) -> Result<bool, Error<C::Error>> {
+        #[cfg(feature = "13-7-0")]
+        {
+            let roles = self.access.roles().chain(command.access.roles());
+            let user_roles = self
+                .role_members
+                .roles_of(&*self.client, note.user_id, roles)
+                .await?;
+            Ok(self.access.is_allowed(note.user_id, &user_roles)
+                && command.access.is_allowed(note.user_id, &user_roles))
+        }
+        #[cfg(not(feature = "13-7-0"))]
+        Ok(self.access.is_allowed(note.user_id) && command.access.is_allowed(note.user_id))
+    }
+}
+
+/// Splits the text of a note into the lowercased command name and the rest,
+/// skipping the mentions at the beginning.
+fn split_command(text: &str) -> Option<(String, String)> {
+    let mut rest = text.trim_start();
+    while rest.starts_with('@') {
+        let end = rest.find(char::is_whitespace)?;
+        rest = rest[end..].trim_start();
+    }
+
+    let end = rest.find(char::is_whitespace).unwrap_or(rest.len());
+    if end == 0 {
+        return None;
+    }
+    Some((rest[..end].to_lowercase(), rest[end..].to_owned()))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::split_command;
+
+    fn command(name: &str, rest: &str) -> Option<(String, String)> {
+        Some((name.to_owned(), rest.to_owned()))
+    }
+
+    #[test]
+    fn test_split_command() {
+        assert_eq!(split_command("ping"), command("ping", ""));
+        assert_eq!(split_command("  Ping  a b"), command("ping", "  a b"));
+        assert_eq!(split_command("remind\n10m x"), command("remind", "\n10m x"));
+    }
+
+    #[test]
+    fn test_split_command_mentions() {
+        assert_eq!(split_command("@bot ping"), command("ping", ""));
+        assert_eq!(
+            split_command("@bot@example.com  @other\nping x"),
+            command("ping", " x")
+        );
+        // mentions after the command name are arguments
+        assert_eq!(
+            split_command("@bot ping @other"),
+            command("ping", " @other")
+        );
+    }
+
+    #[test]
+    fn test_split_command_none() {
+        // bare mentions
+        assert_eq!(split_command("@bot"), None);
+        assert_eq!(split_command("@bot @other"), None);
+        assert_eq!(split_command("@bot  "), None);
+        assert_eq!(split_command(""), None);
+        assert_eq!(split_command("   "), None);
+    }
+}
@@ -0,0 +1,165 @@
+use std::collections::{HashMap, HashSet};
+#[cfg(feature = "13-7-0")]
+use std::sync::Arc;
+use std::sync::Mutex;
+use std::time::{Duration, Instant};
+
+#[cfg(feature = "13-7-0")]
+use crate::{ClientExt, Error};
+
+#[cfg(feature = "13-7-0")]
+use futures::stream::TryStreamExt;
+#[cfg(feature = "13-7-0")]
+use misskey_api::model::role::Role;
+use misskey_api::model::{id::Id, user::User};
+
+/// Lists of users allowed or denied to use commands.
+///
+/// Denials take precedence over allowances. If nothing is allowed explicitly,
+/// everyone who is not denied is allowed.
+#[derive(Debug, Clone, Default)]
+pub(crate) struct AccessList {
+    pub(crate) allowed_users: HashSet<Id<User>>,
+    pub(crate) denied_users: HashSet<Id<User>>,
+    #[cfg(feature = "13-7-0")]
+    pub(crate) allowed_roles: HashSet<Id<Role>>,
+    #[cfg(feature = "13-7-0")]
+    pub(crate) denied_roles: HashSet<Id<Role>>,
+}
+
+impl AccessList {
+    /// Tests if the user is allowed, where `user_roles` are the roles the user is assigned
+    /// among the ones listed in this list.
+    pub(crate) fn is_allowed(
+        &self,
+        user_id: Id<User>,
+        #[cfg(feature = "13-7-0")] user_roles: &HashSet<Id<Role>>,
+    ) -> bool {
+        if self.denied_users.contains(&user_id) {
+            return false;
+        }
+        #[cfg(feature = "13-7-0")]
+        if !self.denied_roles.is_disjoint(user_roles) {
+            return false;
+        }
+
+        #[cfg(feature = "13-7-0")]
+        let restricted = !self.allowed_users.is_empty() || !self.allowed_roles.is_empty();
+        #[cfg(not(feature = "13-7-0"))]
+        let restricted = !self.allowed_users.is_empty();
+        if !restricted {
+            return true;
+        }
+
+        if self.allowed_users.contains(&user_id) {
+            return true;
+        }
+        #[cfg(feature = "13-7-0")]
+        if !self.allowed_roles.is_disjoint(user_roles) {
+            return true;
+        }
+        false
+    }
+
+    /// Returns the roles that need to be looked up to check the access.
+    #[cfg(feature = "13-7-0")]
+    pub(crate) fn roles(&self) -> impl Iterator<Item = Id<Role>> + '_ {
+        self.denied_roles
+            .iter()
+            .chain(self.allowed_roles.iter())
+            .copied()
+    }
+}
+
+/// The time until which each user cannot use each command, used to enforce cooldowns.
+#[derive(Debug, Default)]
+pub(crate) struct Cooldowns {
+    until: Mutex<HashMap<(Id<User>, String), Instant>>,
+}
+
+impl Cooldowns {
+    /// Records the use of `command` by `user_id`, or returns `false` if it is still cooling down.
+    pub(crate) fn try_use(&self, user_id: Id<User>, command: &str, cooldown: Duration) -> bool {
+        if cooldown.is_zero() {
+            return true;
+        }
+
+        let now = Instant::now();
+        let mut until = self.until.lock().unwrap_or_else(|e| e.into_inner());
+        // forget the expired entries not to grow indefinitely
+        until.retain(|_, until| *until > now);
+
+        let key = (user_id, command.to_owned());
+        if until.contains_key(&key) {
+            return false;
+        }
+        until.insert(key, now + cooldown);
+        true
+    }
+}
+
+#[cfg(feature = "13-7-0")]
+type Members = Arc<HashSet<Id<User>>>;
+
+/// Cache of the members of the roles, looked up with [`ClientExt::public_role_users`].
+#[cfg(feature = "13-7-0")]
+#[derive(Debug)]
+pub(crate) struct RoleMembers {
+    ttl: Duration,
+    members: Mutex<HashMap<Id<Role>, (Instant, Members)>>,
+}
+
+#[cfg(feature = "13-7-0")]
+impl RoleMembers {
+    pub(crate) fn new(ttl: Duration) -> RoleMembers {
+        RoleMembers {
+            ttl,
+            members: Mutex::new(HashMap::new()),
+        }
+    }
+
+    pub(crate) fn set_ttl(&mut self, ttl: Duration) {
+        self.ttl = ttl;
+    }
+
+    /// Returns the roles among `roles` that are assigned to `user_id`.
+    pub(crate) async fn roles_of<C: ClientExt>(
+        &self,
+        client: &C,
+        user_id: Id<User>,
+        roles: impl IntoIterator<Item = Id<Role>>,
+    ) -> Result<HashSet<Id<Role>>, Error<C::Error>> {
+        let mut user_roles = HashSet::new();
+        for role in roles {
+            if self.members_of(client, role).await?.contains(&user_id) {
+                user_roles.insert(role);
+            }
+        }
+        Ok(user_roles)
+    }
+
+    async fn members_of<C: ClientExt>(
+        &self,
+        client: &C,
+        role: Id<Role>,
+    ) -> Result<Members, Error<C::Error>> {
+        {
+            let members = self.members.lock().unwrap_or_else(|e| e.into_inner());
+            if let Some((fetched_at, users)) = members.get(&role) {
+                if fetched_at.elapsed() < self.ttl {
+                    return Ok(Arc::clone(users));
+                }
+            }
+        }
+
+        let users: HashSet<Id<User>> = client
+            .public_role_users(role)
+            .map_ok(|user| user.id)
+            .try_collect()
+            .await?;
+        let users = Arc::new(users);
+        let mut members = self.members.lock().unwrap_or_else(|e| e.into_inner());
+        members.insert(role, (Instant::now(), Arc::clone(&users)));
+        Ok(users)
+    }
+}
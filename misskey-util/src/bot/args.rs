@@ -0,0 +1,400 @@
+use std::fmt::{self, Display};
+use std::str::FromStr;
+use std::time::Duration;
+
+/// Arguments of a command, which are the words following the command name.
+///
+/// Words are separated by whitespaces, and a word can contain whitespaces if it is
+/// enclosed in double quotes (e.g. `"hello world"`), where `\"` and `\\` are the escapes of
+/// `"` and `\`.
+#[derive(Debug, Clone)]
+pub struct Args {
+    input: String,
+    offset: usize,
+    position: usize,
+}
+
+impl Args {
+    /// Creates `Args` from the text that follows the command name.
+    pub fn new(input: impl Into<String>) -> Args {
+        Args {
+            input: input.into(),
+            offset: 0,
+            position: 0,
+        }
+    }
+
+    /// Returns the remaining text as is, without consuming it.
+    pub fn remaining(&self) -> &str {
+        self.input[self.offset..].trim()
+    }
+
+    /// Returns `true` if there are no arguments left.
+    pub fn is_empty(&self) -> bool {
+        self.remaining().is_empty()
+    }
+
+    /// Takes the next word, or returns `None` if there are no arguments left.
+    pub fn next_word(&mut self) -> Option<String> {
+        let rest = &self.input[self.offset..];
+        let start = rest.len() - rest.trim_start().len();
+        let rest = &rest[start..];
+        if rest.is_empty() {
+            self.offset = self.input.len();
+            return None;
+        }
+
+        let (word, len) = match rest.strip_prefix('"') {
+            Some(quoted) => read_quoted(quoted),
+            None => {
+                let end = rest.find(char::is_whitespace).unwrap_or(rest.len());
+                (rest[..end].to_owned(), end)
+            }
+        };
+
+        self.offset += start + len;
+        self.position += 1;
+        Some(word)
+    }
+
+    /// Takes all the remaining text as is.
+    pub fn take_remaining(&mut self) -> String {
+        let rest = self.remaining().to_owned();
+        self.offset = self.input.len();
+        if !rest.is_empty() {
+            self.position += 1;
+        }
+        rest
+    }
+
+    /// Parses the arguments into `T`, failing if some arguments are left unused.
+    pub fn parse<T: FromArgs>(&mut self) -> Result<T, ArgError> {
+        let value = T::from_args(self)?;
+        match self.next_word() {
+            Some(word) => Err(ArgError::Unexpected { value: word }),
+            None => Ok(value),
+        }
+    }
+
+    fn expect_word(&mut self) -> Result<String, ArgError> {
+        self.next_word().ok_or(ArgError::Missing {
+            position: self.position + 1,
+        })
+    }
+}
+
+/// Reads the quoted word from the text after the opening quote, returning the word and the
+/// length of the text it takes including the quotes.
+fn read_quoted(quoted: &str) -> (String, usize) {
+    let mut word = String::new();
+    let mut chars = quoted.char_indices().peekable();
+    while let Some((i, c)) = chars.next() {
+        match c {
+            '"' => return (word, i + 2),
+            '\\' => match chars.peek() {
+                Some(&(_, c)) if c == '"' || c == '\\' => {
+                    word.push(c);
+                    chars.next();
+                }
+                _ => word.push('\\'),
+            },
+            c => word.push(c),
+        }
+    }
+    // unterminated quote takes the rest of the input
+    (word, quoted.len() + 1)
+}
+
+/// Possible errors from parsing command arguments.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum ArgError {
+    /// The argument at `position` (counted from 1) is missing.
+    Missing {
+        /// The position of the missing argument, counted from 1.
+        position: usize,
+    },
+    /// The argument cannot be parsed as the expected type.
+    Invalid {
+        /// The argument as written.
+        value: String,
+        /// The description of the expected argument, such as `"an integer"`.
+        expected: &'static str,
+    },
+    /// An extra argument is given.
+    Unexpected {
+        /// The first extra argument.
+        value: String,
+    },
+}
+
+impl std::error::Error for ArgError {}
+
+impl Display for ArgError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            ArgError::Missing { position } => write!(f, "argument #{} is missing", position),
+            ArgError::Invalid { value, expected } => {
+                write!(f, "invalid argument `{}`: expected {}", value, expected)
+            }
+            ArgError::Unexpected { value } => write!(f, "unexpected argument `{}`", value),
+        }
+    }
+}
+
+/// A type that can be parsed from command arguments.
+///
+/// Tuples of `FromArgs` types take the arguments in order, so that a command handler can
+/// receive several typed arguments at once.
+pub trait FromArgs: Sized {
+    /// Takes the arguments needed from `args` and parses them.
+    fn from_args(args: &mut Args) -> Result<Self, ArgError>;
+}
+
+impl FromArgs for Args {
+    fn from_args(args: &mut Args) -> Result<Self, ArgError> {
+        let rest = Args::new(args.remaining());
+        args.offset = args.input.len();
+        Ok(rest)
+    }
+}
+
+impl FromArgs for String {
+    fn from_args(args: &mut Args) -> Result<Self, ArgError> {
+        args.expect_word()
+    }
+}
+
+impl<T: FromArgs> FromArgs for Option<T> {
+    fn from_args(args: &mut Args) -> Result<Self, ArgError> {
+        if args.is_empty() {
+            return Ok(None);
+        }
+        T::from_args(args).map(Some)
+    }
+}
+
+macro_rules! impl_from_args_for_from_str {
+    ($($ty:ty => $expected:literal),* $(,)?) => {
+        $(
+        impl FromArgs for $ty {
+            fn from_args(args: &mut Args) -> Result<Self, ArgError> {
+                let word = args.expect_word()?;
+                <$ty>::from_str(&word).map_err(|_| ArgError::Invalid {
+                    value: word,
+                    expected: $expected,
+                })
+            }
+        }
+        )*
+    };
+}
+
+impl_from_args_for_from_str! {
+    i8 => "an integer",
+    i16 => "an integer",
+    i32 => "an integer",
+    i64 => "an integer",
+    isize => "an integer",
+    u8 => "a non-negative integer",
+    u16 => "a non-negative integer",
+    u32 => "a non-negative integer",
+    u64 => "a non-negative integer",
+    usize => "a non-negative integer",
+    f32 => "a number",
+    f64 => "a number",
+    bool => "`true` or `false`",
+    char => "a character",
+}
+
+macro_rules! impl_from_args_for_tuple {
+    ($($name:ident),*) => {
+        impl<$($name: FromArgs),*> FromArgs for ($($name,)*) {
+            #[allow(unused_variables)]
+            fn from_args(args: &mut Args) -> Result<Self, ArgError> {
+                Ok(($($name::from_args(args)?,)*))
+            }
+        }
+    };
+}
+
+impl_from_args_for_tuple!();
+impl_from_args_for_tuple!(A);
+impl_from_args_for_tuple!(A, B);
+impl_from_args_for_tuple!(A, B, C);
+impl_from_args_for_tuple!(A, B, C, D);
+impl_from_args_for_tuple!(A, B, C, D, E);
+impl_from_args_for_tuple!(A, B, C, D, E, F);
+
+/// An argument that takes all the remaining text as is.
+///
+/// This fails if no text is left. Use `Option<Rest>` to accept the empty text.
+#[derive(Debug, Clone, PartialEq, Eq, Hash)]
+pub struct Rest(pub String);
+
+impl FromArgs for Rest {
+    fn from_args(args: &mut Args) -> Result<Self, ArgError> {
+        if args.is_empty() {
+            return Err(ArgError::Missing {
+                position: args.position + 1,
+            });
+        }
+        Ok(Rest(args.take_remaining()))
+    }
+}
+
+/// An argument that represents a period of time, such as `10m` or `1h30m`.
+///
+/// The period is written as a sequence of numbers with the units `s` (seconds), `m` (minutes),
+/// `h` (hours), `d` (days) or `w` (weeks).
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash, PartialOrd, Ord)]
+pub struct Interval(pub Duration);
+
+/// Error type for parsing [`Interval`].
+#[derive(Debug, Clone)]
+pub struct ParseIntervalError {
+    _priv: (),
+}
+
+impl std::error::Error for ParseIntervalError {}
+
+impl Display for ParseIntervalError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        f.write_str("invalid interval")
+    }
+}
+
+impl FromStr for Interval {
+    type Err = ParseIntervalError;
+
+    fn from_str(s: &str) -> Result<Interval, Self::Err> {
+        let err = || ParseIntervalError { _priv: () };
+        if s.is_empty() {
+            return Err(err());
+        }
+
+        let mut secs: u64 = 0;
+        let mut rest = s;
+        while !rest.is_empty() {
+            let digits = rest.find(|c: char| !c.is_ascii_digit()).ok_or_else(err)?;
+            let n: u64 = rest[..digits].parse().map_err(|_| err())?;
+            let mut chars = rest[digits..].chars();
+            let unit = match chars.next() {
+                Some('s') => 1,
+                Some('m') => 60,
+                Some('h') => 60 * 60,
+                Some('d') => 24 * 60 * 60,
+                Some('w') => 7 * 24 * 60 * 60,
+                _ => return Err(err()),
+            };
+            secs = n
+                .checked_mul(unit)
+                .and_then(|x| secs.checked_add(x))
+                .ok_or_else(err)?;
+            rest = chars.as_str();
+        }
+
+        Ok(Interval(Duration::from_secs(secs)))
+    }
+}
+
+impl_from_args_for_from_str! {
+    Interval => "a period of time such as `10m`",
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{ArgError, Args, Interval, Rest};
+
+    use std::time::Duration;
+
+    fn words(input: &str) -> Vec<String> {
+        let mut args = Args::new(input);
+        std::iter::from_fn(|| args.next_word()).collect()
+    }
+
+    #[test]
+    fn test_words() {
+        assert_eq!(words("  a b\tc\n d  "), ["a", "b", "c", "d"]);
+        assert!(words("").is_empty());
+        assert!(words(" \n ").is_empty());
+    }
+
+    #[test]
+    fn test_quoted() {
+        assert_eq!(words(r#""hello world" x"#), ["hello world", "x"]);
+        assert_eq!(words(r#""" x"#), ["", "x"]);
+        // quotes in the middle of a word are kept
+        assert_eq!(words(r#"a"b c"#), ["a\"b", "c"]);
+        // unterminated quote takes the rest
+        assert_eq!(words(r#"x "a b  "#), ["x", "a b  "]);
+    }
+
+    #[test]
+    fn test_escapes() {
+        assert_eq!(words(r#""a \"b\" \\c" d"#), ["a \"b\" \\c", "d"]);
+        // other backslashes are kept
+        assert_eq!(words(r#""a\b" c\"d"#), ["a\\b", "c\\\"d"]);
+        assert_eq!(words(r#""a\" b"#), ["a\" b"]);
+        assert_eq!(words(r#""a\\" b"#), ["a\\", "b"]);
+    }
+
+    #[test]
+    fn test_remaining() {
+        let mut args = Args::new(" a  b c ");
+        assert_eq!(args.next_word().as_deref(), Some("a"));
+        assert_eq!(args.remaining(), "b c");
+        assert_eq!(args.take_remaining(), "b c");
+        assert!(args.is_empty());
+        assert_eq!(args.next_word(), None);
+    }
+
+    #[test]
+    fn test_parse() {
+        let parsed: (String, u32, Option<bool>) = Args::new("\"a b\" 10").parse().unwrap();
+        assert_eq!(parsed, ("a b".to_owned(), 10, None));
+        let (n, Rest(text)): (i8, Rest) = Args::new("-1  hello  world ").parse().unwrap();
+        assert_eq!((n, text.as_str()), (-1, "hello  world"));
+
+        let err = Args::new("a").parse::<(String, String)>().unwrap_err();
+        assert_eq!(err, ArgError::Missing { position: 2 });
+        let err = Args::new("x").parse::<u8>().unwrap_err();
+        assert_eq!(
+            err,
+            ArgError::Invalid {
+                value: "x".to_owned(),
+                expected: "a non-negative integer",
+            }
+        );
+        let err = Args::new("1 2").parse::<u8>().unwrap_err();
+        assert_eq!(
+            err,
+            ArgError::Unexpected {
+                value: "2".to_owned()
+            }
+        );
+        let err = Args::new("1").parse::<(u8, Rest)>().unwrap_err();
+        assert_eq!(err, ArgError::Missing { position: 2 });
+    }
+
+    #[test]
+    fn test_interval() {
+        let secs = |s: &str| s.parse::<Interval>().map(|Interval(d)| d.as_secs()).ok();
+        assert_eq!(secs("10s"), Some(10));
+        assert_eq!(secs("10m"), Some(600));
+        assert_eq!(secs("1h30m"), Some(5400));
+        assert_eq!(secs("1w1d"), Some(8 * 24 * 60 * 60));
+        assert_eq!(secs("1m1m"), Some(120));
+        assert_eq!(secs("0s"), Some(0));
+        for invalid in ["", "10", "m", "1x", "1.5h", "-1m", "1h 30m", "1H", "1hm"] {
+            assert_eq!(secs(invalid), None, "{}", invalid);
+        }
+        // overflow
+        assert_eq!(secs("99999999999999999999s"), None);
+        assert_eq!(secs(&format!("{}w", u64::MAX / 60)), None);
+
+        let (Interval(after),): (Interval,) = Args::new("5m").parse().unwrap();
+        assert_eq!(after, Duration::from_secs(300));
+        let err = Args::new("soon").parse::<Interval>().unwrap_err();
+        assert!(matches!(err, ArgError::Invalid { value, .. } if value == "soon"));
+    }
+}
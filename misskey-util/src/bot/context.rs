@@ -0,0 +1,91 @@
+use std::sync::Arc;
+
+use crate::builder::NoteBuilder;
+use crate::{ClientExt, Error};
+
+use misskey_api::model::{
+    note::{Note, Visibility},
+    user::User,
+};
+
+/// The context in which a command is invoked, which is passed to the command handlers.
+#[derive(Debug)]
+pub struct CommandContext<C> {
+    pub(crate) client: Arc<C>,
+    pub(crate) me: Arc<User>,
+    pub(crate) note: Note,
+    pub(crate) command: String,
+}
+
+impl<C> Clone for CommandContext<C> {
+    fn clone(&self) -> Self {
+        CommandContext {
+            client: Arc::clone(&self.client),
+            me: Arc::clone(&self.me),
+            note: self.note.clone(),
+            command: self.command.clone(),
+        }
+    }
+}
+
+impl<C> CommandContext<C> {
+    /// Returns the client the bot works with.
+    pub fn client(&self) -> &C {
+        &self.client
+    }
+
+    /// Returns the note in which the command is invoked.
+    pub fn note(&self) -> &Note {
+        &self.note
+    }
+
+    /// Returns the user who invoked the command.
+    pub fn author(&self) -> &User {
+        &self.note.user
+    }
+
+    /// Returns the account of the bot.
+    pub fn me(&self) -> &User {
+        &self.me
+    }
+
+    /// Returns the name of the command as registered, even if it is invoked with an alias.
+    pub fn command(&self) -> &str {
+        &self.command
+    }
+}
+
+impl<C: ClientExt> CommandContext<C> {
+    /// Returns a [`NoteBuilder`] for a reply to the command.
+    ///
+    /// The reply is visible to the same audience as the command note, but no more widely;
+    /// for example, the reply to a direct note is sent directly to the author (and the other
+    /// recipients of the note), and the reply to a local-only note is also local-only.
+    pub fn build_reply(&self) -> NoteBuilder<&C> {
+        let mut builder = self.client.build_note();
+        builder.reply(&self.note).local_only(self.note.local_only);
+        match self.note.visibility {
+            Visibility::Specified => {
+                let recipients = std::iter::once(self.note.user_id).chain(
+                    self.note
+                        .visible_user_ids
+                        .iter()
+                        .copied()
+                        .filter(|&id| id != self.me.id && id != self.note.user_id),
+                );
+                builder.direct(recipients);
+            }
+            visibility => {
+                builder.visibility(visibility);
+            }
+        }
+        builder
+    }
+
+    /// Replies to the command with the given text.
+    ///
+    /// See [`build_reply`][`CommandContext::build_reply`] for the visibility of the reply.
+    pub async fn reply(&self, text: impl Into<String>) -> Result<Note, Error<C::Error>> {
+        self.build_reply().text(text).create().await
+    }
+}
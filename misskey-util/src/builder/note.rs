@@ -1,3 +1,4 @@
+use crate::split::{self, SplitOptions};
 use crate::Error;
 
 use chrono::{DateTime, Duration, Utc};
@@ -329,4 +330,54 @@ impl<C: Client> NoteBuilder<C> {
             .into_result()?;
         Ok(response.created_note)
     }
+
+    /// Creates the note as a thread, splitting the text if it is too long for the instance.
+    ///
+    /// The text is split with [`split_text`][`crate::split::split_text`], and the chunks are
+    /// posted as a chain of replies with the same visibility, content warning and other settings.
+    /// Attached files, the poll and the renote are only set on the first note.
+    /// Returns all the created notes in order.
+    ///
+    /// If posting one of the notes fails, the notes posted so far are left as they are.
+    pub async fn create_thread(
+        &self,
+        options: &SplitOptions,
+    ) -> Result<Vec<Note>, Error<C::Error>> {
+        let text = match &self.request.text {
+            Some(text) => text,
+            None => return Ok(vec![self.create().await?]),
+        };
+        let max_length = match options.max_length {
+            Some(max_length) => max_length,
+            None => {
+                let meta = self
+                    .client
+                    .request(endpoint::meta::Request::default())
+                    .await
+                    .map_err(Error::Client)?
+                    .into_result()?;
+                meta.max_note_text_length as usize
+            }
+        };
+
+        let mut request = self.request.clone();
+        let mut notes = Vec::new();
+        for chunk in split::split_text(text, max_length, options) {
+            request.text = Some(chunk);
+            let response = self
+                .client
+                .request(&request)
+                .await
+                .map_err(Error::Client)?
+                .into_result()?;
+            let note = response.created_note;
+
+            request.reply_id = Some(note.id);
+            request.renote_id = None;
+            request.file_ids = None;
+            request.poll = None;
+            notes.push(note);
+        }
+        Ok(notes)
+    }
 }
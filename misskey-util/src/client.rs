@@ -35,6 +35,7 @@ use crate::builder::{
     DefaultPoliciesUpdateBuilder, FlashBuilder, FlashUpdateBuilder, RoleBuilder, RoleUpdateBuilder,
 };
 use crate::pager::{BackwardPager, BoxPager, ForwardPager, OffsetPager, PagerStream};
+use crate::split::SplitOptions;
 use crate::Error;
 use crate::{TimelineCursor, TimelineRange};
 
@@ -1261,6 +1262,34 @@ pub trait ClientExt: Client + Sync {
         Box::pin(async move { self.build_note().text(text).create().await })
     }
 
+    /// Creates a thread of notes with the given text, splitting it if it is too long for the instance.
+    ///
+    /// See [`NoteBuilder::create_thread`][`crate::builder::NoteBuilder::create_thread`] for details.
+    ///
+    /// # Examples
+    ///
+    /// ```
+    /// # use misskey_util::ClientExt;
+    /// use misskey_util::split::SplitOptions;
+    /// # #[tokio::main]
+    /// # async fn main() -> anyhow::Result<()> {
+    /// # let client = misskey_test::test_client().await?;
+    /// let text = "Lorem ipsum dolor sit amet. ".repeat(200);
+    /// let notes = client.create_thread(text, SplitOptions::with_markers()).await?;
+    /// assert!(notes.len() > 1);
+    /// assert_eq!(notes[1].reply_id, Some(notes[0].id));
+    /// # Ok(())
+    /// # }
+    /// ```
+    fn create_thread(
+        &self,
+        text: impl Into<String>,
+        options: SplitOptions,
+    ) -> BoxFuture<Result<Vec<Note>, Error<Self::Error>>> {
+        let text = text.into();
+        Box::pin(async move { self.build_note().text(text).create_thread(&options).await })
+    }
+
     /// Creates a poll with the given text and choices.
     ///
     /// # Examples
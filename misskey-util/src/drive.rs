@@ -0,0 +1,95 @@
+//! Utilities for the drive.
+//!
+//! The files and folders on the drive can be handled by their paths such as
+//! `/photos/2024/cat.png` with [`resolve_file`], [`create_folders`], [`move_file`], [`tree`] and
+//! other functions. Since the drive allows files and folders of the same name in a folder, the
+//! paths that match more than one of them result in [`PathError::Ambiguous`].
+//!
+//! [`upload_directory`] mirrors a local directory tree into a folder tree on the drive, and
+//! [`download_directory`] does the opposite. Files are compared by their MD5 hashes, so only
+//! the new and changed files are transferred.
+//!
+//! [`DriveInventory`] lists all the files on the drive to group them by types, folders and
+//! ages, and to find duplicated or unused files, which can be cleaned up with
+//! [`delete_files`]. [`usage`] reports the usage against the capacity of the drive.
+//!
+//! # Examples
+//!
+//! ```no_run
+//! # #[tokio::main]
+//! # async fn main() -> anyhow::Result<()> {
+//! # let client = misskey_test::test_client().await?;
+//! use misskey_util::drive::{self, SyncOptions};
+//!
+//! // see what would be done first
+//! let options = SyncOptions {
+//!     dry_run: true,
+//!     delete_missing: true,
+//! };
+//! let report = drive::upload_directory(&client, "assets", None, &options).await?;
+//! for path in report.created.iter().chain(&report.updated) {
+//!     println!("will upload {}", path.display());
+//! }
+//! for path in report.deleted {
+//!     println!("will delete {}", path.display());
+//! }
+//! # Ok(())
+//! # }
+//! ```
+//!
+//! ```no_run
+//! # #[tokio::main]
+//! # async fn main() -> anyhow::Result<()> {
+//! # let client = misskey_test::test_client().await?;
+//! use misskey_util::drive;
+//!
+//! drive::create_folders(&client, "/photos/2024").await?;
+//! drive::move_file(&client, "/cat.png", "/photos/2024/cat.png").await?;
+//! let file = drive::resolve_file(&client, "/photos/2024/cat.png").await?;
+//!
+//! for (path, file) in drive::tree(&client, "/photos").await?.all_files() {
+//!     println!("{}: {} bytes", path, file.size);
+//! }
+//! # Ok(())
+//! # }
+//! ```
+//!
+//! ```no_run
+//! # #[tokio::main]
+//! # async fn main() -> anyhow::Result<()> {
+//! # let client = misskey_test::test_client().await?;
+//! use misskey_util::drive::{self, DriveInventory};
+//!
+//! let usage = drive::usage(&client, 30).await?;
+//! println!("{:.1}% used", usage.ratio() * 100.0);
+//!
+//! let inventory = DriveInventory::fetch(&client).await?;
+//! let unused = inventory.unused(&client).await?;
+//! let deleted = drive::delete_files(
+//!     &client,
+//!     unused.iter().map(|file| &file.file),
+//!     |files| {
+//!         println!("deleting {} files", files.len());
+//!         true
+//!     },
+//!     |done, total, file| println!("[{}/{}] deleted {}", done, total, file.name),
+//! )
+//! .await?;
+//! # Ok(())
+//! # }
+//! ```
+
+mod housekeeping;
+mod path;
+mod sync;
+
+pub use housekeeping::{
+    delete_files, usage, DriveInventory, DriveUsage, FileAge, FileGroup, InventoryFile,
+};
+
+pub use path::{
+    create_folders, move_file, move_folder, remove_file, remove_folder_all, resolve_file,
+    resolve_folder, tree, DriveTree, PathError,
+};
+
+pub use sync::{download_directory, upload_directory, SyncOptions, SyncReport};
@@ -0,0 +1,471 @@
+use std::cmp::Reverse;
+use std::collections::{HashMap, HashSet};
+use std::hash::Hash;
+
+use crate::{ClientExt, Error};
+
+use chrono::{DateTime, Duration, Utc};
+use futures::stream::TryStreamExt;
+use mime::Mime;
+use misskey_api::endpoint;
+use misskey_api::model::{chart::ChartSpan, drive::DriveFile, id::Id};
+
+use super::path::fetch_tree;
+
+/// The drive usage of the user logged in with the client, returned from [`usage`].
+#[derive(Debug, Clone)]
+pub struct DriveUsage {
+    /// The total size of the files in bytes.
+    pub usage: u64,
+    /// The capacity of the drive in bytes reported by `drive`.
+    pub capacity: u64,
+    /// The capacity of the drive in bytes given by the policies of the user, or by the
+    /// instance setting before 13.0.0.
+    pub policy_capacity: Option<u64>,
+    /// The daily changes of the usage in bytes from the `charts/user/drive` chart, newest
+    /// first.
+    pub daily_changes: Vec<i64>,
+}
+
+impl DriveUsage {
+    /// Returns the remaining capacity in bytes.
+    pub fn remaining(&self) -> u64 {
+        self.capacity.saturating_sub(self.usage)
+    }
+
+    /// Returns the ratio of the usage to the capacity.
+    pub fn ratio(&self) -> f64 {
+        if self.capacity == 0 {
+            return 1.0;
+        }
+        self.usage as f64 / self.capacity as f64
+    }
+}
+
+/// Fetches the drive usage of the user logged in with `client`, with the daily changes for the
+/// last `days` days.
+pub async fn usage<C: ClientExt>(client: &C, days: u64) -> Result<DriveUsage, Error<C::Error>> {
+    let drive = client
+        .request(endpoint::drive::Request::default())
+        .await
+        .map_err(Error::Client)?
+        .into_result()?;
+    let me = client.me().await?;
+
+    #[cfg(feature = "13-0-0")]
+    let policy_capacity = me
+        .policies
+        .as_ref()
+        .and_then(|policies| policies.drive_capacity_mb);
+    #[cfg(not(feature = "13-0-0"))]
+    let policy_capacity = Some(client.meta().await?.drive_capacity_per_local_user_mb);
+
+    let chart = client
+        .request(
+            endpoint::charts::user::drive::Request::builder()
+                .span(ChartSpan::Day)
+                .user_id(me.id)
+                .limit(days)
+                .build(),
+        )
+        .await
+        .map_err(Error::Client)?
+        .into_result()?;
+    let daily_changes = chart
+        .inc_size
+        .iter()
+        .zip(&chart.dec_size)
+        .map(|(inc, dec)| *inc as i64 - *dec as i64)
+        .collect();
+
+    Ok(DriveUsage {
+        usage: drive.usage,
+        capacity: drive.capacity,
+        policy_capacity: policy_capacity.map(|mb| mb * 1024 * 1024),
+        daily_changes,
+    })
+}
+
+/// Age of a file, used to group the files with [`DriveInventory::by_age`].
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord, Hash)]
+pub enum FileAge {
+    /// Created within a day.
+    Day,
+    /// Created within a week.
+    Week,
+    /// Created within 30 days.
+    Month,
+    /// Created within 365 days.
+    Year,
+    /// Created more than 365 days ago.
+    Older,
+}
+
+impl FileAge {
+    /// Returns the age of the file created at `created_at`, as of `now`.
+    pub fn of(created_at: DateTime<Utc>, now: DateTime<Utc>) -> FileAge {
+        let age = now - created_at;
+        if age < Duration::days(1) {
+            FileAge::Day
+        } else if age < Duration::weeks(1) {
+            FileAge::Week
+        } else if age < Duration::days(30) {
+            FileAge::Month
+        } else if age < Duration::days(365) {
+            FileAge::Year
+        } else {
+            FileAge::Older
+        }
+    }
+}
+
+/// Files grouped by some key, returned from the grouping methods of [`DriveInventory`].
+#[derive(Debug, Clone)]
+pub struct FileGroup<K> {
+    /// The key of the group.
+    pub key: K,
+    /// The number of the files.
+    pub count: usize,
+    /// The total size of the files in bytes.
+    pub size: u64,
+}
+
+/// A file in [`DriveInventory`].
+#[derive(Debug, Clone)]
+pub struct InventoryFile {
+    /// The path of the file, such as `/photos/cat.png`.
+    pub path: String,
+    /// The file.
+    pub file: DriveFile,
+}
+
+impl InventoryFile {
+    /// Returns the path of the folder the file is in, such as `/photos`.
+    pub fn folder_path(&self) -> &str {
+        match self.path.rfind('/') {
+            Some(0) | None => "/",
+            Some(i) => &self.path[..i],
+        }
+    }
+}
+
+/// All the files on the drive of the user logged in with the client.
+#[derive(Debug, Clone)]
+pub struct DriveInventory {
+    /// The files.
+    pub files: Vec<InventoryFile>,
+    /// The time the files are fetched.
+    pub fetched_at: DateTime<Utc>,
+}
+
+impl DriveInventory {
+    /// Lists all the files on the drive, walking the folders from the root.
+    pub async fn fetch<C: ClientExt>(client: &C) -> Result<DriveInventory, Error<C::Error>> {
+        let fetched_at = Utc::now();
+        let tree = fetch_tree(client, None).await?;
+        let files = tree
+            .all_files()
+            .into_iter()
+            .map(|(path, file)| InventoryFile {
+                path: format!("/{}", path),
+                file: file.clone(),
+            })
+            .collect();
+        Ok(DriveInventory { files, fetched_at })
+    }
+
+    /// Returns the total size of the files in bytes.
+    pub fn total_size(&self) -> u64 {
+        self.files.iter().map(|file| file.file.size).sum()
+    }
+
+    /// Groups the files by their MIME types, in descending order of the total size.
+    pub fn by_type(&self) -> Vec<FileGroup<Mime>> {
+        self.group_by(|file| file.file.type_.clone())
+    }
+
+    /// Groups the files by their folders, in descending order of the total size.
+    pub fn by_folder(&self) -> Vec<FileGroup<String>> {
+        self.group_by(|file| file.folder_path().to_owned())
+    }
+
+    /// Groups the files by their ages as of [`fetched_at`][`DriveInventory::fetched_at`],
+    /// from the newest.
+    pub fn by_age(&self) -> Vec<FileGroup<FileAge>> {
+        let mut groups = self.group_by(|file| FileAge::of(file.file.created_at, self.fetched_at));
+        groups.sort_by_key(|group| group.key);
+        groups
+    }
+
+    fn group_by<K, F>(&self, key: F) -> Vec<FileGroup<K>>
+    where
+        K: Eq + Hash,
+        F: Fn(&InventoryFile) -> K,
+    {
+        let mut groups: HashMap<K, (usize, u64)> = HashMap::new();
+        for file in &self.files {
+            let group = groups.entry(key(file)).or_default();
+            group.0 += 1;
+            group.1 += file.file.size;
+        }
+        let mut groups: Vec<_> = groups
+            .into_iter()
+            .map(|(key, (count, size))| FileGroup { key, count, size })
+            .collect();
+        groups.sort_by_key(|group| Reverse(group.size));
+        groups
+    }
+
+    /// Finds the files with the same contents by their MD5 hashes.
+    ///
+    /// Each group contains the files of the same contents from the oldest, and the groups are
+    /// in descending order of the size that would be freed by leaving only one of them.
+    pub fn duplicates(&self) -> Vec<Vec<&InventoryFile>> {
+        let mut groups: HashMap<&str, Vec<&InventoryFile>> = HashMap::new();
+        for file in &self.files {
+            groups.entry(&file.file.md5).or_default().push(file);
+        }
+        let mut duplicates: Vec<_> = groups
+            .into_values()
+            .filter(|files| files.len() > 1)
+            .collect();
+        for files in &mut duplicates {
+            files.sort_by_key(|file| file.file.created_at);
+        }
+        duplicates.sort_by_key(|files| Reverse(files[0].file.size * (files.len() as u64 - 1)));
+        duplicates
+    }
+
+    /// Finds the files that are not used by the user logged in with `client`.
+    ///
+    /// The files are considered used if they are the avatar or the banner of the user, the
+    /// eyecatching images or attached files of the pages, the images of the gallery posts, or
+    /// attached to any note. The notes are checked with
+    /// [`attached_notes`][`ClientExt::attached_notes`] for each file, so this sends a request
+    /// per file not used otherwise.
+    pub async fn unused<C: ClientExt>(
+        &self,
+        client: &C,
+    ) -> Result<Vec<&InventoryFile>, Error<C::Error>> {
+        let me = client.me().await?;
+        let mut used: HashSet<Id<DriveFile>> = HashSet::new();
+        used.extend(me.avatar_id);
+        used.extend(me.banner_id);
+        let mut pages = client.pages();
+        while let Some(page) = pages.try_next().await? {
+            used.extend(page.eye_catching_image_id);
+            used.extend(page.attached_files.iter().map(|file| file.id));
+        }
+        #[cfg(feature = "12-79-0")]
+        {
+            let mut posts = client.gallery_posts();
+            while let Some(post) = posts.try_next().await? {
+                used.extend(post.file_ids);
+            }
+        }
+
+        let mut unused = Vec::new();
+        for file in &self.files {
+            if used.contains(&file.file.id) {
+                continue;
+            }
+            if client.attached_notes(&file.file).await?.is_empty() {
+                unused.push(file);
+            }
+        }
+        Ok(unused)
+    }
+}
+
+/// Deletes the files after the confirmation.
+///
+/// `confirm` is called with all the files to be deleted, and nothing is deleted unless it
+/// returns `true`. `progress` is called after each file is deleted with the number of the
+/// deleted files, the number of all the files, and the deleted file.
+/// Returns the number of the deleted files.
+///
+/// Note that the deleted files are also removed from the notes they are attached to.
+pub async fn delete_files<'a, C, F, P>(
+    client: &C,
+    files: impl IntoIterator<Item = &'a DriveFile>,
+    confirm: F,
+    mut progress: P,
+) -> Result<usize, Error<C::Error>>
+where
+    C: ClientExt,
+    F: FnOnce(&[&DriveFile]) -> bool,
+    P: FnMut(usize, usize, &DriveFile),
+{
+    let files: Vec<&DriveFile> = files.into_iter().collect();
+    if files.is_empty() || !confirm(&files) {
+        return Ok(0);
+    }
+    for (i, file) in files.iter().enumerate() {
+        client.delete_file(*file).await?;
+        progress(i + 1, files.len(), file);
+    }
+    Ok(files.len())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{delete_files, DriveInventory, FileAge, InventoryFile};
+    use crate::test_util::{drive_file_json, id, MockClient};
+
+    use chrono::{DateTime, Duration, Utc};
+    use serde_json::{json, Value};
+
+    fn file(n: u64, path: &str, md5: &str, size: u64, created_at: &str) -> InventoryFile {
+        let name = path.rsplit('/').next().unwrap();
+        let mut file = drive_file_json(n, name, md5, None);
+        file["size"] = json!(size);
+        file["createdAt"] = json!(created_at);
+        InventoryFile {
+            path: path.to_owned(),
+            file: serde_json::from_value(file).unwrap(),
+        }
+    }
+
+    fn inventory() -> DriveInventory {
+        DriveInventory {
+            files: vec![
+                file(1, "/a.png", "aaa", 10, "2000-01-03T00:00:00Z"),
+                file(2, "/photos/b.png", "bbb", 100, "2000-01-01T00:00:00Z"),
+                file(3, "/photos/a.png", "aaa", 10, "2000-01-01T00:00:00Z"),
+                file(4, "/photos/2024/a.png", "aaa", 10, "2000-01-02T00:00:00Z"),
+                file(5, "/c.png", "ccc", 1000, "2000-01-01T00:00:00Z"),
+                file(6, "/old/b.png", "bbb", 100, "1999-06-01T00:00:00Z"),
+            ],
+            fetched_at: "2000-01-05T00:00:00Z".parse().unwrap(),
+        }
+    }
+
+    fn paths(files: &[&InventoryFile]) -> Vec<String> {
+        files.iter().map(|file| file.path.clone()).collect()
+    }
+
+    #[test]
+    fn test_duplicates() {
+        let inventory = inventory();
+        let duplicates: Vec<_> = inventory
+            .duplicates()
+            .iter()
+            .map(|files| paths(files))
+            .collect();
+        // 100 bytes would be freed for `bbb` and 20 bytes for `aaa`, and `ccc` is unique
+        assert_eq!(
+            duplicates,
+            [
+                vec!["/old/b.png", "/photos/b.png"],
+                vec!["/photos/a.png", "/photos/2024/a.png", "/a.png"],
+            ]
+        );
+    }
+
+    #[test]
+    fn test_no_duplicates() {
+        let mut inventory = inventory();
+        inventory.files.retain(|file| file.file.md5 != "aaa");
+        inventory.files.pop();
+        assert!(inventory.duplicates().is_empty());
+        inventory.files.clear();
+        assert!(inventory.duplicates().is_empty());
+    }
+
+    #[test]
+    fn test_folder_path() {
+        for (path, folder) in [
+            ("/a.png", "/"),
+            ("/photos/a.png", "/photos"),
+            ("/photos/2024/a.png", "/photos/2024"),
+        ] {
+            assert_eq!(
+                file(1, path, "", 0, "2000-01-01T00:00:00Z").folder_path(),
+                folder
+            );
+        }
+    }
+
+    #[test]
+    fn test_group_by() {
+        let inventory = inventory();
+        assert_eq!(inventory.total_size(), 1230);
+
+        let folders: Vec<_> = inventory
+            .by_folder()
+            .into_iter()
+            .map(|group| (group.key, group.count, group.size))
+            .collect();
+        assert_eq!(
+            folders,
+            [
+                ("/".to_owned(), 2, 1010),
+                ("/photos".to_owned(), 2, 110),
+                ("/old".to_owned(), 1, 100),
+                ("/photos/2024".to_owned(), 1, 10),
+            ]
+        );
+
+        let ages: Vec<_> = inventory
+            .by_age()
+            .into_iter()
+            .map(|group| (group.key, group.count, group.size))
+            .collect();
+        assert_eq!(ages, [(FileAge::Week, 5, 1130), (FileAge::Year, 1, 100)]);
+
+        let types = inventory.by_type();
+        assert_eq!(types.len(), 1);
+        assert_eq!(types[0].key, mime::IMAGE_PNG);
+        assert_eq!(types[0].count, 6);
+    }
+
+    #[test]
+    fn test_file_age() {
+        let now: DateTime<Utc> = "2000-01-01T00:00:00Z".parse().unwrap();
+        for (age, expected) in [
+            (Duration::zero(), FileAge::Day),
+            (Duration::hours(23), FileAge::Day),
+            (Duration::days(1), FileAge::Week),
+            (Duration::weeks(1), FileAge::Month),
+            (Duration::days(30), FileAge::Year),
+            (Duration::days(364), FileAge::Year),
+            (Duration::days(365), FileAge::Older),
+        ] {
+            assert_eq!(FileAge::of(now - age, now), expected, "{}", age);
+        }
+    }
+
+    #[tokio::test]
+    async fn test_delete_files() {
+        let client = MockClient::new(|endpoint, request| {
+            assert_eq!(endpoint, "drive/files/delete");
+            assert!(
+                request["fileId"] == json!(id::<()>(4)) || request["fileId"] == json!(id::<()>(1))
+            );
+            Value::Null
+        });
+        let inventory = inventory();
+        let duplicates = inventory.duplicates();
+        // leave the oldest one of each group
+        let files = duplicates[1][1..].iter().map(|file| &file.file);
+
+        let mut progress = Vec::new();
+        let deleted = delete_files(
+            &client,
+            files.clone(),
+            |files| files.len() == 2,
+            |i, len, file| progress.push((i, len, file.id)),
+        )
+        .await
+        .unwrap();
+        assert_eq!(deleted, 2);
+        assert_eq!(progress, [(1, 2, id(4)), (2, 2, id(1))]);
+
+        // nothing is deleted without the confirmation
+        let deleted = delete_files(&client, files, |_| false, |_, _, _| unreachable!())
+            .await
+            .unwrap();
+        assert_eq!(deleted, 0);
+        assert_eq!(client.endpoints().len(), 2);
+    }
+}
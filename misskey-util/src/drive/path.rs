@@ -0,0 +1,519 @@
+use std::fmt::{self, Debug, Display};
+
+use crate::{ClientExt, Error};
+
+use futures::future::BoxFuture;
+use misskey_api::model::{
+    drive::{DriveFile, DriveFolder},
+    id::Id,
+};
+
+use super::sync::list_folder;
+
+/// Possible errors from the path-based operations on the drive.
+pub enum PathError<E> {
+    /// The path contains `.` or `..`, or is the root where a file or a folder is required.
+    InvalidPath(String),
+    /// No file or folder exists at the path.
+    NotFound(String),
+    /// More than one file or folder exists at the path, which the drive allows.
+    Ambiguous {
+        /// The path that matches the files or folders.
+        path: String,
+        /// The number of the files or folders at the path.
+        count: usize,
+    },
+    /// A file or folder already exists at the destination.
+    AlreadyExists(String),
+    /// Errors from the requests, where `E` is the error type of the client.
+    Request(Error<E>),
+}
+
+impl<E> From<Error<E>> for PathError<E> {
+    fn from(err: Error<E>) -> Self {
+        PathError::Request(err)
+    }
+}
+
+impl<E: std::error::Error> std::error::Error for PathError<E> {
+    fn source(&self) -> Option<&(dyn std::error::Error + 'static)> {
+        match self {
+            PathError::Request(err) => err.source(),
+            _ => None,
+        }
+    }
+}
+
+impl<E: std::error::Error> Display for PathError<E> {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            PathError::InvalidPath(path) => write!(f, "invalid path on the drive: {}", path),
+            PathError::NotFound(path) => write!(f, "not found on the drive: {}", path),
+            PathError::Ambiguous { path, count } => {
+                write!(
+                    f,
+                    "{} entries with the same name on the drive: {}",
+                    count, path
+                )
+            }
+            PathError::AlreadyExists(path) => write!(f, "already exists on the drive: {}", path),
+            PathError::Request(err) => Display::fmt(err, f),
+        }
+    }
+}
+
+impl<E: std::error::Error> Debug for PathError<E> {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            PathError::InvalidPath(path) => f.debug_tuple("InvalidPath").field(path).finish(),
+            PathError::NotFound(path) => f.debug_tuple("NotFound").field(path).finish(),
+            PathError::Ambiguous { path, count } => f
+                .debug_struct("Ambiguous")
+                .field("path", path)
+                .field("count", count)
+                .finish(),
+            PathError::AlreadyExists(path) => f.debug_tuple("AlreadyExists").field(path).finish(),
+            PathError::Request(err) => f.debug_tuple("Request").field(&err).finish(),
+        }
+    }
+}
+
+/// A folder on the drive with all its contents, returned from [`tree`].
+#[derive(Debug, Clone)]
+pub struct DriveTree {
+    /// The folder, or `None` for the root.
+    pub folder: Option<DriveFolder>,
+    /// The files directly in the folder.
+    pub files: Vec<DriveFile>,
+    /// The subfolders.
+    pub folders: Vec<DriveTree>,
+}
+
+impl DriveTree {
+    /// Returns all the files in the tree with their paths relative to the folder.
+    pub fn all_files(&self) -> Vec<(String, &DriveFile)> {
+        let mut files = Vec::new();
+        let mut stack = vec![(String::new(), self)];
+        while let Some((prefix, tree)) = stack.pop() {
+            for file in &tree.files {
+                files.push((format!("{}{}", prefix, file.name), file));
+            }
+            for child in tree.folders.iter().rev() {
+                let name = child.folder.as_ref().map_or("", |folder| &folder.name);
+                stack.push((format!("{}{}/", prefix, name), child));
+            }
+        }
+        files
+    }
+}
+
+/// A parsed path, such as `/photos/2024/cat.png`.
+struct DrivePath<'a> {
+    components: Vec<&'a str>,
+}
+
+impl<'a> DrivePath<'a> {
+    /// Parses the path, where the leading, trailing and repeated slashes are ignored.
+    fn parse<E>(path: &'a str) -> Result<Self, PathError<E>> {
+        let components: Vec<&str> = path.split('/').filter(|c| !c.is_empty()).collect();
+        if components.iter().any(|c| *c == "." || *c == "..") {
+            return Err(PathError::InvalidPath(path.to_owned()));
+        }
+        Ok(DrivePath { components })
+    }
+
+    /// Splits the path into the parent and the last component.
+    fn split_last<E>(&self) -> Result<(DrivePath<'a>, &'a str), PathError<E>> {
+        match self.components.split_last() {
+            Some((name, parent)) => Ok((
+                DrivePath {
+                    components: parent.to_vec(),
+                },
+                name,
+            )),
+            None => Err(PathError::InvalidPath("/".to_owned())),
+        }
+    }
+
+    /// Formats the first `len` components as an absolute path.
+    fn prefix(&self, len: usize) -> String {
+        format!("/{}", self.components[..len].join("/"))
+    }
+
+    /// Formats the path as an absolute path.
+    fn absolute(&self) -> String {
+        self.prefix(self.components.len())
+    }
+}
+
+/// Finds the only folder with the name in the parent folder.
+async fn find_folder<C: ClientExt>(
+    client: &C,
+    parent: Option<Id<DriveFolder>>,
+    name: &str,
+    path: String,
+) -> Result<Option<DriveFolder>, PathError<C::Error>> {
+    let mut folders = match parent {
+        Some(parent) => client.find_folder_by_name_in_folder(name, parent).await?,
+        None => client.find_folder_by_name(name).await?,
+    };
+    match folders.len() {
+        0 => Ok(None),
+        1 => Ok(folders.pop()),
+        count => Err(PathError::Ambiguous { path, count }),
+    }
+}
+
+/// Finds the only file with the name in the folder.
+async fn find_file<C: ClientExt>(
+    client: &C,
+    folder: Option<Id<DriveFolder>>,
+    name: &str,
+    path: String,
+) -> Result<Option<DriveFile>, PathError<C::Error>> {
+    let mut files = match folder {
+        Some(folder) => client.find_file_by_name_in_folder(name, folder).await?,
+        None => client.find_file_by_name(name).await?,
+    };
+    match files.len() {
+        0 => Ok(None),
+        1 => Ok(files.pop()),
+        count => Err(PathError::Ambiguous { path, count }),
+    }
+}
+
+async fn resolve_parsed_folder<C: ClientExt>(
+    client: &C,
+    path: &DrivePath<'_>,
+) -> Result<Option<DriveFolder>, PathError<C::Error>> {
+    let mut folder: Option<DriveFolder> = None;
+    for (i, name) in path.components.iter().enumerate() {
+        let parent = folder.as_ref().map(|folder| folder.id);
+        match find_folder(client, parent, name, path.prefix(i + 1)).await? {
+            Some(found) => folder = Some(found),
+            None => return Err(PathError::NotFound(path.prefix(i + 1))),
+        }
+    }
+    Ok(folder)
+}
+
+/// Finds the folder at the path, such as `/photos/2024`, returning `None` for the root.
+///
+/// The path is resolved from the root, following the folders by their names.
+/// If there are folders of the same name on the way, [`PathError::Ambiguous`] is returned.
+pub async fn resolve_folder<C: ClientExt>(
+    client: &C,
+    path: &str,
+) -> Result<Option<DriveFolder>, PathError<C::Error>> {
+    resolve_parsed_folder(client, &DrivePath::parse(path)?).await
+}
+
+/// Finds the file at the path, such as `/photos/2024/cat.png`.
+pub async fn resolve_file<C: ClientExt>(
+    client: &C,
+    path: &str,
+) -> Result<DriveFile, PathError<C::Error>> {
+    let path = DrivePath::parse(path)?;
+    let (parent, name) = path.split_last()?;
+    let folder = resolve_parsed_folder(client, &parent).await?;
+    let folder_id = folder.map(|folder| folder.id);
+    find_file(client, folder_id, name, path.absolute())
+        .await?
+        .ok_or_else(|| PathError::NotFound(path.absolute()))
+}
+
+/// Creates the folder at the path along with the missing parent folders, like `mkdir -p`.
+///
+/// Returns the folder at the path, which may already exist, or `None` for the root.
+pub async fn create_folders<C: ClientExt>(
+    client: &C,
+    path: &str,
+) -> Result<Option<DriveFolder>, PathError<C::Error>> {
+    let path = DrivePath::parse(path)?;
+    let mut folder: Option<DriveFolder> = None;
+    for (i, name) in path.components.iter().enumerate() {
+        let parent = folder.as_ref().map(|folder| folder.id);
+        let found = find_folder(client, parent, name, path.prefix(i + 1)).await?;
+        let next = match (found, parent) {
+            (Some(found), _) => found,
+            (None, Some(parent)) => client.create_folder_with_parent(*name, parent).await?,
+            (None, None) => client.create_folder(*name).await?,
+        };
+        folder = Some(next);
+    }
+    Ok(folder)
+}
+
+/// Moves or renames the file at `from` to `to`.
+///
+/// The parent folder of `to` must exist, and no file may exist at `to`.
+pub async fn move_file<C: ClientExt>(
+    client: &C,
+    from: &str,
+    to: &str,
+) -> Result<DriveFile, PathError<C::Error>> {
+    let file = resolve_file(client, from).await?;
+    let to = DrivePath::parse(to)?;
+    let (parent, name) = to.split_last()?;
+    let folder_id = resolve_parsed_folder(client, &parent)
+        .await?
+        .map(|folder| folder.id);
+    match find_file(client, folder_id, name, to.absolute()).await? {
+        Some(existing) if existing.id == file.id => return Ok(file),
+        Some(_) => return Err(PathError::AlreadyExists(to.absolute())),
+        None => {}
+    }
+
+    let mut builder = client.update_file(&file);
+    if file.folder_id != folder_id {
+        match folder_id {
+            Some(folder_id) => builder.set_folder(folder_id),
+            None => builder.delete_folder(),
+        };
+    }
+    if file.name != name {
+        builder.name(name);
+    }
+    Ok(builder.update().await?)
+}
+
+/// Moves or renames the folder at `from` to `to`.
+///
+/// The parent folder of `to` must exist, and no folder may exist at `to`.
+pub async fn move_folder<C: ClientExt>(
+    client: &C,
+    from: &str,
+    to: &str,
+) -> Result<DriveFolder, PathError<C::Error>> {
+    let folder = match resolve_folder(client, from).await? {
+        Some(folder) => folder,
+        None => return Err(PathError::InvalidPath(from.to_owned())),
+    };
+    let to = DrivePath::parse(to)?;
+    let (parent, name) = to.split_last()?;
+    let parent_id = resolve_parsed_folder(client, &parent)
+        .await?
+        .map(|folder| folder.id);
+    match find_folder(client, parent_id, name, to.absolute()).await? {
+        Some(existing) if existing.id == folder.id => return Ok(folder),
+        Some(_) => return Err(PathError::AlreadyExists(to.absolute())),
+        None => {}
+    }
+
+    let mut builder = client.update_folder(&folder);
+    if folder.parent_id != parent_id {
+        match parent_id {
+            Some(parent_id) => builder.set_parent(parent_id),
+            None => builder.delete_parent(),
+        };
+    }
+    if folder.name != name {
+        builder.name(name);
+    }
+    Ok(builder.update().await?)
+}
+
+/// Lists the contents of the folder at the path recursively.
+pub async fn tree<C: ClientExt>(client: &C, path: &str) -> Result<DriveTree, PathError<C::Error>> {
+    let folder = resolve_folder(client, path).await?;
+    Ok(fetch_tree(client, folder).await?)
+}
+
+pub(super) fn fetch_tree<'a, C: ClientExt>(
+    client: &'a C,
+    folder: Option<DriveFolder>,
+) -> BoxFuture<'a, Result<DriveTree, Error<C::Error>>> {
+    Box::pin(async move {
+        let (files, children) =
+            list_folder(client, folder.as_ref().map(|folder| folder.id)).await?;
+        let mut folders = Vec::with_capacity(children.len());
+        for child in children {
+            folders.push(fetch_tree(client, Some(child)).await?);
+        }
+        Ok(DriveTree {
+            folder,
+            files,
+            folders,
+        })
+    })
+}
+
+/// Deletes the file at the path.
+pub async fn remove_file<C: ClientExt>(client: &C, path: &str) -> Result<(), PathError<C::Error>> {
+    let file = resolve_file(client, path).await?;
+    client.delete_file(&file).await?;
+    Ok(())
+}
+
+/// Deletes the folder at the path with all its contents, like `rm -r`.
+///
+/// The files and folders are deleted one by one, so some of them may be left deleted when an
+/// error occurs on the way. Note that the deleted files are also removed from the notes they
+/// are attached to.
+pub async fn remove_folder_all<C: ClientExt>(
+    client: &C,
+    path: &str,
+) -> Result<(), PathError<C::Error>> {
+    let folder = match resolve_folder(client, path).await? {
+        Some(folder) => folder,
+        None => return Err(PathError::InvalidPath(path.to_owned())),
+    };
+    let tree = fetch_tree(client, Some(folder)).await?;
+
+    // delete the folders after their contents, in reverse order of the traversal
+    let mut folders = Vec::new();
+    let mut stack = vec![&tree];
+    while let Some(tree) = stack.pop() {
+        folders.push(tree);
+        stack.extend(&tree.folders);
+    }
+    for tree in folders.into_iter().rev() {
+        for file in &tree.files {
+            client.delete_file(file).await?;
+        }
+        if let Some(folder) = &tree.folder {
+            client.delete_folder(folder).await?;
+        }
+    }
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{resolve_file, resolve_folder, DrivePath, PathError};
+    use crate::test_util::{drive_file_json, id, MockClient};
+
+    use std::io;
+
+    use serde_json::{json, Value};
+
+    fn parse(path: &str) -> Result<DrivePath<'_>, PathError<io::Error>> {
+        DrivePath::parse(path)
+    }
+
+    fn folder_json(n: u64, name: &str, parent: Option<u64>) -> Value {
+        json!({
+            "id": id::<()>(n),
+            "createdAt": "2000-01-01T00:00:00Z",
+            "name": name,
+            "parentId": parent.map(id::<()>),
+        })
+    }
+
+    /// `/photos/2024/cat.png`, with two folders named `dup` in the root.
+    fn drive() -> MockClient {
+        MockClient::new(|endpoint, request| {
+            let folders = [
+                folder_json(1, "photos", None),
+                folder_json(2, "dup", None),
+                folder_json(3, "dup", None),
+                folder_json(4, "2024", Some(1)),
+            ];
+            let files = [drive_file_json(10, "cat.png", "", Some(4))];
+            let (entries, parent) = match endpoint {
+                "drive/folders/find" => (&folders[..], "parentId"),
+                "drive/files/find" => (&files[..], "folderId"),
+                _ => unreachable!("{}", endpoint),
+            };
+            let found = entries.iter().filter(|entry| {
+                entry["name"] == request["name"] && entry[parent] == request[parent]
+            });
+            Value::Array(found.cloned().collect())
+        })
+    }
+
+    #[test]
+    fn test_parse() {
+        for (path, components) in [
+            ("/photos/2024/cat.png", &["photos", "2024", "cat.png"][..]),
+            ("photos//2024/", &["photos", "2024"]),
+            ("//a///b", &["a", "b"]),
+            ("...", &["..."]),
+            ("/", &[]),
+            ("", &[]),
+        ] {
+            assert_eq!(parse(path).unwrap().components, components, "{:?}", path);
+        }
+        for path in ["/photos/../cat.png", "./cat.png", "/photos/.", ".."] {
+            match parse(path) {
+                Err(PathError::InvalidPath(invalid)) => assert_eq!(invalid, path),
+                _ => panic!("{:?} is not rejected", path),
+            }
+        }
+    }
+
+    #[test]
+    fn test_format() {
+        let path = parse("photos//2024/cat.png/").unwrap();
+        assert_eq!(path.absolute(), "/photos/2024/cat.png");
+        assert_eq!(path.prefix(1), "/photos");
+        assert_eq!(path.prefix(0), "/");
+        assert_eq!(parse("").unwrap().absolute(), "/");
+    }
+
+    #[test]
+    fn test_split_last() {
+        let path = parse("/photos/2024/cat.png").unwrap();
+        let (parent, name) = path.split_last::<io::Error>().unwrap();
+        assert_eq!(parent.components, ["photos", "2024"]);
+        assert_eq!(name, "cat.png");
+
+        let path = parse("cat.png").unwrap();
+        let (parent, name) = path.split_last::<io::Error>().unwrap();
+        assert!(parent.components.is_empty());
+        assert_eq!(name, "cat.png");
+
+        match parse("/").unwrap().split_last::<io::Error>() {
+            Err(PathError::InvalidPath(path)) => assert_eq!(path, "/"),
+            _ => panic!("the root is split"),
+        }
+    }
+
+    #[tokio::test]
+    async fn test_resolve() {
+        let client = drive();
+        assert!(resolve_folder(&client, "/").await.unwrap().is_none());
+        let folder = resolve_folder(&client, "photos/2024/").await.unwrap();
+        assert_eq!(folder.unwrap().id, id(4));
+        let file = resolve_file(&client, "//photos/2024//cat.png")
+            .await
+            .unwrap();
+        assert_eq!(file.id, id(10));
+        assert_eq!(
+            client.endpoints(),
+            [
+                "drive/folders/find",
+                "drive/folders/find",
+                "drive/folders/find",
+                "drive/folders/find",
+                "drive/files/find",
+            ]
+        );
+    }
+
+    #[tokio::test]
+    async fn test_resolve_errors() {
+        let client = drive();
+        match resolve_file(&client, "/photos/2023/cat.png").await {
+            Err(PathError::NotFound(path)) => assert_eq!(path, "/photos/2023"),
+            result => panic!("{:?}", result),
+        }
+        match resolve_file(&client, "/photos/2024/dog.png").await {
+            Err(PathError::NotFound(path)) => assert_eq!(path, "/photos/2024/dog.png"),
+            result => panic!("{:?}", result),
+        }
+        match resolve_folder(&client, "/dup/sub").await {
+            Err(PathError::Ambiguous { path, count }) => {
+                assert_eq!(path, "/dup");
+                assert_eq!(count, 2);
+            }
+            result => panic!("{:?}", result),
+        }
+        match resolve_file(&client, "/").await {
+            Err(PathError::InvalidPath(path)) => assert_eq!(path, "/"),
+            result => panic!("{:?}", result),
+        }
+        // nothing is requested for invalid paths
+        assert_eq!(client.endpoints().len(), 6);
+    }
+}
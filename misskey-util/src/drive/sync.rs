@@ -0,0 +1,675 @@
+use std::collections::HashSet;
+use std::fs;
+use std::future::Future;
+use std::io::{self, Read};
+use std::path::{Component, Path, PathBuf};
+
+use crate::{ClientExt, Error, UploadFileClientExt};
+
+use futures::stream::TryStreamExt;
+use misskey_api::endpoint;
+use misskey_api::model::{
+    drive::{DriveFile, DriveFolder},
+    id::Id,
+};
+use misskey_core::Client;
+
+/// Options for [`upload_directory`] and [`download_directory`].
+#[derive(Debug, Clone, Default)]
+pub struct SyncOptions {
+    /// Only compares the files without changing anything.
+    pub dry_run: bool,
+    /// Deletes the files and folders that do not exist in the source.
+    pub delete_missing: bool,
+}
+
+/// The result of [`upload_directory`] and [`download_directory`].
+///
+/// The paths are relative to the roots of the synchronized trees. In the dry run, the report
+/// lists the changes that would be made.
+#[derive(Debug, Clone, Default)]
+pub struct SyncReport {
+    /// The folders created in the destination.
+    pub created_folders: Vec<PathBuf>,
+    /// The files newly created in the destination.
+    pub created: Vec<PathBuf>,
+    /// The files replaced because their contents are changed.
+    pub updated: Vec<PathBuf>,
+    /// The files that are already up to date.
+    pub unchanged: Vec<PathBuf>,
+    /// The files on the drive renamed to the local names because their contents are the same.
+    pub renamed: Vec<PathBuf>,
+    /// The files deleted from the destination.
+    pub deleted: Vec<PathBuf>,
+    /// The folders deleted from the destination.
+    pub deleted_folders: Vec<PathBuf>,
+}
+
+/// A folder on the drive to be synchronized.
+#[derive(Debug, Clone, Copy)]
+enum Target {
+    /// The folder exists on the drive, where `None` is the root.
+    Existing(Option<Id<DriveFolder>>),
+    /// The folder is not created because of the dry run.
+    Planned,
+}
+
+/// Mirrors the local directory `local` into `folder` on the drive, or into the root if
+/// `folder` is `None`.
+///
+/// The subdirectories are mirrored into the folders of the same names, which are created with
+/// [`create_folder_with_parent`][`ClientExt::create_folder_with_parent`] if missing.
+/// The files are compared with the ones of the same names by their MD5 hashes. For a new file,
+/// the file with the same contents in the folder under a name not used locally is looked up
+/// with `drive/files/check-existence` and `drive/files/find-by-hash`, and renamed with
+/// `drive/files/update` instead of uploading the contents again. The other files are uploaded,
+/// and when the file is changed, the previous one on the drive is deleted after the upload.
+///
+/// With [`delete_missing`][`SyncOptions::delete_missing`], the files and the folders in the
+/// folder tree that do not exist locally are deleted. Note that the deleted files are also
+/// removed from the notes they are attached to.
+///
+/// The symbolic links in `local` are skipped.
+///
+/// The local files are read with [`std::fs`], which blocks the thread while it waits for the
+/// file system. On an async runtime, call this where blocking is allowed, e.g. in
+/// `tokio::task::block_in_place`.
+pub async fn upload_directory<C>(
+    client: &C,
+    local: impl AsRef<Path>,
+    folder: Option<Id<DriveFolder>>,
+    options: &SyncOptions,
+) -> Result<SyncReport, Error<C::Error>>
+where
+    C: ClientExt + UploadFileClientExt,
+{
+    let local = local.as_ref();
+    let mut report = SyncReport::default();
+    let mut obsolete_folders = Vec::new();
+    let mut stack = vec![(PathBuf::new(), Target::Existing(folder))];
+
+    while let Some((path, target)) = stack.pop() {
+        let (remote_files, remote_folders) = match target {
+            Target::Existing(id) => list_folder(client, id).await?,
+            Target::Planned => (Vec::new(), Vec::new()),
+        };
+        let entries = read_dir_sorted(&local.join(&path))?;
+        let local_names: HashSet<&str> = entries.iter().map(|(name, _)| name.as_str()).collect();
+        let mut kept_files = HashSet::new();
+        let mut kept_folders = HashSet::new();
+
+        for (name, entry_path) in &entries {
+            let relative = path.join(name);
+            // the links are not followed, which might lead to the directory itself
+            let metadata = fs::symlink_metadata(entry_path)?;
+            if metadata.is_dir() {
+                let child = match remote_folders.iter().find(|folder| &folder.name == name) {
+                    Some(folder) => {
+                        kept_folders.insert(folder.id);
+                        Target::Existing(Some(folder.id))
+                    }
+                    None => {
+                        report.created_folders.push(relative.clone());
+                        match target {
+                            Target::Existing(parent) if !options.dry_run => {
+                                let folder = match parent {
+                                    Some(parent) => {
+                                        client.create_folder_with_parent(name, parent).await?
+                                    }
+                                    None => client.create_folder(name).await?,
+                                };
+                                Target::Existing(Some(folder.id))
+                            }
+                            _ => Target::Planned,
+                        }
+                    }
+                };
+                stack.push((relative, child));
+                continue;
+            }
+            if !metadata.is_file() {
+                continue;
+            }
+
+            let md5 = file_md5(entry_path)?;
+            let previous: Vec<&DriveFile> = remote_files
+                .iter()
+                .filter(|file| &file.name == name)
+                .collect();
+            if let Some(file) = previous.iter().find(|file| file.md5 == md5) {
+                kept_files.insert(file.id);
+                report.unchanged.push(relative);
+                continue;
+            }
+            if let (true, Target::Existing(id)) = (previous.is_empty(), target) {
+                // the file with the same contents may be there under the name that is not
+                // used locally, e.g. if the file is renamed only on the drive; each of them is
+                // renamed for one local file, and the other files of the contents are uploaded
+                let uploaded = find_by_hash(client, &md5, id)
+                    .await?
+                    .into_iter()
+                    .find(|file| {
+                        !local_names.contains(file.name.as_str()) && !kept_files.contains(&file.id)
+                    });
+                if let Some(file) = uploaded {
+                    if !options.dry_run {
+                        client
+                            .update_file(file.id)
+                            .name(name.as_str())
+                            .update()
+                            .await?;
+                    }
+                    kept_files.insert(file.id);
+                    report.renamed.push(relative);
+                    continue;
+                }
+            }
+
+            if let (false, Target::Existing(id)) = (options.dry_run, target) {
+                let mut builder = client.build_file(entry_path);
+                builder.name(name.as_str()).use_existing_if_uploaded(false);
+                if let Some(id) = id {
+                    builder.folder(id);
+                }
+                builder.upload().await?;
+                for file in &previous {
+                    client.delete_file(file.id).await?;
+                }
+            }
+            kept_files.extend(previous.iter().map(|file| file.id));
+            if previous.is_empty() {
+                report.created.push(relative);
+            } else {
+                report.updated.push(relative);
+            }
+        }
+
+        if options.delete_missing {
+            for file in &remote_files {
+                if !kept_files.contains(&file.id) {
+                    if !options.dry_run {
+                        client.delete_file(file.id).await?;
+                    }
+                    report.deleted.push(path.join(&file.name));
+                }
+            }
+            for folder in &remote_folders {
+                if !kept_folders.contains(&folder.id) {
+                    obsolete_folders.push((path.join(&folder.name), folder.id));
+                }
+            }
+        }
+    }
+
+    for (path, id) in obsolete_folders {
+        delete_folder_tree(client, path, id, options, &mut report).await?;
+    }
+    Ok(report)
+}
+
+/// Deletes the folder on the drive with all its contents.
+async fn delete_folder_tree<C: ClientExt>(
+    client: &C,
+    path: PathBuf,
+    id: Id<DriveFolder>,
+    options: &SyncOptions,
+    report: &mut SyncReport,
+) -> Result<(), Error<C::Error>> {
+    // folders are deleted after their contents, in reverse order of the traversal
+    let mut folders = Vec::new();
+    let mut stack = vec![(path, id)];
+    while let Some((path, id)) = stack.pop() {
+        let (files, children) = list_folder(client, Some(id)).await?;
+        for file in files {
+            if !options.dry_run {
+                client.delete_file(file.id).await?;
+            }
+            report.deleted.push(path.join(&file.name));
+        }
+        for child in children {
+            stack.push((path.join(&child.name), child.id));
+        }
+        folders.push((path, id));
+    }
+    for (path, id) in folders.into_iter().rev() {
+        if !options.dry_run {
+            client.delete_folder(id).await?;
+        }
+        report.deleted_folders.push(path);
+    }
+    Ok(())
+}
+
+/// Mirrors `folder` on the drive, or the root if `folder` is `None`, into the local directory
+/// `local`.
+///
+/// `fetch` is called with each file to be downloaded and returns its contents, e.g. by
+/// downloading it from [`url`][`DriveFile::url`] with an HTTP client. The files whose MD5
+/// hashes are the same as the local ones are skipped.
+///
+/// With [`delete_missing`][`SyncOptions::delete_missing`], the local files and directories
+/// that do not exist on the drive are deleted.
+/// The files and folders on the drive whose names cannot be used as local file names, such as
+/// `..`, result in an error of [`io::ErrorKind::InvalidData`]. So do the symbolic links in
+/// `local` at the paths of the files and folders on the drive, which are not followed.
+///
+/// The local files are read and written with [`std::fs`], which blocks the thread while it
+/// waits for the file system. On an async runtime, call this where blocking is allowed, e.g.
+/// in `tokio::task::block_in_place`.
+pub async fn download_directory<C, F, Fut>(
+    client: &C,
+    folder: Option<Id<DriveFolder>>,
+    local: impl AsRef<Path>,
+    options: &SyncOptions,
+    mut fetch: F,
+) -> Result<SyncReport, Error<C::Error>>
+where
+    C: ClientExt,
+    F: FnMut(&DriveFile) -> Fut,
+    Fut: Future<Output = io::Result<Vec<u8>>>,
+{
+    let local = local.as_ref();
+    let mut report = SyncReport::default();
+    let mut stack = vec![(PathBuf::new(), folder)];
+
+    while let Some((path, id)) = stack.pop() {
+        let dir = local.join(&path);
+        if !path.as_os_str().is_empty() {
+            check_not_link(&dir)?;
+        }
+        let entries = if dir.is_dir() {
+            read_dir_sorted(&dir)?
+        } else {
+            if !path.as_os_str().is_empty() {
+                report.created_folders.push(path.clone());
+            }
+            if !options.dry_run {
+                fs::create_dir_all(&dir)?;
+            }
+            Vec::new()
+        };
+        let (remote_files, remote_folders) = list_folder(client, id).await?;
+        let mut remote_names = HashSet::new();
+
+        for file in &remote_files {
+            check_name(&file.name)?;
+            remote_names.insert(file.name.as_str());
+            let relative = path.join(&file.name);
+            let file_path = dir.join(&file.name);
+            check_not_link(&file_path)?;
+            let updated = if file_path.is_file() {
+                if file_md5(&file_path)? == file.md5 {
+                    report.unchanged.push(relative);
+                    continue;
+                }
+                true
+            } else {
+                false
+            };
+            if !options.dry_run {
+                let contents = fetch(file).await?;
+                fs::write(&file_path, contents)?;
+            }
+            if updated {
+                report.updated.push(relative);
+            } else {
+                report.created.push(relative);
+            }
+        }
+        for folder in &remote_folders {
+            check_name(&folder.name)?;
+            remote_names.insert(folder.name.as_str());
+            stack.push((path.join(&folder.name), Some(folder.id)));
+        }
+
+        if options.delete_missing {
+            for (name, entry_path) in &entries {
+                if remote_names.contains(name.as_str()) {
+                    continue;
+                }
+                if fs::symlink_metadata(entry_path)?.is_dir() {
+                    if !options.dry_run {
+                        fs::remove_dir_all(entry_path)?;
+                    }
+                    report.deleted_folders.push(path.join(name));
+                } else {
+                    if !options.dry_run {
+                        fs::remove_file(entry_path)?;
+                    }
+                    report.deleted.push(path.join(name));
+                }
+            }
+        }
+    }
+    Ok(report)
+}
+
+/// Lists the files and the folders directly in the folder.
+pub(super) async fn list_folder<C: ClientExt>(
+    client: &C,
+    id: Option<Id<DriveFolder>>,
+) -> Result<(Vec<DriveFile>, Vec<DriveFolder>), Error<C::Error>> {
+    let mut files = client.files();
+    if let Some(id) = id {
+        files.folder(id);
+    }
+    let files = files.list().try_collect().await?;
+    let folders = match id {
+        Some(id) => client.folders_in_folder(id).try_collect().await?,
+        None => client.folders().try_collect().await?,
+    };
+    Ok((files, folders))
+}
+
+/// Finds the files in the folder with the MD5 hash.
+async fn find_by_hash<C: Client + Sync>(
+    client: &C,
+    md5: &str,
+    folder: Option<Id<DriveFolder>>,
+) -> Result<Vec<DriveFile>, Error<C::Error>> {
+    let exists = client
+        .request(endpoint::drive::files::check_existence::Request {
+            md5: md5.to_owned(),
+        })
+        .await
+        .map_err(Error::Client)?
+        .into_result()?;
+    if !exists {
+        return Ok(Vec::new());
+    }
+    let files = client
+        .request(endpoint::drive::files::find_by_hash::Request {
+            md5: md5.to_owned(),
+        })
+        .await
+        .map_err(Error::Client)?
+        .into_result()?;
+    Ok(files
+        .into_iter()
+        .filter(|file| file.folder_id == folder)
+        .collect())
+}
+
+/// Lists the entries in the directory with their names, sorted by the names.
+fn read_dir_sorted(dir: &Path) -> io::Result<Vec<(String, PathBuf)>> {
+    let mut entries = Vec::new();
+    for entry in fs::read_dir(dir)? {
+        let entry = entry?;
+        let name = entry.file_name().to_string_lossy().into_owned();
+        entries.push((name, entry.path()));
+    }
+    entries.sort();
+    Ok(entries)
+}
+
+/// Computes the MD5 hash of the file in lowercase hex, as in [`DriveFile::md5`].
+fn file_md5(path: &Path) -> io::Result<String> {
+    let mut file = fs::File::open(path)?;
+    let mut context = md5::Context::new();
+    let mut buf = vec![0; 64 * 1024];
+    loop {
+        let len = file.read(&mut buf)?;
+        if len == 0 {
+            break;
+        }
+        context.consume(&buf[..len]);
+    }
+    Ok(format!("{:x}", context.compute()))
+}
+
+/// Checks that the name on the drive can be used as a local file name.
+fn check_name(name: &str) -> io::Result<()> {
+    // `Path` ignores a trailing separator, as in `foo/`
+    let mut components = Path::new(name).components();
+    match (components.next(), components.next()) {
+        (Some(Component::Normal(_)), None) if !name.contains(std::path::is_separator) => Ok(()),
+        _ => Err(io::Error::new(
+            io::ErrorKind::InvalidData,
+            format!("invalid file name on the drive: {:?}", name),
+        )),
+    }
+}
+
+/// Checks that the local path is not a symbolic link, through which the files outside the
+/// synchronized directory would be changed.
+fn check_not_link(path: &Path) -> io::Result<()> {
+    match fs::symlink_metadata(path) {
+        Ok(metadata) if metadata.file_type().is_symlink() => Err(io::Error::new(
+            io::ErrorKind::InvalidData,
+            format!("symbolic link in the local directory: {}", path.display()),
+        )),
+        _ => Ok(()),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{check_name, download_directory, file_md5, read_dir_sorted, upload_directory};
+    use super::{SyncOptions, SyncReport};
+    use crate::test_util::{drive_file_json, id, temp_dir, MockClient};
+
+    use std::fs;
+    use std::path::PathBuf;
+
+    use serde_json::{json, Value};
+
+    fn md5(contents: &str) -> String {
+        format!("{:x}", md5::compute(contents))
+    }
+
+    fn paths(paths: &[PathBuf]) -> Vec<&str> {
+        paths.iter().map(|path| path.to_str().unwrap()).collect()
+    }
+
+    /// The files in the root folder of the drive, with the folder `sub` created on request.
+    fn drive() -> MockClient {
+        MockClient::new(|endpoint, request| {
+            let root = [
+                drive_file_json(1, "changed.txt", &md5("old"), None),
+                drive_file_json(2, "same.txt", &md5("same"), None),
+                drive_file_json(3, "renamed-on-drive.txt", &md5("copy"), None),
+                drive_file_json(4, "missing.txt", &md5("missing"), None),
+            ];
+            match endpoint {
+                "drive/files" if request.get("untilId").is_some() => json!([]),
+                "drive/files" if request["folderId"].is_null() => json!(root),
+                "drive/files" | "drive/folders" => json!([]),
+                "drive/files/check-existence" => {
+                    json!(root.iter().any(|file| file["md5"] == request["md5"]))
+                }
+                "drive/files/find-by-hash" => Value::Array(
+                    root.iter()
+                        .filter(|file| file["md5"] == request["md5"])
+                        .cloned()
+                        .collect(),
+                ),
+                "drive/files/create" => drive_file_json(10, "new", "", None),
+                "drive/files/update" => drive_file_json(3, "renamed", &md5("copy"), None),
+                "drive/files/delete" => Value::Null,
+                "drive/folders/create" => json!({
+                    "id": id::<()>(20),
+                    "createdAt": "2000-01-01T00:00:00Z",
+                    "name": request["name"],
+                    "parentId": request["parentId"],
+                }),
+                _ => unreachable!("{}", endpoint),
+            }
+        })
+    }
+
+    fn local(name: &str) -> PathBuf {
+        let dir = temp_dir(name);
+        fs::write(dir.join("changed.txt"), "new").unwrap();
+        fs::write(dir.join("same.txt"), "same").unwrap();
+        fs::write(dir.join("copy1.txt"), "copy").unwrap();
+        fs::write(dir.join("copy2.txt"), "copy").unwrap();
+        fs::create_dir(dir.join("sub")).unwrap();
+        fs::write(dir.join("sub/new.txt"), "new").unwrap();
+        dir
+    }
+
+    fn assert_report(report: &SyncReport) {
+        assert_eq!(paths(&report.created_folders), ["sub"]);
+        assert_eq!(paths(&report.created), ["copy2.txt", "sub/new.txt"]);
+        assert_eq!(paths(&report.updated), ["changed.txt"]);
+        assert_eq!(paths(&report.unchanged), ["same.txt"]);
+        assert_eq!(paths(&report.renamed), ["copy1.txt"]);
+        assert_eq!(paths(&report.deleted), ["missing.txt"]);
+    }
+
+    #[test]
+    fn test_check_name() {
+        assert!(check_name("file.txt").is_ok());
+        assert!(check_name(".hidden").is_ok());
+        for name in ["", ".", "..", "a/b", "/a", "a/"] {
+            assert!(check_name(name).is_err(), "{:?}", name);
+        }
+    }
+
+    #[test]
+    fn test_read_dir() {
+        let dir = temp_dir("sync-read-dir");
+        fs::write(dir.join("b"), "").unwrap();
+        fs::write(dir.join("a"), "hello").unwrap();
+        fs::create_dir(dir.join("c")).unwrap();
+        let names: Vec<_> = read_dir_sorted(&dir)
+            .unwrap()
+            .into_iter()
+            .map(|(name, path)| {
+                assert_eq!(path, dir.join(&name));
+                name
+            })
+            .collect();
+        assert_eq!(names, ["a", "b", "c"]);
+        assert_eq!(
+            file_md5(&dir.join("a")).unwrap(),
+            "5d41402abc4b2a76b9719d911017c592"
+        );
+        assert_eq!(
+            file_md5(&dir.join("b")).unwrap(),
+            "d41d8cd98f00b204e9800998ecf8427e"
+        );
+    }
+
+    #[tokio::test]
+    async fn test_upload_directory() {
+        let dir = local("sync-upload");
+        let client = drive();
+        let options = SyncOptions {
+            dry_run: false,
+            delete_missing: true,
+        };
+        let report = upload_directory(&client, &dir, None, &options)
+            .await
+            .unwrap();
+        assert_report(&report);
+
+        let renames: Vec<_> = client
+            .requests()
+            .into_iter()
+            .filter(|(endpoint, _)| endpoint == "drive/files/update")
+            .map(|(_, request)| request)
+            .collect();
+        assert_eq!(
+            renames,
+            [json!({ "fileId": id::<()>(3), "name": "copy1.txt" })]
+        );
+        let mut uploaded: Vec<_> = client.files().into_iter().map(|(name, _)| name).collect();
+        uploaded.sort();
+        assert_eq!(uploaded, ["changed.txt", "copy2.txt", "new.txt"]);
+        let deleted: Vec<_> = client
+            .requests()
+            .into_iter()
+            .filter(|(endpoint, _)| endpoint == "drive/files/delete")
+            .map(|(_, request)| request["fileId"].clone())
+            .collect();
+        assert_eq!(deleted, [json!(id::<()>(1)), json!(id::<()>(4))]);
+    }
+
+    #[tokio::test]
+    async fn test_upload_directory_dry_run() {
+        let dir = local("sync-upload-dry-run");
+        let client = drive();
+        let options = SyncOptions {
+            dry_run: true,
+            delete_missing: true,
+        };
+        let report = upload_directory(&client, &dir, None, &options)
+            .await
+            .unwrap();
+        assert_report(&report);
+        assert!(client.files().is_empty());
+        assert!(client.endpoints().iter().all(|endpoint| matches!(
+            endpoint.as_str(),
+            "drive/files"
+                | "drive/folders"
+                | "drive/files/check-existence"
+                | "drive/files/find-by-hash"
+        )));
+    }
+
+    #[cfg(unix)]
+    #[tokio::test]
+    async fn test_upload_directory_link() {
+        let dir = temp_dir("sync-upload-link");
+        let outside = temp_dir("sync-upload-link-outside");
+        fs::write(outside.join("secret.txt"), "secret").unwrap();
+        std::os::unix::fs::symlink(&outside, dir.join("dir")).unwrap();
+        std::os::unix::fs::symlink(outside.join("secret.txt"), dir.join("file.txt")).unwrap();
+        let client = drive();
+        let report = upload_directory(&client, &dir, None, &SyncOptions::default())
+            .await
+            .unwrap();
+        assert!(report.created.is_empty());
+        assert!(report.created_folders.is_empty());
+        assert!(client.files().is_empty());
+    }
+
+    #[tokio::test]
+    async fn test_download_directory() {
+        let dir = temp_dir("sync-download");
+        fs::write(dir.join("changed.txt"), "new").unwrap();
+        fs::write(dir.join("same.txt"), "same").unwrap();
+        fs::write(dir.join("local.txt"), "local").unwrap();
+        let client = drive();
+        let options = SyncOptions {
+            dry_run: false,
+            delete_missing: true,
+        };
+        let report = download_directory(&client, None, &dir, &options, |file| {
+            let contents = format!("contents of {}", file.name);
+            async move { Ok(contents.into_bytes()) }
+        })
+        .await
+        .unwrap();
+        assert_eq!(
+            paths(&report.created),
+            ["renamed-on-drive.txt", "missing.txt"]
+        );
+        assert_eq!(paths(&report.updated), ["changed.txt"]);
+        assert_eq!(paths(&report.unchanged), ["same.txt"]);
+        assert_eq!(paths(&report.deleted), ["local.txt"]);
+        assert_eq!(
+            fs::read_to_string(dir.join("changed.txt")).unwrap(),
+            "contents of changed.txt"
+        );
+        assert!(!dir.join("local.txt").exists());
+    }
+
+    #[tokio::test]
+    async fn test_download_directory_invalid_name() {
+        let dir = temp_dir("sync-download-invalid-name");
+        let client = MockClient::new(|endpoint, request| match endpoint {
+            "drive/files" if request.get("untilId").is_none() => {
+                json!([drive_file_json(1, "..", &md5(""), None)])
+            }
+            "drive/files" | "drive/folders" => json!([]),
+            _ => unreachable!("{}", endpoint),
+        });
+        let result = download_directory(&client, None, &dir, &SyncOptions::default(), |_| async {
+            Ok(Vec::new())
+        })
+        .await;
+        assert!(matches!(result, Err(crate::Error::Io(_))));
+    }
+}
@@ -0,0 +1,704 @@
+//! Import and export of custom emoji packs.
+//!
+//! An emoji pack is a zip file that contains the images of custom emojis and `meta.json`
+//! describing them, in the format that Misskey exports from the control panel and accepts
+//! in `admin/emoji/import-zip`.
+//!
+//! [`EmojiPack::export`] collects the custom emojis of the instance, and
+//! [`EmojiPack::from_dir`] builds a pack from the images in a local directory.
+//! The pack can be saved with [`EmojiPack::write_zip`], and imported into an instance with
+//! [`EmojiPack::import_zip`], or with [`EmojiPack::import`] that adds the emojis one by one.
+//!
+//! # Examples
+//!
+//! ```no_run
+//! # #[tokio::main]
+//! # async fn main() -> anyhow::Result<()> {
+//! # let client = misskey_test::test_admin_client().await?;
+//! use misskey_util::emoji_pack::EmojiPack;
+//! use std::path::Path;
+//!
+//! // `emojis.json` maps the file names to the metadata, e.g.
+//! // {"blobcat.png": {"category": "blobcat", "aliases": ["cat"], "license": "Apache-2.0"}}
+//! let pack = EmojiPack::from_dir("emojis", Some(Path::new("emojis.json")))?;
+//! pack.write_zip(std::fs::File::create("emojis.zip")?)?;
+//! pack.import_zip(&client).await?;
+//! # Ok(())
+//! # }
+//! ```
+
+use std::collections::{HashMap, HashSet};
+use std::fs;
+use std::future::Future;
+use std::io::{self, Cursor, Read, Seek, Write};
+use std::path::Path;
+use std::time::Duration;
+
+use crate::pager::{BackwardPager, PagerStream};
+#[cfg(feature = "13-13-0")]
+use crate::ClientExt;
+use crate::Error;
+
+use chrono::Utc;
+use futures::stream::TryStreamExt;
+use futures_timer::Delay;
+use misskey_api::endpoint;
+use misskey_api::model::{drive::DriveFile, emoji::Emoji, id::Id};
+use misskey_core::{Client, UploadFileClient};
+use serde::{Deserialize, Serialize};
+use url::Url;
+use zip::{write::FileOptions, CompressionMethod, ZipArchive, ZipWriter};
+
+/// The version of `meta.json` written by this module.
+const META_VERSION: u32 = 2;
+
+/// How often [`EmojiPack::import_zip`] checks whether the import has finished.
+const IMPORT_POLL_INTERVAL: Duration = Duration::from_secs(1);
+
+/// How long [`EmojiPack::import_zip`] waits for the import to finish.
+const IMPORT_TIMEOUT: Duration = Duration::from_secs(600);
+
+/// Metadata of a custom emoji in an emoji pack.
+#[derive(Serialize, Deserialize, Debug, Clone, Default)]
+#[serde(rename_all = "camelCase")]
+pub struct EmojiMeta {
+    /// Name of the emoji.
+    #[serde(default)]
+    pub name: String,
+    /// Category of the emoji.
+    #[serde(default)]
+    pub category: Option<String>,
+    /// Aliases of the emoji.
+    #[serde(default)]
+    pub aliases: Vec<String>,
+    /// License of the emoji.
+    #[serde(default)]
+    pub license: Option<String>,
+    /// Whether the emoji is sensitive.
+    #[serde(default)]
+    pub is_sensitive: bool,
+    /// Whether the emoji is only available to the local users.
+    #[serde(default)]
+    pub local_only: bool,
+}
+
+/// A custom emoji with its image in [`EmojiPack`].
+#[derive(Debug, Clone)]
+pub struct PackedEmoji {
+    /// Metadata of the emoji.
+    pub meta: EmojiMeta,
+    /// Name of the image file in the pack.
+    pub file_name: String,
+    /// Contents of the image file.
+    pub data: Vec<u8>,
+}
+
+/// A set of custom emojis with their images.
+#[derive(Debug, Clone, Default)]
+pub struct EmojiPack {
+    /// Host of the instance the emojis are exported from.
+    pub host: Option<String>,
+    /// The time the pack is created, as written in `meta.json`.
+    pub exported_at: Option<String>,
+    /// The emojis.
+    pub emojis: Vec<PackedEmoji>,
+}
+
+#[derive(Serialize, Deserialize)]
+#[serde(rename_all = "camelCase")]
+struct PackMeta {
+    #[serde(default)]
+    meta_version: Option<u32>,
+    #[serde(default)]
+    host: Option<String>,
+    #[serde(default)]
+    exported_at: Option<String>,
+    emojis: Vec<PackRecord>,
+}
+
+#[derive(Serialize, Deserialize)]
+#[serde(rename_all = "camelCase")]
+struct PackRecord {
+    file_name: String,
+    downloaded: bool,
+    emoji: EmojiMeta,
+}
+
+/// The result of [`EmojiPack::import`].
+#[cfg(feature = "13-13-0")]
+#[cfg_attr(docsrs, doc(cfg(feature = "13-13-0")))]
+#[derive(Debug, Clone, Default)]
+pub struct ImportReport {
+    /// IDs of the added emojis.
+    pub added: Vec<Id<Emoji>>,
+    /// Names of the emojis skipped because the emojis of the same names already exist.
+    pub skipped: Vec<String>,
+}
+
+impl EmojiPack {
+    /// Exports the local custom emojis of the instance, which requires moderator privileges.
+    ///
+    /// `fetch` is called with each emoji and returns the contents of its image, e.g. by
+    /// downloading it from [`url`][`Emoji::url`] with an HTTP client.
+    pub async fn export<C, F, Fut>(client: &C, mut fetch: F) -> Result<EmojiPack, Error<C::Error>>
+    where
+        C: Client + Sync,
+        F: FnMut(&Emoji) -> Fut,
+        Fut: Future<Output = io::Result<Vec<u8>>>,
+    {
+        let meta = client
+            .request(endpoint::meta::Request::default())
+            .await
+            .map_err(Error::Client)?
+            .into_result()?;
+        let host = Url::parse(&meta.uri)
+            .ok()
+            .and_then(|url| url.host_str().map(str::to_owned));
+
+        let pager = BackwardPager::new(client, endpoint::admin::emoji::list::Request::default());
+        let mut emojis = PagerStream::new(Box::pin(pager));
+        let mut packed = Vec::new();
+        while let Some(emoji) = emojis.try_next().await? {
+            let data = fetch(&emoji).await?;
+            let extension = image_extension(&data).ok_or_else(|| {
+                io::Error::new(
+                    io::ErrorKind::InvalidData,
+                    format!("unknown image format of the emoji: {}", emoji.name),
+                )
+            })?;
+            let file_name = format!("{}.{}", emoji.name, extension);
+            packed.push(PackedEmoji {
+                meta: EmojiMeta {
+                    name: emoji.name.clone(),
+                    category: emoji.category.clone(),
+                    aliases: emoji.aliases.clone(),
+                    #[cfg(feature = "13-10-0")]
+                    license: emoji.license.clone(),
+                    #[cfg(not(feature = "13-10-0"))]
+                    license: None,
+                    #[cfg(feature = "13-13-0")]
+                    is_sensitive: emoji.is_sensitive.unwrap_or(false),
+                    #[cfg(not(feature = "13-13-0"))]
+                    is_sensitive: false,
+                    #[cfg(feature = "13-13-0")]
+                    local_only: emoji.local_only.unwrap_or(false),
+                    #[cfg(not(feature = "13-13-0"))]
+                    local_only: false,
+                },
+                file_name,
+                data,
+            });
+        }
+        Ok(EmojiPack {
+            host,
+            exported_at: Some(Utc::now().to_rfc3339()),
+            emojis: packed,
+        })
+    }
+
+    /// Builds a pack from the images in the directory `dir`.
+    ///
+    /// `metadata` is the path to a JSON file that maps the file names of the images to their
+    /// [`EmojiMeta`], where the fields can be omitted. The name of the emoji defaults to the
+    /// file name without the extension. The images without metadata are also included.
+    ///
+    /// Returns an error of [`io::ErrorKind::InvalidInput`] if the name of an emoji is not
+    /// accepted by Misskey, which allows only alphanumerics, `_` and `.` in the names.
+    pub fn from_dir(dir: impl AsRef<Path>, metadata: Option<&Path>) -> io::Result<EmojiPack> {
+        let mut metadata: HashMap<String, EmojiMeta> = match metadata {
+            Some(path) => serde_json::from_slice(&fs::read(path)?)
+                .map_err(|e| io::Error::new(io::ErrorKind::InvalidData, e))?,
+            None => HashMap::new(),
+        };
+
+        let mut paths = Vec::new();
+        for entry in fs::read_dir(dir)? {
+            let path = entry?.path();
+            let is_image = matches!(
+                mime_guess::from_path(&path).first(),
+                Some(mime) if mime.type_() == mime::IMAGE
+            );
+            if path.is_file() && is_image {
+                paths.push(path);
+            }
+        }
+        paths.sort();
+
+        let mut emojis = Vec::new();
+        for path in paths {
+            let file_name = match path.file_name().and_then(|name| name.to_str()) {
+                Some(name) => name.to_owned(),
+                None => continue,
+            };
+            let mut meta = metadata.remove(&file_name).unwrap_or_default();
+            if meta.name.is_empty() {
+                let stem = path.file_stem().and_then(|stem| stem.to_str());
+                meta.name = stem.unwrap_or_default().to_owned();
+            }
+            check_name(&meta.name)?;
+            emojis.push(PackedEmoji {
+                meta,
+                file_name,
+                data: fs::read(&path)?,
+            });
+        }
+        Ok(EmojiPack {
+            host: None,
+            exported_at: Some(Utc::now().to_rfc3339()),
+            emojis,
+        })
+    }
+
+    /// Reads a pack from the zip file.
+    ///
+    /// The emojis whose images failed to be downloaded on export are skipped.
+    pub fn read_zip<R: Read + Seek>(reader: R) -> io::Result<EmojiPack> {
+        let mut archive = ZipArchive::new(reader)?;
+        let meta: PackMeta = {
+            let file = archive.by_name("meta.json")?;
+            serde_json::from_reader(file)
+                .map_err(|e| io::Error::new(io::ErrorKind::InvalidData, e))?
+        };
+        let mut emojis = Vec::new();
+        for record in meta.emojis {
+            if !record.downloaded {
+                continue;
+            }
+            let mut data = Vec::new();
+            archive.by_name(&record.file_name)?.read_to_end(&mut data)?;
+            emojis.push(PackedEmoji {
+                meta: record.emoji,
+                file_name: record.file_name,
+                data,
+            });
+        }
+        Ok(EmojiPack {
+            host: meta.host,
+            exported_at: meta.exported_at,
+            emojis,
+        })
+    }
+
+    /// Writes the pack as a zip file.
+    ///
+    /// Returns an error of [`io::ErrorKind::InvalidInput`] if the name of an emoji is not
+    /// accepted by Misskey, which would skip the emoji on import.
+    pub fn write_zip<W: Write + Seek>(&self, writer: W) -> io::Result<()> {
+        for emoji in &self.emojis {
+            check_name(&emoji.meta.name)?;
+        }
+        let mut zip = ZipWriter::new(writer);
+        let options = FileOptions::default().compression_method(CompressionMethod::Deflated);
+        let mut file_names = HashSet::new();
+        let mut records = Vec::new();
+        for emoji in &self.emojis {
+            if !file_names.insert(emoji.file_name.as_str()) {
+                return Err(io::Error::new(
+                    io::ErrorKind::InvalidInput,
+                    format!(
+                        "duplicated file name in the emoji pack: {}",
+                        emoji.file_name
+                    ),
+                ));
+            }
+            zip.start_file(emoji.file_name.as_str(), options)?;
+            zip.write_all(&emoji.data)?;
+            records.push(PackRecord {
+                file_name: emoji.file_name.clone(),
+                downloaded: true,
+                emoji: emoji.meta.clone(),
+            });
+        }
+        let meta = PackMeta {
+            meta_version: Some(META_VERSION),
+            host: self.host.clone(),
+            exported_at: self.exported_at.clone(),
+            emojis: records,
+        };
+        zip.start_file("meta.json", options)?;
+        serde_json::to_writer(&mut zip, &meta)?;
+        zip.finish()?;
+        Ok(())
+    }
+
+    /// Uploads the pack to the drive and imports it with `admin/emoji/import-zip`, which
+    /// requires moderator privileges.
+    ///
+    /// The import is processed in the background on the server, replacing the existing
+    /// emojis of the same names. Since the server reads the uploaded file when it processes
+    /// the import, this waits until all the emojis in the pack are replaced or added, and
+    /// then deletes the file from the drive.
+    /// If the import does not finish in 10 minutes, e.g. because the server failed to add some
+    /// of the emojis, the file is left on the drive and an error of
+    /// [`io::ErrorKind::TimedOut`] is returned.
+    pub async fn import_zip<C>(&self, client: &C) -> Result<(), Error<C::Error>>
+    where
+        C: UploadFileClient + Sync,
+    {
+        let mut zip = Cursor::new(Vec::new());
+        self.write_zip(&mut zip)?;
+        let existing = local_emoji_ids(client).await?;
+        let file = upload(client, "emojis.zip", zip.into_inner()).await?;
+        client
+            .request(endpoint::admin::emoji::import_zip::Request { file_id: file.id })
+            .await
+            .map_err(Error::Client)?
+            .into_result()?;
+
+        let mut waited = Duration::from_secs(0);
+        loop {
+            let current = local_emoji_ids(client).await?;
+            let imported = self.emojis.iter().all(|emoji| {
+                let name = &emoji.meta.name;
+                current.contains_key(name) && current.get(name) != existing.get(name)
+            });
+            if imported {
+                break;
+            }
+            if waited >= IMPORT_TIMEOUT {
+                return Err(io::Error::new(
+                    io::ErrorKind::TimedOut,
+                    format!(
+                        "the import of the emoji pack did not finish, leaving the file {}",
+                        file.id
+                    ),
+                )
+                .into());
+            }
+            Delay::new(IMPORT_POLL_INTERVAL).await;
+            waited += IMPORT_POLL_INTERVAL;
+        }
+
+        client
+            .request(endpoint::drive::files::delete::Request { file_id: file.id })
+            .await
+            .map_err(Error::Client)?
+            .into_result()?;
+        Ok(())
+    }
+
+    /// Uploads the images one by one and adds them as custom emojis with their metadata,
+    /// which requires moderator privileges.
+    ///
+    /// Unlike [`import_zip`][`EmojiPack::import_zip`], the emojis whose names are already used
+    /// are skipped.
+    /// The names are checked before any request is made, as in
+    /// [`write_zip`][`EmojiPack::write_zip`].
+    #[cfg(feature = "13-13-0")]
+    #[cfg_attr(docsrs, doc(cfg(feature = "13-13-0")))]
+    pub async fn import<C>(&self, client: &C) -> Result<ImportReport, Error<C::Error>>
+    where
+        C: ClientExt + UploadFileClient,
+    {
+        for emoji in &self.emojis {
+            check_name(&emoji.meta.name)?;
+        }
+        let existing: HashSet<String> = client
+            .admin_emojis()
+            .map_ok(|emoji| emoji.name)
+            .try_collect()
+            .await?;
+        let mut report = ImportReport::default();
+        for emoji in &self.emojis {
+            if existing.contains(&emoji.meta.name) {
+                report.skipped.push(emoji.meta.name.clone());
+                continue;
+            }
+            let file = upload(client, &emoji.file_name, emoji.data.clone()).await?;
+            let request = endpoint::admin::emoji::add::Request {
+                file_id: file.id,
+                name: emoji.meta.name.clone(),
+                category: emoji.meta.category.clone(),
+                aliases: Some(emoji.meta.aliases.clone()),
+                license: emoji.meta.license.clone(),
+                is_sensitive: Some(emoji.meta.is_sensitive),
+                local_only: Some(emoji.meta.local_only),
+                role_ids_that_can_be_used_this_emoji_as_reaction: None,
+            };
+            let added = client
+                .request(request)
+                .await
+                .map_err(Error::Client)?
+                .into_result()?;
+            report.added.push(added.id);
+        }
+        Ok(report)
+    }
+}
+
+/// Returns the IDs of the local custom emojis by their names.
+async fn local_emoji_ids<C: Client + Sync>(
+    client: &C,
+) -> Result<HashMap<String, Id<Emoji>>, Error<C::Error>> {
+    let pager = BackwardPager::new(client, endpoint::admin::emoji::list::Request::default());
+    PagerStream::new(Box::pin(pager))
+        .map_ok(|emoji| (emoji.name, emoji.id))
+        .try_collect()
+        .await
+}
+
+/// Checks that Misskey accepts `name` as the name of a custom emoji, i.e. it matches
+/// `^[a-zA-Z0-9_]+?([a-zA-Z0-9\.]+)?$`.
+fn check_name(name: &str) -> io::Result<()> {
+    // `_` is not allowed after `.`
+    let (head, tail) = name.split_at(name.find('.').unwrap_or(name.len()));
+    let valid = !head.is_empty()
+        && head.chars().all(|c| c.is_ascii_alphanumeric() || c == '_')
+        && tail.chars().all(|c| c.is_ascii_alphanumeric() || c == '.');
+    if valid {
+        Ok(())
+    } else {
+        Err(io::Error::new(
+            io::ErrorKind::InvalidInput,
+            format!("invalid emoji name: {:?}", name),
+        ))
+    }
+}
+
+/// Uploads the contents as a file on the drive.
+async fn upload<C: UploadFileClient + Sync>(
+    client: &C,
+    name: &str,
+    data: Vec<u8>,
+) -> Result<DriveFile, Error<C::Error>> {
+    let request = endpoint::drive::files::create::Request {
+        folder_id: None,
+        name: Some(name.to_owned()),
+        comment: None,
+        is_sensitive: Some(false),
+        force: Some(true),
+    };
+    let type_ = mime_guess::from_path(name).first_or_octet_stream();
+    let file = client
+        .request_with_file(request, type_, name.to_owned(), Cursor::new(data))
+        .await
+        .map_err(Error::Client)?
+        .into_result()?;
+    Ok(file)
+}
+
+/// Guesses the extension of the image from its signature, or returns `None` if the format is
+/// unknown.
+fn image_extension(data: &[u8]) -> Option<&'static str> {
+    if data.starts_with(b"\x89PNG\r\n\x1a\n") {
+        Some("png")
+    } else if data.starts_with(b"GIF8") {
+        Some("gif")
+    } else if data.starts_with(b"\xff\xd8\xff") {
+        Some("jpg")
+    } else if data.len() >= 12 && &data[..4] == b"RIFF" && &data[8..12] == b"WEBP" {
+        Some("webp")
+    } else if data.len() >= 12 && &data[4..12] == b"ftypavif" {
+        Some("avif")
+    } else if data.starts_with(b"<svg") || data.starts_with(b"<?xml") {
+        Some("svg")
+    } else {
+        None
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{check_name, image_extension, EmojiPack, PackedEmoji};
+    use crate::test_util::{drive_file_json, id, temp_dir, MockClient};
+
+    use std::fs;
+    use std::io::{self, Cursor};
+    use std::path::Path;
+    use std::sync::atomic::{AtomicUsize, Ordering};
+
+    use misskey_api::model::emoji::Emoji;
+    use serde_json::{json, Value};
+
+    const PNG: &[u8] = b"\x89PNG\r\n\x1a\n....";
+    const GIF: &[u8] = b"GIF89a....";
+
+    fn emoji_json(n: u64, name: &str) -> Value {
+        json!({
+            "id": id::<Emoji>(n),
+            "name": name,
+            "url": format!("https://example.com/emojis/{}.png", name),
+            "host": null,
+            "category": null,
+            "aliases": [],
+        })
+    }
+
+    fn packed(name: &str) -> PackedEmoji {
+        PackedEmoji {
+            meta: super::EmojiMeta {
+                name: name.to_owned(),
+                ..Default::default()
+            },
+            file_name: format!("{}.png", name),
+            data: PNG.to_vec(),
+        }
+    }
+
+    #[test]
+    fn test_check_name() {
+        for name in [
+            "blobcat",
+            "blob_cat",
+            "_",
+            "1",
+            "blob.cat",
+            "blob_cat.2.x",
+            "a..b",
+        ] {
+            assert!(check_name(name).is_ok(), "{}", name);
+        }
+        for name in ["", ".blob", "blob-cat", "blob.cat_2", "blob cat", "ねこ"] {
+            let err = check_name(name).unwrap_err();
+            assert_eq!(err.kind(), io::ErrorKind::InvalidInput, "{}", name);
+        }
+    }
+
+    #[test]
+    fn test_image_extension() {
+        assert_eq!(image_extension(PNG), Some("png"));
+        assert_eq!(image_extension(GIF), Some("gif"));
+        assert_eq!(image_extension(b"\xff\xd8\xff\xe0"), Some("jpg"));
+        assert_eq!(image_extension(b"RIFF\0\0\0\0WEBPVP8 "), Some("webp"));
+        assert_eq!(image_extension(b"\0\0\0\x20ftypavif"), Some("avif"));
+        assert_eq!(image_extension(b"<svg xmlns=\"\"></svg>"), Some("svg"));
+        assert_eq!(image_extension(b"not an image"), None);
+        assert_eq!(image_extension(b""), None);
+    }
+
+    #[test]
+    fn test_from_dir() {
+        let dir = temp_dir("emoji-pack-from-dir");
+        fs::write(dir.join("blobcat.png"), PNG).unwrap();
+        fs::write(dir.join("party.gif"), GIF).unwrap();
+        fs::write(dir.join("readme.txt"), "not an image").unwrap();
+        let metadata = dir.join("emojis.json");
+        fs::write(
+            &metadata,
+            r#"{"blobcat.png": {"category": "blob", "aliases": ["cat"]}, "party.gif": {"name": "party_2"}}"#,
+        )
+        .unwrap();
+
+        let pack = EmojiPack::from_dir(&dir, Some(&metadata)).unwrap();
+        let names: Vec<_> = pack.emojis.iter().map(|e| e.meta.name.as_str()).collect();
+        assert_eq!(names, ["blobcat", "party_2"]);
+        assert_eq!(pack.emojis[0].file_name, "blobcat.png");
+        assert_eq!(pack.emojis[0].data, PNG);
+        assert_eq!(pack.emojis[0].meta.category.as_deref(), Some("blob"));
+        assert_eq!(pack.emojis[0].meta.aliases, ["cat"]);
+        assert_eq!(pack.emojis[1].file_name, "party.gif");
+
+        fs::write(dir.join("blob-cat.png"), PNG).unwrap();
+        let err = EmojiPack::from_dir(&dir, None).unwrap_err();
+        assert_eq!(err.kind(), io::ErrorKind::InvalidInput);
+        fs::remove_dir_all(&dir).unwrap();
+    }
+
+    #[test]
+    fn test_from_dir_invalid_metadata() {
+        let dir = temp_dir("emoji-pack-invalid-metadata");
+        fs::write(dir.join("blobcat.png"), PNG).unwrap();
+        let metadata = dir.join("emojis.json");
+        fs::write(&metadata, "[").unwrap();
+        let err = EmojiPack::from_dir(&dir, Some(&metadata)).unwrap_err();
+        assert_eq!(err.kind(), io::ErrorKind::InvalidData);
+        let err = EmojiPack::from_dir(&dir, Some(Path::new("missing.json"))).unwrap_err();
+        assert_eq!(err.kind(), io::ErrorKind::NotFound);
+        fs::remove_dir_all(&dir).unwrap();
+    }
+
+    #[test]
+    fn test_zip_round_trip() {
+        let mut pack = EmojiPack {
+            host: Some("example.com".to_owned()),
+            exported_at: Some("2000-01-01T00:00:00+00:00".to_owned()),
+            emojis: vec![packed("blobcat"), packed("blob.cat")],
+        };
+        pack.emojis[0].meta.aliases = vec!["cat".to_owned()];
+        let mut zip = Cursor::new(Vec::new());
+        pack.write_zip(&mut zip).unwrap();
+
+        let read = EmojiPack::read_zip(Cursor::new(zip.into_inner())).unwrap();
+        assert_eq!(read.host, pack.host);
+        assert_eq!(read.exported_at, pack.exported_at);
+        assert_eq!(read.emojis.len(), 2);
+        assert_eq!(read.emojis[0].meta.name, "blobcat");
+        assert_eq!(read.emojis[0].meta.aliases, ["cat"]);
+        assert_eq!(read.emojis[1].file_name, "blob.cat.png");
+        assert_eq!(read.emojis[1].data, PNG);
+    }
+
+    #[test]
+    fn test_write_zip_invalid() {
+        let pack = EmojiPack {
+            emojis: vec![packed("blob-cat")],
+            ..Default::default()
+        };
+        let err = pack.write_zip(Cursor::new(Vec::new())).unwrap_err();
+        assert_eq!(err.kind(), io::ErrorKind::InvalidInput);
+
+        let pack = EmojiPack {
+            emojis: vec![packed("blobcat"), packed("blobcat")],
+            ..Default::default()
+        };
+        let err = pack.write_zip(Cursor::new(Vec::new())).unwrap_err();
+        assert_eq!(err.kind(), io::ErrorKind::InvalidInput);
+    }
+
+    #[tokio::test]
+    async fn test_import_zip() {
+        let lists = AtomicUsize::new(0);
+        let client = MockClient::new(move |endpoint, request| match endpoint {
+            "admin/emoji/list" if request.get("untilId").is_some() => json!([]),
+            // the import replaces `blobcat` and adds `party` on the second check
+            "admin/emoji/list" => match lists.fetch_add(1, Ordering::SeqCst) {
+                0 | 1 => json!([emoji_json(1, "blobcat")]),
+                _ => json!([emoji_json(3, "party"), emoji_json(2, "blobcat")]),
+            },
+            "drive/files/create" => drive_file_json(10, "emojis.zip", "", None),
+            "admin/emoji/import-zip" | "drive/files/delete" => Value::Null,
+            endpoint => panic!("unexpected request to {}", endpoint),
+        });
+        let pack = EmojiPack {
+            emojis: vec![packed("blobcat"), packed("party")],
+            ..Default::default()
+        };
+        pack.import_zip(&client).await.unwrap();
+
+        let requests = client.requests();
+        let endpoints: Vec<_> = requests
+            .iter()
+            .map(|(endpoint, _)| endpoint.as_str())
+            .filter(|endpoint| *endpoint != "admin/emoji/list")
+            .collect();
+        assert_eq!(
+            endpoints,
+            [
+                "drive/files/create",
+                "admin/emoji/import-zip",
+                "drive/files/delete"
+            ]
+        );
+        let (_, delete) = requests.last().unwrap();
+        assert_eq!(delete["fileId"], json!(id::<()>(10)));
+
+        let files = client.files();
+        assert_eq!(files.len(), 1);
+        assert_eq!(files[0].0, "emojis.zip");
+        let uploaded = EmojiPack::read_zip(Cursor::new(files[0].1.clone())).unwrap();
+        assert_eq!(uploaded.emojis.len(), 2);
+    }
+
+    #[tokio::test]
+    async fn test_import_zip_invalid() {
+        let client = MockClient::new(|endpoint, _| panic!("unexpected request to {}", endpoint));
+        let pack = EmojiPack {
+            emojis: vec![packed("blob-cat")],
+            ..Default::default()
+        };
+        assert!(pack.import_zip(&client).await.is_err());
+        #[cfg(feature = "13-13-0")]
+        assert!(pack.import(&client).await.is_err());
+        assert!(client.requests().is_empty());
+    }
+}
@@ -0,0 +1,357 @@
+//! Analysis and synchronization of follow graphs.
+//!
+//! [`SocialGraph::fetch`] takes a snapshot of the following and the followers of a user, which
+//! can be analyzed offline to find mutual and one-way follows, or compared with another snapshot
+//! with [`SocialGraph::diff`]. Snapshots can be saved with [`serde`] to track the changes over
+//! time.
+//!
+//! [`sync_following`] follows the users in a snapshot from the account logged in with
+//! another client, which is useful to migrate an account to another instance.
+//!
+//! # Examples
+//!
+//! ```no_run
+//! # use misskey_util::ClientExt;
+//! # #[tokio::main]
+//! # async fn main() -> anyhow::Result<()> {
+//! # let old_client = misskey_test::test_client().await?;
+//! # let new_client = misskey_test::test_client().await?;
+//! use misskey_util::graph::{self, SocialGraph, SyncOptions};
+//!
+//! let me = old_client.me().await?;
+//! let graph = SocialGraph::fetch(&old_client, &me).await?;
+//! println!("{} mutuals", graph.mutuals().len());
+//!
+//! // see what would be done first
+//! let options = SyncOptions {
+//!     dry_run: true,
+//!     ..SyncOptions::default()
+//! };
+//! let report = graph::sync_following(&new_client, &graph, &options).await?;
+//! for user in report.followed {
+//!     println!("will follow @{}", user.username);
+//! }
+//! # Ok(())
+//! # }
+//! ```
+
+use std::collections::HashSet;
+use std::future::Future;
+use std::io;
+use std::time::Duration;
+
+use crate::{ClientExt, Error};
+
+use chrono::{DateTime, Utc};
+use futures::stream::TryStreamExt;
+use futures_timer::Delay;
+use misskey_api::model::{id::Id, user::User};
+use misskey_api::{endpoint, EntityRef};
+use misskey_core::{model::ApiResult, Client};
+use serde::{Deserialize, Serialize};
+use url::Url;
+
+/// A snapshot of the following and the followers of a user.
+#[derive(Serialize, Deserialize, Debug, Clone)]
+#[serde(rename_all = "camelCase")]
+pub struct SocialGraph {
+    /// URL of the instance the snapshot is taken on.
+    pub origin: Url,
+    /// The user.
+    pub user: User,
+    /// The time the snapshot is taken.
+    pub fetched_at: DateTime<Utc>,
+    /// The users the user is following.
+    pub following: Vec<User>,
+    /// The followers of the user.
+    pub followers: Vec<User>,
+}
+
+/// Changes between two snapshots of [`SocialGraph`], returned from [`SocialGraph::diff`].
+#[derive(Debug, Clone, Default)]
+pub struct GraphDiff {
+    /// The users newly followed.
+    pub followed: Vec<User>,
+    /// The users no longer followed.
+    pub unfollowed: Vec<User>,
+    /// The new followers.
+    pub gained_followers: Vec<User>,
+    /// The users who are no longer followers.
+    pub lost_followers: Vec<User>,
+}
+
+impl GraphDiff {
+    /// Returns `true` if nothing is changed.
+    pub fn is_empty(&self) -> bool {
+        self.followed.is_empty()
+            && self.unfollowed.is_empty()
+            && self.gained_followers.is_empty()
+            && self.lost_followers.is_empty()
+    }
+}
+
+/// A followed user who has not posted recently, returned from
+/// [`SocialGraph::inactive_following`].
+#[derive(Debug, Clone)]
+pub struct InactiveUser {
+    /// The user.
+    pub user: User,
+    /// The time of the last note of the user, or `None` if no notes are found.
+    pub last_note_at: Option<DateTime<Utc>>,
+}
+
+impl SocialGraph {
+    /// Fetches the following and the followers of `user`.
+    pub async fn fetch<C: ClientExt>(
+        client: &C,
+        user: impl EntityRef<User>,
+    ) -> Result<SocialGraph, Error<C::Error>> {
+        let meta = client.meta().await?;
+        let origin =
+            Url::parse(&meta.uri).map_err(|e| io::Error::new(io::ErrorKind::InvalidData, e))?;
+        let user = client.get_user(user.entity_ref()).await?;
+        let fetched_at = Utc::now();
+        let following = client.following(&user).try_collect().await?;
+        let followers = client.followers(&user).try_collect().await?;
+        Ok(SocialGraph {
+            origin,
+            user,
+            fetched_at,
+            following,
+            followers,
+        })
+    }
+
+    /// Returns the users who follow each other with the user.
+    pub fn mutuals(&self) -> Vec<&User> {
+        let followers = ids(&self.followers);
+        self.following
+            .iter()
+            .filter(|user| followers.contains(&user.id))
+            .collect()
+    }
+
+    /// Returns the users the user is following but who do not follow back.
+    pub fn following_only(&self) -> Vec<&User> {
+        let followers = ids(&self.followers);
+        self.following
+            .iter()
+            .filter(|user| !followers.contains(&user.id))
+            .collect()
+    }
+
+    /// Returns the followers the user does not follow back.
+    pub fn followers_only(&self) -> Vec<&User> {
+        let following = ids(&self.following);
+        self.followers
+            .iter()
+            .filter(|user| !following.contains(&user.id))
+            .collect()
+    }
+
+    /// Returns the changes from this snapshot to the newer one.
+    pub fn diff(&self, newer: &SocialGraph) -> GraphDiff {
+        GraphDiff {
+            followed: difference(&newer.following, &self.following),
+            unfollowed: difference(&self.following, &newer.following),
+            gained_followers: difference(&newer.followers, &self.followers),
+            lost_followers: difference(&self.followers, &newer.followers),
+        }
+    }
+
+    /// Finds the followed users who have not posted since `since`.
+    ///
+    /// The last note of each user is looked up with `users/notes`, which only returns the notes
+    /// known to the instance of `client`, so remote users may look less active than they are.
+    pub async fn inactive_following<C: ClientExt>(
+        &self,
+        client: &C,
+        since: DateTime<Utc>,
+    ) -> Result<Vec<InactiveUser>, Error<C::Error>> {
+        let mut inactive = Vec::new();
+        for user in &self.following {
+            let last_note_at = client
+                .user_notes(user, ..)
+                .try_next()
+                .await?
+                .map(|note| note.created_at);
+            if !matches!(last_note_at, Some(at) if at >= since) {
+                inactive.push(InactiveUser {
+                    user: user.clone(),
+                    last_note_at,
+                });
+            }
+        }
+        Ok(inactive)
+    }
+}
+
+fn ids(users: &[User]) -> HashSet<Id<User>> {
+    users.iter().map(|user| user.id).collect()
+}
+
+/// Returns the users in `a` but not in `b`.
+fn difference(a: &[User], b: &[User]) -> Vec<User> {
+    let b = ids(b);
+    a.iter()
+        .filter(|user| !b.contains(&user.id))
+        .cloned()
+        .collect()
+}
+
+/// Options for [`sync_following`].
+#[derive(Debug, Clone)]
+pub struct SyncOptions {
+    /// Only looks up the users without following them.
+    pub dry_run: bool,
+    /// The minimum interval between the users to process.
+    pub interval: Duration,
+    /// The number of retries when the request is rejected with `RATE_LIMIT_EXCEEDED`.
+    pub max_retries: u32,
+    /// The interval before the first retry, which doubles on each retry.
+    pub initial_backoff: Duration,
+}
+
+impl Default for SyncOptions {
+    /// Processes a user per second, and retries 5 times from 30 seconds.
+    fn default() -> SyncOptions {
+        SyncOptions {
+            dry_run: false,
+            interval: Duration::from_secs(1),
+            max_retries: 5,
+            initial_backoff: Duration::from_secs(30),
+        }
+    }
+}
+
+/// The result of [`sync_following`].
+#[derive(Debug, Clone, Default)]
+pub struct SyncReport {
+    /// The newly followed users, or the users to be followed in the dry run.
+    pub followed: Vec<User>,
+    /// The users who are already followed or requested to follow.
+    pub already_following: Vec<User>,
+    /// The users who cannot be followed because of blocking.
+    pub blocked: Vec<User>,
+    /// The users in the source that are not found on the instance.
+    pub unresolved: Vec<User>,
+}
+
+/// Follows the users followed in `source` from the account logged in with `client`.
+///
+/// The users are looked up on the instance of `client` with
+/// `users/search-by-username-and-host`, so `source` may be taken on another instance.
+/// The users are processed one by one with [`interval`][`SyncOptions::interval`], and the
+/// requests rejected for the rate limit are retried after a while.
+pub async fn sync_following<C: ClientExt>(
+    client: &C,
+    source: &SocialGraph,
+    options: &SyncOptions,
+) -> Result<SyncReport, Error<C::Error>> {
+    let meta = client.meta().await?;
+    let target =
+        Url::parse(&meta.uri).map_err(|e| io::Error::new(io::ErrorKind::InvalidData, e))?;
+    let origin_host = source.origin.host_str().unwrap_or_default();
+    let target_host = target.host_str().unwrap_or_default();
+
+    let mut report = SyncReport::default();
+    for (i, user) in source.following.iter().enumerate() {
+        if i > 0 {
+            Delay::new(options.interval).await;
+        }
+        let host = user.host.as_deref().unwrap_or(origin_host);
+        let host = if host.eq_ignore_ascii_case(target_host) {
+            None
+        } else {
+            Some(host)
+        };
+        let resolved = retry(options, || search_user(client, &user.username, host)).await?;
+        let resolved = match resolved {
+            Some(resolved) => resolved,
+            None => {
+                report.unresolved.push(user.clone());
+                continue;
+            }
+        };
+        let relation = retry(options, || client.user_relation(&resolved)).await?;
+        if relation.is_following || relation.has_pending_follow_request_from_you {
+            report.already_following.push(resolved);
+        } else if relation.is_blocking || relation.is_blocked {
+            report.blocked.push(resolved);
+        } else if options.dry_run {
+            report.followed.push(resolved);
+        } else {
+            let followed = retry(options, || client.follow(&resolved)).await?;
+            report.followed.push(followed);
+        }
+    }
+    Ok(report)
+}
+
+/// Finds the user with exactly the same username and host.
+///
+/// The remote users unknown to the instance are not found by the search, so they are
+/// looked up with `users/show`, which fetches them from the remote instance.
+async fn search_user<C: Client + Sync>(
+    client: &C,
+    username: &str,
+    host: Option<&str>,
+) -> Result<Option<User>, Error<C::Error>> {
+    let mut request = endpoint::users::search_by_username_and_host::Request::builder()
+        .username(username)
+        .limit(100)
+        .build();
+    request.host = host.map(str::to_owned);
+    let users = client
+        .request(request)
+        .await
+        .map_err(Error::Client)?
+        .into_result()?;
+    let found = users.into_iter().find(|user| {
+        user.username.eq_ignore_ascii_case(username)
+            && match (&user.host, host) {
+                (Some(user_host), Some(host)) => user_host.eq_ignore_ascii_case(host),
+                (None, None) => true,
+                _ => false,
+            }
+    });
+    if found.is_some() || host.is_none() {
+        return Ok(found);
+    }
+    let request = endpoint::users::show::Request::WithUsername {
+        username: username.to_owned(),
+        host: host.map(str::to_owned),
+    };
+    match client.request(request).await.map_err(Error::Client)? {
+        ApiResult::Ok(user) => Ok(Some(user)),
+        // the user does not exist or its instance cannot be reached
+        ApiResult::Err { error }
+            if error.code == "NO_SUCH_USER" || error.code == "FAILED_TO_RESOLVE_REMOTE_USER" =>
+        {
+            Ok(None)
+        }
+        ApiResult::Err { error } => Err(Error::Api(error)),
+    }
+}
+
+/// Retries `f` while it is rejected for the rate limit.
+async fn retry<T, E, F, Fut>(options: &SyncOptions, mut f: F) -> Result<T, Error<E>>
+where
+    F: FnMut() -> Fut,
+    Fut: Future<Output = Result<T, Error<E>>>,
+{
+    let mut backoff = options.initial_backoff;
+    let mut retries = 0;
+    loop {
+        match f().await {
+            Err(Error::Api(error))
+                if error.code == "RATE_LIMIT_EXCEEDED" && retries < options.max_retries =>
+            {
+                Delay::new(backoff).await;
+                backoff = backoff.saturating_mul(2);
+                retries += 1;
+            }
+            result => return result,
+        }
+    }
+}
@@ -169,10 +169,31 @@ mod client;
 pub use client::{ClientExt, UploadFileClientExt};
 
 mod streaming;
-pub use streaming::StreamingClientExt;
+pub use streaming::{
+    BackfillError, BusEvent, EventBus, EventBusHandle, StreamingClientExt, Subscription,
+    TimelineSource,
+};
 
+pub mod analysis;
+pub mod antenna;
+pub mod archive;
+pub mod bot;
 pub mod builder;
+pub mod drive;
+#[cfg(all(feature = "12-102-0", feature = "emoji-pack"))]
+#[cfg_attr(docsrs, doc(cfg(all(feature = "12-102-0", feature = "emoji-pack"))))]
+pub mod emoji_pack;
+pub mod graph;
+pub mod mfm;
 pub mod pager;
+#[cfg(feature = "13-0-0")]
+#[cfg_attr(docsrs, doc(cfg(feature = "13-0-0")))]
+pub mod role;
+pub mod schedule;
+pub mod split;
 
 mod timeline;
 pub use timeline::{TimelineCursor, TimelineRange};
+
+#[cfg(test)]
+mod test_util;
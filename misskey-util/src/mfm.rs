@@ -0,0 +1,116 @@
+//! Parsing of MFM (Misskey Flavored Markdown).
+//!
+//! The text of notes, content warnings and profiles is written in MFM. [`parse`] parses it into
+//! a tree of [`Node`]s following [mfm-js](https://github.com/misskey-dev/mfm.js), the parser
+//! used by Misskey, and [`to_string`] turns the tree back into MFM.
+//! [`parse_simple`] only recognizes emojis and `<plain>`, as Misskey does for user names.
+//!
+//! The text can also be converted into other formats: HTML and Markdown with [`Renderer`], and
+//! plain text with [`to_plain_text`].
+//! To compose MFM text safely, use [`MfmBuilder`].
+//!
+//! # Examples
+//!
+//! ```
+//! use misskey_util::mfm::{self, Node};
+//!
+//! let nodes = mfm::parse("Hello, **@ai@misskey.io** :wave:");
+//! assert_eq!(
+//!     nodes,
+//!     vec![
+//!         Node::Text {
+//!             text: "Hello, ".to_string()
+//!         },
+//!         Node::Bold {
+//!             children: vec![Node::Mention {
+//!                 username: "ai".to_string(),
+//!                 host: Some("misskey.io".to_string()),
+//!                 acct: "@ai@misskey.io".to_string(),
+//!             }],
+//!         },
+//!         Node::Text {
+//!             text: " ".to_string()
+//!         },
+//!         Node::EmojiCode {
+//!             name: "wave".to_string()
+//!         },
+//!     ]
+//! );
+//! assert_eq!(mfm::to_string(&nodes), "Hello, **@ai@misskey.io** :wave:");
+//! ```
+
+mod builder;
+mod node;
+mod parser;
+mod render;
+mod serializer;
+
+pub use builder::{BuildMfmError, MfmBuilder};
+pub use node::Node;
+pub use render::Renderer;
+
+pub(crate) use render::note_emojis;
+
+/// Parses MFM text into nodes.
+///
+/// Every text can be parsed, and the parts that are not valid MFM syntax are
+/// returned as [`Node::Text`].
+pub fn parse(text: &str) -> Vec<Node> {
+    parser::parse(text)
+}
+
+/// Parses MFM text in the simple mode, used for user names.
+///
+/// Only [`Node::UnicodeEmoji`], [`Node::EmojiCode`] and [`Node::Plain`] are recognized and
+/// the rest is returned as [`Node::Text`].
+pub fn parse_simple(text: &str) -> Vec<Node> {
+    parser::parse_simple(text)
+}
+
+/// Serializes nodes into MFM text.
+///
+/// The result is rendered the same way as the nodes, and parsing it gives the same structure
+/// except that [`Node::Text`] may come back as [`Node::Plain`] with the same text.
+/// The text itself may also differ, e.g. `<b>text</b>` is serialized as `**text**`.
+///
+/// When a text would be read as MFM syntax where it is placed, the texts of the nodes are
+/// escaped with `<plain>`. For example, the nodes parsed from
+/// ``"`xhttps://>)<plain>\n</a```></plain>\u{fe0f}```"`` are a text, a plain and a text, but
+/// the serialized result is parsed back as three plains.
+/// The result is parsed once more to check this only if some text might need escaping.
+pub fn to_string(nodes: &[Node]) -> String {
+    let mut text = String::new();
+    serializer::write_nodes(&mut text, nodes);
+    if builder::any_needs_escape(nodes, false) && parse(&text) != nodes {
+        text.clear();
+        serializer::write_nodes(&mut text, &builder::escape_nodes(nodes, false));
+    }
+    text
+}
+
+/// Converts MFM text into plain text, removing the decorations.
+///
+/// Custom emojis, mentions and hashtags are left as they are written, and links are written
+/// as `label (url)`.
+///
+/// # Examples
+///
+/// ```
+/// use misskey_util::mfm;
+///
+/// let text = "$[x2 **Hello**], [Misskey](https://misskey-hub.net) :wave:";
+/// assert_eq!(
+///     mfm::to_plain_text(text),
+///     "Hello, Misskey (https://misskey-hub.net) :wave:"
+/// );
+/// ```
+pub fn to_plain_text(text: &str) -> String {
+    nodes_to_plain_text(&parse(text))
+}
+
+/// Converts MFM nodes into plain text, removing the decorations.
+pub fn nodes_to_plain_text(nodes: &[Node]) -> String {
+    let mut text = String::new();
+    render::write_plain_text(&mut text, nodes);
+    text
+}
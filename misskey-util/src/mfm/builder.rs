@@ -0,0 +1,379 @@
+use std::fmt::{self, Display};
+
+use crate::mfm::{self, Node};
+
+use misskey_api::model::{emoji::Emoji, note::Tag, user::User};
+use url::Url;
+
+/// Delimiters of MFM that can be paired with the ones in other nodes.
+const DELIMITERS: &[char] = &['*', '~', '`', '$', '[', ']', '<', '\\'];
+
+/// Error type for [`MfmBuilder::build`].
+#[derive(Debug, Clone)]
+pub struct BuildMfmError {
+    text: String,
+}
+
+impl BuildMfmError {
+    /// Returns the built text that does not represent the intended nodes.
+    pub fn text(&self) -> &str {
+        &self.text
+    }
+}
+
+impl std::error::Error for BuildMfmError {}
+
+impl Display for BuildMfmError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(
+            f,
+            "built MFM is parsed differently than intended: {}",
+            self.text
+        )
+    }
+}
+
+/// Builder for MFM text.
+///
+/// The text given to [`text`][`MfmBuilder::text`] is escaped not to be interpreted as MFM,
+/// so it is safe to include the input from users.
+/// [`build`][`MfmBuilder::build`] parses the result to check that it represents what is built,
+/// which fails in the cases that cannot be written in MFM, such as a mention right after
+/// an alphanumeric character or a mention in the label of a link.
+///
+/// # Examples
+///
+/// ```
+/// use misskey_util::mfm::MfmBuilder;
+///
+/// let mut mfm = MfmBuilder::new();
+/// mfm.text("Result of ")
+///     .inline_code("2 * 3")
+///     .text(": ")
+///     .bold(|b| b.text("6"))
+///     .text(" ")
+///     .function("tada", &[], |b| b.text("*yay*"));
+/// assert_eq!(
+///     mfm.build()?,
+///     "Result of `2 * 3`: **6** $[tada <plain>*yay*</plain>]"
+/// );
+/// # Ok::<(), misskey_util::mfm::BuildMfmError>(())
+/// ```
+///
+/// The result can be used as the text of a note.
+///
+/// ```no_run
+/// # use misskey_util::ClientExt;
+/// # #[tokio::main]
+/// # async fn main() -> anyhow::Result<()> {
+/// # let client = misskey_test::test_client().await?;
+/// use misskey_util::mfm::MfmBuilder;
+///
+/// let user = client.me().await?;
+/// let mut mfm = MfmBuilder::new();
+/// mfm.text("Hello, ").mention(&user).text("!");
+/// client.build_note().text(mfm.build()?).create().await?;
+/// # Ok(())
+/// # }
+/// ```
+#[derive(Debug, Clone, Default)]
+pub struct MfmBuilder {
+    nodes: Vec<Node>,
+}
+
+impl MfmBuilder {
+    /// Creates an empty builder.
+    pub fn new() -> Self {
+        MfmBuilder::default()
+    }
+
+    fn push(&mut self, node: Node) -> &mut Self {
+        self.nodes.push(node);
+        self
+    }
+
+    fn children(f: impl FnOnce(&mut MfmBuilder) -> &mut MfmBuilder) -> Vec<Node> {
+        let mut builder = MfmBuilder::new();
+        f(&mut builder);
+        builder.nodes
+    }
+
+    /// Appends the literal text, which is not interpreted as MFM.
+    pub fn text(&mut self, text: impl Into<String>) -> &mut Self {
+        self.push(Node::Text { text: text.into() })
+    }
+
+    /// Appends the nodes built by `f` in bold.
+    pub fn bold(&mut self, f: impl FnOnce(&mut MfmBuilder) -> &mut MfmBuilder) -> &mut Self {
+        let children = Self::children(f);
+        self.push(Node::Bold { children })
+    }
+
+    /// Appends the nodes built by `f` in italic.
+    pub fn italic(&mut self, f: impl FnOnce(&mut MfmBuilder) -> &mut MfmBuilder) -> &mut Self {
+        let children = Self::children(f);
+        self.push(Node::Italic { children })
+    }
+
+    /// Appends the nodes built by `f` with strikethrough.
+    pub fn strike(&mut self, f: impl FnOnce(&mut MfmBuilder) -> &mut MfmBuilder) -> &mut Self {
+        let children = Self::children(f);
+        self.push(Node::Strike { children })
+    }
+
+    /// Appends the nodes built by `f` in small text.
+    pub fn small(&mut self, f: impl FnOnce(&mut MfmBuilder) -> &mut MfmBuilder) -> &mut Self {
+        let children = Self::children(f);
+        self.push(Node::Small { children })
+    }
+
+    /// Appends the nodes built by `f` as centered lines.
+    pub fn center(&mut self, f: impl FnOnce(&mut MfmBuilder) -> &mut MfmBuilder) -> &mut Self {
+        let children = Self::children(f);
+        self.push(Node::Center { children })
+    }
+
+    /// Appends the nodes built by `f` as a quote.
+    pub fn quote(&mut self, f: impl FnOnce(&mut MfmBuilder) -> &mut MfmBuilder) -> &mut Self {
+        let children = Self::children(f);
+        self.push(Node::Quote { children })
+    }
+
+    /// Appends a mention of the user.
+    pub fn mention(&mut self, user: &User) -> &mut Self {
+        let acct = match &user.host {
+            Some(host) => format!("@{}@{}", user.username, host),
+            None => format!("@{}", user.username),
+        };
+        self.push(Node::Mention {
+            username: user.username.clone(),
+            host: user.host.clone(),
+            acct,
+        })
+    }
+
+    /// Appends the hashtag.
+    pub fn hashtag(&mut self, tag: &Tag) -> &mut Self {
+        let hashtag = tag.0.strip_prefix('#').unwrap_or(&tag.0).to_owned();
+        self.push(Node::Hashtag { hashtag })
+    }
+
+    /// Appends the custom emoji.
+    pub fn emoji(&mut self, emoji: &Emoji) -> &mut Self {
+        self.emoji_code(emoji.name.clone())
+    }
+
+    /// Appends the custom emoji with the given name, such as `blobcat` for `:blobcat:`.
+    pub fn emoji_code(&mut self, name: impl Into<String>) -> &mut Self {
+        self.push(Node::EmojiCode { name: name.into() })
+    }
+
+    /// Appends the URL.
+    pub fn url(&mut self, url: &Url) -> &mut Self {
+        self.push(Node::Url {
+            url: url.to_string(),
+            brackets: false,
+        })
+    }
+
+    /// Appends a link to `url` with the label built by `f`.
+    pub fn link(
+        &mut self,
+        url: &Url,
+        f: impl FnOnce(&mut MfmBuilder) -> &mut MfmBuilder,
+    ) -> &mut Self {
+        let children = Self::children(f);
+        self.push(Node::Link {
+            silent: false,
+            url: url.to_string(),
+            children,
+        })
+    }
+
+    /// Appends a link to `url` with the label built by `f`, without the preview of the URL.
+    pub fn silent_link(
+        &mut self,
+        url: &Url,
+        f: impl FnOnce(&mut MfmBuilder) -> &mut MfmBuilder,
+    ) -> &mut Self {
+        let children = Self::children(f);
+        self.push(Node::Link {
+            silent: true,
+            url: url.to_string(),
+            children,
+        })
+    }
+
+    /// Appends the inline code.
+    pub fn inline_code(&mut self, code: impl Into<String>) -> &mut Self {
+        self.push(Node::InlineCode { code: code.into() })
+    }
+
+    /// Appends the code block, optionally with the language for highlighting.
+    pub fn code_block(&mut self, code: impl Into<String>, lang: Option<&str>) -> &mut Self {
+        self.push(Node::BlockCode {
+            code: code.into(),
+            lang: lang.map(str::to_owned),
+        })
+    }
+
+    /// Appends the nodes built by `f` with the effect of the function, such as
+    /// `$[x2 ...]` or `$[spin.speed=1s ...]`.
+    ///
+    /// The arguments are given as the pairs of the key and the optional value.
+    pub fn function(
+        &mut self,
+        name: impl Into<String>,
+        args: &[(&str, Option<&str>)],
+        f: impl FnOnce(&mut MfmBuilder) -> &mut MfmBuilder,
+    ) -> &mut Self {
+        let children = Self::children(f);
+        self.push(Node::Fn {
+            name: name.into(),
+            args: args
+                .iter()
+                .map(|&(key, value)| (key.to_owned(), value.map(str::to_owned)))
+                .collect(),
+            children,
+        })
+    }
+
+    /// Appends the nodes.
+    pub fn nodes(&mut self, nodes: impl IntoIterator<Item = Node>) -> &mut Self {
+        self.nodes.extend(nodes);
+        self
+    }
+
+    /// Builds the MFM text, checking that it is parsed into the built nodes.
+    pub fn build(&self) -> Result<String, BuildMfmError> {
+        let nodes = normalize(&self.nodes);
+        let text = mfm::to_string(&escape_nodes(&nodes, false));
+        if normalize(&mfm::parse(&text)) != nodes {
+            return Err(BuildMfmError { text });
+        }
+        Ok(text)
+    }
+}
+
+/// Replaces the texts that might be interpreted as MFM with `<plain>`.
+///
+/// `nested` is `true` for the children of another node, which are followed by its closing
+/// delimiter.
+pub(super) fn escape_nodes(nodes: &[Node], nested: bool) -> Vec<Node> {
+    let mut escaped = Vec::new();
+    for (i, node) in nodes.iter().enumerate() {
+        let mut node = node.clone();
+        if let Node::Text { text } = &node {
+            let prev = i.checked_sub(1).and_then(|i| nodes.get(i));
+            let next = nodes.get(i + 1);
+            if needs_escape(text, prev, next, nested && next.is_none()) {
+                escaped.extend(plain_nodes(text));
+                continue;
+            }
+        }
+        if let Some(children) = node.children_mut() {
+            *children = escape_nodes(children, true);
+        }
+        escaped.push(node);
+    }
+    escaped
+}
+
+/// Returns `true` if any of the texts in `nodes` would be replaced by [`escape_nodes`].
+pub(super) fn any_needs_escape(nodes: &[Node], nested: bool) -> bool {
+    nodes.iter().enumerate().any(|(i, node)| match node {
+        Node::Text { text } => {
+            let prev = i.checked_sub(1).and_then(|i| nodes.get(i));
+            let next = nodes.get(i + 1);
+            needs_escape(text, prev, next, nested && next.is_none())
+        }
+        node => any_needs_escape(node.children(), true),
+    })
+}
+
+fn needs_escape(text: &str, prev: Option<&Node>, next: Option<&Node>, closing: bool) -> bool {
+    if text.contains(DELIMITERS)
+        // a quote if the text is at the beginning of a line
+        || text.starts_with('>')
+        || normalize(&mfm::parse(text)) != normalize_text(text)
+    {
+        return true;
+    }
+    // the text would be a part of the node before it, or prevent it from being recognized
+    let continues_prev = match prev {
+        Some(
+            Node::Url {
+                brackets: false, ..
+            }
+            | Node::Mention { .. }
+            | Node::Hashtag { .. },
+        ) => !text.starts_with(char::is_whitespace),
+        Some(Node::EmojiCode { .. }) => text.starts_with(|c: char| c.is_ascii_alphanumeric()),
+        _ => false,
+    };
+    // the node after the text would not be recognized, or would be a part of the text
+    let breaks_next = match next {
+        Some(Node::Mention { .. } | Node::Hashtag { .. } | Node::EmojiCode { .. }) => {
+            text.ends_with(|c: char| c.is_ascii_alphanumeric() || "#@:_".contains(c))
+        }
+        Some(Node::Text { .. }) => false,
+        Some(_) => text.ends_with(['#', '@', '?', ':']),
+        None => closing && text.ends_with(['#', '@', '?', ':']),
+    };
+    continues_prev || breaks_next
+}
+
+/// Splits the text into `<plain>` nodes, avoiding `</plain>` in them.
+fn plain_nodes(text: &str) -> Vec<Node> {
+    let mut nodes = Vec::new();
+    let mut rest = text;
+    while let Some(i) = rest.find("</plain>") {
+        nodes.push(Node::Plain {
+            text: rest[..i + 1].to_owned(),
+        });
+        rest = &rest[i + 1..];
+    }
+    if !rest.is_empty() {
+        nodes.push(Node::Plain {
+            text: rest.to_owned(),
+        });
+    }
+    nodes
+}
+
+fn normalize_text(text: &str) -> Vec<Node> {
+    if text.is_empty() {
+        Vec::new()
+    } else {
+        vec![Node::Text {
+            text: text.to_owned(),
+        }]
+    }
+}
+
+/// Turns the nodes that appear as they are written into texts, and merges adjacent texts.
+pub(super) fn normalize(nodes: &[Node]) -> Vec<Node> {
+    let mut normalized: Vec<Node> = Vec::new();
+    for node in nodes {
+        let text = match node {
+            Node::Text { text } | Node::Plain { text } => text,
+            Node::UnicodeEmoji { emoji } => emoji,
+            node => {
+                let mut node = node.clone();
+                if let Some(children) = node.children_mut() {
+                    *children = normalize(children);
+                }
+                normalized.push(node);
+                continue;
+            }
+        };
+        if text.is_empty() {
+            continue;
+        }
+        match normalized.last_mut() {
+            Some(Node::Text { text: last }) => last.push_str(text),
+            _ => normalized.push(Node::Text { text: text.clone() }),
+        }
+    }
+    normalized
+}
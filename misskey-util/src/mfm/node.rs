@@ -0,0 +1,185 @@
+/// A node of MFM.
+///
+/// The variants correspond to the node types of [mfm-js](https://github.com/misskey-dev/mfm.js).
+/// Equivalent syntaxes are not distinguished, e.g. both `**text**` and `<b>text</b>` are
+/// parsed into [`Node::Bold`].
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum Node {
+    /// Quote block, `> text`.
+    Quote {
+        /// The quoted nodes.
+        children: Vec<Node>,
+    },
+    /// Search box, `query Search`.
+    Search {
+        /// The words to search for.
+        query: String,
+        /// The whole line including the `Search` part.
+        content: String,
+    },
+    /// Code block, surrounded by ` ``` `.
+    BlockCode {
+        /// The code.
+        code: String,
+        /// The language written after the opening ` ``` `.
+        lang: Option<String>,
+    },
+    /// Math block, `\[formula\]`.
+    MathBlock {
+        /// The formula in LaTeX.
+        formula: String,
+    },
+    /// `<center>children</center>`.
+    Center {
+        /// The centered nodes.
+        children: Vec<Node>,
+    },
+    /// Unicode emoji.
+    UnicodeEmoji {
+        /// The emoji itself.
+        emoji: String,
+    },
+    /// Custom emoji, `:name:`.
+    EmojiCode {
+        /// The name of the emoji, without colons.
+        name: String,
+    },
+    /// `**children**`, `__text__` or `<b>children</b>`.
+    Bold {
+        /// The nodes in bold.
+        children: Vec<Node>,
+    },
+    /// `<small>children</small>`.
+    Small {
+        /// The nodes in small text.
+        children: Vec<Node>,
+    },
+    /// `*text*`, `_text_` or `<i>children</i>`.
+    Italic {
+        /// The nodes in italic.
+        children: Vec<Node>,
+    },
+    /// `~~children~~` or `<s>children</s>`.
+    Strike {
+        /// The struck-through nodes.
+        children: Vec<Node>,
+    },
+    /// Inline code, surrounded by `` ` ``.
+    InlineCode {
+        /// The code.
+        code: String,
+    },
+    /// Inline math, `\(formula\)`.
+    MathInline {
+        /// The formula in LaTeX.
+        formula: String,
+    },
+    /// Mention, `@username` or `@username@host`.
+    Mention {
+        /// The username of the mentioned user.
+        username: String,
+        /// The host of the mentioned user, or `None` if it is omitted.
+        host: Option<String>,
+        /// The whole mention, such as `@username@host`.
+        acct: String,
+    },
+    /// Hashtag, `#hashtag`.
+    Hashtag {
+        /// The name of the hashtag, without `#`.
+        hashtag: String,
+    },
+    /// URL, optionally surrounded by `<>`.
+    Url {
+        /// The URL.
+        url: String,
+        /// Whether the URL is surrounded by `<>`.
+        brackets: bool,
+    },
+    /// Link, `[children](url)` or `?[children](url)` if `silent`.
+    Link {
+        /// Whether the link is written as `?[...]`, which does not show the preview.
+        silent: bool,
+        /// The URL to link to.
+        url: String,
+        /// The label of the link.
+        children: Vec<Node>,
+    },
+    /// Function, `$[name.args children]`.
+    ///
+    /// `***children***` is parsed into the `tada` function.
+    Fn {
+        /// The name of the function, such as `x2` or `spin`.
+        name: String,
+        /// Arguments in the order of appearance, with the value if written as `key=value`.
+        args: Vec<(String, Option<String>)>,
+        /// The nodes the function applies to.
+        children: Vec<Node>,
+    },
+    /// `<plain>text</plain>`, in which MFM syntax is not interpreted.
+    Plain {
+        /// The text as it is.
+        text: String,
+    },
+    /// Plain text.
+    Text {
+        /// The text.
+        text: String,
+    },
+}
+
+impl Node {
+    /// Returns `true` if the node is a block, which occupies whole lines.
+    pub fn is_block(&self) -> bool {
+        matches!(
+            self,
+            Node::Quote { .. }
+                | Node::Search { .. }
+                | Node::BlockCode { .. }
+                | Node::MathBlock { .. }
+                | Node::Center { .. }
+        )
+    }
+
+    /// Returns the child nodes, or an empty slice if the node has no children.
+    pub fn children(&self) -> &[Node] {
+        match self {
+            Node::Quote { children }
+            | Node::Center { children }
+            | Node::Bold { children }
+            | Node::Small { children }
+            | Node::Italic { children }
+            | Node::Strike { children }
+            | Node::Link { children, .. }
+            | Node::Fn { children, .. } => children,
+            _ => &[],
+        }
+    }
+
+    pub(crate) fn children_mut(&mut self) -> Option<&mut Vec<Node>> {
+        match self {
+            Node::Quote { children }
+            | Node::Center { children }
+            | Node::Bold { children }
+            | Node::Small { children }
+            | Node::Italic { children }
+            | Node::Strike { children }
+            | Node::Link { children, .. }
+            | Node::Fn { children, .. } => Some(children),
+            _ => None,
+        }
+    }
+
+    /// Returns the value of the argument of [`Node::Fn`], or `None` if it is not a function or
+    /// the argument is not given.
+    ///
+    /// The value of the argument without `=` is `Some("")`.
+    pub fn fn_arg(&self, key: &str) -> Option<&str> {
+        match self {
+            Node::Fn { args, .. } => args
+                .iter()
+                .find(|(k, _)| k == key)
+                .map(|(_, v)| v.as_deref().unwrap_or("")),
+            _ => None,
+        }
+    }
+}
@@ -0,0 +1,872 @@
+use std::collections::HashMap;
+use std::rc::Rc;
+
+use crate::mfm::Node;
+
+use unicode_segmentation::UnicodeSegmentation;
+
+/// The maximum depth of nested nodes, after which the syntax is left as text (same as mfm-js).
+const NEST_LIMIT: usize = 20;
+
+pub(super) const SEARCH_KEYWORDS: &[&str] = &["[検索]", "[Search]", "検索", "Search", "search"];
+
+pub(crate) fn parse(text: &str) -> Vec<Node> {
+    let mut parser = Parser::new(text, 0);
+    parser.parse_until(0, true, None, false).unwrap().0
+}
+
+pub(crate) fn parse_simple(text: &str) -> Vec<Node> {
+    let parser = Parser::new(text, 0);
+    let mut nodes = Vec::new();
+    let mut pos = 0;
+    while pos < text.len() {
+        let (node, next) = parser
+            .unicode_emoji(pos)
+            .or_else(|| parser.emoji_code(pos))
+            .or_else(|| parser.plain_tag(pos))
+            .unwrap_or_else(|| parser.text(pos));
+        push_node(&mut nodes, node);
+        pos = next;
+    }
+    nodes
+}
+
+/// Pushes the node, merging adjacent texts.
+fn push_node(nodes: &mut Vec<Node>, node: Node) {
+    if let Node::Text { text } = &node {
+        if let Some(Node::Text { text: last }) = nodes.last_mut() {
+            last.push_str(text);
+            return;
+        }
+    }
+    nodes.push(node);
+}
+
+fn is_alnum(c: char) -> bool {
+    c.is_ascii_alphanumeric()
+}
+
+fn is_space(c: char) -> bool {
+    matches!(c, ' ' | '\u{3000}' | '\t')
+}
+
+/// Returns the length of the prefix of `s` that consists of the characters satisfying `f`.
+fn prefix_len(s: &str, f: impl Fn(char) -> bool) -> usize {
+    s.find(|c| !f(c)).unwrap_or(s.len())
+}
+
+/// Returns the length of the prefix of `s` that consists of the characters satisfying `f`,
+/// where brackets are allowed if they are balanced.
+fn balanced_prefix_len(s: &str, f: impl Fn(char) -> bool) -> usize {
+    const PAIRS: &[(char, char)] = &[('(', ')'), ('[', ']'), ('「', '」'), ('（', '）')];
+
+    // (position, closing bracket) of the unclosed brackets
+    let mut stack: Vec<(usize, char)> = Vec::new();
+    let mut end = s.len();
+    for (i, c) in s.char_indices() {
+        if let Some(&(_, close)) = PAIRS.iter().find(|&&(open, _)| open == c) {
+            stack.push((i, close));
+        } else if PAIRS.iter().any(|&(_, close)| close == c) {
+            if stack.last().map(|&(_, close)| close) == Some(c) {
+                stack.pop();
+            } else {
+                end = i;
+                break;
+            }
+        } else if !f(c) {
+            end = i;
+            break;
+        }
+    }
+    match stack.first() {
+        Some(&(i, _)) => i,
+        None => end,
+    }
+}
+
+fn is_emoji_grapheme(grapheme: &str) -> bool {
+    let mut chars = grapheme.chars();
+    let first = match chars.next() {
+        Some(c) => c,
+        None => return false,
+    };
+    let presentation = grapheme.contains('\u{fe0f}');
+    match first as u32 {
+        // keycaps such as 1️⃣
+        _ if grapheme.contains('\u{20e3}') => true,
+        0x1f000..=0x1faff => true,
+        0x2600..=0x27bf | 0x231a..=0x23ff | 0x2b05..=0x2b55 => true,
+        // symbols that are emojis only with the variation selector, such as ©️
+        0x00a9
+        | 0x00ae
+        | 0x203c
+        | 0x2049
+        | 0x2122
+        | 0x2139
+        | 0x2194..=0x21aa
+        | 0x24c2
+        | 0x25aa..=0x25fe
+        | 0x2934
+        | 0x2935
+        | 0x3030
+        | 0x303d
+        | 0x3297
+        | 0x3299 => presentation,
+        _ => false,
+    }
+}
+
+fn url_scheme_len(s: &str) -> Option<usize> {
+    if s.starts_with("https://") {
+        Some(8)
+    } else if s.starts_with("http://") {
+        Some(7)
+    } else {
+        None
+    }
+}
+
+/// A position of [`Parser::parse_until`] looking for `close`, which leads to the same result
+/// wherever the parsing started from.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+struct State {
+    pos: usize,
+    close: &'static str,
+    full: bool,
+    single_line: bool,
+    depth: usize,
+    in_link_label: bool,
+}
+
+/// What [`Parser::parse_until`] resulted in from a [`State`].
+enum Outcome {
+    /// `close` is not found.
+    Failed,
+    /// `close` is found at `end`, after the nodes parsed from the state, which are the ones
+    /// from `index` in `nodes` and the part of the text at `index - 1` after `text_len`.
+    Found {
+        nodes: Rc<Vec<Node>>,
+        index: usize,
+        text_len: usize,
+        end: usize,
+    },
+}
+
+/// Returns the length of the text at the end of `nodes`.
+fn last_text_len(nodes: &[Node]) -> usize {
+    match nodes.last() {
+        Some(Node::Text { text }) => text.len(),
+        _ => 0,
+    }
+}
+
+struct Parser<'a> {
+    src: &'a str,
+    depth: usize,
+    in_link_label: bool,
+    /// outcomes from the states passed through, not to parse the same text again and again
+    outcomes: HashMap<State, Outcome>,
+}
+
+impl<'a> Parser<'a> {
+    fn new(src: &'a str, depth: usize) -> Self {
+        Parser {
+            src,
+            depth,
+            in_link_label: false,
+            outcomes: HashMap::new(),
+        }
+    }
+
+    fn rest(&self, pos: usize) -> &'a str {
+        &self.src[pos..]
+    }
+
+    fn prev_char(&self, pos: usize) -> Option<char> {
+        self.src[..pos].chars().next_back()
+    }
+
+    fn after_alnum(&self, pos: usize) -> bool {
+        matches!(self.prev_char(pos), Some(c) if is_alnum(c))
+    }
+
+    fn is_line_begin(&self, pos: usize) -> bool {
+        pos == 0 || self.prev_char(pos) == Some('\n')
+    }
+
+    fn is_line_end(&self, pos: usize) -> bool {
+        pos == self.src.len() || self.rest(pos).starts_with('\n')
+    }
+
+    /// Runs `f` one level deeper, or returns `None` if the nesting is too deep.
+    fn nested<T>(&mut self, f: impl FnOnce(&mut Self) -> Option<T>) -> Option<T> {
+        if self.depth + 1 >= NEST_LIMIT {
+            return None;
+        }
+        self.depth += 1;
+        let result = f(self);
+        self.depth -= 1;
+        result
+    }
+
+    /// Parses nodes until `close`, which is consumed, or until the end if `close` is `None`.
+    ///
+    /// Returns `None` if `close` is not found, or if the children are empty.
+    /// The outcomes from the positions passed through are remembered, so that unclosed nested
+    /// syntax does not take exponential time to be tried in every combination.
+    fn parse_until(
+        &mut self,
+        mut pos: usize,
+        full: bool,
+        close: Option<&'static str>,
+        single_line: bool,
+    ) -> Option<(Vec<Node>, usize)> {
+        let mut nodes = Vec::new();
+        // the states passed through, with the number of nodes and the length of the last text
+        let mut visited = Vec::new();
+        let end = loop {
+            if let Some(close) = close {
+                let state = State {
+                    pos,
+                    close,
+                    full,
+                    single_line,
+                    depth: self.depth,
+                    in_link_label: self.in_link_label,
+                };
+                match self.outcomes.get(&state) {
+                    Some(Outcome::Failed) => break None,
+                    Some(Outcome::Found {
+                        nodes: found,
+                        index,
+                        text_len,
+                        end,
+                    }) => {
+                        if let Some(Node::Text { text }) = index.checked_sub(1).map(|i| &found[i]) {
+                            if text.len() > *text_len {
+                                push_node(
+                                    &mut nodes,
+                                    Node::Text {
+                                        text: text[*text_len..].to_owned(),
+                                    },
+                                );
+                            }
+                        }
+                        for node in &found[*index..] {
+                            push_node(&mut nodes, node.clone());
+                        }
+                        break Some(*end);
+                    }
+                    None => visited.push((state, nodes.len(), last_text_len(&nodes))),
+                }
+            }
+
+            match close {
+                Some(close) if self.rest(pos).starts_with(close) => break Some(pos + close.len()),
+                Some(_) if pos == self.src.len() => break None,
+                None if pos == self.src.len() => return Some((nodes, pos)),
+                _ => {}
+            }
+            if single_line && self.rest(pos).starts_with('\n') {
+                break None;
+            }
+            let (node, next) = self.parse_one(pos, full);
+            push_node(&mut nodes, node);
+            pos = next;
+        };
+
+        match end {
+            Some(end) => {
+                let found = Rc::new(nodes.clone());
+                for (state, index, text_len) in visited {
+                    let outcome = Outcome::Found {
+                        nodes: Rc::clone(&found),
+                        index,
+                        text_len,
+                        end,
+                    };
+                    self.outcomes.insert(state, outcome);
+                }
+                // this depends on where the parsing started, so it is checked after the above
+                if nodes.is_empty() {
+                    return None;
+                }
+                Some((nodes, end))
+            }
+            None => {
+                for (state, _, _) in visited {
+                    self.outcomes.insert(state, Outcome::Failed);
+                }
+                None
+            }
+        }
+    }
+
+    fn parse_one(&mut self, pos: usize, full: bool) -> (Node, usize) {
+        if full {
+            // blocks also consume the line break before them
+            let block_pos = if self.rest(pos).starts_with('\n') {
+                Some(pos + 1)
+            } else if self.is_line_begin(pos) {
+                Some(pos)
+            } else {
+                None
+            };
+            if let Some(result) = block_pos.and_then(|p| self.block(p)) {
+                return result;
+            }
+        }
+        if let Some(result) = self.inline(pos) {
+            return result;
+        }
+        if full {
+            if let Some(result) = self.search(pos) {
+                return result;
+            }
+            if self.rest(pos).starts_with('\n') {
+                if let Some(result) = self.search(pos + 1) {
+                    return result;
+                }
+            }
+        }
+        self.text(pos)
+    }
+
+    /// Finishes a block, consuming the line break after it.
+    fn end_block(&self, node: Node, pos: usize) -> (Node, usize) {
+        if self.rest(pos).starts_with('\n') {
+            (node, pos + 1)
+        } else {
+            (node, pos)
+        }
+    }
+
+    fn block(&mut self, pos: usize) -> Option<(Node, usize)> {
+        if !self.is_line_begin(pos) {
+            return None;
+        }
+        self.center(pos)
+            .or_else(|| self.block_code(pos))
+            .or_else(|| self.quote(pos))
+            .or_else(|| self.math_block(pos))
+    }
+
+    fn inline(&mut self, pos: usize) -> Option<(Node, usize)> {
+        self.unicode_emoji(pos)
+            .or_else(|| {
+                self.tag(pos, "<small>", "</small>", |children| Node::Small {
+                    children,
+                })
+            })
+            .or_else(|| self.plain_tag(pos))
+            .or_else(|| self.tag(pos, "<b>", "</b>", |children| Node::Bold { children }))
+            .or_else(|| self.tag(pos, "<i>", "</i>", |children| Node::Italic { children }))
+            .or_else(|| self.tag(pos, "<s>", "</s>", |children| Node::Strike { children }))
+            .or_else(|| self.url_alt(pos))
+            .or_else(|| self.big(pos))
+            .or_else(|| self.bold_asta(pos))
+            .or_else(|| self.italic_asta(pos))
+            .or_else(|| self.bold_under(pos))
+            .or_else(|| self.italic_under(pos))
+            .or_else(|| self.inline_code(pos))
+            .or_else(|| self.math_inline(pos))
+            .or_else(|| self.strike_wave(pos))
+            .or_else(|| self.function(pos))
+            .or_else(|| self.mention(pos))
+            .or_else(|| self.hashtag(pos))
+            .or_else(|| self.emoji_code(pos))
+            .or_else(|| self.link(pos))
+            .or_else(|| self.url(pos))
+    }
+
+    fn text(&self, pos: usize) -> (Node, usize) {
+        let len = self.rest(pos).chars().next().map_or(0, char::len_utf8);
+        let text = self.src[pos..pos + len].to_owned();
+        (Node::Text { text }, pos + len)
+    }
+
+    fn quote(&mut self, pos: usize) -> Option<(Node, usize)> {
+        let mut lines = Vec::new();
+        let mut end = pos;
+        let mut line_start = pos;
+        while let Some(line) = self.rest(line_start).strip_prefix('>') {
+            let line = line.split('\n').next().unwrap();
+            lines.push(line.strip_prefix(' ').unwrap_or(line));
+            end = line_start + 1 + line.len();
+            if !self.rest(end).starts_with('\n') {
+                break;
+            }
+            line_start = end + 1;
+        }
+        let content = lines.join("\n");
+        if content.trim().is_empty() {
+            return None;
+        }
+
+        let depth = self.depth;
+        let children = self.nested(|_| {
+            let mut parser = Parser::new(&content, depth + 1);
+            parser
+                .parse_until(0, true, None, false)
+                .map(|(nodes, _)| nodes)
+        })?;
+        Some(self.end_block(Node::Quote { children }, end))
+    }
+
+    fn search(&self, pos: usize) -> Option<(Node, usize)> {
+        if !self.is_line_begin(pos) {
+            return None;
+        }
+        let line = self.rest(pos).split('\n').next().unwrap();
+        let query = SEARCH_KEYWORDS.iter().find_map(|keyword| {
+            let query = line.strip_suffix(keyword)?;
+            let query = query
+                .strip_suffix(' ')
+                .or_else(|| query.strip_suffix('\u{3000}'))?;
+            Some(query).filter(|query| !query.is_empty())
+        })?;
+        let node = Node::Search {
+            query: query.to_owned(),
+            content: line.to_owned(),
+        };
+        Some(self.end_block(node, pos + line.len()))
+    }
+
+    fn block_code(&self, pos: usize) -> Option<(Node, usize)> {
+        let rest = self.rest(pos).strip_prefix("```")?;
+        let lang_len = rest.find('\n')?;
+        let lang = rest[..lang_len].trim();
+        if lang.contains('`') {
+            return None;
+        }
+
+        // the code starts after the line break and ends before the line break of "```"
+        let code_start = pos + 3 + lang_len;
+        let mut search_from = code_start;
+        loop {
+            let close = search_from + self.rest(search_from).find("\n```")?;
+            let end = close + 4;
+            if self.is_line_end(end) {
+                let code = if close > code_start {
+                    self.src[code_start + 1..close].to_owned()
+                } else {
+                    String::new()
+                };
+                let lang = Some(lang.to_owned()).filter(|lang| !lang.is_empty());
+                return Some(self.end_block(Node::BlockCode { code, lang }, end));
+            }
+            search_from = close + 1;
+        }
+    }
+
+    fn math_block(&self, pos: usize) -> Option<(Node, usize)> {
+        self.rest(pos).strip_prefix("\\[")?;
+        let start = pos + 2;
+        let mut search_from = start;
+        loop {
+            let close = search_from + self.rest(search_from).find("\\]")?;
+            let end = close + 2;
+            if self.is_line_end(end) {
+                let formula = &self.src[start..close];
+                let formula = formula.strip_prefix('\n').unwrap_or(formula);
+                let formula = formula.strip_suffix('\n').unwrap_or(formula);
+                if formula.is_empty() {
+                    return None;
+                }
+                let node = Node::MathBlock {
+                    formula: formula.to_owned(),
+                };
+                return Some(self.end_block(node, end));
+            }
+            search_from = close + 1;
+        }
+    }
+
+    fn center(&mut self, pos: usize) -> Option<(Node, usize)> {
+        let mut start = pos + self.rest(pos).strip_prefix("<center>").map(|_| 8)?;
+        if self.rest(start).starts_with('\n') {
+            start += 1;
+        }
+        let (mut children, end) =
+            self.nested(|p| p.parse_until(start, false, Some("</center>"), false))?;
+        if !self.is_line_end(end) {
+            return None;
+        }
+        strip_last_line_break(&mut children);
+        if children.is_empty() {
+            return None;
+        }
+        Some(self.end_block(Node::Center { children }, end))
+    }
+
+    fn tag(
+        &mut self,
+        pos: usize,
+        open: &str,
+        close: &'static str,
+        node: impl FnOnce(Vec<Node>) -> Node,
+    ) -> Option<(Node, usize)> {
+        if !self.rest(pos).starts_with(open) {
+            return None;
+        }
+        let (children, end) =
+            self.nested(|p| p.parse_until(pos + open.len(), false, Some(close), false))?;
+        Some((node(children), end))
+    }
+
+    fn plain_tag(&self, pos: usize) -> Option<(Node, usize)> {
+        let rest = self.rest(pos).strip_prefix("<plain>")?;
+        let len = rest.find("</plain>")?;
+        let text = &rest[..len];
+        let text = text.strip_prefix('\n').unwrap_or(text);
+        let text = text.strip_suffix('\n').unwrap_or(text);
+        if text.is_empty() {
+            return None;
+        }
+        let node = Node::Plain {
+            text: text.to_owned(),
+        };
+        Some((node, pos + 7 + len + 8))
+    }
+
+    fn big(&mut self, pos: usize) -> Option<(Node, usize)> {
+        if !self.rest(pos).starts_with("***") {
+            return None;
+        }
+        let (children, end) = self.nested(|p| p.parse_until(pos + 3, false, Some("***"), false))?;
+        let node = Node::Fn {
+            name: "tada".to_owned(),
+            args: Vec::new(),
+            children,
+        };
+        Some((node, end))
+    }
+
+    fn bold_asta(&mut self, pos: usize) -> Option<(Node, usize)> {
+        if !self.rest(pos).starts_with("**") {
+            return None;
+        }
+        let (children, end) = self.nested(|p| p.parse_until(pos + 2, false, Some("**"), false))?;
+        Some((Node::Bold { children }, end))
+    }
+
+    /// Parses text that consists of alphanumerics and spaces, surrounded by `mark`.
+    fn simple_emphasis(&self, pos: usize, mark: &str) -> Option<(String, usize)> {
+        let rest = self.rest(pos).strip_prefix(mark)?;
+        let len = prefix_len(rest, |c| is_alnum(c) || is_space(c));
+        if len == 0 || !rest[len..].starts_with(mark) {
+            return None;
+        }
+        Some((rest[..len].to_owned(), pos + mark.len() * 2 + len))
+    }
+
+    fn italic_asta(&self, pos: usize) -> Option<(Node, usize)> {
+        if self.after_alnum(pos) {
+            return None;
+        }
+        let (text, end) = self.simple_emphasis(pos, "*")?;
+        let children = vec![Node::Text { text }];
+        Some((Node::Italic { children }, end))
+    }
+
+    fn bold_under(&self, pos: usize) -> Option<(Node, usize)> {
+        let (text, end) = self.simple_emphasis(pos, "__")?;
+        let children = vec![Node::Text { text }];
+        Some((Node::Bold { children }, end))
+    }
+
+    fn italic_under(&self, pos: usize) -> Option<(Node, usize)> {
+        if self.after_alnum(pos) {
+            return None;
+        }
+        let (text, end) = self.simple_emphasis(pos, "_")?;
+        let children = vec![Node::Text { text }];
+        Some((Node::Italic { children }, end))
+    }
+
+    fn inline_code(&self, pos: usize) -> Option<(Node, usize)> {
+        let rest = self.rest(pos).strip_prefix('`')?;
+        let len = prefix_len(rest, |c| !matches!(c, '`' | '´' | '\n'));
+        if len == 0 || !rest[len..].starts_with('`') {
+            return None;
+        }
+        let node = Node::InlineCode {
+            code: rest[..len].to_owned(),
+        };
+        Some((node, pos + len + 2))
+    }
+
+    fn math_inline(&self, pos: usize) -> Option<(Node, usize)> {
+        let rest = self.rest(pos).strip_prefix("\\(")?;
+        let len = rest.find("\\)")?;
+        let formula = &rest[..len];
+        if formula.is_empty() || formula.contains('\n') {
+            return None;
+        }
+        let node = Node::MathInline {
+            formula: formula.to_owned(),
+        };
+        Some((node, pos + len + 4))
+    }
+
+    fn strike_wave(&mut self, pos: usize) -> Option<(Node, usize)> {
+        if !self.rest(pos).starts_with("~~") {
+            return None;
+        }
+        let (children, end) = self.nested(|p| p.parse_until(pos + 2, false, Some("~~"), true))?;
+        Some((Node::Strike { children }, end))
+    }
+
+    fn function(&mut self, pos: usize) -> Option<(Node, usize)> {
+        let rest = self.rest(pos).strip_prefix("$[")?;
+        let name_len = prefix_len(rest, |c| is_alnum(c) || c == '_');
+        if name_len == 0 {
+            return None;
+        }
+        let name = rest[..name_len].to_owned();
+
+        let mut args = Vec::new();
+        let mut len = name_len;
+        if let Some(args_str) = rest[len..].strip_prefix('.') {
+            let args_len = prefix_len(args_str, |c| {
+                is_alnum(c) || matches!(c, '_' | '.' | '-' | '=' | ',')
+            });
+            for arg in args_str[..args_len].split(',') {
+                let (key, value) = match arg.split_once('=') {
+                    Some((key, value)) => (key, Some(value.to_owned())),
+                    None => (arg, None),
+                };
+                if key.is_empty() || !key.chars().all(|c| is_alnum(c) || c == '_') {
+                    return None;
+                }
+                args.push((key.to_owned(), value));
+            }
+            len += 1 + args_len;
+        }
+        if !rest[len..].starts_with(' ') {
+            return None;
+        }
+
+        let start = pos + 2 + len + 1;
+        let (children, end) = self.nested(|p| p.parse_until(start, false, Some("]"), false))?;
+        Some((
+            Node::Fn {
+                name,
+                args,
+                children,
+            },
+            end,
+        ))
+    }
+
+    fn mention(&self, pos: usize) -> Option<(Node, usize)> {
+        if self.in_link_label || self.after_alnum(pos) {
+            return None;
+        }
+        let rest = self.rest(pos).strip_prefix('@')?;
+        let username = &rest[..prefix_len(rest, |c| is_alnum(c) || matches!(c, '_' | '-'))];
+        let username = username.trim_end_matches('-');
+        if username.is_empty() || username.starts_with('-') {
+            return None;
+        }
+
+        let mut len = 1 + username.len();
+        let host = rest[username.len()..].strip_prefix('@').and_then(|host| {
+            let host = &host[..prefix_len(host, |c| is_alnum(c) || matches!(c, '_' | '.' | '-'))];
+            let host = host.trim_end_matches(['.', '-']);
+            Some(host).filter(|host| !host.is_empty() && !host.starts_with(['.', '-']))
+        });
+        if let Some(host) = host {
+            len += 1 + host.len();
+        }
+
+        let node = Node::Mention {
+            username: username.to_owned(),
+            host: host.map(str::to_owned),
+            acct: self.src[pos..pos + len].to_owned(),
+        };
+        Some((node, pos + len))
+    }
+
+    fn hashtag(&self, pos: usize) -> Option<(Node, usize)> {
+        if self.after_alnum(pos) {
+            return None;
+        }
+        let rest = self.rest(pos).strip_prefix('#')?;
+        let len = balanced_prefix_len(rest, |c| {
+            !c.is_whitespace() && !".,!?'\"#:/【】<>".contains(c)
+        });
+        let hashtag = &rest[..len];
+        if hashtag.is_empty() || hashtag.chars().all(|c| c.is_ascii_digit()) {
+            return None;
+        }
+        let node = Node::Hashtag {
+            hashtag: hashtag.to_owned(),
+        };
+        Some((node, pos + 1 + len))
+    }
+
+    fn emoji_code(&self, pos: usize) -> Option<(Node, usize)> {
+        if self.after_alnum(pos) {
+            return None;
+        }
+        let rest = self.rest(pos).strip_prefix(':')?;
+        let len = prefix_len(rest, |c| is_alnum(c) || matches!(c, '_' | '+' | '-'));
+        if len == 0 || !rest[len..].starts_with(':') {
+            return None;
+        }
+        let end = pos + len + 2;
+        if self.rest(end).starts_with(is_alnum) {
+            return None;
+        }
+        let node = Node::EmojiCode {
+            name: rest[..len].to_owned(),
+        };
+        Some((node, end))
+    }
+
+    fn unicode_emoji(&self, pos: usize) -> Option<(Node, usize)> {
+        let grapheme = self.rest(pos).graphemes(true).next()?;
+        if !is_emoji_grapheme(grapheme) {
+            return None;
+        }
+        let node = Node::UnicodeEmoji {
+            emoji: grapheme.to_owned(),
+        };
+        Some((node, pos + grapheme.len()))
+    }
+
+    fn link(&mut self, pos: usize) -> Option<(Node, usize)> {
+        if self.in_link_label {
+            return None;
+        }
+        let (silent, label_start) = if self.rest(pos).starts_with("?[") {
+            (true, pos + 2)
+        } else if self.rest(pos).starts_with('[') {
+            (false, pos + 1)
+        } else {
+            return None;
+        };
+
+        self.in_link_label = true;
+        let label = self.nested(|p| p.parse_until(label_start, false, Some("]"), false));
+        self.in_link_label = false;
+        let (children, label_end) = label?;
+
+        let url_start = label_end + self.rest(label_end).strip_prefix('(').map(|_| 1)?;
+        let (url, url_end) = match self.url_alt(url_start).or_else(|| self.url(url_start))? {
+            (Node::Url { url, .. }, end) => (url, end),
+            _ => unreachable!(),
+        };
+        if !self.rest(url_end).starts_with(')') {
+            return None;
+        }
+        let node = Node::Link {
+            silent,
+            url,
+            children,
+        };
+        Some((node, url_end + 1))
+    }
+
+    fn url(&self, pos: usize) -> Option<(Node, usize)> {
+        if self.in_link_label {
+            return None;
+        }
+        let rest = self.rest(pos);
+        let scheme_len = url_scheme_len(rest)?;
+        let len = balanced_prefix_len(&rest[scheme_len..], |c| {
+            is_alnum(c) || ".,_/:%#@$&?!~=+-".contains(c)
+        });
+        let url = rest[..scheme_len + len].trim_end_matches(['.', ',']);
+        if url.len() == scheme_len {
+            return None;
+        }
+        let node = Node::Url {
+            url: url.to_owned(),
+            brackets: false,
+        };
+        Some((node, pos + url.len()))
+    }
+
+    fn url_alt(&self, pos: usize) -> Option<(Node, usize)> {
+        let rest = self.rest(pos).strip_prefix('<')?;
+        let scheme_len = url_scheme_len(rest)?;
+        let len = rest.find(|c: char| c == '>' || c == '<' || c.is_whitespace())?;
+        if len == scheme_len || !rest[len..].starts_with('>') {
+            return None;
+        }
+        let node = Node::Url {
+            url: rest[..len].to_owned(),
+            brackets: true,
+        };
+        Some((node, pos + len + 2))
+    }
+}
+
+/// Removes the line break at the end of the last text node, such as the one before `</center>`.
+fn strip_last_line_break(nodes: &mut Vec<Node>) {
+    if let Some(Node::Text { text }) = nodes.last_mut() {
+        if text.ends_with('\n') {
+            text.pop();
+            if text.is_empty() {
+                nodes.pop();
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::parse;
+    use crate::mfm::Node;
+
+    use std::time::{Duration, Instant};
+
+    fn assert_parsed_in_time(text: &str) -> Vec<Node> {
+        let start = Instant::now();
+        let nodes = parse(text);
+        let elapsed = start.elapsed();
+        assert!(
+            elapsed < Duration::from_secs(5),
+            "parsing {:?} took {:?}",
+            text,
+            elapsed
+        );
+        nodes
+    }
+
+    #[test]
+    fn test_unclosed_nesting() {
+        for unit in [
+            "$[x2 ", "<b>", "<small>", "<i>", "<s>", "**", "~~", "[", "?[", "***",
+        ] {
+            let text = unit.repeat(200);
+            let nodes = assert_parsed_in_time(&text);
+            assert_eq!(nodes, vec![Node::Text { text }]);
+        }
+    }
+
+    #[test]
+    fn test_unclosed_mixed_nesting() {
+        let text = "$[x2 <b><small>~~[**a ".repeat(100);
+        assert_parsed_in_time(&text);
+
+        // closed only at the end, after many unclosed ones
+        let text = format!("{}]", "$[x2 <b>".repeat(100));
+        assert_parsed_in_time(&text);
+    }
+
+    #[test]
+    fn test_nested_within_limit() {
+        let text = format!("{}a{}", "<b>".repeat(3), "</b>".repeat(3));
+        let nodes = assert_parsed_in_time(&text);
+        let mut expected = vec![Node::Text {
+            text: "a".to_owned(),
+        }];
+        for _ in 0..3 {
+            expected = vec![Node::Bold { children: expected }];
+        }
+        assert_eq!(nodes, expected);
+    }
+}
@@ -0,0 +1,171 @@
+use std::collections::HashMap;
+
+use crate::mfm::{self, Node};
+
+use misskey_api::model::{note::Note, user::User};
+use url::Url;
+
+mod html;
+mod markdown;
+mod plain;
+
+pub(crate) use plain::write_plain_text;
+
+/// Renderer of MFM into HTML and Markdown.
+///
+/// Mentions and hashtags are rendered as links, and custom emojis are rendered as images if
+/// their URLs are known. Effects of `$[...]` functions that have no equivalent are dropped,
+/// leaving their contents.
+///
+/// The text of [`Note`] and [`User`] should be rendered with the renderer created by
+/// [`for_note`][`Renderer::for_note`] and [`for_user`][`Renderer::for_user`], which know
+/// the host of the author and the custom emojis used in the text.
+/// For other text such as the one in [`Announcement`][misskey_api::model::announcement::Announcement]
+/// or the description of [`Channel`][misskey_api::model::channel::Channel], use
+/// [`new`][`Renderer::new`] and register the emojis with [`emoji`][`Renderer::emoji`] if needed.
+///
+/// # Examples
+///
+/// ```
+/// use misskey_util::mfm::Renderer;
+///
+/// let renderer = Renderer::new("https://misskey.example".parse()?);
+/// assert_eq!(
+///     renderer.to_html("**Hello** @ai <script>"),
+///     "<b>Hello</b> <a href=\"https://misskey.example/@ai\" class=\"mention\">@ai</a> &lt;script&gt;"
+/// );
+/// assert_eq!(
+///     renderer.to_markdown("**Hello** #misskey"),
+///     "**Hello** [#misskey](https://misskey.example/tags/misskey)"
+/// );
+/// # Ok::<(), url::ParseError>(())
+/// ```
+#[derive(Debug, Clone)]
+pub struct Renderer {
+    base_url: Url,
+    host: Option<String>,
+    emojis: HashMap<String, Url>,
+}
+
+impl Renderer {
+    /// Creates a renderer for the text written in the instance at `base_url`.
+    pub fn new(base_url: Url) -> Renderer {
+        Renderer {
+            base_url,
+            host: None,
+            emojis: HashMap::new(),
+        }
+    }
+
+    /// Creates a renderer for the text and the content warning of `note`.
+    pub fn for_note(base_url: Url, note: &Note) -> Renderer {
+        let mut renderer = Renderer::new(base_url);
+        if let Some(host) = &note.user.host {
+            renderer.host(host.clone());
+        }
+        renderer.emojis.extend(note_emojis(note));
+        renderer
+    }
+
+    /// Creates a renderer for the name and the description of `user`.
+    pub fn for_user(base_url: Url, user: &User) -> Renderer {
+        let mut renderer = Renderer::new(base_url);
+        if let Some(host) = &user.host {
+            renderer.host(host.clone());
+        }
+        #[cfg(not(feature = "13-0-0"))]
+        for emoji in user.emojis.iter().flatten() {
+            renderer.emoji(emoji.name.clone(), emoji.url.clone());
+        }
+        #[cfg(feature = "13-2-4")]
+        for (name, url) in user.emojis.iter().flatten() {
+            renderer.emoji(name.clone(), url.clone());
+        }
+        renderer
+    }
+
+    /// Sets the host of the author, which is used for the mentions without host.
+    pub fn host(&mut self, host: impl Into<String>) -> &mut Self {
+        self.host = Some(host.into());
+        self
+    }
+
+    /// Registers the URL of the custom emoji `:name:`.
+    pub fn emoji(&mut self, name: impl Into<String>, url: Url) -> &mut Self {
+        self.emojis.insert(name.into(), url);
+        self
+    }
+
+    /// Renders MFM text into HTML, escaping the text.
+    pub fn to_html(&self, text: &str) -> String {
+        self.nodes_to_html(&mfm::parse(text))
+    }
+
+    /// Renders MFM nodes into HTML, escaping the text.
+    pub fn nodes_to_html(&self, nodes: &[Node]) -> String {
+        let mut html = String::new();
+        html::write_nodes(self, &mut html, nodes);
+        html
+    }
+
+    /// Renders MFM text into CommonMark.
+    ///
+    /// Strikethrough is written as `~~text~~`, which is supported by most implementations.
+    pub fn to_markdown(&self, text: &str) -> String {
+        self.nodes_to_markdown(&mfm::parse(text))
+    }
+
+    /// Renders MFM nodes into CommonMark.
+    pub fn nodes_to_markdown(&self, nodes: &[Node]) -> String {
+        let mut markdown = String::new();
+        markdown::write_nodes(self, &mut markdown, nodes);
+        markdown
+    }
+
+    fn mention_url(&self, username: &str, host: Option<&str>) -> Url {
+        let url = match host.or(self.host.as_deref()) {
+            Some(host) => Url::parse(&format!("https://{}/@{}", host, username)),
+            None => self.base_url.join(&format!("/@{}", username)),
+        };
+        url.unwrap_or_else(|_| self.base_url.clone())
+    }
+
+    fn hashtag_url(&self, hashtag: &str) -> Url {
+        let mut url = self.base_url.clone();
+        if let Ok(mut segments) = url.path_segments_mut() {
+            segments.clear().push("tags").push(hashtag);
+        }
+        url
+    }
+
+    fn emoji_url(&self, name: &str) -> Option<&Url> {
+        self.emojis.get(name).or_else(|| {
+            let host = self.host.as_deref()?;
+            self.emojis.get(&format!("{}@{}", name, host))
+        })
+    }
+}
+
+/// Collects the URLs of the custom emojis in the text and the reactions of `note`.
+// the emojis are not included in the notes between 13.0.0 and 13.2.4
+#[cfg_attr(
+    all(feature = "13-0-0", not(feature = "13-2-4")),
+    allow(unused_mut, unused_variables)
+)]
+pub(crate) fn note_emojis(note: &Note) -> HashMap<String, Url> {
+    let mut emojis = HashMap::new();
+    #[cfg(not(feature = "13-0-0"))]
+    for emoji in &note.emojis {
+        emojis.insert(emoji.name.clone(), emoji.url.clone());
+    }
+    #[cfg(feature = "13-2-4")]
+    {
+        for (name, url) in note.emojis.iter().flatten() {
+            emojis.insert(name.clone(), url.clone());
+        }
+        for (reaction, url) in &note.reaction_emojis {
+            emojis.insert(reaction.0.trim_matches(':').to_owned(), url.clone());
+        }
+    }
+    emojis
+}
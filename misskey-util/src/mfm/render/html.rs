@@ -0,0 +1,181 @@
+use crate::mfm::{render::Renderer, Node};
+
+pub(super) fn write_nodes(renderer: &Renderer, out: &mut String, nodes: &[Node]) {
+    for node in nodes {
+        write_node(renderer, out, node);
+    }
+}
+
+fn escape(out: &mut String, text: &str) {
+    for c in text.chars() {
+        match c {
+            '&' => out.push_str("&amp;"),
+            '<' => out.push_str("&lt;"),
+            '>' => out.push_str("&gt;"),
+            '"' => out.push_str("&quot;"),
+            '\'' => out.push_str("&#39;"),
+            c => out.push(c),
+        }
+    }
+}
+
+fn escape_text(out: &mut String, text: &str) {
+    for (i, line) in text.split('\n').enumerate() {
+        if i > 0 {
+            out.push_str("<br>");
+        }
+        escape(out, line);
+    }
+}
+
+fn write_enclosed(renderer: &Renderer, out: &mut String, tag: &str, children: &[Node]) {
+    out.push('<');
+    out.push_str(tag);
+    out.push('>');
+    write_nodes(renderer, out, children);
+    out.push_str("</");
+    out.push_str(tag);
+    out.push('>');
+}
+
+fn write_link(out: &mut String, url: &str, class: Option<&str>, f: impl FnOnce(&mut String)) {
+    out.push_str("<a href=\"");
+    escape(out, url);
+    out.push('"');
+    if let Some(class) = class {
+        out.push_str(" class=\"");
+        out.push_str(class);
+        out.push('"');
+    }
+    out.push('>');
+    f(out);
+    out.push_str("</a>");
+}
+
+/// Returns the CSS for the effect of the function, or `None` if it cannot be expressed.
+fn fn_style(node: &Node) -> Option<String> {
+    let name = match node {
+        Node::Fn { name, .. } => name.as_str(),
+        _ => return None,
+    };
+    let is_color = |color: &&str| {
+        matches!(color.len(), 3 | 4 | 6 | 8) && color.chars().all(|c| c.is_ascii_hexdigit())
+    };
+    match name {
+        "x2" => Some("font-size: 200%".to_owned()),
+        "x3" => Some("font-size: 400%".to_owned()),
+        "x4" => Some("font-size: 600%".to_owned()),
+        "fg" => node
+            .fn_arg("color")
+            .filter(is_color)
+            .map(|color| format!("color: #{}", color)),
+        "bg" => node
+            .fn_arg("color")
+            .filter(is_color)
+            .map(|color| format!("background-color: #{}", color)),
+        "font" => ["serif", "monospace", "cursive", "fantasy"]
+            .iter()
+            .find(|family| node.fn_arg(family).is_some())
+            .map(|family| format!("font-family: {}", family)),
+        _ => None,
+    }
+}
+
+fn write_node(renderer: &Renderer, out: &mut String, node: &Node) {
+    match node {
+        Node::Quote { children } => write_enclosed(renderer, out, "blockquote", children),
+        Node::Search { content, .. } => {
+            out.push_str("<div>");
+            escape(out, content);
+            out.push_str("</div>");
+        }
+        Node::BlockCode { code, lang } => {
+            match lang {
+                Some(lang) => {
+                    out.push_str("<pre><code class=\"language-");
+                    escape(out, lang);
+                    out.push_str("\">");
+                }
+                None => out.push_str("<pre><code>"),
+            }
+            escape(out, code);
+            out.push_str("</code></pre>");
+        }
+        Node::MathBlock { formula } => {
+            out.push_str("<pre><code class=\"language-latex\">");
+            escape(out, formula);
+            out.push_str("</code></pre>");
+        }
+        Node::Center { children } => {
+            out.push_str("<div style=\"text-align: center\">");
+            write_nodes(renderer, out, children);
+            out.push_str("</div>");
+        }
+        Node::UnicodeEmoji { emoji } => escape(out, emoji),
+        Node::EmojiCode { name } => match renderer.emoji_url(name) {
+            Some(url) => {
+                out.push_str("<img src=\"");
+                escape(out, url.as_str());
+                out.push_str("\" alt=\":");
+                escape(out, name);
+                out.push_str(":\" title=\":");
+                escape(out, name);
+                out.push_str(":\" class=\"emoji\">");
+            }
+            None => {
+                out.push(':');
+                escape(out, name);
+                out.push(':');
+            }
+        },
+        Node::Bold { children } => write_enclosed(renderer, out, "b", children),
+        Node::Small { children } => write_enclosed(renderer, out, "small", children),
+        Node::Italic { children } => write_enclosed(renderer, out, "i", children),
+        Node::Strike { children } => write_enclosed(renderer, out, "del", children),
+        Node::InlineCode { code } => {
+            out.push_str("<code>");
+            escape(out, code);
+            out.push_str("</code>");
+        }
+        Node::MathInline { formula } => {
+            out.push_str("<code>");
+            escape(out, formula);
+            out.push_str("</code>");
+        }
+        Node::Mention {
+            username,
+            host,
+            acct,
+        } => {
+            let url = renderer.mention_url(username, host.as_deref());
+            write_link(out, url.as_str(), Some("mention"), |out| escape(out, acct));
+        }
+        Node::Hashtag { hashtag } => {
+            let url = renderer.hashtag_url(hashtag);
+            write_link(out, url.as_str(), Some("hashtag"), |out| {
+                out.push('#');
+                escape(out, hashtag);
+            });
+        }
+        Node::Url { url, .. } => write_link(out, url, None, |out| escape(out, url)),
+        Node::Link { url, children, .. } => {
+            write_link(out, url, None, |out| write_nodes(renderer, out, children));
+        }
+        Node::Fn { children, .. } => match fn_style(node) {
+            Some(style) => {
+                out.push_str("<span style=\"");
+                escape(out, &style);
+                out.push_str("\">");
+                write_nodes(renderer, out, children);
+                out.push_str("</span>");
+            }
+            None => write_enclosed(renderer, out, "span", children),
+        },
+        Node::Plain { text } => {
+            out.push_str("<span>");
+            escape_text(out, text);
+            out.push_str("</span>");
+        }
+        Node::Text { text } => escape_text(out, text),
+    }
+}
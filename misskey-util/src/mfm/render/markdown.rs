@@ -0,0 +1,200 @@
+use crate::mfm::{render::Renderer, Node};
+
+pub(super) fn write_nodes(renderer: &Renderer, out: &mut String, nodes: &[Node]) {
+    for (i, node) in nodes.iter().enumerate() {
+        // blocks need blank lines around them not to be merged with the paragraphs
+        if node.is_block() {
+            end_paragraph(out);
+        }
+        write_node(renderer, out, node);
+        if node.is_block() && i + 1 < nodes.len() {
+            out.push_str("\n\n");
+        }
+    }
+}
+
+fn end_paragraph(out: &mut String) {
+    if out.is_empty() || out.ends_with("\n\n") {
+        return;
+    }
+    out.push_str(if out.ends_with('\n') { "\n" } else { "\n\n" });
+}
+
+fn is_line_start(out: &str) -> bool {
+    out.is_empty() || out.ends_with('\n')
+}
+
+/// Escapes the text not to be interpreted as Markdown.
+fn escape(out: &mut String, text: &str) {
+    let mut chars = text.chars().peekable();
+    while let Some(c) = chars.next() {
+        if is_line_start(out) {
+            match c {
+                // prevent indented code blocks
+                ' ' => {
+                    out.push_str("&#32;");
+                    continue;
+                }
+                '\t' => {
+                    out.push_str("&#9;");
+                    continue;
+                }
+                // prevent headings, lists and setext underlines
+                '#' | '-' | '+' | '=' => out.push('\\'),
+                '0'..='9' => {
+                    // prevent ordered lists such as `1.`
+                    out.push(c);
+                    while let Some(&d) = chars.peek().filter(|d| d.is_ascii_digit()) {
+                        out.push(d);
+                        chars.next();
+                    }
+                    if let Some(&d) = chars.peek().filter(|&&d| d == '.' || d == ')') {
+                        out.push('\\');
+                        out.push(d);
+                        chars.next();
+                    }
+                    continue;
+                }
+                _ => {}
+            }
+        }
+        if matches!(
+            c,
+            '\\' | '`' | '*' | '_' | '[' | ']' | '<' | '>' | '~' | '|' | '&'
+        ) {
+            out.push('\\');
+        }
+        out.push(c);
+    }
+}
+
+/// Escapes the text, turning the line breaks into hard line breaks.
+fn escape_text(out: &mut String, text: &str) {
+    let lines: Vec<_> = text.split('\n').collect();
+    for (i, line) in lines.iter().enumerate() {
+        if i > 0 {
+            // a hard line break is only valid between non-empty lines
+            let current_line = out.rsplit('\n').next().unwrap_or("");
+            if !current_line.is_empty() && !line.is_empty() {
+                out.push('\\');
+            }
+            out.push('\n');
+        }
+        escape(out, line);
+    }
+}
+
+fn write_code_span(out: &mut String, code: &str) {
+    let fence = "`".repeat(longest_backticks(code) + 1);
+    let pad = code.starts_with('`')
+        || code.ends_with('`')
+        || (code.starts_with(' ') && code.ends_with(' ') && !code.trim().is_empty());
+    let pad = if pad { " " } else { "" };
+    out.push_str(&fence);
+    out.push_str(pad);
+    out.push_str(code);
+    out.push_str(pad);
+    out.push_str(&fence);
+}
+
+fn write_code_block(out: &mut String, code: &str, lang: Option<&str>) {
+    let fence = "`".repeat(std::cmp::max(3, longest_backticks(code) + 1));
+    out.push_str(&fence);
+    out.push_str(lang.unwrap_or(""));
+    out.push('\n');
+    out.push_str(code);
+    out.push('\n');
+    out.push_str(&fence);
+}
+
+fn longest_backticks(s: &str) -> usize {
+    s.split(|c| c != '`').map(str::len).max().unwrap_or(0)
+}
+
+fn write_enclosed(renderer: &Renderer, out: &mut String, mark: &str, children: &[Node]) {
+    out.push_str(mark);
+    write_nodes(renderer, out, children);
+    out.push_str(mark);
+}
+
+fn write_link(out: &mut String, url: &str, f: impl FnOnce(&mut String)) {
+    out.push('[');
+    f(out);
+    out.push_str("](");
+    if url.contains(['(', ')', ' ']) {
+        out.push('<');
+        out.push_str(url);
+        out.push('>');
+    } else {
+        out.push_str(url);
+    }
+    out.push(')');
+}
+
+fn write_node(renderer: &Renderer, out: &mut String, node: &Node) {
+    match node {
+        Node::Quote { children } => {
+            let mut content = String::new();
+            write_nodes(renderer, &mut content, children);
+            for (i, line) in content.split('\n').enumerate() {
+                if i > 0 {
+                    out.push('\n');
+                }
+                out.push('>');
+                if !line.is_empty() {
+                    out.push(' ');
+                    out.push_str(line);
+                }
+            }
+        }
+        Node::Search { content, .. } => escape(out, content),
+        Node::BlockCode { code, lang } => write_code_block(out, code, lang.as_deref()),
+        Node::MathBlock { formula } => write_code_block(out, formula, Some("math")),
+        Node::Center { children } | Node::Small { children } | Node::Fn { children, .. } => {
+            write_nodes(renderer, out, children)
+        }
+        Node::UnicodeEmoji { emoji } => out.push_str(emoji),
+        Node::EmojiCode { name } => {
+            let mut alt = String::new();
+            alt.push(':');
+            escape(&mut alt, name);
+            alt.push(':');
+            match renderer.emoji_url(name) {
+                Some(url) => {
+                    out.push('!');
+                    write_link(out, url.as_str(), |out| out.push_str(&alt));
+                }
+                None => out.push_str(&alt),
+            }
+        }
+        Node::Bold { children } => write_enclosed(renderer, out, "**", children),
+        Node::Italic { children } => write_enclosed(renderer, out, "*", children),
+        Node::Strike { children } => write_enclosed(renderer, out, "~~", children),
+        Node::InlineCode { code } => write_code_span(out, code),
+        Node::MathInline { formula } => write_code_span(out, formula),
+        Node::Mention {
+            username,
+            host,
+            acct,
+        } => {
+            let url = renderer.mention_url(username, host.as_deref());
+            write_link(out, url.as_str(), |out| escape(out, acct));
+        }
+        Node::Hashtag { hashtag } => {
+            let url = renderer.hashtag_url(hashtag);
+            write_link(out, url.as_str(), |out| {
+                out.push('#');
+                escape(out, hashtag);
+            });
+        }
+        Node::Url { url, .. } => {
+            out.push('<');
+            out.push_str(url);
+            out.push('>');
+        }
+        Node::Link { url, children, .. } => {
+            write_link(out, url, |out| write_nodes(renderer, out, children));
+        }
+        Node::Plain { text } | Node::Text { text } => escape_text(out, text),
+    }
+}
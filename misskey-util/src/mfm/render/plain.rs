@@ -0,0 +1,62 @@
+use crate::mfm::Node;
+
+pub(crate) fn write_plain_text(out: &mut String, nodes: &[Node]) {
+    for (i, node) in nodes.iter().enumerate() {
+        // put back the line breaks around the blocks, which are consumed by the parser
+        if node.is_block() && !out.is_empty() && !out.ends_with('\n') {
+            out.push('\n');
+        }
+        write_node(out, node);
+        if node.is_block() && i + 1 < nodes.len() {
+            out.push('\n');
+        }
+    }
+}
+
+fn write_node(out: &mut String, node: &Node) {
+    match node {
+        Node::Quote { children } => {
+            let mut content = String::new();
+            write_plain_text(&mut content, children);
+            for (i, line) in content.split('\n').enumerate() {
+                if i > 0 {
+                    out.push('\n');
+                }
+                out.push_str("> ");
+                out.push_str(line);
+            }
+        }
+        Node::Search { content, .. } => out.push_str(content),
+        Node::BlockCode { code, .. } => out.push_str(code),
+        Node::MathBlock { formula } | Node::MathInline { formula } => out.push_str(formula),
+        Node::UnicodeEmoji { emoji } => out.push_str(emoji),
+        Node::EmojiCode { name } => {
+            out.push(':');
+            out.push_str(name);
+            out.push(':');
+        }
+        Node::InlineCode { code } => out.push_str(code),
+        Node::Mention { acct, .. } => out.push_str(acct),
+        Node::Hashtag { hashtag } => {
+            out.push('#');
+            out.push_str(hashtag);
+        }
+        Node::Url { url, .. } => out.push_str(url),
+        Node::Link { url, children, .. } => {
+            let start = out.len();
+            write_plain_text(out, children);
+            if out[start..] != *url {
+                out.push_str(" (");
+                out.push_str(url);
+                out.push(')');
+            }
+        }
+        Node::Center { children }
+        | Node::Bold { children }
+        | Node::Small { children }
+        | Node::Italic { children }
+        | Node::Strike { children }
+        | Node::Fn { children, .. } => write_plain_text(out, children),
+        Node::Plain { text } | Node::Text { text } => out.push_str(text),
+    }
+}
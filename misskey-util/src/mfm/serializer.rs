@@ -0,0 +1,337 @@
+use std::fmt::{self, Display};
+
+use crate::mfm::parser::SEARCH_KEYWORDS;
+use crate::mfm::Node;
+
+pub(crate) fn write_nodes(out: &mut String, nodes: &[Node]) {
+    // the parser consumes a line break on each side of a block, so put them back
+    for (i, node) in nodes.iter().enumerate() {
+        // the line break lets the search be recognized even if the line starts with other syntax
+        if node.is_block() && (i > 0 || matches!(node, Node::Search { .. })) {
+            out.push('\n');
+        }
+        write_node(out, node, &nodes[..i]);
+        if node.is_block() && i + 1 < nodes.len() {
+            out.push('\n');
+        }
+    }
+}
+
+fn write_node(out: &mut String, node: &Node, before: &[Node]) {
+    match node {
+        Node::Quote { children } => {
+            let mut content = String::new();
+            write_nodes(&mut content, children);
+            for (i, line) in content.split('\n').enumerate() {
+                if i > 0 {
+                    out.push('\n');
+                }
+                out.push_str("> ");
+                out.push_str(line);
+            }
+        }
+        Node::Search { content, .. } => out.push_str(content),
+        Node::BlockCode { code, lang } => {
+            out.push_str("```");
+            out.push_str(lang.as_deref().unwrap_or(""));
+            out.push('\n');
+            out.push_str(code);
+            out.push_str("\n```");
+        }
+        Node::MathBlock { formula } => {
+            out.push_str("\\[\n");
+            out.push_str(formula);
+            // `\]` at the end of a line closes the block
+            if !formula.ends_with("\\]") {
+                out.push('\n');
+            }
+            out.push_str("\\]");
+        }
+        Node::Center { children } => {
+            out.push_str("<center>\n");
+            write_nodes(out, children);
+            out.push_str("\n</center>");
+        }
+        Node::UnicodeEmoji { emoji } => out.push_str(emoji),
+        Node::EmojiCode { name } => {
+            out.push(':');
+            out.push_str(name);
+            out.push(':');
+        }
+        Node::Bold { children } => {
+            write_enclosed(out, before, &[("**", "**"), ("<b>", "</b>")], children)
+        }
+        Node::Small { children } => {
+            write_enclosed(out, before, &[("<small>", "</small>")], children)
+        }
+        Node::Italic { children } => write_enclosed(out, before, &[("<i>", "</i>")], children),
+        Node::Strike { children } => {
+            write_enclosed(out, before, &[("~~", "~~"), ("<s>", "</s>")], children)
+        }
+        Node::InlineCode { code } => {
+            out.push('`');
+            out.push_str(code);
+            out.push('`');
+        }
+        Node::MathInline { formula } => {
+            out.push_str("\\(");
+            out.push_str(formula);
+            out.push_str("\\)");
+        }
+        Node::Mention { acct, .. } => out.push_str(acct),
+        Node::Hashtag { hashtag } => {
+            out.push('#');
+            out.push_str(hashtag);
+        }
+        Node::Url {
+            url,
+            brackets: true,
+        } => {
+            out.push('<');
+            out.push_str(url);
+            out.push('>');
+        }
+        Node::Url { url, .. } => out.push_str(url),
+        Node::Link {
+            silent,
+            url,
+            children,
+        } => {
+            let open = if *silent { "?[" } else { "[" };
+            let close = format!("]({})", url);
+            write_enclosed(out, before, &[(open, &close)], children);
+        }
+        Node::Fn {
+            name,
+            args,
+            children,
+        } => {
+            let mut open = format!("$[{}", name);
+            for (i, (key, value)) in args.iter().enumerate() {
+                open.push(if i == 0 { '.' } else { ',' });
+                open.push_str(key);
+                if let Some(value) = value {
+                    open.push('=');
+                    open.push_str(value);
+                }
+            }
+            open.push(' ');
+            if name == "tada" && args.is_empty() {
+                write_enclosed(out, before, &[(&open, "]"), ("***", "***")], children);
+            } else {
+                write_enclosed(out, before, &[(&open, "]")], children);
+            }
+        }
+        Node::Plain { text } => {
+            // the parser strips a line break on each side, and the first line of the text
+            // should not end the line of the tag as a search
+            out.push_str("<plain>");
+            let first_line = text.split('\n').next().unwrap_or_default();
+            if text.starts_with('\n')
+                || (text.contains('\n') && SEARCH_KEYWORDS.iter().any(|k| first_line.ends_with(k)))
+            {
+                out.push('\n');
+            }
+            out.push_str(text);
+            if text.ends_with('\n') {
+                out.push('\n');
+            }
+            out.push_str("</plain>");
+        }
+        Node::Text { text } => out.push_str(text),
+    }
+}
+
+/// Writes the children enclosed with the first of `syntaxes` that keeps them as they are.
+///
+/// If none of them does, the texts in the children are escaped with `<plain>` and the last one
+/// not taken in by the node before it is used, preferring the ones not delimited by `*` or `~`.
+fn write_enclosed(out: &mut String, before: &[Node], syntaxes: &[(&str, &str)], children: &[Node]) {
+    let mut content = String::new();
+    write_nodes(&mut content, children);
+
+    let syntax = syntaxes
+        .iter()
+        .find(|&&(open, close)| encloses(before, open, close, children, &content));
+    let (open, close, content) = match syntax {
+        Some(&(open, close)) => (open, close, content),
+        None => {
+            let (open, close) = syntaxes
+                .iter()
+                .rev()
+                .filter(|(open, _)| !open.starts_with(['*', '~']))
+                .chain(syntaxes.iter().rev())
+                .find(|(open, _)| !open.starts_with(|c| absorbs(before.last(), c)))
+                .unwrap_or(&syntaxes[syntaxes.len() - 1]);
+            let mut content = String::new();
+            write_nodes(&mut content, &escape_texts(children));
+            (*open, *close, content)
+        }
+    };
+    out.push_str(open);
+    out.push_str(&content);
+    out.push_str(close);
+}
+
+/// Returns `true` if `open` and `close` around `content` are parsed back into the node.
+///
+/// `before` is the nodes before it in the same parent.
+fn encloses(before: &[Node], open: &str, close: &str, children: &[Node], content: &str) -> bool {
+    // the texts would be closed early, or the same syntax in them would be completed
+    // (the nested nodes consume their own delimiters)
+    let nested = if open.starts_with("$[") {
+        Some("$[")
+    } else if open.starts_with('<') {
+        Some(open)
+    } else {
+        None
+    };
+    let closed_early = children.iter().any(|node| match node {
+        Node::Text { text } => {
+            text.contains(close) || matches!(nested, Some(nested) if text.contains(nested))
+        }
+        _ => false,
+    });
+    // the delimiters such as `**` are confused with the same characters in the texts around them
+    let confused = match open.chars().next() {
+        Some(mark @ ('*' | '~')) => {
+            content.contains(mark)
+                || texts_contain(before, mark)
+                || (mark == '~' && content.contains('\n'))
+        }
+        _ => false,
+    };
+    // the delimiters would be taken in as a part of the hashtag or URL before them
+    let absorbed = open.starts_with(|c| absorbs(before.last(), c))
+        || close.starts_with(|c| absorbs(children.last(), c));
+    !(closed_early || confused || absorbed)
+}
+
+/// Returns `true` if any of the texts in `nodes` and their descendants contains `c`.
+fn texts_contain(nodes: &[Node], c: char) -> bool {
+    nodes.iter().any(|node| match node {
+        Node::Text { text } => text.contains(c),
+        node => texts_contain(node.children(), c),
+    })
+}
+
+/// Returns `true` if `c` written after `node` becomes a part of it.
+fn absorbs(node: Option<&Node>, c: char) -> bool {
+    match node {
+        Some(Node::Hashtag { .. }) => matches!(c, '*' | '~' | '$'),
+        Some(Node::Url {
+            brackets: false, ..
+        }) => matches!(c, '~' | '$'),
+        _ => false,
+    }
+}
+
+/// Replaces the texts in `nodes` with `<plain>`.
+fn escape_texts(nodes: &[Node]) -> Vec<Node> {
+    let mut escaped = Vec::new();
+    for node in nodes {
+        match node {
+            Node::Text { text } => {
+                // `</plain>` cannot be in `<plain>`, so split it
+                let mut rest = text.as_str();
+                while let Some(i) = rest.find("</plain>") {
+                    escaped.push(Node::Plain {
+                        text: rest[..i + 1].to_owned(),
+                    });
+                    rest = &rest[i + 1..];
+                }
+                if !rest.is_empty() {
+                    escaped.push(Node::Plain {
+                        text: rest.to_owned(),
+                    });
+                }
+            }
+            node => escaped.push(node.clone()),
+        }
+    }
+    escaped
+}
+
+impl Display for Node {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        let mut out = String::new();
+        write_node(&mut out, self, &[]);
+        f.write_str(&out)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::mfm::{builder::normalize, parse, to_string};
+
+    fn assert_round_trip(text: &str) -> String {
+        let nodes = parse(text);
+        let serialized = to_string(&nodes);
+        assert_eq!(parse(&serialized), nodes, "{:?} -> {:?}", text, serialized);
+        serialized
+    }
+
+    #[test]
+    fn test_round_trip() {
+        for text in [
+            "Hello, **@ai@misskey.io** :wave:",
+            "<b>bold</b> <i>italic</i> <small>small</small> ~~strike~~",
+            "$[x2 $[flip.h,v text]] $[tada tada] ***tada***",
+            "[label](https://example.com) ?[silent](https://example.com) <https://example.com>",
+            "> quote\n> <b>quoted</b>\ntext",
+            "<center>\ncentered\n</center>\n```rust\nlet x = 1;\n```\n\\[\nx\n\\]",
+            "`code` \\(x\\) #tag @user <plain>**plain**</plain>",
+            "<plain>\nline\n</plain>",
+            "<plain>a\nb</plain> <plain>\n\na\n\n</plain> <plain>a Search\nb</plain>",
+            "https://example.com<s>~~</s>",
+            "`<b>**a**</b>` <b>b</b>",
+            "\\[\n```</i>\\]\\]",
+        ] {
+            assert_round_trip(text);
+        }
+    }
+
+    #[test]
+    fn test_round_trip_delimiters() {
+        // delimiters that would be taken in by the hashtag before them
+        assert_eq!(assert_round_trip("#tag<b>x</b>"), "#tag<b>x</b>");
+        // delimiters in the text
+        assert_eq!(assert_round_trip("<b>a**b</b>"), "<b>a**b</b>");
+        assert_eq!(assert_round_trip("<s>a~~b</s>"), "<s>a~~b</s>");
+        // delimiters after the other ones
+        assert_eq!(assert_round_trip("**a** <b>**</b>"), "**a** <b>**</b>");
+        assert_eq!(assert_round_trip("a**b <b>c</b>"), "a**b <b>c</b>");
+        // delimiters that would be taken in by the hashtag in the children
+        assert_eq!(assert_round_trip("<b>#tag</b>"), "<b>#tag</b>");
+        assert_eq!(assert_round_trip("$[tada **a**]"), "$[tada **a**]");
+    }
+
+    #[test]
+    fn test_round_trip_search() {
+        assert_eq!(assert_round_trip("a\nmisskey Search"), "a\nmisskey Search");
+        assert_round_trip("\nhttps://example.com<i>__</i> Search");
+        assert_round_trip("\n<b>a</b> Search");
+    }
+
+    #[test]
+    fn test_round_trip_escaped() {
+        // texts that cannot be written as they are become `<plain>`
+        for text in [
+            "$[x2 $[</plain>]",
+            "<b>*</b></b>**",
+            "https://example.com***<small>*\\[***",
+            "`xhttps://>)<plain>\n</a```></plain>\u{fe0f}```",
+            "</small>(```\n</center>*\n```https://x.y$[ Search\n``````\n<i>",
+        ] {
+            let nodes = parse(text);
+            let serialized = to_string(&nodes);
+            assert_eq!(
+                normalize(&parse(&serialized)),
+                normalize(&nodes),
+                "{:?} -> {:?}",
+                text,
+                serialized
+            );
+        }
+    }
+}
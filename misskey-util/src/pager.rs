@@ -5,6 +5,10 @@
 //! [`Pager`] trait serves as an alias for [`Stream`][stream] that fetches each page one by
 //! one, and [`PagerStream`] wraps it into [`Stream`][stream] that takes it by element.
 //!
+//! The position of a pager can be saved as [`PagerCursor`] to resume the pagination later, for
+//! example after the process is restarted.
+//! [`PrefetchStream`] fetches the windows of a timeline concurrently for large exports.
+//!
 //! [pagination_request]: misskey_api::PaginationRequest
 //! [stream]: futures::stream::Stream
 use std::collections::VecDeque;
@@ -23,6 +27,15 @@ use futures_timer::Delay;
 use misskey_api::{OffsetPaginationRequest, PaginationItem, PaginationRequest};
 use misskey_core::model::ApiResult;
 use misskey_core::{Client, Request};
+use serde::Serialize;
+
+mod cursor;
+mod prefetch;
+
+pub use cursor::{InvalidCursorError, PagerCursor};
+pub use prefetch::PrefetchStream;
+
+use cursor::PagerKind;
 
 const DEFAULT_PAGE_SIZE: u8 = 30;
 
@@ -36,10 +49,75 @@ enum PagerState<'a, C: Client + ?Sized, R: Request> {
     },
 }
 
+/// The last page fetched by a pager, kept to export the cursor in the middle of the page.
+struct LastPage<I> {
+    /// The cursor to fetch the page again.
+    cursor: Option<PagerCursor>,
+    /// The deferred `since_id` of [`BackwardPager`] when the page is fetched.
+    since_id: Option<I>,
+    /// The IDs of the items in the page.
+    ids: Vec<I>,
+    /// Whether the request for the page has failed and should be retried.
+    failed: bool,
+}
+
+impl<I> LastPage<I> {
+    fn new(cursor: Option<PagerCursor>, since_id: Option<I>, ids: Vec<I>) -> Self {
+        LastPage {
+            cursor,
+            since_id,
+            ids,
+            failed: false,
+        }
+    }
+
+    fn done(cursor: Option<PagerCursor>) -> Self {
+        LastPage::new(cursor, None, Vec::new())
+    }
+
+    fn failed(cursor: Option<PagerCursor>) -> Self {
+        LastPage {
+            failed: true,
+            ..LastPage::done(cursor)
+        }
+    }
+}
+
+/// Returns the cursor to continue after the last page, or the done cursor if the pager is
+/// terminated.
+fn next_cursor<C: Client + ?Sized, R: Request, I>(
+    kind: PagerKind,
+    state: &Option<PagerState<'_, C, R>>,
+    page: &Option<LastPage<I>>,
+) -> Option<PagerCursor> {
+    match state {
+        Some(PagerState::Ready { next_request }) => PagerCursor::new(kind, next_request),
+        Some(PagerState::Pending { request, .. }) => PagerCursor::new(kind, request),
+        None => {
+            let page = page.as_ref()?;
+            let mut cursor = page.cursor.clone()?;
+            cursor.done = !page.failed;
+            Some(cursor)
+        }
+    }
+}
+
+fn into_response<T, E>(res: Result<ApiResult<T>, E>) -> Result<T, Error<E>> {
+    Ok(res.map_err(Error::Client)?.into_result()?)
+}
+
+fn to_value<T: Serialize>(value: &T) -> Option<serde_json::Value> {
+    match serde_json::to_value(value) {
+        Ok(serde_json::Value::Null) | Err(_) => None,
+        Ok(value) => Some(value),
+    }
+}
+
 pub(crate) struct BackwardPager<'a, C: Client + ?Sized, R: PaginationRequest> {
     client: &'a C,
     since_id: Option<<R::Item as PaginationItem>::Id>,
     state: Option<PagerState<'a, C, R>>,
+    page: Option<LastPage<<R::Item as PaginationItem>::Id>>,
 }
 
 impl<'a, C: Client + ?Sized, R: PaginationRequest> BackwardPager<'a, C, R> {
@@ -49,18 +127,40 @@ impl<'a, C: Client + ?Sized, R: PaginationRequest> BackwardPager<'a, C, R> {
         mut request: R,
     ) -> Self {
         request.set_limit(DEFAULT_PAGE_SIZE);
-        BackwardPager {
-            client,
-            since_id,
-            state: Some(PagerState::Ready {
-                next_request: request,
-            }),
-        }
+        BackwardPager::resume(client, since_id, request, false)
     }
 
     pub(crate) fn new(client: &'a C, request: R) -> Self {
         BackwardPager::with_since_id(client, None, request)
     }
+
+    fn resume(
+        client: &'a C,
+        since_id: Option<<R::Item as PaginationItem>::Id>,
+        request: R,
+        done: bool,
+    ) -> Self {
+        if done {
+            BackwardPager {
+                client,
+                since_id: None,
+                page: Some(LastPage::done(PagerCursor::new(
+                    PagerKind::Backward,
+                    &request,
+                ))),
+                state: None,
+            }
+        } else {
+            BackwardPager {
+                client,
+                since_id,
+                page: None,
+                state: Some(PagerState::Ready {
+                    next_request: request,
+                }),
+            }
+        }
+    }
 }
 
 impl<'a, C, R> Stream for BackwardPager<'a, C, R>
@@ -95,8 +195,17 @@ where
                         });
                         return Poll::Pending;
                     }
-                    Poll::Ready(res) => res.map_err(Error::Client)?.into_result()?,
+                    Poll::Ready(res) => match into_response(res) {
+                        Ok(response) => response,
+                        Err(err) => {
+                            let cursor = PagerCursor::new(PagerKind::Backward, &request);
+                            self.page = Some(LastPage::failed(cursor));
+                            return Poll::Ready(Some(Err(err)));
+                        }
+                    },
                 };
+                let cursor = PagerCursor::new(PagerKind::Backward, &request);
+                let page_since_id = self.since_id.clone();
                 let mut response: Vec<_> = response.into_iter().collect();
                 if let Some(until) = response.last() {
                     request.set_until_id(until.item_id());
@@ -108,6 +217,8 @@ where
                         next_request: request,
                     });
                 }
+                let ids = response.iter().map(PaginationItem::item_id).collect();
+                self.page = Some(LastPage::new(cursor, page_since_id, ids));
                 Poll::Ready(Some(Ok(response)))
             }
             None => Poll::Ready(None),
@@ -132,7 +243,7 @@ where
     C: Client + ?Sized,
     R: PaginationRequest + Unpin,
     R::Response: IntoIterator<Item = R::Item>,
-    <R::Item as PaginationItem>::Id: Clone + Unpin,
+    <R::Item as PaginationItem>::Id: Serialize + Clone + Unpin,
 {
     type Content = R::Item;
     type Client = C;
@@ -144,21 +255,61 @@ where
             None => {}
         }
     }
+
+    fn cursor(&self, consumed: usize) -> Option<PagerCursor> {
+        let page = match &self.page {
+            Some(page) if consumed < page.ids.len() => page,
+            _ => {
+                let mut cursor = next_cursor(PagerKind::Backward, &self.state, &self.page)?;
+                cursor.since_id = to_value(&self.since_id);
+                return Some(cursor);
+            }
+        };
+        let mut cursor = page.cursor.clone()?;
+        if consumed == 0 {
+            cursor.since_id = to_value(&page.since_id);
+        } else {
+            // the rest of the page is below the last consumed item, and the deferred
+            // `since_id` is set to the requests after the first page
+            cursor.set_param("untilId", to_value(&page.ids[consumed - 1]));
+            if let Some(since_id) = to_value(&page.since_id) {
+                cursor.set_param("sinceId", Some(since_id));
+            }
+        }
+        Some(cursor)
+    }
 }
 
-pub(crate) struct ForwardPager<'a, C: Client + ?Sized, R: Request> {
+pub(crate) struct ForwardPager<'a, C: Client + ?Sized, R: PaginationRequest> {
     client: &'a C,
     state: Option<PagerState<'a, C, R>>,
+    page: Option<LastPage<<R::Item as PaginationItem>::Id>>,
 }
 
 impl<'a, C: Client + ?Sized, R: PaginationRequest> ForwardPager<'a, C, R> {
     pub(crate) fn new(client: &'a C, mut request: R) -> Self {
         request.set_limit(DEFAULT_PAGE_SIZE);
-        ForwardPager {
-            client,
-            state: Some(PagerState::Ready {
-                next_request: request,
-            }),
+        ForwardPager::resume(client, request, false)
+    }
+
+    fn resume(client: &'a C, request: R, done: bool) -> Self {
+        if done {
+            ForwardPager {
+                client,
+                page: Some(LastPage::done(PagerCursor::new(
+                    PagerKind::Forward,
+                    &request,
+                ))),
+                state: None,
+            }
+        } else {
+            ForwardPager {
+                client,
+                page: None,
+                state: Some(PagerState::Ready {
+                    next_request: request,
+                }),
+            }
         }
     }
 }
@@ -168,6 +319,7 @@ where
     C: Client + ?Sized,
     R: PaginationRequest + Unpin,
     R::Response: IntoIterator<Item = R::Item>,
+    <R::Item as PaginationItem>::Id: Unpin,
 {
     type Item = Result<Vec<R::Item>, Error<C::Error>>;
 
@@ -194,8 +346,16 @@ where
                         });
                         return Poll::Pending;
                     }
-                    Poll::Ready(res) => res.map_err(Error::Client)?.into_result()?,
+                    Poll::Ready(res) => match into_response(res) {
+                        Ok(response) => response,
+                        Err(err) => {
+                            let cursor = PagerCursor::new(PagerKind::Forward, &request);
+                            self.page = Some(LastPage::failed(cursor));
+                            return Poll::Ready(Some(Err(err)));
+                        }
+                    },
                 };
+                let cursor = PagerCursor::new(PagerKind::Forward, &request);
                 let response: Vec<_> = response.into_iter().collect();
                 if let Some(since) = response.last() {
                     request.set_since_id(since.item_id());
@@ -203,6 +363,8 @@ where
                         next_request: request,
                     });
                 }
+                let ids = response.iter().map(PaginationItem::item_id).collect();
+                self.page = Some(LastPage::new(cursor, None, ids));
                 Poll::Ready(Some(Ok(response)))
             }
             None => Poll::Ready(None),
@@ -215,6 +377,7 @@ where
     C: Client + ?Sized,
     R: PaginationRequest + Unpin,
     R::Response: IntoIterator<Item = R::Item>,
+    <R::Item as PaginationItem>::Id: Unpin,
 {
     fn is_terminated(&self) -> bool {
         self.state.is_none()
@@ -226,6 +389,7 @@ where
     C: Client + ?Sized,
     R: PaginationRequest + Unpin,
     R::Response: IntoIterator<Item = R::Item>,
+    <R::Item as PaginationItem>::Id: Serialize + Unpin,
 {
     type Content = R::Item;
     type Client = C;
@@ -237,23 +401,54 @@ where
             None => {}
         }
     }
+
+    fn cursor(&self, consumed: usize) -> Option<PagerCursor> {
+        match &self.page {
+            Some(page) if consumed < page.ids.len() => {
+                let mut cursor = page.cursor.clone()?;
+                if consumed > 0 {
+                    cursor.set_param("sinceId", to_value(&page.ids[consumed - 1]));
+                }
+                Some(cursor)
+            }
+            _ => next_cursor(PagerKind::Forward, &self.state, &self.page),
+        }
+    }
 }
 
 pub(crate) struct OffsetPager<'a, C: Client + ?Sized, R: Request> {
     client: &'a C,
     state: Option<PagerState<'a, C, R>>,
     total_count: u64,
+    page: Option<LastPage<()>>,
 }
 
 impl<'a, C: Client + ?Sized, R: OffsetPaginationRequest> OffsetPager<'a, C, R> {
     pub(crate) fn new(client: &'a C, mut request: R) -> Self {
         request.set_limit(DEFAULT_PAGE_SIZE);
-        OffsetPager {
-            client,
-            state: Some(PagerState::Ready {
-                next_request: request,
-            }),
-            total_count: 0,
+        OffsetPager::resume(client, request, 0, false)
+    }
+
+    fn resume(client: &'a C, request: R, offset: u64, done: bool) -> Self {
+        if done {
+            OffsetPager {
+                client,
+                page: Some(LastPage::done(PagerCursor::new(
+                    PagerKind::Offset,
+                    &request,
+                ))),
+                state: None,
+                total_count: offset,
+            }
+        } else {
+            OffsetPager {
+                client,
+                page: None,
+                state: Some(PagerState::Ready {
+                    next_request: request,
+                }),
+                total_count: offset,
+            }
         }
     }
 }
@@ -289,9 +484,21 @@ where
                         });
                         return Poll::Pending;
                     }
-                    Poll::Ready(res) => res.map_err(Error::Client)?.into_result()?,
+                    Poll::Ready(res) => match into_response(res) {
+                        Ok(response) => response,
+                        Err(err) => {
+                            let cursor = PagerCursor::new(PagerKind::Offset, &request);
+                            self.page = Some(LastPage::failed(cursor));
+                            return Poll::Ready(Some(Err(err)));
+                        }
+                    },
                 };
+                let mut cursor = PagerCursor::new(PagerKind::Offset, &request);
+                if let Some(cursor) = &mut cursor {
+                    cursor.set_param("offset", Some(self.total_count.into()));
+                }
                 let response: Vec<_> = response.into_iter().collect();
+                self.page = Some(LastPage::new(cursor, None, vec![(); response.len()]));
                 if !response.is_empty() {
                     self.total_count += response.len() as u64;
                     request.set_offset(self.total_count);
@@ -333,6 +540,24 @@ where
             None => {}
         }
     }
+
+    fn cursor(&self, consumed: usize) -> Option<PagerCursor> {
+        match &self.page {
+            Some(page) if consumed < page.ids.len() => {
+                let offset = self.total_count - (page.ids.len() - consumed) as u64;
+                let mut cursor = page.cursor.clone()?;
+                cursor.set_param("offset", Some(offset.into()));
+                Some(cursor)
+            }
+            _ => {
+                let mut cursor = next_cursor(PagerKind::Offset, &self.state, &self.page)?;
+                if !cursor.done {
+                    cursor.set_param("offset", Some(self.total_count.into()));
+                }
+                Some(cursor)
+            }
+        }
+    }
 }
 
 /// A stream of pages..
@@ -348,6 +573,15 @@ pub trait Pager:
 
     /// Sets the number of items to be fetched at once.
     fn set_page_size(self: Pin<&mut Self>, size: u8);
+
+    /// Returns the cursor to resume the pagination after the first `consumed` items of the
+    /// last page yielded by the pager have been processed.
+    ///
+    /// Returns `None` if the pager does not support cursors.
+    fn cursor(&self, consumed: usize) -> Option<PagerCursor> {
+        let _ = consumed;
+        None
+    }
 }
 
 impl<P: Pager + Unpin + ?Sized> Pager for &mut P {
@@ -357,6 +591,10 @@ impl<P: Pager + Unpin + ?Sized> Pager for &mut P {
     fn set_page_size(mut self: Pin<&mut Self>, size: u8) {
         P::set_page_size(Pin::new(&mut **self), size)
     }
+
+    fn cursor(&self, consumed: usize) -> Option<PagerCursor> {
+        P::cursor(&**self, consumed)
+    }
 }
 
 impl<P: Pager + Unpin + ?Sized> Pager for Box<P> {
@@ -366,6 +604,10 @@ impl<P: Pager + Unpin + ?Sized> Pager for Box<P> {
     fn set_page_size(mut self: Pin<&mut Self>, size: u8) {
         P::set_page_size(Pin::new(&mut **self), size)
     }
+
+    fn cursor(&self, consumed: usize) -> Option<PagerCursor> {
+        P::cursor(&**self, consumed)
+    }
 }
 
 impl<P> Pager for Pin<P>
@@ -379,8 +621,14 @@ where
     fn set_page_size(self: Pin<&mut Self>, size: u8) {
         <<P as Deref>::Target as Pager>::set_page_size(self.get_mut().as_mut(), size)
     }
+
+    fn cursor(&self, consumed: usize) -> Option<PagerCursor> {
+        <<P as Deref>::Target as Pager>::cursor(&**self, consumed)
+    }
 }
 
+/// The mapping should keep the items one to one, as `v.into_iter().map(f).collect()` does,
+/// so that the cursor points to the same position in the inner pager.
 impl<S, F, T> Pager for futures::stream::MapOk<S, F>
 where
     S: Pager + Unpin,
@@ -392,6 +640,10 @@ where
     fn set_page_size(mut self: Pin<&mut Self>, size: u8) {
         <S as Pager>::set_page_size(Pin::new(&mut *(*self).get_mut()), size)
     }
+
+    fn cursor(&self, consumed: usize) -> Option<PagerCursor> {
+        <S as Pager>::cursor(self.get_ref(), consumed)
+    }
 }
 
 /// An owned dynamically typed [`Pager`].
@@ -418,6 +670,7 @@ pub struct PagerStream<P: Pager> {
     pager: P,
     minimum_interval: Duration,
     state: Option<PagerStreamState<P>>,
+    page_len: usize,
 }
 
 impl<P: Pager> PagerStream<P> {
@@ -426,6 +679,7 @@ impl<P: Pager> PagerStream<P> {
             pager,
             minimum_interval: Duration::new(0, 0),
             state: Some(PagerStreamState::Fetch),
+            page_len: 0,
         }
     }
 
@@ -445,6 +699,21 @@ impl<P: Pager> PagerStream<P> {
         self.minimum_interval = minimum_interval;
     }
 
+    /// Returns the cursor to resume the pagination after the items yielded so far.
+    ///
+    /// Returns `None` if the inner pager does not support cursors.
+    /// See [`PagerCursor`] for the example.
+    ///
+    /// If the pages of the inner pager are mapped with [`map_ok`][`futures::TryStreamExt::map_ok`],
+    /// the position is correct only if the mapping keeps the number of the items in each page.
+    pub fn cursor(&self) -> Option<PagerCursor> {
+        let consumed = match &self.state {
+            Some(PagerStreamState::Ready { buffer, .. }) => self.page_len - buffer.len() - 1,
+            _ => self.page_len,
+        };
+        self.pager.cursor(consumed)
+    }
+
     /// Returns the inner pager.
     pub fn into_inner(self) -> P {
         self.pager
@@ -490,6 +759,7 @@ where
                 Poll::Ready(None) => Poll::Ready(None),
                 Poll::Ready(Some(Err(err))) => Poll::Ready(Some(Err(err))),
                 Poll::Ready(Some(Ok(page))) => {
+                    self.page_len = page.len();
                     let mut buffer: VecDeque<_> = page.into();
                     if let Some(item) = buffer.pop_front() {
                         self.state = Some(PagerStreamState::Ready {
@@ -527,3 +797,55 @@ where
         self.state.is_none()
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use crate::test_util::{id, user_json, MockClient};
+    use crate::ClientExt;
+
+    use futures::stream::TryStreamExt;
+    use misskey_api::endpoint::users::followers;
+    use misskey_api::model::user::User;
+    use serde_json::{json, Value};
+
+    /// Returns the page of the items from 5 to 1 in descending order of IDs, 3 items at once.
+    pub(super) fn page(request: &Value, item: impl Fn(u64) -> Value) -> Vec<Value> {
+        let until = (1..=5)
+            .find(|&n| request["untilId"] == json!(id::<()>(n)))
+            .unwrap_or(6);
+        (1..until).rev().take(3).map(item).collect()
+    }
+
+    fn following_json(n: u64) -> Value {
+        json!({
+            "id": id::<()>(n),
+            "createdAt": "2000-01-01T00:00:00Z",
+            "followeeId": id::<User>(0),
+            "followerId": id::<User>(n),
+            "follower": user_json(n, &format!("user{}", n), None),
+        })
+    }
+
+    #[tokio::test]
+    async fn test_mapped_cursor() {
+        let client = MockClient::new(|endpoint, request| {
+            assert_eq!(endpoint, "users/followers");
+            json!(page(request, following_json))
+        });
+        let mut followers = client.followers(id::<User>(0));
+        let first = followers.try_next().await.unwrap().unwrap();
+        assert_eq!(first.id, id(5));
+
+        // the cursor continues after the consumed item, not from the start of the page
+        let cursor = followers.cursor().unwrap();
+        assert_eq!(cursor.params()["untilId"], json!(id::<User>(5)));
+        let resumed: Vec<_> = cursor
+            .resume::<followers::RequestWithUserId, _>(&client)
+            .unwrap()
+            .try_collect()
+            .await
+            .unwrap();
+        let ids: Vec<_> = resumed.iter().map(|f| f.follower.id).collect();
+        assert_eq!(ids, [id(4), id(3), id(2), id(1)]);
+    }
+}
@@ -0,0 +1,341 @@
+use std::fmt::{self, Display};
+use std::marker::PhantomData;
+
+use super::{BackwardPager, BoxPager, ForwardPager, OffsetPager, PagerStream};
+
+use misskey_api::{OffsetPaginationRequest, PaginationItem, PaginationRequest};
+use misskey_core::{Client, Request};
+use serde::de::DeserializeOwned;
+use serde::{Deserialize, Serialize, Serializer};
+use serde_json::{Map, Value};
+
+/// The way a pager proceeds, recorded in [`PagerCursor`].
+#[derive(Serialize, Deserialize, Debug, Clone, Copy, PartialEq, Eq)]
+#[serde(rename_all = "camelCase")]
+pub(super) enum PagerKind {
+    Backward,
+    Forward,
+    Offset,
+}
+
+/// A serializable position of a pager.
+///
+/// This is obtained with [`PagerStream::cursor`] and holds the endpoint, the request parameters
+/// and the position such as `untilId`, `sinceId` or `offset`, so that long-running jobs can save
+/// it to disk and resume the pagination later with [`resume`][`PagerCursor::resume`] or
+/// [`resume_offset`][`PagerCursor::resume_offset`].
+///
+/// # Examples
+///
+/// ```no_run
+/// # #[tokio::main]
+/// # async fn main() -> anyhow::Result<()> {
+/// # let client = misskey_test::test_client().await?;
+/// use futures::stream::TryStreamExt;
+/// use misskey_api::endpoint::notes::local_timeline;
+/// use misskey_util::pager::PagerCursor;
+/// use misskey_util::ClientExt;
+///
+/// let mut notes = match std::fs::read_to_string("cursor.json") {
+///     Ok(json) => {
+///         let cursor: PagerCursor = serde_json::from_str(&json)?;
+///         cursor.resume::<local_timeline::Request, _>(&client)?
+///     }
+///     Err(_) => client.local_notes(..),
+/// };
+/// while let Some(note) = notes.try_next().await? {
+///     // process the note...
+///     if let Some(cursor) = notes.cursor() {
+///         std::fs::write("cursor.json", serde_json::to_string(&cursor)?)?;
+///     }
+/// }
+/// # Ok(())
+/// # }
+/// ```
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq)]
+#[serde(rename_all = "camelCase")]
+pub struct PagerCursor {
+    pub(super) endpoint: String,
+    pub(super) kind: PagerKind,
+    pub(super) params: Map<String, Value>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub(super) since_id: Option<Value>,
+    #[serde(default)]
+    pub(super) done: bool,
+}
+
+impl PagerCursor {
+    pub(super) fn new<R: Request>(kind: PagerKind, request: &R) -> Option<Self> {
+        match serde_json::to_value(request) {
+            Ok(Value::Object(params)) => Some(PagerCursor {
+                endpoint: R::ENDPOINT.to_string(),
+                kind,
+                params,
+                since_id: None,
+                done: false,
+            }),
+            _ => None,
+        }
+    }
+
+    pub(super) fn set_param(&mut self, name: &str, value: Option<Value>) {
+        match value {
+            Some(value) => self.params.insert(name.to_string(), value),
+            None => self.params.remove(name),
+        };
+    }
+
+    /// Returns the endpoint the pager requests.
+    pub fn endpoint(&self) -> &str {
+        &self.endpoint
+    }
+
+    /// Returns the parameters of the next request, including the position of the pager.
+    pub fn params(&self) -> &Map<String, Value> {
+        &self.params
+    }
+
+    /// Returns `true` if the pager has reached the end.
+    pub fn is_done(&self) -> bool {
+        self.done
+    }
+
+    fn check<R: Request>(&self, kinds: &[PagerKind]) -> Result<(), InvalidCursorError> {
+        if self.endpoint != R::ENDPOINT {
+            return Err(InvalidCursorError {
+                message: format!(
+                    "the cursor is for `{}`, not `{}`",
+                    self.endpoint,
+                    R::ENDPOINT
+                ),
+            });
+        }
+        if !kinds.contains(&self.kind) {
+            return Err(InvalidCursorError {
+                message: format!("the cursor is for {:?} pagination", self.kind),
+            });
+        }
+        Ok(())
+    }
+
+    /// Resumes the pagination with `since_id` and `until_id` from the cursor.
+    ///
+    /// `R` is the request type of the endpoint, such as
+    /// [`notes::local_timeline::Request`][`misskey_api::endpoint::notes::local_timeline::Request`]
+    /// for [`local_notes`][`crate::ClientExt::local_notes`].
+    pub fn resume<'a, R, C>(
+        &self,
+        client: &'a C,
+    ) -> Result<PagerStream<BoxPager<'a, C, R::Item>>, InvalidCursorError>
+    where
+        R: PaginationRequest + 'a,
+        R::Response: IntoIterator<Item = R::Item>,
+        R::Item: Send + 'a,
+        <R::Item as PaginationItem>::Id: Serialize + DeserializeOwned + Clone + Send + Unpin,
+        C: Client + Sync + ?Sized,
+    {
+        self.check::<R>(&[PagerKind::Backward, PagerKind::Forward])?;
+        let request = ResumedRequest::<R>::new(self.params.clone());
+        let pager: BoxPager<'a, C, R::Item> = if self.kind == PagerKind::Backward {
+            let since_id =
+                match &self.since_id {
+                    Some(id) => Some(serde_json::from_value(id.clone()).map_err(|e| {
+                        InvalidCursorError {
+                            message: e.to_string(),
+                        }
+                    })?),
+                    None => None,
+                };
+            Box::pin(BackwardPager::resume(client, since_id, request, self.done))
+        } else {
+            Box::pin(ForwardPager::resume(client, request, self.done))
+        };
+        Ok(PagerStream::new(pager))
+    }
+
+    /// Resumes the pagination with `offset` from the cursor.
+    ///
+    /// See [`resume`][`PagerCursor::resume`] for `R`.
+    pub fn resume_offset<'a, R, C>(
+        &self,
+        client: &'a C,
+    ) -> Result<PagerStream<BoxPager<'a, C, R::Item>>, InvalidCursorError>
+    where
+        R: OffsetPaginationRequest + 'a,
+        R::Response: IntoIterator<Item = R::Item>,
+        R::Item: Send + 'a,
+        C: Client + Sync + ?Sized,
+    {
+        self.check::<R>(&[PagerKind::Offset])?;
+        let offset = self
+            .params
+            .get("offset")
+            .and_then(Value::as_u64)
+            .unwrap_or(0);
+        let request = ResumedRequest::<R>::new(self.params.clone());
+        let pager = OffsetPager::resume(client, request, offset, self.done);
+        Ok(PagerStream::new(Box::pin(pager)))
+    }
+}
+
+/// Error type for resuming pagers from [`PagerCursor`].
+#[derive(Debug, Clone)]
+pub struct InvalidCursorError {
+    message: String,
+}
+
+impl std::error::Error for InvalidCursorError {}
+
+impl Display for InvalidCursorError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "invalid pager cursor: {}", self.message)
+    }
+}
+
+/// A request of `R` built from the parameters saved in [`PagerCursor`].
+struct ResumedRequest<R> {
+    params: Map<String, Value>,
+    _request: PhantomData<fn() -> R>,
+}
+
+impl<R> ResumedRequest<R> {
+    fn new(params: Map<String, Value>) -> Self {
+        ResumedRequest {
+            params,
+            _request: PhantomData,
+        }
+    }
+
+    fn set(&mut self, name: &str, value: impl Serialize) {
+        if let Ok(value) = serde_json::to_value(value) {
+            self.params.insert(name.to_string(), value);
+        }
+    }
+}
+
+impl<R> Serialize for ResumedRequest<R> {
+    fn serialize<S: Serializer>(&self, serializer: S) -> Result<S::Ok, S::Error> {
+        self.params.serialize(serializer)
+    }
+}
+
+impl<R: Request> Request for ResumedRequest<R> {
+    type Response = R::Response;
+    const ENDPOINT: &'static str = R::ENDPOINT;
+}
+
+impl<R> PaginationRequest for ResumedRequest<R>
+where
+    R: PaginationRequest,
+    <R::Item as PaginationItem>::Id: Serialize,
+{
+    type Item = R::Item;
+
+    fn set_since_id(&mut self, since_id: <R::Item as PaginationItem>::Id) {
+        self.set("sinceId", since_id);
+    }
+
+    fn set_until_id(&mut self, until_id: <R::Item as PaginationItem>::Id) {
+        self.set("untilId", until_id);
+    }
+
+    fn set_limit(&mut self, limit: u8) {
+        self.set("limit", limit);
+    }
+}
+
+impl<R: OffsetPaginationRequest> OffsetPaginationRequest for ResumedRequest<R> {
+    type Item = R::Item;
+
+    fn set_offset(&mut self, offset: u64) {
+        self.set("offset", offset);
+    }
+
+    fn set_limit(&mut self, limit: u8) {
+        self.set("limit", limit);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{PagerCursor, PagerKind};
+    use crate::pager::tests::page;
+    use crate::test_util::{drive_file_json, id, MockClient};
+    use crate::ClientExt;
+
+    use futures::stream::TryStreamExt;
+    use misskey_api::endpoint::drive;
+    use serde_json::json;
+
+    fn drive_client() -> MockClient {
+        MockClient::new(|endpoint, request| {
+            assert_eq!(endpoint, "drive/files");
+            json!(page(request, |n| drive_file_json(n, "file", "", None)))
+        })
+    }
+
+    #[test]
+    fn test_serialize() {
+        let request = drive::files::Request::builder().limit(10).build();
+        let cursor = PagerCursor::new(PagerKind::Backward, &request).unwrap();
+        let mut value = serde_json::to_value(&cursor).unwrap();
+        assert_eq!(value["endpoint"], "drive/files");
+        assert_eq!(value["kind"], "backward");
+        assert_eq!(value["params"]["limit"], 10);
+        assert_eq!(value["done"], false);
+        assert!(value.get("sinceId").is_none());
+        assert_eq!(
+            serde_json::from_value::<PagerCursor>(value.clone()).unwrap(),
+            cursor
+        );
+
+        value.as_object_mut().unwrap().remove("done");
+        let cursor: PagerCursor = serde_json::from_value(value).unwrap();
+        assert!(!cursor.is_done());
+        assert!(serde_json::from_value::<PagerCursor>(json!({ "endpoint": "x" })).is_err());
+    }
+
+    #[tokio::test]
+    async fn test_resume() {
+        let client = drive_client();
+        let builder = ClientExt::files(&client);
+        let mut files = builder.list();
+        for n in [5, 4] {
+            assert_eq!(files.try_next().await.unwrap().unwrap().id, id(n));
+        }
+        let cursor = files.cursor().unwrap();
+        assert_eq!(cursor.endpoint(), "drive/files");
+        assert_eq!(cursor.params()["untilId"], json!(id::<()>(4)));
+
+        // resume from the saved cursor in the middle of the page
+        let json = serde_json::to_string(&cursor).unwrap();
+        let cursor: PagerCursor = serde_json::from_str(&json).unwrap();
+        let mut files = cursor.resume::<drive::files::Request, _>(&client).unwrap();
+        let mut ids = Vec::new();
+        while let Some(file) = files.try_next().await.unwrap() {
+            ids.push(file.id);
+        }
+        assert_eq!(ids, [id(3), id(2), id(1)]);
+
+        // the cursor at the end does not request anything
+        let cursor = files.cursor().unwrap();
+        assert!(cursor.is_done());
+        let requests = client.requests().len();
+        let mut files = cursor.resume::<drive::files::Request, _>(&client).unwrap();
+        assert!(files.try_next().await.unwrap().is_none());
+        assert_eq!(client.requests().len(), requests);
+    }
+
+    #[test]
+    fn test_resume_other_endpoint() {
+        let client = drive_client();
+        let request = drive::files::Request::default();
+        let cursor = PagerCursor::new(PagerKind::Backward, &request).unwrap();
+        assert!(cursor
+            .resume::<drive::folders::Request, _>(&client)
+            .is_err());
+        assert!(cursor
+            .resume_offset::<misskey_api::endpoint::admin::show_users::Request, _>(&client)
+            .is_err());
+    }
+}
@@ -0,0 +1,346 @@
+use std::collections::VecDeque;
+use std::pin::Pin;
+use std::task::{Context, Poll};
+
+use crate::TimelineRange;
+
+use chrono::{DateTime, Duration, Utc};
+use futures::stream::{FusedStream, Stream, TryStream, TryStreamExt};
+use misskey_api::{model::id::Id, PaginationItem};
+
+const DEFAULT_CONCURRENCY: usize = 4;
+
+/// The number of items fetched ahead in each window after the first one, which is the maximum
+/// page size of Misskey.
+const PREFETCH_LIMIT: usize = 100;
+
+/// Splits `since..until` into windows of `window` from the newest one.
+///
+/// The windows overlap by a millisecond, since the IDs created in the same millisecond as the
+/// boundary can fall on either side of it.
+fn split_dates(
+    since: DateTime<Utc>,
+    until: DateTime<Utc>,
+    window: Duration,
+) -> Vec<(DateTime<Utc>, DateTime<Utc>)> {
+    let mut windows = Vec::new();
+    let mut upper = until;
+    while upper > since {
+        let lower = std::cmp::max(since, upper - window);
+        let until_date = if windows.is_empty() {
+            upper
+        } else {
+            upper + Duration::milliseconds(1)
+        };
+        windows.push((lower, until_date));
+        upper = lower;
+    }
+    windows
+}
+
+/// A window of the timeline being fetched.
+struct Window<S: TryStream> {
+    stream: S,
+    buffer: VecDeque<S::Ok>,
+    done: bool,
+}
+
+/// A stream that fetches the windows of a timeline concurrently and yields the items in order.
+///
+/// [`PagerStream`][`super::PagerStream`] fetches pages strictly one after another. For the
+/// ranges bounded by dates or IDs, `PrefetchStream` partitions the range into time windows,
+/// fetches up to [`concurrency`][`PrefetchStream::set_concurrency`] of them in parallel, and
+/// stitches them back into the same order as the timeline (i.e. the new item comes first),
+/// without duplicates. The windows after the first one are fetched ahead by about a page.
+///
+/// The range must have the lower bound to be partitioned. If it does not, the whole range is
+/// fetched as one window.
+///
+/// # Examples
+///
+/// ```no_run
+/// # #[tokio::main]
+/// # async fn main() -> anyhow::Result<()> {
+/// # let client = misskey_test::test_client().await?;
+/// use chrono::{Duration, Utc};
+/// use futures::stream::TryStreamExt;
+/// use misskey_util::pager::PrefetchStream;
+/// use misskey_util::ClientExt;
+///
+/// let since = Utc::now() - Duration::days(30);
+/// // Fetch the local notes in the last 30 days, one day per window.
+/// let mut notes = PrefetchStream::new(since.., Duration::days(1), |window| {
+///     let mut notes = client.local_notes(window);
+///     notes.set_page_size(100);
+///     notes
+/// });
+/// notes.set_concurrency(8);
+///
+/// while let Some(note) = notes.try_next().await? {
+///     println!("{}: {:?}", note.created_at, note.text);
+/// }
+/// # Ok(())
+/// # }
+/// ```
+pub struct PrefetchStream<S: TryStream>
+where
+    S::Ok: PaginationItem,
+{
+    windows: VecDeque<S>,
+    active: VecDeque<Window<S>>,
+    concurrency: usize,
+    since_id: Option<<S::Ok as PaginationItem>::Id>,
+    until_id: Option<<S::Ok as PaginationItem>::Id>,
+    last_id: Option<<S::Ok as PaginationItem>::Id>,
+    terminated: bool,
+}
+
+impl<S, E> PrefetchStream<S>
+where
+    S: TryStream<Ok = E>,
+    E: PaginationItem<Id = Id<E>>,
+{
+    /// Creates a stream of the items in `range`, partitioned into windows of `window`.
+    ///
+    /// `fetch` is called for each window to create a stream of the items in the window in the
+    /// same order as the timeline, such as the one returned from
+    /// [`local_notes`][`crate::ClientExt::local_notes`]. The ranges given to `fetch` are bounded
+    /// by dates, and the ranges bounded by IDs are converted with the date of the IDs.
+    ///
+    /// # Panics
+    ///
+    /// Panics if `window` is not positive.
+    pub fn new<F>(range: impl Into<TimelineRange<E>>, window: Duration, fetch: F) -> Self
+    where
+        F: FnMut(TimelineRange<E>) -> S,
+    {
+        assert!(window > Duration::zero(), "window must be positive");
+
+        let (since_date, until_date, since_id, until_id) = match range.into() {
+            TimelineRange::DateTime {
+                since_date,
+                until_date,
+            } => (since_date, until_date, None, None),
+            // the dates are widened by a millisecond since the IDs are filtered afterwards
+            TimelineRange::Id { since_id, until_id } => (
+                since_id.map(|id| id.datetime() - Duration::milliseconds(1)),
+                until_id.map(|id| id.datetime() + Duration::milliseconds(1)),
+                since_id,
+                until_id,
+            ),
+            TimelineRange::Unbounded => (None, None, None, None),
+        };
+        let until_date = until_date.unwrap_or_else(Utc::now);
+
+        let windows = match since_date {
+            Some(since_date) => split_dates(since_date, until_date, window)
+                .into_iter()
+                .map(|(since_date, until_date)| TimelineRange::DateTime {
+                    since_date: Some(since_date),
+                    until_date: Some(until_date),
+                })
+                .collect(),
+            None => vec![TimelineRange::DateTime {
+                since_date: None,
+                until_date: Some(until_date),
+            }],
+        };
+
+        PrefetchStream {
+            windows: windows.into_iter().map(fetch).collect(),
+            active: VecDeque::new(),
+            concurrency: DEFAULT_CONCURRENCY,
+            since_id,
+            until_id,
+            last_id: None,
+            terminated: false,
+        }
+    }
+}
+
+impl<S: TryStream> PrefetchStream<S>
+where
+    S::Ok: PaginationItem,
+{
+    /// Sets the maximum number of windows to be fetched concurrently.
+    ///
+    /// The default is 4. Setting this to 1 makes it fetch the windows one by one.
+    pub fn set_concurrency(&mut self, concurrency: usize) {
+        self.concurrency = concurrency.max(1);
+    }
+
+    /// Returns `true` if the item is in the range and comes strictly after the last item.
+    fn accept(&self, id: &<S::Ok as PaginationItem>::Id) -> bool {
+        !matches!(&self.since_id, Some(since_id) if id <= since_id)
+            && !matches!(&self.until_id, Some(until_id) if id >= until_id)
+            && !matches!(&self.last_id, Some(last_id) if id >= last_id)
+    }
+}
+
+impl<S> Stream for PrefetchStream<S>
+where
+    S: TryStream + Unpin,
+    S::Ok: PaginationItem + Unpin,
+    <S::Ok as PaginationItem>::Id: Unpin,
+{
+    type Item = Result<S::Ok, S::Error>;
+
+    fn poll_next(mut self: Pin<&mut Self>, cx: &mut Context) -> Poll<Option<Self::Item>> {
+        let this = &mut *self;
+        loop {
+            if this.terminated {
+                return Poll::Ready(None);
+            }
+
+            while this.active.len() < this.concurrency {
+                match this.windows.pop_front() {
+                    Some(stream) => this.active.push_back(Window {
+                        stream,
+                        buffer: VecDeque::new(),
+                        done: false,
+                    }),
+                    None => break,
+                }
+            }
+
+            // poll all the windows so that the following ones are fetched in the meantime, but
+            // only about a page ahead so that they do not pile up while the first one is read
+            for (i, window) in this.active.iter_mut().enumerate() {
+                while !window.done && (i == 0 || window.buffer.len() < PREFETCH_LIMIT) {
+                    match window.stream.try_poll_next_unpin(cx) {
+                        Poll::Ready(Some(Ok(item))) => window.buffer.push_back(item),
+                        Poll::Ready(Some(Err(err))) => {
+                            this.terminated = true;
+                            this.windows.clear();
+                            this.active.clear();
+                            return Poll::Ready(Some(Err(err)));
+                        }
+                        Poll::Ready(None) => window.done = true,
+                        Poll::Pending => break,
+                    }
+                }
+            }
+
+            let item = match this.active.front_mut() {
+                Some(head) => match head.buffer.pop_front() {
+                    Some(item) => item,
+                    None if head.done => {
+                        this.active.pop_front();
+                        continue;
+                    }
+                    None => return Poll::Pending,
+                },
+                None => {
+                    this.terminated = true;
+                    return Poll::Ready(None);
+                }
+            };
+            let id = item.item_id();
+            if this.accept(&id) {
+                this.last_id = Some(id);
+                return Poll::Ready(Some(Ok(item)));
+            }
+        }
+    }
+}
+
+impl<S> FusedStream for PrefetchStream<S>
+where
+    S: TryStream + Unpin,
+    S::Ok: PaginationItem + Unpin,
+    <S::Ok as PaginationItem>::Id: Unpin,
+{
+    fn is_terminated(&self) -> bool {
+        self.terminated
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{split_dates, PrefetchStream, PREFETCH_LIMIT};
+
+    use std::collections::VecDeque;
+    use std::task::{Context, Poll};
+
+    use chrono::{Duration, TimeZone, Utc};
+    use futures::stream::{self, BoxStream, StreamExt};
+    use futures::task::noop_waker_ref;
+    use misskey_api::PaginationItem;
+
+    #[derive(Debug, PartialEq)]
+    struct Item(u64);
+
+    impl PaginationItem for Item {
+        type Id = u64;
+        fn item_id(&self) -> u64 {
+            self.0
+        }
+    }
+
+    type ItemStream = BoxStream<'static, Result<Item, ()>>;
+
+    fn prefetch_stream(windows: Vec<ItemStream>) -> PrefetchStream<ItemStream> {
+        PrefetchStream {
+            windows: windows.into(),
+            active: VecDeque::new(),
+            concurrency: 4,
+            since_id: None,
+            until_id: None,
+            last_id: None,
+            terminated: false,
+        }
+    }
+
+    fn items(ids: Vec<u64>) -> ItemStream {
+        stream::iter(ids.into_iter().map(|id| Ok(Item(id)))).boxed()
+    }
+
+    #[test]
+    fn test_split_dates() {
+        let since = Utc.timestamp_opt(0, 0).unwrap();
+        let until = Utc.timestamp_opt(10, 0).unwrap();
+        let windows = split_dates(since, until, Duration::seconds(4));
+        let millis = |(since, until): (chrono::DateTime<Utc>, chrono::DateTime<Utc>)| {
+            (since.timestamp_millis(), until.timestamp_millis())
+        };
+        assert_eq!(
+            windows.into_iter().map(millis).collect::<Vec<_>>(),
+            // each window overlaps the newer one by a millisecond
+            vec![(6000, 10000), (2000, 6001), (0, 2001)]
+        );
+        assert!(split_dates(until, until, Duration::seconds(4)).is_empty());
+    }
+
+    #[test]
+    fn test_boundary_dedupe() {
+        // the items created in the millisecond of the boundary are fetched in both windows
+        let mut stream = prefetch_stream(vec![
+            items(vec![9, 8, 7]),
+            items(vec![7, 6]),
+            items(vec![6, 5]),
+        ]);
+        let mut cx = Context::from_waker(noop_waker_ref());
+        let mut ids = Vec::new();
+        while let Poll::Ready(Some(item)) = stream.poll_next_unpin(&mut cx) {
+            ids.push(item.unwrap().0);
+        }
+        assert_eq!(ids, vec![9, 8, 7, 6, 5]);
+    }
+
+    #[test]
+    fn test_prefetch_limit() {
+        let mut stream = prefetch_stream(vec![
+            stream::pending().boxed(),
+            items((0..1000).rev().collect()),
+        ]);
+        let mut cx = Context::from_waker(noop_waker_ref());
+        assert!(stream.poll_next_unpin(&mut cx).is_pending());
+        assert!(stream.poll_next_unpin(&mut cx).is_pending());
+        let buffered: Vec<usize> = stream
+            .active
+            .iter()
+            .map(|window| window.buffer.len())
+            .collect();
+        assert_eq!(buffered, vec![0, PREFETCH_LIMIT]);
+    }
+}
@@ -0,0 +1,90 @@
+//! Utilities for roles.
+//!
+//! The conditions of conditional roles ([`RoleCondFormulaValue`]) can be written in a small
+//! expression language with [`parse_formula`] and turned back into it with
+//! [`formula_to_string`], which makes them easy to review.
+//! The conditions are combined with `&&`, `||`, `!` and parentheses, and each condition is one
+//! of the following:
+//!
+//! | Expression | Condition |
+//! |------------|-----------|
+//! | `isLocal`, `isRemote` | [`IsLocal`][`RoleCondFormulaValue::IsLocal`], [`IsRemote`][`RoleCondFormulaValue::IsRemote`] |
+//! | `createdLessThan(7d)`, `createdMoreThan(1d12h)` | [`CreatedLessThan`][`RoleCondFormulaValue::CreatedLessThan`], [`CreatedMoreThan`][`RoleCondFormulaValue::CreatedMoreThan`] |
+//! | `followers <= 10`, `followers >= 100` | [`FollowersLessThanOrEq`][`RoleCondFormulaValue::FollowersLessThanOrEq`], [`FollowersMoreThanOrEq`][`RoleCondFormulaValue::FollowersMoreThanOrEq`] |
+//! | `following <= 10`, `following >= 100` | [`FollowingLessThanOrEq`][`RoleCondFormulaValue::FollowingLessThanOrEq`], [`FollowingMoreThanOrEq`][`RoleCondFormulaValue::FollowingMoreThanOrEq`] |
+//! | `notes <= 10`, `notes >= 100` | `NotesLessThanOrEq`, `NotesMoreThanOrEq` (13.10.0 or later) |
+//! | `true`, `false` | empty `And` and `Or` |
+//!
+//! Durations are written with the units `w`, `d`, `h`, `m` and `s`, and can be negative as in
+//! `-1d`.
+//!
+//! [`evaluate`] and [`applies`] check the conditions against users locally, to see which users
+//! would get the roles before they are deployed.
+//! [`PolicyResolver`] computes the policies in effect for a user from the default policies and
+//! the roles of the user, and tells which role decided each value.
+//!
+//! # Examples
+//!
+//! ```
+//! use chrono::Duration;
+//! use misskey_api::model::role::RoleCondFormulaValue;
+//! use misskey_util::role;
+//!
+//! let formula = role::parse_formula("isLocal && followers >= 100 && !createdLessThan(7d)")?;
+//! assert!(matches!(
+//!     &formula,
+//!     RoleCondFormulaValue::And { values } if values.len() == 3
+//! ));
+//! assert_eq!(
+//!     role::formula_to_string(&formula),
+//!     "isLocal && followers >= 100 && !createdLessThan(7d)"
+//! );
+//! # Ok::<(), misskey_util::role::ParseFormulaError>(())
+//! ```
+//!
+//! ```no_run
+//! # #[tokio::main]
+//! # async fn main() -> anyhow::Result<()> {
+//! # let client = misskey_test::test_admin_client().await?;
+//! use chrono::Utc;
+//! use misskey_util::{role, ClientExt};
+//!
+//! let formula = role::parse_formula("isRemote || followers >= 10")?;
+//! let user = client.get_user(client.me().await?.id).await?;
+//! match role::evaluate(&formula, &user, Utc::now()) {
+//!     Some(true) => println!("the role applies"),
+//!     Some(false) => println!("the role does not apply"),
+//!     None => println!("cannot tell from the user"),
+//! }
+//! # Ok(())
+//! # }
+//! ```
+
+use misskey_api::model::role::RoleCondFormulaValue;
+
+mod eval;
+mod formula;
+mod policy;
+
+pub use eval::{applies, evaluate};
+pub use formula::ParseFormulaError;
+pub use policy::{EffectivePolicies, PolicyDecision, PolicyResolver, PolicySource};
+
+/// Parses the condition of conditional roles from the expression.
+///
+/// See the [module documentation][self] for the syntax. The `!` and parentheses can be nested
+/// up to 128 levels.
+pub fn parse_formula(s: &str) -> Result<RoleCondFormulaValue, ParseFormulaError> {
+    formula::Parser::new(s).parse()
+}
+
+/// Turns the condition of conditional roles into the expression.
+///
+/// Nested conjunctions and disjunctions are kept in parentheses, so that
+/// [`parse_formula`] gives back the same structure. The exception is a conjunction or
+/// disjunction of a single condition, which is written as the condition itself.
+pub fn formula_to_string(formula: &RoleCondFormulaValue) -> String {
+    let mut out = String::new();
+    formula::write_formula(&mut out, formula).unwrap();
+    out
+}
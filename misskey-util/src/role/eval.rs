@@ -0,0 +1,78 @@
+use chrono::{DateTime, Utc};
+use misskey_api::model::{
+    role::{Role, RoleCondFormulaValue, Target},
+    user::User,
+};
+
+/// Evaluates the condition for the user as of `now`, as Misskey does for conditional roles.
+///
+/// The creation date and the counts of the followers, following and notes are only included
+/// in the detailed users, such as the ones returned from
+/// [`get_user`][`crate::ClientExt::get_user`].
+/// Returns `None` if the result depends on any of them missing in `user`.
+pub fn evaluate(formula: &RoleCondFormulaValue, user: &User, now: DateTime<Utc>) -> Option<bool> {
+    match formula {
+        RoleCondFormulaValue::And { values } => {
+            let mut result = Some(true);
+            for value in values {
+                match evaluate(value, user, now) {
+                    Some(false) => return Some(false),
+                    None => result = None,
+                    Some(true) => {}
+                }
+            }
+            result
+        }
+        RoleCondFormulaValue::Or { values } => {
+            let mut result = Some(false);
+            for value in values {
+                match evaluate(value, user, now) {
+                    Some(true) => return Some(true),
+                    None => result = None,
+                    Some(false) => {}
+                }
+            }
+            result
+        }
+        RoleCondFormulaValue::Not { value } => evaluate(value, user, now).map(|b| !b),
+        RoleCondFormulaValue::IsLocal => Some(user.host.is_none()),
+        RoleCondFormulaValue::IsRemote => Some(user.host.is_some()),
+        RoleCondFormulaValue::CreatedLessThan { duration } => user
+            .created_at
+            .map(|created_at| created_at > now - *duration),
+        RoleCondFormulaValue::CreatedMoreThan { duration } => user
+            .created_at
+            .map(|created_at| created_at < now - *duration),
+        RoleCondFormulaValue::FollowersLessThanOrEq { value } => {
+            user.followers_count.map(|count| count <= *value)
+        }
+        RoleCondFormulaValue::FollowersMoreThanOrEq { value } => {
+            user.followers_count.map(|count| count >= *value)
+        }
+        RoleCondFormulaValue::FollowingLessThanOrEq { value } => {
+            user.following_count.map(|count| count <= *value)
+        }
+        RoleCondFormulaValue::FollowingMoreThanOrEq { value } => {
+            user.following_count.map(|count| count >= *value)
+        }
+        #[cfg(feature = "13-10-0")]
+        RoleCondFormulaValue::NotesLessThanOrEq { value } => {
+            user.notes_count.map(|count| count <= *value)
+        }
+        #[cfg(feature = "13-10-0")]
+        RoleCondFormulaValue::NotesMoreThanOrEq { value } => {
+            user.notes_count.map(|count| count >= *value)
+        }
+    }
+}
+
+/// Returns whether the conditional role would be assigned to the user as of `now`.
+///
+/// Returns `Some(false)` for the roles without conditions, since they are only assigned
+/// manually. See [`evaluate`] for the case of `None`.
+pub fn applies(role: &Role, user: &User, now: DateTime<Utc>) -> Option<bool> {
+    match (&role.target, &role.cond_formula) {
+        (Target::Conditional, Some(formula)) => evaluate(formula, user, now),
+        _ => Some(false),
+    }
+}
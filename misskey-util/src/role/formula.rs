@@ -0,0 +1,452 @@
+use std::fmt::{self, Display, Write};
+
+use chrono::Duration;
+use misskey_api::model::role::RoleCondFormulaValue;
+
+/// Error type for [`parse_formula`][`super::parse_formula`].
+#[derive(Debug, Clone)]
+pub struct ParseFormulaError {
+    position: usize,
+    message: String,
+}
+
+impl ParseFormulaError {
+    /// Returns the byte offset in the input where the error occurred.
+    pub fn position(&self) -> usize {
+        self.position
+    }
+}
+
+impl std::error::Error for ParseFormulaError {}
+
+impl Display for ParseFormulaError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(
+            f,
+            "invalid role condition at {}: {}",
+            self.position, self.message
+        )
+    }
+}
+
+fn parse_error(position: usize, message: impl Into<String>) -> ParseFormulaError {
+    ParseFormulaError {
+        position,
+        message: message.into(),
+    }
+}
+
+const DURATION_UNITS: &[(char, i64)] = &[
+    ('w', 604800),
+    ('d', 86400),
+    ('h', 3600),
+    ('m', 60),
+    ('s', 1),
+];
+
+/// The maximum depth of the nested `!` and parentheses, which keeps the recursion of the parser
+/// within the stack.
+const MAX_DEPTH: usize = 128;
+
+pub(super) struct Parser<'a> {
+    input: &'a str,
+    pos: usize,
+    depth: usize,
+}
+
+impl<'a> Parser<'a> {
+    pub(super) fn new(input: &'a str) -> Self {
+        Parser {
+            input,
+            pos: 0,
+            depth: 0,
+        }
+    }
+
+    pub(super) fn parse(mut self) -> Result<RoleCondFormulaValue, ParseFormulaError> {
+        let value = self.parse_or()?;
+        self.skip_whitespace();
+        if self.pos < self.input.len() {
+            return Err(parse_error(self.pos, "unexpected input"));
+        }
+        Ok(value)
+    }
+
+    fn rest(&self) -> &'a str {
+        &self.input[self.pos..]
+    }
+
+    fn skip_whitespace(&mut self) {
+        let rest = self.rest();
+        self.pos += rest.len() - rest.trim_start().len();
+    }
+
+    fn eat(&mut self, token: &str) -> bool {
+        self.skip_whitespace();
+        if self.rest().starts_with(token) {
+            self.pos += token.len();
+            true
+        } else {
+            false
+        }
+    }
+
+    fn expect(&mut self, token: &str) -> Result<(), ParseFormulaError> {
+        if self.eat(token) {
+            Ok(())
+        } else {
+            Err(parse_error(self.pos, format!("expected `{}`", token)))
+        }
+    }
+
+    fn take_while(&mut self, f: impl Fn(char) -> bool) -> &'a str {
+        let rest = self.rest();
+        let len = rest.find(|c| !f(c)).unwrap_or(rest.len());
+        self.pos += len;
+        &rest[..len]
+    }
+
+    fn parse_or(&mut self) -> Result<RoleCondFormulaValue, ParseFormulaError> {
+        let mut values = vec![self.parse_and()?];
+        while self.eat("||") {
+            values.push(self.parse_and()?);
+        }
+        Ok(if values.len() == 1 {
+            values.pop().unwrap()
+        } else {
+            RoleCondFormulaValue::Or { values }
+        })
+    }
+
+    fn parse_and(&mut self) -> Result<RoleCondFormulaValue, ParseFormulaError> {
+        let mut values = vec![self.parse_unary()?];
+        while self.eat("&&") {
+            values.push(self.parse_unary()?);
+        }
+        Ok(if values.len() == 1 {
+            values.pop().unwrap()
+        } else {
+            RoleCondFormulaValue::And { values }
+        })
+    }
+
+    fn nested<T>(
+        &mut self,
+        start: usize,
+        f: impl FnOnce(&mut Self) -> Result<T, ParseFormulaError>,
+    ) -> Result<T, ParseFormulaError> {
+        if self.depth >= MAX_DEPTH {
+            return Err(parse_error(start, "too deeply nested"));
+        }
+        self.depth += 1;
+        let result = f(self);
+        self.depth -= 1;
+        result
+    }
+
+    fn parse_unary(&mut self) -> Result<RoleCondFormulaValue, ParseFormulaError> {
+        self.skip_whitespace();
+        let start = self.pos;
+        if self.eat("!") {
+            return Ok(!self.nested(start, Self::parse_unary)?);
+        }
+        if self.eat("(") {
+            let value = self.nested(start, Self::parse_or)?;
+            self.expect(")")?;
+            return Ok(value);
+        }
+
+        self.skip_whitespace();
+        let start = self.pos;
+        let name = self.take_while(|c| c.is_ascii_alphanumeric() || c == '_');
+        let value = match name {
+            "" => return Err(parse_error(start, "expected a condition")),
+            "true" => RoleCondFormulaValue::And { values: Vec::new() },
+            "false" => RoleCondFormulaValue::Or { values: Vec::new() },
+            "isLocal" => RoleCondFormulaValue::IsLocal,
+            "isRemote" => RoleCondFormulaValue::IsRemote,
+            "createdLessThan" => RoleCondFormulaValue::CreatedLessThan {
+                duration: self.parse_duration_argument()?,
+            },
+            "createdMoreThan" => RoleCondFormulaValue::CreatedMoreThan {
+                duration: self.parse_duration_argument()?,
+            },
+            "followers" | "following" | "notes" => {
+                let at_most = if self.eat("<=") {
+                    true
+                } else if self.eat(">=") {
+                    false
+                } else {
+                    return Err(parse_error(self.pos, "expected `<=` or `>=`"));
+                };
+                let value = self.parse_number()?;
+                match (name, at_most) {
+                    ("followers", true) => RoleCondFormulaValue::FollowersLessThanOrEq { value },
+                    ("followers", false) => RoleCondFormulaValue::FollowersMoreThanOrEq { value },
+                    ("following", true) => RoleCondFormulaValue::FollowingLessThanOrEq { value },
+                    ("following", false) => RoleCondFormulaValue::FollowingMoreThanOrEq { value },
+                    #[cfg(feature = "13-10-0")]
+                    ("notes", true) => RoleCondFormulaValue::NotesLessThanOrEq { value },
+                    #[cfg(feature = "13-10-0")]
+                    ("notes", false) => RoleCondFormulaValue::NotesMoreThanOrEq { value },
+                    _ => {
+                        return Err(parse_error(
+                            start,
+                            format!("`{}` is not supported in this version", name),
+                        ))
+                    }
+                }
+            }
+            _ => return Err(parse_error(start, format!("unknown condition `{}`", name))),
+        };
+        Ok(value)
+    }
+
+    fn parse_number(&mut self) -> Result<u64, ParseFormulaError> {
+        self.skip_whitespace();
+        let start = self.pos;
+        let digits = self.take_while(|c| c.is_ascii_digit());
+        digits
+            .parse()
+            .map_err(|_| parse_error(start, "expected a number"))
+    }
+
+    fn parse_duration_argument(&mut self) -> Result<Duration, ParseFormulaError> {
+        self.expect("(")?;
+        self.skip_whitespace();
+        let start = self.pos;
+        let literal = self.take_while(|c| c.is_ascii_alphanumeric() || c == '-');
+        let duration = parse_duration(literal)
+            .ok_or_else(|| parse_error(start, format!("invalid duration `{}`", literal)))?;
+        self.expect(")")?;
+        Ok(duration)
+    }
+}
+
+/// Parses durations such as `7d`, `1d12h` or `-5s`.
+fn parse_duration(s: &str) -> Option<Duration> {
+    let mut seconds: i64 = 0;
+    let (negative, mut rest) = match s.strip_prefix('-') {
+        Some(rest) => (true, rest),
+        None => (false, s),
+    };
+    if rest.is_empty() {
+        return None;
+    }
+    while !rest.is_empty() {
+        let len = rest.find(|c: char| !c.is_ascii_digit())?;
+        let value: i64 = rest[..len].parse().ok()?;
+        let unit = rest[len..].chars().next()?;
+        let &(_, scale) = DURATION_UNITS.iter().find(|(u, _)| *u == unit)?;
+        seconds = seconds.checked_add(value.checked_mul(scale)?)?;
+        rest = &rest[len + unit.len_utf8()..];
+    }
+    if negative {
+        seconds = -seconds;
+    }
+    // `Duration::seconds` panics on the values out of its range
+    seconds.checked_mul(1000).map(Duration::milliseconds)
+}
+
+fn write_duration(out: &mut String, duration: Duration) -> fmt::Result {
+    let mut seconds = duration.num_seconds();
+    if seconds == 0 {
+        return out.write_str("0s");
+    }
+    if seconds < 0 {
+        out.write_str("-")?;
+        seconds = -seconds;
+    }
+    // weeks are left to days, as `14d` reads better than `2w`
+    for &(unit, scale) in &DURATION_UNITS[1..] {
+        if seconds >= scale {
+            write!(out, "{}{}", seconds / scale, unit)?;
+            seconds %= scale;
+        }
+    }
+    Ok(())
+}
+
+#[derive(Clone, Copy, PartialEq, Eq)]
+enum Context {
+    Top,
+    Or,
+    Operand,
+}
+
+pub(super) fn write_formula(out: &mut String, value: &RoleCondFormulaValue) -> fmt::Result {
+    write_value(out, value, Context::Top)
+}
+
+fn write_value(out: &mut String, value: &RoleCondFormulaValue, context: Context) -> fmt::Result {
+    let (values, separator) = match value {
+        RoleCondFormulaValue::And { values } if values.is_empty() => return out.write_str("true"),
+        RoleCondFormulaValue::Or { values } if values.is_empty() => return out.write_str("false"),
+        // the condition alone means the same, though the structure is not kept
+        RoleCondFormulaValue::And { values } | RoleCondFormulaValue::Or { values }
+            if values.len() == 1 =>
+        {
+            return write_value(out, &values[0], context)
+        }
+        RoleCondFormulaValue::And { values } => (values, " && "),
+        RoleCondFormulaValue::Or { values } => (values, " || "),
+        RoleCondFormulaValue::Not { value } => {
+            out.write_str("!")?;
+            return write_value(out, value, Context::Operand);
+        }
+        value => return write_atom(out, value),
+    };
+
+    // keep the nested conjunctions and disjunctions in parentheses so that the structure
+    // survives the round trip, except for the conjunctions directly in a disjunction
+    let is_and = separator == " && ";
+    let parenthesize = match context {
+        Context::Top => false,
+        Context::Or => !is_and,
+        Context::Operand => true,
+    };
+    if parenthesize {
+        out.write_str("(")?;
+    }
+    let operand = if is_and {
+        Context::Operand
+    } else {
+        Context::Or
+    };
+    for (i, value) in values.iter().enumerate() {
+        if i > 0 {
+            out.write_str(separator)?;
+        }
+        write_value(out, value, operand)?;
+    }
+    if parenthesize {
+        out.write_str(")")?;
+    }
+    Ok(())
+}
+
+fn write_atom(out: &mut String, value: &RoleCondFormulaValue) -> fmt::Result {
+    match value {
+        RoleCondFormulaValue::IsLocal => out.write_str("isLocal"),
+        RoleCondFormulaValue::IsRemote => out.write_str("isRemote"),
+        RoleCondFormulaValue::CreatedLessThan { duration } => {
+            out.write_str("createdLessThan(")?;
+            write_duration(out, *duration)?;
+            out.write_str(")")
+        }
+        RoleCondFormulaValue::CreatedMoreThan { duration } => {
+            out.write_str("createdMoreThan(")?;
+            write_duration(out, *duration)?;
+            out.write_str(")")
+        }
+        RoleCondFormulaValue::FollowersLessThanOrEq { value } => {
+            write!(out, "followers <= {}", value)
+        }
+        RoleCondFormulaValue::FollowersMoreThanOrEq { value } => {
+            write!(out, "followers >= {}", value)
+        }
+        RoleCondFormulaValue::FollowingLessThanOrEq { value } => {
+            write!(out, "following <= {}", value)
+        }
+        RoleCondFormulaValue::FollowingMoreThanOrEq { value } => {
+            write!(out, "following >= {}", value)
+        }
+        #[cfg(feature = "13-10-0")]
+        RoleCondFormulaValue::NotesLessThanOrEq { value } => write!(out, "notes <= {}", value),
+        #[cfg(feature = "13-10-0")]
+        RoleCondFormulaValue::NotesMoreThanOrEq { value } => write!(out, "notes >= {}", value),
+        RoleCondFormulaValue::And { .. }
+        | RoleCondFormulaValue::Or { .. }
+        | RoleCondFormulaValue::Not { .. } => unreachable!(),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{Parser, MAX_DEPTH};
+    use crate::role::{formula_to_string, parse_formula};
+
+    use chrono::Duration;
+    use misskey_api::model::role::RoleCondFormulaValue;
+
+    fn assert_round_trip(formula: &RoleCondFormulaValue) {
+        let text = formula_to_string(formula);
+        let parsed = parse_formula(&text).unwrap();
+        assert_eq!(formula_to_string(&parsed), text);
+        assert_eq!(
+            serde_json::to_value(&parsed).unwrap(),
+            serde_json::to_value(formula).unwrap(),
+            "{}",
+            text
+        );
+    }
+
+    #[test]
+    fn test_round_trip() {
+        for text in [
+            "isLocal",
+            "true",
+            "false",
+            "!isRemote",
+            "!!true",
+            "isLocal && followers >= 100 && !createdLessThan(7d)",
+            "isLocal && following <= 10 || isRemote && followers >= 10",
+            "(isLocal || isRemote) && (false || true)",
+            "((isLocal || isRemote) || true) && !(isLocal && (isRemote && true))",
+            "createdMoreThan(1d12h30m15s) || createdLessThan(14d)",
+            "createdLessThan(0s) || createdLessThan(-5s) || createdMoreThan(-1d1s)",
+        ] {
+            assert_round_trip(&parse_formula(text).unwrap());
+        }
+    }
+
+    #[test]
+    fn test_round_trip_duration() {
+        for seconds in [0, 1, -1, 59, -3600, 90061, -90061, 604800 * 3] {
+            assert_round_trip(&RoleCondFormulaValue::CreatedLessThan {
+                duration: Duration::seconds(seconds),
+            });
+        }
+        assert_eq!(
+            formula_to_string(&RoleCondFormulaValue::CreatedMoreThan {
+                duration: Duration::seconds(-90061),
+            }),
+            "createdMoreThan(-1d1h1m1s)"
+        );
+        assert!(parse_formula("createdLessThan(--5s)").is_err());
+        assert!(parse_formula("createdLessThan(9999999999999999s)").is_err());
+    }
+
+    #[test]
+    fn test_single_condition() {
+        let formula = RoleCondFormulaValue::Or {
+            values: vec![RoleCondFormulaValue::And {
+                values: vec![RoleCondFormulaValue::IsLocal],
+            }],
+        };
+        assert_eq!(formula_to_string(&formula), "isLocal");
+        let formula = RoleCondFormulaValue::And {
+            values: vec![
+                RoleCondFormulaValue::Or {
+                    values: vec![RoleCondFormulaValue::IsLocal],
+                },
+                RoleCondFormulaValue::IsRemote,
+            ],
+        };
+        assert_eq!(formula_to_string(&formula), "isLocal && isRemote");
+    }
+
+    #[test]
+    fn test_depth_limit() {
+        let within = format!("{}isLocal", "!".repeat(MAX_DEPTH));
+        assert!(Parser::new(&within).parse().is_ok());
+        let within = format!("{}isLocal{}", "(".repeat(MAX_DEPTH), ")".repeat(MAX_DEPTH));
+        assert!(Parser::new(&within).parse().is_ok());
+
+        for open in ["!", "("] {
+            let deep = format!("{}isLocal", open.repeat(20000));
+            let error = Parser::new(&deep).parse().unwrap_err();
+            assert_eq!(error.position(), MAX_DEPTH);
+        }
+    }
+}
@@ -0,0 +1,295 @@
+use std::fmt::{self, Display};
+
+use crate::{ClientExt, Error};
+
+use chrono::{DateTime, Utc};
+use misskey_api::model::{
+    id::Id,
+    role::{Policies, PoliciesSimple, PolicyValue, Priority, Role},
+    user::User,
+};
+
+use super::eval::applies;
+
+/// Where the value of a policy comes from, in [`PolicyDecision`].
+#[derive(Debug, Clone)]
+pub enum PolicySource {
+    /// No role is assigned to the user, so the default value applies.
+    Default,
+    /// The role decided the value.
+    Role {
+        /// The ID of the role.
+        id: Id<Role>,
+        /// The name of the role.
+        name: String,
+        /// The priority of the policy in the role.
+        priority: Priority,
+        /// Whether the role uses the default value for the policy.
+        use_default: bool,
+    },
+}
+
+impl Display for PolicySource {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            PolicySource::Default => f.write_str("default"),
+            PolicySource::Role {
+                name,
+                priority,
+                use_default,
+                ..
+            } => {
+                if *use_default {
+                    f.write_str("default via ")?;
+                }
+                write!(f, "role `{}`, {} priority", name, priority_name(priority))
+            }
+        }
+    }
+}
+
+/// The effective value of a policy and the source of it.
+#[derive(Debug, Clone)]
+pub struct PolicyDecision<T> {
+    /// The effective value.
+    pub value: T,
+    /// The source of the value.
+    pub source: PolicySource,
+}
+
+fn priority_rank(priority: &Priority) -> u8 {
+    match priority {
+        Priority::Low => 0,
+        Priority::Middle => 1,
+        Priority::High => 2,
+    }
+}
+
+fn priority_name(priority: &Priority) -> &'static str {
+    match priority {
+        Priority::Low => "low",
+        Priority::Middle => "middle",
+        Priority::High => "high",
+    }
+}
+
+/// Decides the value of a policy in the same way as `RoleService` of Misskey.
+///
+/// Only the policies of the highest priority among the roles are considered, and the value is
+/// aggregated with `better`, which returns `true` if the first value is preferred to the second.
+fn decide<T, G, B>(default: &T, roles: &[&Role], get: G, better: B) -> PolicyDecision<T>
+where
+    T: Clone,
+    G: Fn(&Policies) -> Option<&PolicyValue<T>>,
+    B: Fn(&T, &T) -> bool,
+{
+    // roles without the policy use the default value with the lowest priority
+    let candidates: Vec<_> = roles
+        .iter()
+        .map(|role| {
+            let (priority, use_default, value) = match get(&role.policies) {
+                Some(policy) => (policy.priority.clone(), policy.use_default, &policy.value),
+                None => (Priority::Low, true, default),
+            };
+            let value = if use_default { default } else { value };
+            (role, priority, use_default, value)
+        })
+        .collect();
+
+    let top = match candidates.iter().map(|c| priority_rank(&c.1)).max() {
+        Some(top) => top,
+        None => {
+            return PolicyDecision {
+                value: default.clone(),
+                source: PolicySource::Default,
+            }
+        }
+    };
+    let mut best: Option<&(&&Role, Priority, bool, &T)> = None;
+    for candidate in candidates.iter().filter(|c| priority_rank(&c.1) == top) {
+        match best {
+            Some(&(_, _, _, value)) if !better(candidate.3, value) => {}
+            _ => best = Some(candidate),
+        }
+    }
+    let (role, priority, use_default, value) = best.unwrap();
+    PolicyDecision {
+        value: (*value).clone(),
+        source: PolicySource::Role {
+            id: role.id,
+            name: role.name.clone(),
+            priority: priority.clone(),
+            use_default: *use_default,
+        },
+    }
+}
+
+fn any(a: &bool, b: &bool) -> bool {
+    *a && !*b
+}
+
+fn max<T: PartialOrd>(a: &T, b: &T) -> bool {
+    a > b
+}
+
+macro_rules! impl_policies {
+    ($(
+        $(#[cfg($cfg:meta)])?
+        $field:ident : $ty:ty = $default:expr, $name:literal, $aggregate:ident;
+    )*) => {
+        /// The policies in effect for a user, returned from [`PolicyResolver`].
+        ///
+        /// Each value comes with the role that decided it. The [`Display`] implementation
+        /// writes one policy per line with the source.
+        #[derive(Debug, Clone)]
+        pub struct EffectivePolicies {
+            $(
+            $(#[cfg($cfg)] #[cfg_attr(docsrs, doc(cfg($cfg)))])?
+            #[doc = concat!("The effective value of `", $name, "`.")]
+            pub $field: PolicyDecision<$ty>,
+            )*
+        }
+
+        impl EffectivePolicies {
+            /// Returns the effective values without the sources.
+            pub fn to_simple(&self) -> PoliciesSimple {
+                PoliciesSimple {
+                    $(
+                    $(#[cfg($cfg)])?
+                    $field: Some(self.$field.value.clone()),
+                    )*
+                }
+            }
+        }
+
+        impl Display for EffectivePolicies {
+            fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+                $(
+                $(#[cfg($cfg)])?
+                writeln!(f, "{}: {} ({})", $name, self.$field.value, self.$field.source)?;
+                )*
+                Ok(())
+            }
+        }
+
+        impl PolicyResolver {
+            /// Creates a resolver with the default policies of the instance.
+            ///
+            /// The policies missing in `defaults` fall back to the built-in defaults of
+            /// Misskey.
+            pub fn new(defaults: PoliciesSimple) -> Self {
+                PolicyResolver {
+                    defaults: PoliciesSimple {
+                        $(
+                        $(#[cfg($cfg)])?
+                        $field: Some(defaults.$field.unwrap_or($default)),
+                        )*
+                    },
+                }
+            }
+
+            /// Computes the policies in effect for the user who has the roles.
+            pub fn resolve<'a>(
+                &self,
+                roles: impl IntoIterator<Item = &'a Role>,
+            ) -> EffectivePolicies {
+                let roles: Vec<&Role> = roles.into_iter().collect();
+                EffectivePolicies {
+                    $(
+                    $(#[cfg($cfg)])?
+                    $field: decide(
+                        self.defaults.$field.as_ref().unwrap(),
+                        &roles,
+                        |policies| policies.$field.as_ref(),
+                        $aggregate,
+                    ),
+                    )*
+                }
+            }
+        }
+    };
+}
+
+/// Computes the policies in effect for users from the default policies and roles.
+///
+/// As Misskey does, only the policies of the highest priority among the roles of the user are
+/// taken into account, and a role that uses the default value for a policy (or does not set it)
+/// contributes the default value of the instance. The values are then combined: a permission
+/// is granted if any of them grants it, and a limit is the largest of them (including
+/// `rateLimitFactor`).
+///
+/// # Examples
+///
+/// ```no_run
+/// # #[tokio::main]
+/// # async fn main() -> anyhow::Result<()> {
+/// # let client = misskey_test::test_admin_client().await?;
+/// use chrono::Utc;
+/// use misskey_util::{role::PolicyResolver, ClientExt};
+///
+/// let resolver = PolicyResolver::fetch(&client).await?;
+/// let roles = client.roles().await?;
+/// let user = client.get_user(client.me().await?.id).await?;
+/// let assigned = vec![roles[0].id];
+/// let policies = resolver.resolve_user(&roles, &assigned, &user, Utc::now());
+/// println!("{}", policies);
+/// # Ok(())
+/// # }
+/// ```
+#[derive(Debug, Clone)]
+pub struct PolicyResolver {
+    defaults: PoliciesSimple,
+}
+
+impl_policies! {
+    gtl_available: bool = true, "gtlAvailable", any;
+    ltl_available: bool = true, "ltlAvailable", any;
+    can_public_note: bool = true, "canPublicNote", any;
+    can_invite: bool = false, "canInvite", any;
+    can_manage_custom_emojis: bool = false, "canManageCustomEmojis", any;
+    #[cfg(feature = "13-10-0")]
+    can_search_notes: bool = false, "canSearchNotes", any;
+    can_hide_ads: bool = false, "canHideAds", any;
+    drive_capacity_mb: u64 = 100, "driveCapacityMb", max;
+    pin_limit: u64 = 5, "pinLimit", max;
+    antenna_limit: u64 = 5, "antennaLimit", max;
+    word_mute_limit: u64 = 200, "wordMuteLimit", max;
+    webhook_limit: u64 = 3, "webhookLimit", max;
+    clip_limit: u64 = 10, "clipLimit", max;
+    note_each_clips_limit: u64 = 200, "noteEachClipsLimit", max;
+    user_list_limit: u64 = 10, "userListLimit", max;
+    user_each_user_lists_limit: u64 = 50, "userEachUserListsLimit", max;
+    rate_limit_factor: f64 = 1.0, "rateLimitFactor", max;
+}
+
+impl PolicyResolver {
+    /// Creates a resolver with the default policies of the instance, fetched from `meta`.
+    pub async fn fetch<C: ClientExt>(client: &C) -> Result<Self, Error<C::Error>> {
+        let meta = client.meta().await?;
+        Ok(PolicyResolver::new(meta.policies))
+    }
+
+    /// Returns the default policies of the instance.
+    pub fn defaults(&self) -> &PoliciesSimple {
+        &self.defaults
+    }
+
+    /// Computes the policies in effect for the user, from the roles assigned to the user and
+    /// the conditional roles that apply to the user as of `now`.
+    ///
+    /// `roles` are all the roles on the instance, and `assigned` are the IDs of the roles
+    /// assigned to the user. The conditional roles whose conditions cannot be evaluated with
+    /// `user` (see [`evaluate`][`super::evaluate`]) are treated as not applying.
+    pub fn resolve_user(
+        &self,
+        roles: &[Role],
+        assigned: &[Id<Role>],
+        user: &User,
+        now: DateTime<Utc>,
+    ) -> EffectivePolicies {
+        let roles = roles
+            .iter()
+            .filter(|role| assigned.contains(&role.id) || applies(role, user, now) == Some(true));
+        self.resolve(roles)
+    }
+}
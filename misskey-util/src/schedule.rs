@@ -0,0 +1,578 @@
+//! Scheduled posting of notes.
+//!
+//! [`Scheduler`] keeps the notes to be posted later in a local JSON file, and posts them on
+//! time through the client. Notes can be scheduled at a fixed time ([`Schedule::At`]) or
+//! repeatedly in the cron format ([`Schedule::Cron`]).
+//! The results of the posts and the pending retries are also written to the file, so that
+//! the schedule continues after the program restarts without posting the same note twice.
+//!
+//! # Examples
+//!
+//! ```no_run
+//! # use misskey_util::ClientExt;
+//! # #[tokio::main]
+//! # async fn main() -> anyhow::Result<()> {
+//! # let client = misskey_test::test_client().await?;
+//! use chrono::{Duration, Utc};
+//! use misskey_util::schedule::{Schedule, Scheduler};
+//!
+//! let scheduler = Scheduler::open(&client, "scheduled-notes.json")?;
+//!
+//! // Post a note with a content warning one hour later.
+//! let mut note = client.build_note();
+//! note.text("It's time!").hide_content("announcement");
+//! scheduler.schedule(&note, Schedule::At(Utc::now() + Duration::hours(1)))?;
+//!
+//! // Post a note at 9:00 (UTC) every morning.
+//! let mut note = client.build_note();
+//! note.text("Good morning!");
+//! scheduler.schedule(&note, Schedule::Cron("0 9 * * *".parse()?))?;
+//!
+//! // Keep posting the scheduled notes on time.
+//! scheduler.run().await?;
+//! # Ok(())
+//! # }
+//! ```
+
+use std::cmp;
+use std::fmt::{self, Display};
+use std::io;
+use std::path::Path;
+use std::sync::{Mutex, MutexGuard};
+
+use crate::builder::NoteBuilder;
+use crate::{ClientExt, Error};
+
+use chrono::{DateTime, Utc};
+use futures::{
+    channel::mpsc::{self, UnboundedReceiver, UnboundedSender},
+    future,
+    stream::StreamExt,
+};
+use futures_timer::Delay;
+use misskey_api::endpoint::notes::create::{self, Request};
+use misskey_api::model::{id::Id, note::Note};
+use misskey_core::model::{ApiError, ApiErrorKind};
+use serde::{Deserialize, Serialize};
+use serde_json::Value;
+use ulid_crate::Ulid;
+
+mod cron;
+mod store;
+
+pub use cron::{CronSchedule, ParseCronError};
+use store::FileStore;
+
+/// How long [`Scheduler::run`] sleeps at most, to follow the changes of the system clock.
+const MAX_SLEEP: std::time::Duration = std::time::Duration::from_secs(60);
+
+/// The number of attempts kept in the history of each scheduled note.
+const HISTORY_LIMIT: usize = 20;
+
+/// When a scheduled note is posted.
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq, Eq)]
+#[serde(rename_all = "camelCase")]
+pub enum Schedule {
+    /// Posts the note once at the time.
+    ///
+    /// If the time has already passed, the note is posted as soon as possible.
+    At(DateTime<Utc>),
+    /// Posts the note repeatedly at the times that match the cron expression.
+    ///
+    /// If several times are missed while the scheduler is not running, the note is posted
+    /// only once for them.
+    /// The expiry of the poll given as a time is taken relative to the first scheduled time,
+    /// so that the poll in each post expires after the same duration.
+    Cron(CronSchedule),
+}
+
+impl Schedule {
+    fn first_due(&self, now: DateTime<Utc>) -> Option<DateTime<Utc>> {
+        match self {
+            Schedule::At(at) => Some(*at),
+            Schedule::Cron(cron) => cron.next_after(now),
+        }
+    }
+
+    fn next_due(&self, after: DateTime<Utc>) -> Option<DateTime<Utc>> {
+        match self {
+            Schedule::At(_) => None,
+            Schedule::Cron(cron) => cron.next_after(after),
+        }
+    }
+}
+
+/// ID of a scheduled note, which is assigned by [`Scheduler`].
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq, Eq, Hash)]
+#[serde(transparent)]
+pub struct ScheduleId(String);
+
+impl Display for ScheduleId {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        Display::fmt(&self.0, f)
+    }
+}
+
+/// How the failed posts are retried.
+///
+/// The interval between the retries starts from `initial_backoff` and doubles on each failure,
+/// up to `max_backoff`.
+/// Errors that will not be resolved by retrying, such as invalid parameters, are not retried.
+#[derive(Debug, Clone)]
+pub struct RetryPolicy {
+    /// The number of attempts to post a note at each scheduled time, including the first one.
+    pub max_attempts: u32,
+    /// The interval before the first retry.
+    pub initial_backoff: std::time::Duration,
+    /// The maximum interval between the retries.
+    pub max_backoff: std::time::Duration,
+}
+
+impl Default for RetryPolicy {
+    /// Tries 5 times with the interval from 1 minute up to 1 hour.
+    fn default() -> RetryPolicy {
+        RetryPolicy {
+            max_attempts: 5,
+            initial_backoff: std::time::Duration::from_secs(60),
+            max_backoff: std::time::Duration::from_secs(60 * 60),
+        }
+    }
+}
+
+impl RetryPolicy {
+    fn retry_at(&self, failures: u32, now: DateTime<Utc>) -> Option<DateTime<Utc>> {
+        let factor = 2u32.saturating_pow(failures.saturating_sub(1));
+        let backoff = self
+            .initial_backoff
+            .checked_mul(factor)
+            .map_or(self.max_backoff, |backoff| {
+                cmp::min(backoff, self.max_backoff)
+            });
+        now.checked_add_signed(chrono::Duration::from_std(backoff).ok()?)
+    }
+}
+
+/// The result of an attempt to post a scheduled note.
+#[derive(Serialize, Deserialize, Debug, Clone)]
+#[serde(rename_all = "camelCase")]
+pub enum AttemptResult {
+    /// The note is posted.
+    Posted {
+        /// The ID of the posted note.
+        note_id: Id<Note>,
+    },
+    /// Misskey returned an error.
+    Rejected {
+        /// The error returned from Misskey.
+        error: ApiError,
+    },
+    /// The request failed in the client.
+    Failed {
+        /// The description of the error.
+        message: String,
+    },
+}
+
+impl AttemptResult {
+    fn is_retryable(&self) -> bool {
+        match self {
+            AttemptResult::Posted { .. } => false,
+            AttemptResult::Rejected { error } => {
+                error.kind == ApiErrorKind::Server || error.code == "RATE_LIMIT_EXCEEDED"
+            }
+            AttemptResult::Failed { .. } => true,
+        }
+    }
+}
+
+/// An attempt to post a scheduled note.
+#[derive(Serialize, Deserialize, Debug, Clone)]
+#[serde(rename_all = "camelCase")]
+pub struct Attempt {
+    /// The scheduled time the attempt is for.
+    pub due_at: DateTime<Utc>,
+    /// The time of the attempt.
+    pub attempted_at: DateTime<Utc>,
+    /// The result of the attempt.
+    pub result: AttemptResult,
+}
+
+/// A note registered to [`Scheduler`].
+#[derive(Serialize, Deserialize, Debug, Clone)]
+#[serde(rename_all = "camelCase")]
+pub struct ScheduledNote {
+    id: ScheduleId,
+    note: Value,
+    schedule: Schedule,
+    due_at: Option<DateTime<Utc>>,
+    next_attempt_at: Option<DateTime<Utc>>,
+    failures: u32,
+    history: Vec<Attempt>,
+}
+
+impl ScheduledNote {
+    /// Returns the ID of the scheduled note.
+    pub fn id(&self) -> &ScheduleId {
+        &self.id
+    }
+
+    /// Returns the parameters of `notes/create` to create the note, in JSON.
+    pub fn note(&self) -> &Value {
+        &self.note
+    }
+
+    /// Returns when the note is posted.
+    pub fn schedule(&self) -> &Schedule {
+        &self.schedule
+    }
+
+    /// Returns the next scheduled time, or `None` if the schedule is finished.
+    pub fn due_at(&self) -> Option<DateTime<Utc>> {
+        self.due_at
+    }
+
+    /// Returns the time of the next attempt, which is later than
+    /// [`due_at`][`ScheduledNote::due_at`] if the note is waiting for a retry.
+    pub fn next_attempt_at(&self) -> Option<DateTime<Utc>> {
+        self.next_attempt_at
+    }
+
+    /// Returns the number of failed attempts for the current scheduled time.
+    pub fn failures(&self) -> u32 {
+        self.failures
+    }
+
+    /// Returns the recent attempts to post the note, from the oldest.
+    pub fn history(&self) -> &[Attempt] {
+        &self.history
+    }
+
+    /// Returns `true` if the note will not be posted anymore.
+    pub fn is_finished(&self) -> bool {
+        self.next_attempt_at.is_none()
+    }
+
+    fn record(&mut self, attempt: Attempt, retry: &RetryPolicy) {
+        let now = attempt.attempted_at;
+        let retryable = attempt.result.is_retryable();
+        let due_at = attempt.due_at;
+
+        self.history.push(attempt);
+        if self.history.len() > HISTORY_LIMIT {
+            self.history.remove(0);
+        }
+
+        let retry_at = if retryable && self.failures + 1 < retry.max_attempts {
+            retry.retry_at(self.failures + 1, now)
+        } else {
+            None
+        };
+
+        if let Some(retry_at) = retry_at {
+            self.failures += 1;
+            self.next_attempt_at = Some(retry_at);
+        } else {
+            self.failures = 0;
+            self.due_at = self.schedule.next_due(cmp::max(due_at, now));
+            self.next_attempt_at = self.due_at;
+        }
+    }
+}
+
+/// The request to `notes/create` with the parameters kept in the file.
+#[derive(Serialize)]
+#[serde(transparent)]
+struct StoredRequest<'a>(&'a Value);
+
+impl misskey_core::Request for StoredRequest<'_> {
+    type Response = create::Response;
+    const ENDPOINT: &'static str = Request::ENDPOINT;
+}
+
+/// Poster of the scheduled notes, which are persisted to a local file.
+///
+/// See the [module-level documentation][`crate::schedule`] for an example.
+///
+/// The notes are posted by [`run`][`Scheduler::run`], or by calling
+/// [`post_due`][`Scheduler::post_due`] periodically (e.g. from a cron job).
+/// Only one scheduler should use the same file at a time.
+#[derive(Debug)]
+pub struct Scheduler<C> {
+    client: C,
+    store: FileStore,
+    retry: RetryPolicy,
+    notes: Mutex<Vec<ScheduledNote>>,
+    wake_tx: UnboundedSender<()>,
+    wake_rx: futures::lock::Mutex<UnboundedReceiver<()>>,
+}
+
+impl<C: ClientExt> Scheduler<C> {
+    /// Opens the scheduler that keeps the notes in the file at `path`, creating it on the first change.
+    pub fn open(client: C, path: impl AsRef<Path>) -> Result<Scheduler<C>, Error<C::Error>> {
+        let store = FileStore::new(path.as_ref());
+        let notes = store.load()?;
+        let (wake_tx, wake_rx) = mpsc::unbounded();
+        Ok(Scheduler {
+            client,
+            store,
+            retry: RetryPolicy::default(),
+            notes: Mutex::new(notes),
+            wake_tx,
+            wake_rx: futures::lock::Mutex::new(wake_rx),
+        })
+    }
+
+    /// Sets how the failed posts are retried.
+    pub fn retry_policy(&mut self, retry: RetryPolicy) -> &mut Self {
+        self.retry = retry;
+        self
+    }
+
+    fn lock_notes(&self) -> MutexGuard<'_, Vec<ScheduledNote>> {
+        self.notes.lock().unwrap_or_else(|e| e.into_inner())
+    }
+
+    /// Saves the notes to the file, releasing the lock before writing not to block the others.
+    fn save(&self, notes: MutexGuard<'_, Vec<ScheduledNote>>) -> io::Result<()> {
+        let snapshot = self.store.snapshot(&notes)?;
+        drop(notes);
+        self.store.write(snapshot)
+    }
+
+    /// Schedules the note built with `note`.
+    pub fn schedule<B>(
+        &self,
+        note: &NoteBuilder<B>,
+        schedule: Schedule,
+    ) -> Result<ScheduleId, Error<C::Error>> {
+        self.schedule_request(note.as_request().clone(), schedule)
+    }
+
+    /// Schedules the note to be created with `request`.
+    ///
+    /// For [`Schedule::Cron`], the poll that expires before the first scheduled time is
+    /// rejected with [`Error::Io`] of [`io::ErrorKind::InvalidInput`].
+    pub fn schedule_request(
+        &self,
+        mut request: Request,
+        schedule: Schedule,
+    ) -> Result<ScheduleId, Error<C::Error>> {
+        let id = ScheduleId(Ulid::new().to_string());
+        let due_at = schedule.first_due(Utc::now());
+
+        // the absolute expiry would have passed in the posts after the first one
+        if let (Schedule::Cron(_), Some(due_at), Some(poll)) =
+            (&schedule, due_at, request.poll.as_mut())
+        {
+            if let Some(expires_at) = poll.expires_at.take() {
+                let expired_after = expires_at - due_at;
+                if expired_after <= chrono::Duration::zero() {
+                    let message = "the poll expires before the first scheduled time";
+                    return Err(io::Error::new(io::ErrorKind::InvalidInput, message).into());
+                }
+                poll.expired_after = Some(expired_after);
+            }
+        }
+        let note = serde_json::to_value(&request).map_err(io::Error::from)?;
+
+        let mut notes = self.lock_notes();
+        notes.push(ScheduledNote {
+            id: id.clone(),
+            note,
+            schedule,
+            due_at,
+            next_attempt_at: due_at,
+            failures: 0,
+            history: Vec::new(),
+        });
+        self.save(notes)?;
+
+        // wake up `run` in case the note is due earlier than the others
+        let _ = self.wake_tx.unbounded_send(());
+        Ok(id)
+    }
+
+    /// Cancels the scheduled note and removes it from the file.
+    ///
+    /// Returns `false` if the note is not found.
+    pub fn cancel(&self, id: &ScheduleId) -> Result<bool, Error<C::Error>> {
+        let mut notes = self.lock_notes();
+        let len = notes.len();
+        notes.retain(|note| &note.id != id);
+        if notes.len() == len {
+            return Ok(false);
+        }
+        self.save(notes)?;
+        Ok(true)
+    }
+
+    /// Removes the finished notes from the file, and returns the number of the removed notes.
+    pub fn clear_finished(&self) -> Result<usize, Error<C::Error>> {
+        let mut notes = self.lock_notes();
+        let len = notes.len();
+        notes.retain(|note| !note.is_finished());
+        let removed = len - notes.len();
+        if removed > 0 {
+            self.save(notes)?;
+        }
+        Ok(removed)
+    }
+
+    /// Returns the scheduled note with the ID.
+    pub fn get(&self, id: &ScheduleId) -> Option<ScheduledNote> {
+        self.lock_notes()
+            .iter()
+            .find(|note| &note.id == id)
+            .cloned()
+    }
+
+    /// Returns all the scheduled notes, including the finished ones.
+    pub fn notes(&self) -> Vec<ScheduledNote> {
+        self.lock_notes().clone()
+    }
+
+    /// Returns the time of the earliest attempt to post the scheduled notes.
+    pub fn next_attempt_at(&self) -> Option<DateTime<Utc>> {
+        self.lock_notes()
+            .iter()
+            .filter_map(|note| note.next_attempt_at)
+            .min()
+    }
+
+    /// Posts the notes whose time has come, and returns the attempts made.
+    ///
+    /// Failures in posting are recorded in the history of each note and retried later
+    /// according to the [`RetryPolicy`], so this fails only when writing the file fails.
+    pub async fn post_due(&self) -> Result<Vec<(ScheduleId, Attempt)>, Error<C::Error>> {
+        let now = Utc::now();
+        let due: Vec<_> = self
+            .lock_notes()
+            .iter()
+            .filter(|note| matches!(note.next_attempt_at, Some(at) if at <= now))
+            .map(|note| {
+                (
+                    note.id.clone(),
+                    note.due_at.unwrap_or(now),
+                    note.note.clone(),
+                )
+            })
+            .collect();
+
+        let mut attempts = Vec::with_capacity(due.len());
+        for (id, due_at, request) in due {
+            let result = match self.client.request(StoredRequest(&request)).await {
+                Ok(response) => match response.into_result() {
+                    Ok(response) => AttemptResult::Posted {
+                        note_id: response.created_note.id,
+                    },
+                    Err(error) => AttemptResult::Rejected { error },
+                },
+                Err(e) => AttemptResult::Failed {
+                    message: e.to_string(),
+                },
+            };
+            let attempt = Attempt {
+                due_at,
+                attempted_at: Utc::now(),
+                result,
+            };
+
+            // save after each post not to post the same note again if the program stops here
+            let mut notes = self.lock_notes();
+            // the note may have been cancelled while posting
+            if let Some(note) = notes.iter_mut().find(|note| note.id == id) {
+                note.record(attempt.clone(), &self.retry);
+                self.save(notes)?;
+            }
+            attempts.push((id, attempt));
+        }
+
+        Ok(attempts)
+    }
+
+    /// Keeps posting the scheduled notes on time.
+    ///
+    /// This does not return unless writing the file fails.
+    pub async fn run(&self) -> Result<(), Error<C::Error>> {
+        let mut wake_rx = self.wake_rx.lock().await;
+        loop {
+            self.post_due().await?;
+
+            let sleep = match self.next_attempt_at() {
+                Some(at) => cmp::min((at - Utc::now()).to_std().unwrap_or_default(), MAX_SLEEP),
+                None => MAX_SLEEP,
+            };
+            future::select(Delay::new(sleep), wake_rx.next()).await;
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{AttemptResult, Schedule, Scheduler};
+    use crate::test_util::{note_json, temp_dir, MockClient};
+    use crate::Error;
+
+    use chrono::{Duration, Utc};
+    use misskey_api::endpoint::notes::create::{PollRequest, Request};
+    use serde_json::json;
+
+    fn poll_request(expires_in: Duration) -> Request {
+        let due_at = Schedule::Cron("0 9 * * *".parse().unwrap())
+            .first_due(Utc::now())
+            .unwrap();
+        let poll = PollRequest::builder()
+            .choices(vec!["a".to_owned(), "b".to_owned()])
+            .expires_at(due_at + expires_in)
+            .build();
+        Request::builder().text("poll").poll(poll).build()
+    }
+
+    #[test]
+    fn test_schedule_cron_poll() {
+        let path = temp_dir("schedule-cron-poll").join("notes.json");
+        let scheduler = Scheduler::open(MockClient::new(|_, _| unreachable!()), &path).unwrap();
+        let schedule = Schedule::Cron("0 9 * * *".parse().unwrap());
+
+        let id = scheduler
+            .schedule_request(poll_request(Duration::hours(1)), schedule.clone())
+            .unwrap();
+        let note = scheduler.get(&id).unwrap();
+        assert_eq!(note.note()["poll"]["expiredAfter"], 60 * 60 * 1000);
+        assert!(note.note()["poll"].get("expiresAt").is_none());
+
+        for expires_in in [Duration::zero(), -Duration::hours(1)] {
+            let result = scheduler.schedule_request(poll_request(expires_in), schedule.clone());
+            assert!(
+                matches!(result, Err(Error::Io(e)) if e.kind() == std::io::ErrorKind::InvalidInput)
+            );
+        }
+        assert_eq!(scheduler.notes().len(), 1);
+    }
+
+    #[tokio::test]
+    async fn test_post_due() {
+        let path = temp_dir("schedule-post-due").join("notes.json");
+        let client = MockClient::new(|endpoint, request| {
+            assert_eq!(endpoint, "notes/create");
+            json!({ "createdNote": note_json(1, request["text"].as_str().unwrap()) })
+        });
+        let scheduler = Scheduler::open(client, &path).unwrap();
+        let request = Request::builder().text("hello").build();
+        let id = scheduler
+            .schedule_request(request, Schedule::At(Utc::now() - Duration::seconds(1)))
+            .unwrap();
+
+        let attempts = scheduler.post_due().await.unwrap();
+        assert_eq!(attempts.len(), 1);
+        assert!(matches!(attempts[0].1.result, AttemptResult::Posted { .. }));
+        assert!(scheduler.post_due().await.unwrap().is_empty());
+
+        // the result is kept in the file
+        let reopened = Scheduler::open(MockClient::new(|_, _| unreachable!()), &path).unwrap();
+        let note = reopened.get(&id).unwrap();
+        assert!(note.is_finished());
+        assert_eq!(note.history().len(), 1);
+        assert_eq!(note.note()["text"], "hello");
+    }
+}
@@ -0,0 +1,284 @@
+use std::convert::TryFrom;
+use std::fmt::{self, Display};
+use std::str::FromStr;
+
+use chrono::{DateTime, Datelike, Duration, FixedOffset, NaiveDate, TimeZone, Timelike, Utc};
+use serde::{Deserialize, Serialize};
+
+/// How many years to search for the next occurrence before giving up.
+const SEARCH_YEARS: i32 = 5;
+
+/// A recurring schedule in the cron format.
+///
+/// The expression consists of five fields separated by whitespaces: minute (0-59), hour (0-23),
+/// day of month (1-31), month (1-12 or `jan`-`dec`) and day of week (0-7 or `sun`-`sat`,
+/// where both 0 and 7 are Sunday).
+/// Each field is `*`, a value, a range `a-b`, or a list of them separated by commas,
+/// optionally followed by a step `/n`.
+/// As in the traditional cron, if both day of month and day of week are restricted,
+/// the schedule matches the days that satisfy either of them.
+///
+/// The expression is evaluated in UTC unless another offset is set with
+/// [`with_offset`][`CronSchedule::with_offset`].
+///
+/// # Examples
+///
+/// ```
+/// use chrono::{TimeZone, Utc};
+/// use misskey_util::schedule::CronSchedule;
+///
+/// // at 9:30 on weekdays
+/// let cron: CronSchedule = "30 9 * * mon-fri".parse()?;
+/// let saturday = Utc.with_ymd_and_hms(2023, 7, 1, 12, 0, 0).unwrap();
+/// let monday = Utc.with_ymd_and_hms(2023, 7, 3, 9, 30, 0).unwrap();
+/// assert_eq!(cron.next_after(saturday), Some(monday));
+/// # Ok::<(), misskey_util::schedule::ParseCronError>(())
+/// ```
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(try_from = "CronRepr", into = "CronRepr")]
+pub struct CronSchedule {
+    expression: String,
+    offset: FixedOffset,
+    minutes: u64,
+    hours: u64,
+    days_of_month: u64,
+    months: u64,
+    days_of_week: u64,
+    restricted_day_of_month: bool,
+    restricted_day_of_week: bool,
+}
+
+/// Error type for parsing [`CronSchedule`].
+#[derive(Debug, Clone)]
+pub struct ParseCronError {
+    message: String,
+}
+
+impl std::error::Error for ParseCronError {}
+
+impl Display for ParseCronError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "invalid cron expression: {}", self.message)
+    }
+}
+
+fn parse_error(message: impl Into<String>) -> ParseCronError {
+    ParseCronError {
+        message: message.into(),
+    }
+}
+
+struct Field {
+    name: &'static str,
+    min: u32,
+    max: u32,
+    names: &'static [&'static str],
+}
+
+const MINUTE: Field = Field {
+    name: "minute",
+    min: 0,
+    max: 59,
+    names: &[],
+};
+const HOUR: Field = Field {
+    name: "hour",
+    min: 0,
+    max: 23,
+    names: &[],
+};
+const DAY_OF_MONTH: Field = Field {
+    name: "day of month",
+    min: 1,
+    max: 31,
+    names: &[],
+};
+const MONTH: Field = Field {
+    name: "month",
+    min: 1,
+    max: 12,
+    names: &[
+        "jan", "feb", "mar", "apr", "may", "jun", "jul", "aug", "sep", "oct", "nov", "dec",
+    ],
+};
+const DAY_OF_WEEK: Field = Field {
+    name: "day of week",
+    min: 0,
+    max: 7,
+    names: &["sun", "mon", "tue", "wed", "thu", "fri", "sat"],
+};
+
+impl Field {
+    fn parse_value(&self, s: &str) -> Result<u32, ParseCronError> {
+        let lower = s.to_ascii_lowercase();
+        if let Some(i) = self.names.iter().position(|&name| name == lower) {
+            // names start from the minimum value (`jan` = 1, `sun` = 0)
+            return Ok(self.min + i as u32);
+        }
+        match s.parse() {
+            Ok(value) if (self.min..=self.max).contains(&value) => Ok(value),
+            _ => Err(parse_error(format!("invalid {} `{}`", self.name, s))),
+        }
+    }
+
+    /// Parses the field into the bit set of the matching values, and whether it is restricted.
+    fn parse(&self, s: &str) -> Result<(u64, bool), ParseCronError> {
+        let mut bits = 0;
+        for part in s.split(',') {
+            let (range, step) = match part.split_once('/') {
+                Some((range, step)) => match step.parse::<u32>() {
+                    Ok(step) if step > 0 => (range, step),
+                    _ => return Err(parse_error(format!("invalid step `{}`", step))),
+                },
+                None => (part, 1),
+            };
+            let (start, end) = if range == "*" {
+                (self.min, self.max)
+            } else if let Some((start, end)) = range.split_once('-') {
+                (self.parse_value(start)?, self.parse_value(end)?)
+            } else {
+                let value = self.parse_value(range)?;
+                // `a/n` means from `a` to the end
+                if step > 1 {
+                    (value, self.max)
+                } else {
+                    (value, value)
+                }
+            };
+            if start > end {
+                return Err(parse_error(format!("invalid range `{}`", range)));
+            }
+            for value in (start..=end).step_by(step as usize) {
+                bits |= 1 << value;
+            }
+        }
+        // like the traditional cron, fields starting with `*` are not restrictions
+        Ok((bits, !s.starts_with('*')))
+    }
+}
+
+impl FromStr for CronSchedule {
+    type Err = ParseCronError;
+
+    fn from_str(s: &str) -> Result<CronSchedule, Self::Err> {
+        let fields: Vec<_> = s.split_whitespace().collect();
+        let (minute, hour, day_of_month, month, day_of_week) = match fields.as_slice() {
+            [minute, hour, day_of_month, month, day_of_week] => {
+                (minute, hour, day_of_month, month, day_of_week)
+            }
+            _ => return Err(parse_error("expected 5 fields")),
+        };
+
+        let (minutes, _) = MINUTE.parse(minute)?;
+        let (hours, _) = HOUR.parse(hour)?;
+        let (days_of_month, restricted_day_of_month) = DAY_OF_MONTH.parse(day_of_month)?;
+        let (months, _) = MONTH.parse(month)?;
+        let (mut days_of_week, restricted_day_of_week) = DAY_OF_WEEK.parse(day_of_week)?;
+        // 7 is also Sunday
+        if days_of_week & (1 << 7) != 0 {
+            days_of_week |= 1;
+        }
+
+        Ok(CronSchedule {
+            expression: fields.join(" "),
+            offset: FixedOffset::east_opt(0).unwrap(),
+            minutes,
+            hours,
+            days_of_month,
+            months,
+            days_of_week,
+            restricted_day_of_month,
+            restricted_day_of_week,
+        })
+    }
+}
+
+impl Display for CronSchedule {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        f.write_str(&self.expression)
+    }
+}
+
+impl CronSchedule {
+    /// Evaluates the schedule in the time zone with `offset` from UTC.
+    pub fn with_offset(mut self, offset: FixedOffset) -> CronSchedule {
+        self.offset = offset;
+        self
+    }
+
+    /// Returns the offset from UTC in which the schedule is evaluated.
+    pub fn offset(&self) -> FixedOffset {
+        self.offset
+    }
+
+    fn matches_day(&self, date: NaiveDate) -> bool {
+        let day_of_month = self.days_of_month & (1 << date.day()) != 0;
+        let day_of_week = self.days_of_week & (1 << date.weekday().num_days_from_sunday()) != 0;
+        if self.restricted_day_of_month && self.restricted_day_of_week {
+            day_of_month || day_of_week
+        } else {
+            day_of_month && day_of_week
+        }
+    }
+
+    /// Returns the first time that matches the schedule strictly after `time`,
+    /// or `None` if there is no such time in the next few years.
+    pub fn next_after(&self, time: DateTime<Utc>) -> Option<DateTime<Utc>> {
+        let local = time.with_timezone(&self.offset).naive_local();
+        let mut t = local.with_second(0)?.with_nanosecond(0)? + Duration::minutes(1);
+        let limit = local.year() + SEARCH_YEARS;
+
+        while t.year() <= limit {
+            if self.months & (1 << t.month()) == 0 {
+                let (year, month) = if t.month() == 12 {
+                    (t.year() + 1, 1)
+                } else {
+                    (t.year(), t.month() + 1)
+                };
+                t = NaiveDate::from_ymd_opt(year, month, 1)?.and_hms_opt(0, 0, 0)?;
+                continue;
+            }
+            if !self.matches_day(t.date()) {
+                t = t.date().succ_opt()?.and_hms_opt(0, 0, 0)?;
+                continue;
+            }
+            if self.hours & (1 << t.hour()) == 0 {
+                t = t.date().and_hms_opt(t.hour(), 0, 0)? + Duration::hours(1);
+                continue;
+            }
+            if self.minutes & (1 << t.minute()) == 0 {
+                t += Duration::minutes(1);
+                continue;
+            }
+            return Some(Utc.from_utc_datetime(&(t - self.offset)));
+        }
+        None
+    }
+}
+
+#[derive(Serialize, Deserialize)]
+#[serde(rename_all = "camelCase")]
+struct CronRepr {
+    expression: String,
+    #[serde(default)]
+    utc_offset_seconds: i32,
+}
+
+impl TryFrom<CronRepr> for CronSchedule {
+    type Error = ParseCronError;
+
+    fn try_from(repr: CronRepr) -> Result<CronSchedule, Self::Error> {
+        let offset = FixedOffset::east_opt(repr.utc_offset_seconds)
+            .ok_or_else(|| parse_error("invalid offset"))?;
+        Ok(repr.expression.parse::<CronSchedule>()?.with_offset(offset))
+    }
+}
+
+impl From<CronSchedule> for CronRepr {
+    fn from(cron: CronSchedule) -> CronRepr {
+        CronRepr {
+            expression: cron.expression,
+            utc_offset_seconds: cron.offset.local_minus_utc(),
+        }
+    }
+}
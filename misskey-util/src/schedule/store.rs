@@ -0,0 +1,112 @@
+use std::fs::{self, File};
+use std::io::{self, BufReader, BufWriter, Write};
+use std::path::{Path, PathBuf};
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::sync::Mutex;
+
+use crate::schedule::ScheduledNote;
+
+use serde::{Deserialize, Serialize};
+
+#[derive(Deserialize)]
+struct StoreFile {
+    notes: Vec<ScheduledNote>,
+}
+
+#[derive(Serialize)]
+struct StoreFileRef<'a> {
+    notes: &'a [ScheduledNote],
+}
+
+/// The scheduled notes serialized by [`FileStore::snapshot`].
+pub(crate) struct Snapshot {
+    seq: u64,
+    contents: Vec<u8>,
+}
+
+/// JSON file that holds the scheduled notes.
+#[derive(Debug)]
+pub(crate) struct FileStore {
+    path: PathBuf,
+    /// The sequence number of the next snapshot.
+    next_seq: AtomicU64,
+    /// The sequence number of the snapshot after the one last written.
+    written_seq: Mutex<u64>,
+}
+
+impl FileStore {
+    pub(crate) fn new(path: &Path) -> FileStore {
+        FileStore {
+            path: path.to_owned(),
+            next_seq: AtomicU64::new(0),
+            written_seq: Mutex::new(0),
+        }
+    }
+
+    /// Loads the scheduled notes, or returns an empty list if the file does not exist yet.
+    pub(crate) fn load(&self) -> io::Result<Vec<ScheduledNote>> {
+        let file = match File::open(&self.path) {
+            Ok(file) => file,
+            Err(e) if e.kind() == io::ErrorKind::NotFound => return Ok(Vec::new()),
+            Err(e) => return Err(e),
+        };
+        let store: StoreFile = serde_json::from_reader(BufReader::new(file))?;
+        Ok(store.notes)
+    }
+
+    /// Serializes the scheduled notes to be saved with [`write`][`FileStore::write`].
+    ///
+    /// This is called while the notes are locked, so that the snapshots are numbered in the
+    /// order of the changes.
+    pub(crate) fn snapshot(&self, notes: &[ScheduledNote]) -> io::Result<Snapshot> {
+        let contents = serde_json::to_vec_pretty(&StoreFileRef { notes })?;
+        let seq = self.next_seq.fetch_add(1, Ordering::SeqCst);
+        Ok(Snapshot { seq, contents })
+    }
+
+    /// Saves the snapshot, replacing the file atomically not to lose the notes on a crash.
+    ///
+    /// The snapshot older than the one already saved is discarded.
+    pub(crate) fn write(&self, snapshot: Snapshot) -> io::Result<()> {
+        let mut written_seq = self.written_seq.lock().unwrap_or_else(|e| e.into_inner());
+        if snapshot.seq < *written_seq {
+            return Ok(());
+        }
+
+        let mut tmp_path = self.path.clone().into_os_string();
+        tmp_path.push(".tmp");
+        let tmp_path = PathBuf::from(tmp_path);
+
+        let mut writer = BufWriter::new(File::create(&tmp_path)?);
+        writer.write_all(&snapshot.contents)?;
+        writer.flush()?;
+        writer.get_ref().sync_all()?;
+        drop(writer);
+
+        fs::rename(&tmp_path, &self.path)?;
+        *written_seq = snapshot.seq + 1;
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::FileStore;
+    use crate::test_util::temp_dir;
+
+    #[test]
+    fn test_write_in_order() {
+        let path = temp_dir("schedule-store").join("notes.json");
+        let store = FileStore::new(&path);
+        assert!(store.load().unwrap().is_empty());
+
+        let empty = store.snapshot(&[]).unwrap();
+        let mut newer = store.snapshot(&[]).unwrap();
+        newer.contents = b"{\"notes\":[],\"newer\":true}".to_vec();
+        store.write(newer).unwrap();
+        // the older snapshot written later does not overwrite the newer one
+        store.write(empty).unwrap();
+        assert!(std::fs::read_to_string(&path).unwrap().contains("newer"));
+        assert!(store.load().unwrap().is_empty());
+    }
+}
@@ -0,0 +1,472 @@
+//! Splitting of long text into notes.
+//!
+//! Misskey rejects notes whose text is longer than
+//! [`max_note_text_length`][misskey_api::model::meta::Meta::max_note_text_length] of the
+//! instance. [`split_text`] splits the text into chunks that fit in the limit, which can be
+//! posted as a thread with [`NoteBuilder::create_thread`][`crate::builder::NoteBuilder::create_thread`].
+//!
+//! The text is split at the most natural boundary available: paragraphs, lines, sentences,
+//! words, and finally grapheme clusters. MFM syntax (such as `$[x2 ...]`, `**...**` and code
+//! blocks), mentions, hashtags, URLs and custom emojis are kept in one piece, unless a single
+//! one of them does not fit in the limit.
+//!
+//! The length is counted in UTF-16 code units as Misskey does, which is never shorter than the
+//! number of characters.
+
+use std::collections::HashMap;
+use std::ops::Range;
+
+use unicode_segmentation::UnicodeSegmentation;
+
+/// Tags of MFM that enclose the contents like HTML, with their closing tags.
+const MFM_TAGS: &[(&str, &str)] = &[
+    ("small", "</small>"),
+    ("center", "</center>"),
+    ("b", "</b>"),
+    ("i", "</i>"),
+    ("s", "</s>"),
+    ("plain", "</plain>"),
+    ("sup", "</sup>"),
+    ("sub", "</sub>"),
+];
+
+/// Pairs of MFM delimiters that enclose the contents, from the longest opening delimiter.
+const MFM_DELIMITERS: &[(&str, &str, bool)] = &[
+    // (opening, closing, whether the contents can span multiple lines)
+    ("```", "```", true),
+    ("***", "***", false),
+    ("**", "**", false),
+    ("__", "__", false),
+    ("~~", "~~", false),
+    ("\\(", "\\)", false),
+    ("\\[", "\\]", true),
+    ("`", "`", false),
+];
+
+/// Options for splitting text.
+#[derive(Debug, Clone, Default)]
+pub struct SplitOptions {
+    /// Appends markers such as ` (1/3)` to the chunks when the text is split.
+    pub markers: bool,
+    /// The maximum length of a chunk, including the marker.
+    ///
+    /// If this is `None`, [`create_thread`][`crate::builder::NoteBuilder::create_thread`] reads
+    /// the limit of the instance from `meta`.
+    pub max_length: Option<usize>,
+}
+
+impl SplitOptions {
+    /// Creates `SplitOptions` that appends the markers to the chunks.
+    pub fn with_markers() -> SplitOptions {
+        SplitOptions {
+            markers: true,
+            max_length: None,
+        }
+    }
+}
+
+/// How natural a boundary is to split the text at.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord)]
+enum Boundary {
+    Grapheme,
+    Word,
+    Sentence,
+    Line,
+    Paragraph,
+}
+
+fn utf16_len(s: &str) -> usize {
+    s.encode_utf16().count()
+}
+
+/// Splits `text` into chunks of at most `max_length` UTF-16 code units.
+///
+/// `options.max_length` is ignored here. If `options.markers` is set and the text is split,
+/// markers such as ` (1/3)` are appended to the chunks within the limit. The markers are
+/// omitted if `max_length` is too short to leave any room for the text besides them.
+///
+/// # Examples
+///
+/// ```
+/// use misskey_util::split::{split_text, SplitOptions};
+///
+/// let text = "The first paragraph.\n\nThe second one, which is a bit longer.";
+/// let chunks = split_text(text, 45, &SplitOptions::with_markers());
+/// assert_eq!(
+///     chunks,
+///     vec![
+///         "The first paragraph. (1/2)",
+///         "The second one, which is a bit longer. (2/2)",
+///     ]
+/// );
+/// ```
+pub fn split_text(text: &str, max_length: usize, options: &SplitOptions) -> Vec<String> {
+    let text = text.trim();
+    if utf16_len(text) <= max_length {
+        return vec![text.to_owned()];
+    }
+
+    let candidates = break_candidates(text);
+    let without_markers = || {
+        split_chunks(text, &candidates, max_length.max(1))
+            .into_iter()
+            .map(str::to_owned)
+            .collect()
+    };
+    if !options.markers {
+        return without_markers();
+    }
+
+    // reserve the space for the markers, growing it until the number of chunks fits
+    let mut digits = 1;
+    loop {
+        if marker_length(digits) >= max_length {
+            return without_markers();
+        }
+        let budget = max_length - marker_length(digits);
+        let chunks = split_chunks(text, &candidates, budget);
+        let total = chunks.len();
+        if total.to_string().len() <= digits {
+            return chunks
+                .into_iter()
+                .enumerate()
+                .map(|(i, chunk)| format!("{} ({}/{})", chunk, i + 1, total))
+                .collect();
+        }
+        digits = total.to_string().len();
+    }
+}
+
+/// The length of ` (i/n)` where both `i` and `n` have `digits` digits at most.
+fn marker_length(digits: usize) -> usize {
+    4 + 2 * digits
+}
+
+fn split_chunks<'a>(
+    text: &'a str,
+    candidates: &[(usize, Boundary)],
+    budget: usize,
+) -> Vec<&'a str> {
+    let mut chunks = Vec::new();
+    let mut start = skip_whitespace(text, 0);
+
+    while start < text.len() {
+        let rest = &text[start..];
+        if utf16_len(rest.trim_end()) <= budget {
+            chunks.push(rest.trim_end());
+            break;
+        }
+
+        let limit = start + prefix_within(rest, budget);
+        let end = choose_break(text, candidates, start, limit, budget)
+            .or_else(|| grapheme_break(text, start, limit))
+            .unwrap_or(text.len());
+        let chunk = text[start..end].trim_end();
+        if !chunk.is_empty() {
+            chunks.push(chunk);
+        }
+        start = skip_whitespace(text, end);
+    }
+
+    chunks
+}
+
+fn skip_whitespace(text: &str, pos: usize) -> usize {
+    let rest = &text[pos..];
+    pos + (rest.len() - rest.trim_start().len())
+}
+
+/// Returns the length in bytes of the longest prefix of `s` within `budget` UTF-16 code units.
+fn prefix_within(s: &str, budget: usize) -> usize {
+    let mut len16 = 0;
+    for (i, c) in s.char_indices() {
+        len16 += c.len_utf16();
+        if len16 > budget {
+            return i;
+        }
+    }
+    s.len()
+}
+
+/// Chooses the most natural break in `(start, limit]`, not to make the chunk too short.
+fn choose_break(
+    text: &str,
+    candidates: &[(usize, Boundary)],
+    start: usize,
+    limit: usize,
+    budget: usize,
+) -> Option<usize> {
+    let in_range = || {
+        candidates
+            .iter()
+            .rev()
+            .filter(move |&&(pos, _)| pos > start && pos <= limit)
+    };
+    let boundaries = [
+        Boundary::Paragraph,
+        Boundary::Line,
+        Boundary::Sentence,
+        Boundary::Word,
+        Boundary::Grapheme,
+    ];
+
+    // prefer the most natural boundary, unless the chunk gets shorter than half of the budget
+    let too_short = start + prefix_within(&text[start..], (budget - 1) / 2);
+    boundaries
+        .iter()
+        .find_map(|&boundary| {
+            in_range()
+                .take_while(|&&(pos, _)| pos > too_short)
+                .find(|&&(_, b)| b == boundary)
+                .map(|&(pos, _)| pos)
+        })
+        .or_else(|| in_range().next().map(|&(pos, _)| pos))
+}
+
+/// Breaks in the middle of a protected span that does not fit in the budget by itself.
+fn grapheme_break(text: &str, start: usize, limit: usize) -> Option<usize> {
+    let rest = &text[start..];
+    let mut found = None;
+    for (i, _) in rest.grapheme_indices(true).skip(1) {
+        if start + i > limit {
+            break;
+        }
+        found = Some(start + i);
+    }
+    // take at least one grapheme cluster to make progress
+    found.or_else(|| rest.grapheme_indices(true).nth(1).map(|(i, _)| start + i))
+}
+
+/// Lists the positions where the text can be split, outside of the protected spans.
+fn break_candidates(text: &str) -> Vec<(usize, Boundary)> {
+    let spans = protected_spans(text);
+    // the spans are sorted and do not overlap
+    let is_protected = |pos: usize| {
+        let i = spans.partition_point(|span| span.end <= pos);
+        matches!(spans.get(i), Some(span) if span.start < pos)
+    };
+
+    let mut candidates = Vec::new();
+    let mut prev: Option<char> = None;
+    for (pos, grapheme) in text.grapheme_indices(true) {
+        if pos == 0 || is_protected(pos) {
+            prev = grapheme.chars().last();
+            continue;
+        }
+
+        let c = grapheme.chars().next().unwrap();
+        // `\r\n` is a single grapheme cluster
+        let is_newline = grapheme == "\n" || grapheme == "\r\n";
+        let boundary = if is_newline && prev != Some('\n') {
+            let next_line = text[pos + grapheme.len()..].trim_start_matches([' ', '\t']);
+            if next_line.starts_with('\n') || next_line.starts_with("\r\n") {
+                Boundary::Paragraph
+            } else {
+                Boundary::Line
+            }
+        } else if matches!(prev, Some('。' | '！' | '？'))
+            || (matches!(prev, Some('.' | '!' | '?')) && c.is_whitespace())
+        {
+            Boundary::Sentence
+        } else if c.is_whitespace() && !matches!(prev, Some(p) if p.is_whitespace()) {
+            Boundary::Word
+        } else {
+            Boundary::Grapheme
+        };
+        candidates.push((pos, boundary));
+        prev = grapheme.chars().last();
+    }
+    candidates
+}
+
+/// Finds the spans of MFM syntax, mentions, hashtags, URLs and emojis that should not be split.
+fn protected_spans(text: &str) -> Vec<Range<usize>> {
+    let mut finder = SpanFinder::new(text);
+    let mut spans = Vec::new();
+    let mut pos = 0;
+    while pos < text.len() {
+        match finder.protected_len(pos) {
+            Some(len) if len > 0 => {
+                spans.push(pos..pos + len);
+                pos += len;
+            }
+            _ => pos += text[pos..].chars().next().map_or(1, char::len_utf8),
+        }
+    }
+    spans
+}
+
+/// Finder of the protected spans, which is queried at increasing positions.
+///
+/// The closing delimiters found are remembered, so that the text after each unclosed opening
+/// delimiter is not scanned again and again.
+struct SpanFinder<'a> {
+    text: &'a str,
+    /// the end of the `]` that closes the `[` at each position
+    brackets: HashMap<usize, usize>,
+    /// the position searched from and the next occurrence found for each closing delimiter
+    closers: HashMap<&'static str, (usize, Option<usize>)>,
+}
+
+impl<'a> SpanFinder<'a> {
+    fn new(text: &'a str) -> Self {
+        let mut brackets = HashMap::new();
+        let mut opened = Vec::new();
+        for (i, c) in text.char_indices() {
+            match c {
+                '[' => opened.push(i),
+                ']' => {
+                    if let Some(open) = opened.pop() {
+                        brackets.insert(open, i + 1);
+                    }
+                }
+                _ => {}
+            }
+        }
+        SpanFinder {
+            text,
+            brackets,
+            closers: HashMap::new(),
+        }
+    }
+
+    /// Returns the position of the first `close` at or after `from`.
+    fn find(&mut self, close: &'static str, from: usize) -> Option<usize> {
+        if let Some(&(searched, found)) = self.closers.get(close) {
+            match found {
+                Some(found) if searched <= from && from <= found => return Some(found),
+                None if searched <= from => return None,
+                _ => {}
+            }
+        }
+        let found = self.text[from..].find(close).map(|i| from + i);
+        self.closers.insert(close, (from, found));
+        found
+    }
+
+    /// Returns the length of `[...]` starting at `pos`.
+    fn bracket_len(&self, pos: usize) -> Option<usize> {
+        self.brackets.get(&pos).map(|end| end - pos)
+    }
+
+    /// Returns the length of the protected span starting at `pos`.
+    fn protected_len(&mut self, pos: usize) -> Option<usize> {
+        let s = &self.text[pos..];
+        let prev = self.text[..pos].chars().next_back();
+        let after_word = matches!(prev, Some(c) if c.is_alphanumeric() || c == '_');
+
+        if s.starts_with("$[") {
+            return self.bracket_len(pos + 1).map(|len| 1 + len);
+        }
+        if s.starts_with('<') {
+            for (tag, close) in MFM_TAGS {
+                let open = format!("<{}>", tag);
+                if s.starts_with(open.as_str()) {
+                    return self
+                        .find(close, pos + open.len())
+                        .map(|i| i + close.len() - pos);
+                }
+            }
+        }
+        for &(open, close, multiline) in MFM_DELIMITERS {
+            if s.starts_with(open) {
+                let inner = pos + open.len();
+                let end = self.find(close, inner)?;
+                if end == inner || (!multiline && self.text[inner..end].contains('\n')) {
+                    return None;
+                }
+                return Some(end + close.len() - pos);
+            }
+        }
+        if s.starts_with('[') || s.starts_with("?[") {
+            let label_start = if s.starts_with('?') { 1 } else { 0 };
+            let after_label = label_start + self.bracket_len(pos + label_start)?;
+            if !s[after_label..].starts_with('(') {
+                return None;
+            }
+            let url_end = self.find(")", pos + after_label + 1)?;
+            return Some(url_end + 1 - pos);
+        }
+        if s.starts_with("https://") || s.starts_with("http://") {
+            return Some(s.find(char::is_whitespace).unwrap_or(s.len()));
+        }
+        if !after_word && s.starts_with('@') {
+            // `@user` or `@user@host`
+            let len = s[1..]
+                .find(|c: char| !(c.is_alphanumeric() || matches!(c, '_' | '-' | '@' | '.')))
+                .map_or(s.len(), |i| 1 + i);
+            return Some(len).filter(|&len| len > 1);
+        }
+        if !after_word && s.starts_with('#') {
+            let len = s[1..]
+                .find(|c: char| c.is_whitespace() || "#.,!?'\"()[]{}:;「」【】、。！？".contains(c))
+                .map_or(s.len(), |i| 1 + i);
+            return Some(len).filter(|&len| len > 1);
+        }
+        if let Some(inner) = s.strip_prefix(':') {
+            let len = inner
+                .find(|c: char| !(c.is_ascii_alphanumeric() || matches!(c, '_' | '+' | '-')))?;
+            if len > 0 && inner[len..].starts_with(':') {
+                return Some(len + 2);
+            }
+        }
+        None
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{split_text, utf16_len, SplitOptions};
+
+    use std::time::{Duration, Instant};
+
+    #[test]
+    fn test_markers_within_limit() {
+        let text = "The first one. The second one.";
+        let chunks = split_text(text, 21, &SplitOptions::with_markers());
+        assert_eq!(
+            chunks,
+            vec!["The first one. (1/2)", "The second one. (2/2)"]
+        );
+    }
+
+    #[test]
+    fn test_markers_omitted_when_too_long() {
+        let chunks = split_text("abcdefghij", 5, &SplitOptions::with_markers());
+        assert_eq!(chunks, vec!["abcde", "fghij"]);
+
+        for max_length in 1..12 {
+            let chunks = split_text("abcdefghij", max_length, &SplitOptions::with_markers());
+            assert!(chunks.iter().all(|chunk| utf16_len(chunk) <= max_length));
+        }
+    }
+
+    #[test]
+    fn test_crlf() {
+        let text = "first paragraph\r\n\r\nsecond paragraph";
+        let chunks = split_text(text, 30, &SplitOptions::default());
+        assert_eq!(chunks, vec!["first paragraph", "second paragraph"]);
+
+        let text = "first line is here\r\nsecond line\r\n\r\nlast";
+        let chunks = split_text(text, 35, &SplitOptions::default());
+        assert_eq!(chunks, vec!["first line is here\r\nsecond line", "last"]);
+    }
+
+    #[test]
+    fn test_protected_spans() {
+        let text = "see **bold text** here";
+        let chunks = split_text(text, 16, &SplitOptions::default());
+        assert_eq!(chunks, vec!["see", "**bold text**", "here"]);
+    }
+
+    #[test]
+    fn test_unclosed_delimiters_in_linear_time() {
+        for unit in ["$[x2 ", "<b>", "**a ", "`a ", "[a](", "~~ "] {
+            let text = unit.repeat(20_000);
+            let start = Instant::now();
+            let chunks = split_text(&text, 3000, &SplitOptions::with_markers());
+            assert!(chunks.iter().all(|chunk| utf16_len(chunk) <= 3000));
+            assert!(start.elapsed() < Duration::from_secs(5), "{:?}", unit);
+        }
+    }
+}
@@ -1,4 +1,5 @@
 use crate::error::Error;
+use crate::ClientExt;
 
 use futures::{
     future::BoxFuture,
@@ -13,6 +14,14 @@ use misskey_api::{
 };
 use misskey_core::streaming::StreamingClient;
 
+mod backfill;
+mod bus;
+pub(crate) mod recent;
+
+use backfill::Backfill;
+pub use backfill::BackfillError;
+pub use bus::{BusEvent, EventBus, EventBusHandle, Subscription, TimelineSource};
+
 /// An extension trait for [`StreamingClient`][client] that provides convenient high-level APIs.
 ///
 /// [client]: misskey_core::streaming::StreamingClient
@@ -24,6 +33,9 @@ use misskey_core::streaming::StreamingClient;
 /// You can use methods from [`TryStreamExt`][try_stream_ext] or [`StreamExt`][stream_ext]
 /// to work with these streams.
 ///
+/// [`timeline_with_backfill`][`StreamingClientExt::timeline_with_backfill`] can be used instead
+/// of the timeline methods to also receive the notes missed while the client is reconnecting.
+///
 /// [future]: futures::future::Future
 /// [stream]: futures::stream::Stream
 /// [try_stream_ext]: futures::stream::TryStreamExt
@@ -345,6 +357,79 @@ pub trait StreamingClientExt: StreamingClient + Sync {
                 .boxed())
         })
     }
+
+    /// Returns a stream to receive the notes in the timeline, which also delivers the notes
+    /// missed while reconnecting, fetching them with `client`.
+    ///
+    /// When the streaming client reports that it has reconnected to the server, the stream
+    /// fetches the notes posted since the last delivered note (or since it connected to the
+    /// timeline, if no note has been delivered yet) from the corresponding endpoint such as
+    /// [`home_notes_since`][`ClientExt::home_notes_since`], and then resumes the live delivery.
+    /// Notes delivered from both sides are de-duplicated.
+    ///
+    /// The hashtag timeline cannot be backfilled, since there is no endpoint to fetch it.
+    /// Neither can the other timelines if the streaming client does not report reconnections
+    /// (see [`StreamingClient::reconnections`][reconnections]). In such cases, this behaves the
+    /// same as the corresponding timeline method such as
+    /// [`local_timeline`][`StreamingClientExt::local_timeline`].
+    ///
+    /// [reconnections]: misskey_core::streaming::StreamingClient::reconnections
+    ///
+    /// # Examples
+    ///
+    /// ```no_run
+    /// # use misskey_util::StreamingClientExt;
+    /// # #[tokio::main]
+    /// # async fn main() -> anyhow::Result<()> {
+    /// # let http_client = misskey_test::test_client().await?;
+    /// # let ws_client = misskey_test::test_websocket_client(misskey_test::env::token()).await?;
+    /// use futures::stream::TryStreamExt;
+    /// use misskey_util::TimelineSource;
+    ///
+    /// let mut notes = ws_client
+    ///     .timeline_with_backfill(&http_client, TimelineSource::Local)
+    ///     .await?;
+    /// while let Some(note) = notes.try_next().await? {
+    ///     println!("{:?}", note.text);
+    /// }
+    /// # Ok(())
+    /// # }
+    /// ```
+    fn timeline_with_backfill<'a, H: ClientExt>(
+        &'a self,
+        client: &'a H,
+        source: TimelineSource,
+    ) -> BoxFuture<
+        'a,
+        Result<
+            BoxStream<'a, Result<Note, BackfillError<Self::Error, H::Error>>>,
+            Error<Self::Error>,
+        >,
+    >
+    where
+        Self: Sized,
+    {
+        Box::pin(async move {
+            // subscribe to reconnections before connecting to the channel not to miss any
+            let reconnections = self.reconnections();
+            let live = bus::connect_timeline(self, &source).await?;
+            let stream = Backfill::new(live, reconnections, move |since| {
+                backfill::fetch_since(client, &source, since)
+            });
+            Ok(stream.boxed())
+        })
+    }
+
+    /// Creates an empty [`EventBus`] on the client, which merges events from several
+    /// subscriptions into one stream.
+    ///
+    /// See [`EventBus`] for details.
+    fn event_bus(&self) -> EventBus<'_, Self>
+    where
+        Self: Sized,
+    {
+        EventBus::new(self)
+    }
 }
 
 impl<C: StreamingClient + Sync> StreamingClientExt for C {}
@@ -0,0 +1,187 @@
+use std::fmt::{self, Debug, Display};
+use std::pin::Pin;
+use std::task::{Context, Poll};
+
+use crate::pager::{BoxPager, PagerStream};
+use crate::streaming::recent::RecentNotes;
+use crate::{ClientExt, Error, TimelineCursor, TimelineSource};
+
+use chrono::{DateTime, Utc};
+use futures::stream::{BoxStream, FusedStream, Stream, StreamExt};
+use misskey_api::model::{id::Id, note::Note};
+use misskey_core::{streaming::ReconnectionStream, Client};
+
+/// Possible errors from the timeline streams that recover missed notes.
+pub enum BackfillError<S, H> {
+    /// Errors from the live stream, where `S` is the error type of the streaming client.
+    Streaming(Error<S>),
+    /// Errors from fetching the missed notes, where `H` is the error type of the client used for it.
+    Backfill(Error<H>),
+}
+
+impl<S: std::error::Error, H: std::error::Error> std::error::Error for BackfillError<S, H> {
+    fn source(&self) -> Option<&(dyn std::error::Error + 'static)> {
+        match self {
+            BackfillError::Streaming(err) => err.source(),
+            BackfillError::Backfill(err) => err.source(),
+        }
+    }
+}
+
+impl<S: std::error::Error, H: std::error::Error> Display for BackfillError<S, H> {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            BackfillError::Streaming(err) => Display::fmt(err, f),
+            BackfillError::Backfill(err) => write!(f, "failed to fetch missed notes: {}", err),
+        }
+    }
+}
+
+impl<S: std::error::Error, H: std::error::Error> Debug for BackfillError<S, H> {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            BackfillError::Streaming(err) => f.debug_tuple("Streaming").field(&err).finish(),
+            BackfillError::Backfill(err) => f.debug_tuple("Backfill").field(&err).finish(),
+        }
+    }
+}
+
+type LiveStream<'a, S> = BoxStream<'a, Result<Note, Error<S>>>;
+
+pub(super) fn fetch_since<'a, H: ClientExt>(
+    client: &'a H,
+    source: &TimelineSource,
+    since: TimelineCursor<Note>,
+) -> Option<PagerStream<BoxPager<'a, H, Note>>> {
+    let notes = match source {
+        TimelineSource::Home => client.home_notes_since(since),
+        TimelineSource::Local => client.local_notes_since(since),
+        TimelineSource::Social => client.social_notes_since(since),
+        TimelineSource::Global => client.global_notes_since(since),
+        TimelineSource::UserList(id) => client.user_list_notes_since(*id, since),
+        #[cfg(feature = "12-47-0")]
+        TimelineSource::Channel(id) => client.channel_notes_since(*id, since),
+        #[cfg(feature = "12-98-0")]
+        TimelineSource::Antenna(id) => client.antenna_notes_since(*id, since),
+        _ => return None,
+    };
+    Some(notes)
+}
+
+type FetchSince<'a, H> =
+    Box<dyn Fn(TimelineCursor<Note>) -> Option<PagerStream<BoxPager<'a, H, Note>>> + Send + 'a>;
+
+/// A stream of notes in the timeline that fetches the notes missed while reconnecting.
+///
+/// Live delivery is suspended during the backfill, so the notes received in the meantime
+/// are queued in the underlying stream and delivered after the missed notes.
+pub(crate) struct Backfill<'a, S, H: Client + ?Sized> {
+    live: LiveStream<'a, S>,
+    reconnections: Option<ReconnectionStream>,
+    fetch_since: FetchSince<'a, H>,
+    backfill: Option<PagerStream<BoxPager<'a, H, Note>>>,
+    last_id: Option<Id<Note>>,
+    connected_at: DateTime<Utc>,
+    recent: RecentNotes,
+    is_terminated: bool,
+}
+
+impl<'a, S, H: Client + ?Sized> Backfill<'a, S, H> {
+    pub(crate) fn new<F>(
+        live: LiveStream<'a, S>,
+        reconnections: Option<ReconnectionStream>,
+        fetch_since: F,
+    ) -> Self
+    where
+        F: Fn(TimelineCursor<Note>) -> Option<PagerStream<BoxPager<'a, H, Note>>> + Send + 'a,
+    {
+        Backfill {
+            live,
+            reconnections,
+            fetch_since: Box::new(fetch_since),
+            backfill: None,
+            last_id: None,
+            connected_at: Utc::now(),
+            recent: RecentNotes::new(),
+            is_terminated: false,
+        }
+    }
+
+    /// Returns `false` if the note is a duplicate and should be skipped.
+    fn deliver(&mut self, note: &Note) -> bool {
+        if !self.recent.insert(note.id) {
+            return false;
+        }
+        // remote notes may have IDs older than the notes delivered before
+        self.last_id = Some(match self.last_id {
+            Some(last_id) if last_id > note.id => last_id,
+            _ => note.id,
+        });
+        true
+    }
+}
+
+impl<'a, S, H: Client + ?Sized> Stream for Backfill<'a, S, H> {
+    type Item = Result<Note, BackfillError<S, H::Error>>;
+
+    fn poll_next(mut self: Pin<&mut Self>, cx: &mut Context) -> Poll<Option<Self::Item>> {
+        let this = &mut *self;
+        if this.is_terminated {
+            return Poll::Ready(None);
+        }
+
+        loop {
+            // Reconnections are checked first so that the missed notes precede the live ones.
+            if let Some(reconnections) = this.reconnections.as_mut() {
+                match reconnections.poll_next_unpin(cx) {
+                    Poll::Ready(Some(())) => {
+                        // without any note delivered, the notes since the connection are missed
+                        let since = match this.last_id {
+                            Some(since_id) => TimelineCursor::Id(since_id),
+                            None => TimelineCursor::DateTime(this.connected_at),
+                        };
+                        this.backfill = (this.fetch_since)(since);
+                        continue;
+                    }
+                    Poll::Ready(None) => this.reconnections = None,
+                    Poll::Pending => {}
+                }
+            }
+
+            if let Some(backfill) = this.backfill.as_mut() {
+                match futures::ready!(backfill.poll_next_unpin(cx)) {
+                    Some(Ok(note)) => {
+                        if this.deliver(&note) {
+                            return Poll::Ready(Some(Ok(note)));
+                        }
+                    }
+                    Some(Err(err)) => {
+                        this.backfill = None;
+                        return Poll::Ready(Some(Err(BackfillError::Backfill(err))));
+                    }
+                    None => this.backfill = None,
+                }
+                continue;
+            }
+
+            match futures::ready!(this.live.poll_next_unpin(cx)) {
+                Some(Ok(note)) => {
+                    if this.deliver(&note) {
+                        return Poll::Ready(Some(Ok(note)));
+                    }
+                }
+                Some(Err(err)) => return Poll::Ready(Some(Err(BackfillError::Streaming(err)))),
+                None => {
+                    this.is_terminated = true;
+                    return Poll::Ready(None);
+                }
+            }
+        }
+    }
+}
+
+impl<'a, S, H: Client + ?Sized> FusedStream for Backfill<'a, S, H> {
+    fn is_terminated(&self) -> bool {
+        self.is_terminated
+    }
+}
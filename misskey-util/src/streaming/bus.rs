@@ -0,0 +1,590 @@
+use std::collections::HashSet;
+use std::fmt::{self, Debug};
+use std::pin::Pin;
+use std::task::{Context, Poll};
+
+use crate::streaming::{recent::RecentNotes, StreamingClientExt};
+use crate::Error;
+
+use futures::{
+    channel::mpsc::{self, UnboundedReceiver, UnboundedSender},
+    future::BoxFuture,
+    stream::{BoxStream, FusedStream, FuturesUnordered, Stream, StreamExt, TryStreamExt},
+};
+#[cfg(feature = "12-47-0")]
+use misskey_api::model::channel::Channel;
+use misskey_api::model::{antenna::Antenna, id::Id, note::Note, query::Query, user_list::UserList};
+use misskey_api::streaming::{channel::main::MainStreamEvent, note::NoteUpdateEvent};
+use misskey_core::streaming::StreamingClient;
+
+/// Timelines that can be subscribed to on [`EventBus`].
+#[derive(Debug, Clone, PartialEq, Eq, Hash)]
+pub enum TimelineSource {
+    /// The home timeline.
+    Home,
+    /// The local timeline.
+    Local,
+    /// The social timeline.
+    Social,
+    /// The global timeline.
+    Global,
+    /// The timeline of notes with the given hashtags.
+    Hashtag(Query<String>),
+    /// The timeline of the antenna.
+    Antenna(Id<Antenna>),
+    /// The timeline of the channel.
+    #[cfg(feature = "12-47-0")]
+    #[cfg_attr(docsrs, doc(cfg(feature = "12-47-0")))]
+    Channel(Id<Channel>),
+    /// The timeline of the user list.
+    UserList(Id<UserList>),
+}
+
+/// Subscriptions that [`EventBus`] owns.
+#[derive(Debug, Clone, PartialEq, Eq, Hash)]
+pub enum Subscription {
+    /// The main stream, which delivers [`BusEvent::Main`].
+    Main,
+    /// The timeline, which delivers [`BusEvent::Note`].
+    Timeline(TimelineSource),
+    /// The captured note, which delivers [`BusEvent::NoteUpdated`].
+    Note(Id<Note>),
+}
+
+impl From<TimelineSource> for Subscription {
+    fn from(source: TimelineSource) -> Subscription {
+        Subscription::Timeline(source)
+    }
+}
+
+impl From<Id<Note>> for Subscription {
+    fn from(id: Id<Note>) -> Subscription {
+        Subscription::Note(id)
+    }
+}
+
+/// Events delivered from [`EventBus`].
+#[derive(Debug, Clone)]
+#[allow(clippy::large_enum_variant)]
+pub enum BusEvent {
+    /// An event from the main stream.
+    Main(MainStreamEvent),
+    /// A note in the timeline.
+    Note {
+        /// The timeline where the note is delivered first.
+        source: TimelineSource,
+        /// The note.
+        note: Note,
+    },
+    /// An event on the captured note.
+    NoteUpdated {
+        /// The ID of the note.
+        note_id: Id<Note>,
+        /// The event.
+        event: NoteUpdateEvent,
+    },
+}
+
+#[derive(Debug)]
+enum Command {
+    Subscribe(Subscription),
+    Unsubscribe(Subscription),
+    Close,
+}
+
+/// Handle to add or remove subscriptions of [`EventBus`] while it is in use.
+///
+/// This can be obtained with [`EventBus::handle`], and is cheap to clone.
+/// The changes are applied when the [`EventBus`] is polled next time.
+#[derive(Debug, Clone)]
+pub struct EventBusHandle {
+    commands: UnboundedSender<Command>,
+}
+
+impl EventBusHandle {
+    /// Adds the subscription to the bus.
+    ///
+    /// Returns `false` if the bus has already been dropped.
+    pub fn subscribe(&self, subscription: impl Into<Subscription>) -> bool {
+        self.commands
+            .unbounded_send(Command::Subscribe(subscription.into()))
+            .is_ok()
+    }
+
+    /// Removes the subscription from the bus.
+    ///
+    /// Returns `false` if the bus has already been dropped.
+    pub fn unsubscribe(&self, subscription: impl Into<Subscription>) -> bool {
+        self.commands
+            .unbounded_send(Command::Unsubscribe(subscription.into()))
+            .is_ok()
+    }
+
+    /// Ends the bus, dropping all of its subscriptions.
+    ///
+    /// Returns `false` if the bus has already been dropped.
+    pub fn close(&self) -> bool {
+        self.commands.unbounded_send(Command::Close).is_ok()
+    }
+}
+
+type EventStream<'a, E> = BoxStream<'a, Result<BusEvent, Error<E>>>;
+type Connecting<'a, E> = BoxFuture<'a, (Subscription, Result<EventStream<'a, E>, Error<E>>)>;
+
+/// A stream that merges events from the main stream, timelines, and captured notes.
+///
+/// [`EventBus`] owns the subscriptions and delivers events from them as [`BusEvent`].
+/// Notes delivered on several timelines are de-duplicated, i.e. only the first delivery is
+/// reported with the timeline as its [`source`][`BusEvent::Note::source`].
+///
+/// Subscriptions can be added or removed at any time with [`EventBus::subscribe`] and
+/// [`EventBus::unsubscribe`], or with [`EventBusHandle`] while the bus is borrowed by the loop
+/// consuming it. Subscribing to what is already subscribed has no effect.
+///
+/// Errors from the subscriptions are delivered as is, and the subscriptions that have
+/// terminated are removed from the bus.
+/// The stream ends when no subscription is left, i.e. when all of them have terminated or
+/// have been unsubscribed, so the subscriptions should be added before polling the bus.
+/// It can also be ended at any time with [`EventBusHandle::close`].
+/// Once the stream has ended, it yields nothing even if new subscriptions are added.
+///
+/// # Examples
+///
+/// ```
+/// # use misskey_util::StreamingClientExt;
+/// # #[tokio::main]
+/// # async fn main() -> anyhow::Result<()> {
+/// # let ws_client = misskey_test::test_websocket_client(misskey_test::env::token()).await?;
+/// # misskey_test::persist(std::time::Duration::from_secs(3), async move {
+/// use futures::stream::TryStreamExt;
+/// use misskey_util::{BusEvent, Subscription, TimelineSource};
+///
+/// let mut bus = ws_client.event_bus();
+/// bus.subscribe(Subscription::Main);
+/// bus.subscribe(TimelineSource::Home);
+/// bus.subscribe(TimelineSource::Local);
+/// let handle = bus.handle();
+///
+/// while let Some(event) = bus.try_next().await? {
+///     match event {
+///         BusEvent::Note { note, .. } => {
+///             // capture the notes to be notified of the reactions
+///             handle.subscribe(note.id);
+///         }
+///         BusEvent::NoteUpdated { note_id, event } => {
+///             println!("{}: {:?}", note_id, event);
+///         }
+///         _ => {}
+///     }
+/// }
+/// # Ok::<(), anyhow::Error>(())
+/// # }).await
+/// # }
+/// ```
+#[must_use = "streams do nothing unless polled"]
+pub struct EventBus<'a, C: StreamingClient> {
+    client: &'a C,
+    commands_tx: UnboundedSender<Command>,
+    commands_rx: UnboundedReceiver<Command>,
+    /// subscriptions that should be active, including the ones being connected
+    subscribed: HashSet<Subscription>,
+    /// subscriptions with a connection in flight, which may have been unsubscribed since then
+    pending: HashSet<Subscription>,
+    connecting: FuturesUnordered<Connecting<'a, C::Error>>,
+    streams: Vec<(Subscription, EventStream<'a, C::Error>)>,
+    /// index in `streams` to start polling from, for fairness
+    next_index: usize,
+    recent: RecentNotes,
+    is_terminated: bool,
+}
+
+impl<'a, C: StreamingClient> Debug for EventBus<'a, C> {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        f.debug_struct("EventBus")
+            .field("subscribed", &self.subscribed)
+            .finish()
+    }
+}
+
+impl<'a, C> EventBus<'a, C>
+where
+    C: StreamingClient + Sync,
+{
+    /// Creates an empty `EventBus` on `client`.
+    pub fn new(client: &'a C) -> Self {
+        let (commands_tx, commands_rx) = mpsc::unbounded();
+        EventBus {
+            client,
+            commands_tx,
+            commands_rx,
+            subscribed: HashSet::new(),
+            pending: HashSet::new(),
+            connecting: FuturesUnordered::new(),
+            streams: Vec::new(),
+            next_index: 0,
+            recent: RecentNotes::new(),
+            is_terminated: false,
+        }
+    }
+
+    /// Returns a handle to change the subscriptions while the bus is in use.
+    pub fn handle(&self) -> EventBusHandle {
+        EventBusHandle {
+            commands: self.commands_tx.clone(),
+        }
+    }
+
+    /// Adds the subscription to the bus.
+    pub fn subscribe(&mut self, subscription: impl Into<Subscription>) {
+        let subscription = subscription.into();
+        if !self.subscribed.insert(subscription.clone()) {
+            return;
+        }
+
+        // resubscribed while connecting, which reuses the connection in flight
+        if !self.pending.insert(subscription.clone()) {
+            return;
+        }
+
+        let client = self.client;
+        self.connecting.push(Box::pin(async move {
+            let stream = connect(client, &subscription).await;
+            (subscription, stream)
+        }));
+    }
+
+    /// Removes the subscription from the bus.
+    pub fn unsubscribe(&mut self, subscription: impl Into<Subscription>) {
+        let subscription = subscription.into();
+        if !self.subscribed.remove(&subscription) {
+            return;
+        }
+
+        // dropping the stream ends the subscription.
+        // ones being connected are dropped when the connection completes unless resubscribed
+        self.streams.retain(|(s, _)| s != &subscription);
+    }
+
+    /// Returns the subscriptions of the bus, including the ones being connected.
+    pub fn subscriptions(&self) -> impl Iterator<Item = &Subscription> {
+        self.subscribed.iter()
+    }
+
+    /// Drops all the subscriptions and ends the stream.
+    fn terminate(&mut self) {
+        self.subscribed.clear();
+        self.pending.clear();
+        self.connecting.clear();
+        self.streams.clear();
+        self.is_terminated = true;
+    }
+
+    fn remove_stream(&mut self, index: usize) {
+        let (subscription, _) = self.streams.remove(index);
+        self.subscribed.remove(&subscription);
+    }
+
+    /// Returns `false` if the note is a duplicate and should be skipped.
+    fn deliver(&mut self, event: &BusEvent) -> bool {
+        match event {
+            BusEvent::Note { note, .. } => self.recent.insert(note.id),
+            _ => true,
+        }
+    }
+}
+
+async fn connect<'a, C>(
+    client: &'a C,
+    subscription: &Subscription,
+) -> Result<EventStream<'a, C::Error>, Error<C::Error>>
+where
+    C: StreamingClient + Sync,
+{
+    let source = match subscription {
+        Subscription::Main => {
+            let stream = client.main_stream().await?;
+            return Ok(stream.map_ok(BusEvent::Main).boxed());
+        }
+        Subscription::Note(note_id) => {
+            let note_id = *note_id;
+            let stream = client.subscribe_note(note_id).await?;
+            return Ok(stream
+                .map_ok(move |event| BusEvent::NoteUpdated { note_id, event })
+                .boxed());
+        }
+        Subscription::Timeline(source) => source.clone(),
+    };
+
+    let notes = connect_timeline(client, &source).await?;
+    Ok(notes
+        .map_ok(move |note| BusEvent::Note {
+            source: source.clone(),
+            note,
+        })
+        .boxed())
+}
+
+/// Connects to the timeline with the corresponding method of [`StreamingClientExt`].
+pub(super) async fn connect_timeline<'a, C>(
+    client: &'a C,
+    source: &TimelineSource,
+) -> Result<BoxStream<'a, Result<Note, Error<C::Error>>>, Error<C::Error>>
+where
+    C: StreamingClient + Sync,
+{
+    let notes = match source {
+        TimelineSource::Home => client.home_timeline().await?,
+        TimelineSource::Local => client.local_timeline().await?,
+        TimelineSource::Social => client.social_timeline().await?,
+        TimelineSource::Global => client.global_timeline().await?,
+        TimelineSource::Hashtag(query) => client.hashtag_timeline(query.clone()).await?,
+        TimelineSource::Antenna(id) => client.antenna_timeline(*id).await?,
+        #[cfg(feature = "12-47-0")]
+        TimelineSource::Channel(id) => client.channel_timeline(*id).await?,
+        TimelineSource::UserList(id) => client.user_list_timeline(*id).await?,
+    };
+    Ok(notes)
+}
+
+impl<'a, C> Stream for EventBus<'a, C>
+where
+    C: StreamingClient + Sync,
+{
+    type Item = Result<BusEvent, Error<C::Error>>;
+
+    fn poll_next(mut self: Pin<&mut Self>, cx: &mut Context) -> Poll<Option<Self::Item>> {
+        let this = &mut *self;
+        if this.is_terminated {
+            return Poll::Ready(None);
+        }
+
+        // the sender in `this` keeps the channel open, so this never returns `Ready(None)`
+        while let Poll::Ready(Some(command)) = this.commands_rx.poll_next_unpin(cx) {
+            match command {
+                Command::Subscribe(subscription) => this.subscribe(subscription),
+                Command::Unsubscribe(subscription) => this.unsubscribe(subscription),
+                Command::Close => {
+                    this.terminate();
+                    return Poll::Ready(None);
+                }
+            }
+        }
+
+        while let Poll::Ready(Some((subscription, result))) = this.connecting.poll_next_unpin(cx) {
+            this.pending.remove(&subscription);
+            if !this.subscribed.contains(&subscription) {
+                // unsubscribed while connecting
+                continue;
+            }
+
+            match result {
+                Ok(stream) => this.streams.push((subscription, stream)),
+                Err(e) => {
+                    this.subscribed.remove(&subscription);
+                    return Poll::Ready(Some(Err(e)));
+                }
+            }
+        }
+
+        let len = this.streams.len();
+        let start = this.next_index;
+        let mut ended = Vec::new();
+        let mut output = None;
+        for i in 0..len {
+            let index = (start + i) % len;
+            output = loop {
+                match this.streams[index].1.poll_next_unpin(cx) {
+                    Poll::Ready(Some(Ok(event))) => {
+                        if this.deliver(&event) {
+                            break Some(Ok(event));
+                        }
+                    }
+                    Poll::Ready(Some(Err(e))) => break Some(Err(e)),
+                    Poll::Ready(None) => {
+                        ended.push(index);
+                        break None;
+                    }
+                    Poll::Pending => break None,
+                }
+            };
+            if output.is_some() {
+                this.next_index = index + 1;
+                break;
+            }
+        }
+
+        // remove from the back not to shift the indices of the rest
+        ended.sort_unstable_by(|a, b| b.cmp(a));
+        for index in ended {
+            this.remove_stream(index);
+        }
+        if this.next_index >= this.streams.len() {
+            this.next_index = 0;
+        }
+
+        if output.is_some() {
+            return Poll::Ready(output);
+        }
+
+        if this.subscribed.is_empty() {
+            this.terminate();
+            return Poll::Ready(None);
+        }
+        Poll::Pending
+    }
+}
+
+impl<'a, C> FusedStream for EventBus<'a, C>
+where
+    C: StreamingClient + Sync,
+{
+    fn is_terminated(&self) -> bool {
+        self.is_terminated
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{BusEvent, EventBus, Subscription, TimelineSource};
+    use crate::test_util::{id, note_json, MockStreamingClient};
+
+    use futures::{
+        future::FutureExt,
+        stream::{FusedStream, StreamExt},
+    };
+    use misskey_api::model::note::Note;
+    use misskey_api::streaming::{channel::main::MainStreamEvent, note::NoteUpdateEvent};
+    use serde_json::{json, Value};
+
+    fn note_event(n: u64) -> Value {
+        json!({ "type": "note", "body": note_json(n, "") })
+    }
+
+    /// Returns the next event if it is ready, panicking on errors and the end of the stream.
+    fn next(bus: &mut EventBus<MockStreamingClient>) -> Option<BusEvent> {
+        bus.next()
+            .now_or_never()
+            .map(|event| event.expect("the bus has ended").unwrap())
+    }
+
+    fn assert_ended(bus: &mut EventBus<MockStreamingClient>) {
+        assert!(matches!(bus.next().now_or_never(), Some(None)));
+        assert!(bus.is_terminated());
+    }
+
+    fn note_id(event: Option<BusEvent>) -> (TimelineSource, String) {
+        match event {
+            Some(BusEvent::Note { source, note }) => (source, note.id.to_string()),
+            event => panic!("unexpected event: {:?}", event),
+        }
+    }
+
+    #[test]
+    fn test_deliver() {
+        let client = MockStreamingClient::new();
+        let mut bus = EventBus::new(&client);
+        bus.subscribe(Subscription::Main);
+        bus.subscribe(TimelineSource::Local);
+        bus.subscribe(TimelineSource::Global);
+        assert!(next(&mut bus).is_none());
+        let mut connections = client.connections();
+        connections.sort();
+        assert_eq!(connections, ["globalTimeline", "localTimeline", "main"]);
+
+        client.send("localTimeline", note_event(1));
+        assert_eq!(
+            note_id(next(&mut bus)),
+            (TimelineSource::Local, id::<Note>(1).to_string())
+        );
+        // the note delivered on the local timeline is skipped
+        client.send("globalTimeline", note_event(1));
+        client.send("globalTimeline", note_event(2));
+        assert_eq!(
+            note_id(next(&mut bus)),
+            (TimelineSource::Global, id::<Note>(2).to_string())
+        );
+        assert!(next(&mut bus).is_none());
+
+        client.send("main", json!({ "type": "readAllNotifications" }));
+        assert!(matches!(
+            next(&mut bus),
+            Some(BusEvent::Main(MainStreamEvent::ReadAllNotifications))
+        ));
+    }
+
+    #[test]
+    fn test_end_with_sources() {
+        let client = MockStreamingClient::new();
+        let mut bus = EventBus::new(&client);
+        bus.subscribe(Subscription::Main);
+        bus.subscribe(TimelineSource::Local);
+        assert!(next(&mut bus).is_none());
+
+        client.close("localTimeline");
+        assert!(next(&mut bus).is_none());
+        assert_eq!(
+            bus.subscriptions().collect::<Vec<_>>(),
+            [&Subscription::Main]
+        );
+        client.close("main");
+        assert_ended(&mut bus);
+        // nothing is delivered after the end
+        bus.subscribe(TimelineSource::Local);
+        assert_ended(&mut bus);
+    }
+
+    #[test]
+    fn test_end_with_unsubscribe() {
+        let client = MockStreamingClient::new();
+        let mut bus = EventBus::new(&client);
+        bus.subscribe(TimelineSource::Local);
+        assert!(next(&mut bus).is_none());
+        bus.unsubscribe(TimelineSource::Local);
+        assert!(client.connections().is_empty());
+        assert_ended(&mut bus);
+    }
+
+    #[test]
+    fn test_empty() {
+        let client = MockStreamingClient::new();
+        let mut bus = EventBus::new(&client);
+        assert_ended(&mut bus);
+    }
+
+    #[test]
+    fn test_handle() {
+        let client = MockStreamingClient::new();
+        let mut bus = EventBus::new(&client);
+        bus.subscribe(TimelineSource::Local);
+        let handle = bus.handle();
+        assert!(next(&mut bus).is_none());
+
+        let note_id = id::<Note>(1);
+        assert!(handle.subscribe(note_id));
+        assert!(next(&mut bus).is_none());
+        let name = format!("note:{}", note_id);
+        assert!(client.connections().contains(&name));
+        client.send(
+            &name,
+            json!({ "type": "deleted", "body": { "deletedAt": "2000-01-01T00:00:00Z" } }),
+        );
+        assert!(matches!(
+            next(&mut bus),
+            Some(BusEvent::NoteUpdated {
+                note_id: id,
+                event: NoteUpdateEvent::Deleted { .. },
+            }) if id == note_id
+        ));
+
+        assert!(handle.unsubscribe(TimelineSource::Local));
+        assert!(next(&mut bus).is_none());
+        assert_eq!(client.connections(), [name]);
+
+        assert!(handle.close());
+        assert_ended(&mut bus);
+        assert!(client.connections().is_empty());
+
+        drop(bus);
+        assert!(!handle.subscribe(TimelineSource::Local));
+    }
+}
@@ -0,0 +1,35 @@
+use std::collections::{HashSet, VecDeque};
+
+use misskey_api::model::{id::Id, note::Note};
+
+/// The number of recently delivered notes remembered to remove duplicates.
+const RECENT_NOTES_CAPACITY: usize = 1000;
+
+/// Bounded set of recently delivered note IDs.
+pub(crate) struct RecentNotes {
+    order: VecDeque<Id<Note>>,
+    set: HashSet<Id<Note>>,
+}
+
+impl RecentNotes {
+    pub(crate) fn new() -> Self {
+        RecentNotes {
+            order: VecDeque::with_capacity(RECENT_NOTES_CAPACITY),
+            set: HashSet::with_capacity(RECENT_NOTES_CAPACITY),
+        }
+    }
+
+    /// Returns `false` if `id` has been delivered recently.
+    pub(crate) fn insert(&mut self, id: Id<Note>) -> bool {
+        if !self.set.insert(id) {
+            return false;
+        }
+        if self.order.len() == RECENT_NOTES_CAPACITY {
+            if let Some(oldest) = self.order.pop_front() {
+                self.set.remove(&oldest);
+            }
+        }
+        self.order.push_back(id);
+        true
+    }
+}
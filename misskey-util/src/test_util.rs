@@ -0,0 +1,331 @@
+//! Mock client and fixtures for the unit tests.
+
+use std::convert::Infallible;
+use std::io::Read;
+use std::marker::PhantomData;
+use std::pin::Pin;
+use std::sync::Mutex;
+use std::task::{Context, Poll};
+
+use futures::channel::mpsc::{self, UnboundedReceiver, UnboundedSender};
+use futures::future::{self, BoxFuture, FutureExt};
+use futures::sink::Sink;
+use futures::stream::{BoxStream, Stream, StreamExt};
+use misskey_api::model::{id::Id, note::Note, user::User};
+use misskey_core::streaming::{
+    BroadcastEvent, BroadcastStream, ChannelStream, ConnectChannelRequest, StreamingClient,
+    SubNoteEvent, SubNoteStream,
+};
+use misskey_core::{model::ApiResult, Client, Request, UploadFileClient, UploadFileRequest};
+use serde::de::DeserializeOwned;
+use serde_json::{json, Value};
+
+type Handler = Box<dyn Fn(&str, &Value) -> Value + Send + Sync>;
+
+/// A client that answers the requests with `handler`, which takes the endpoint and the
+/// request in JSON and returns the response in JSON.
+pub(crate) struct MockClient {
+    handler: Handler,
+    requests: Mutex<Vec<(String, Value)>>,
+    files: Mutex<Vec<(String, Vec<u8>)>>,
+}
+
+impl MockClient {
+    pub(crate) fn new(handler: impl Fn(&str, &Value) -> Value + Send + Sync + 'static) -> Self {
+        MockClient {
+            handler: Box::new(handler),
+            requests: Mutex::new(Vec::new()),
+            files: Mutex::new(Vec::new()),
+        }
+    }
+
+    /// Returns the requests made so far.
+    pub(crate) fn requests(&self) -> Vec<(String, Value)> {
+        self.requests.lock().unwrap().clone()
+    }
+
+    /// Returns the endpoints of the requests made so far.
+    pub(crate) fn endpoints(&self) -> Vec<String> {
+        self.requests()
+            .into_iter()
+            .map(|(endpoint, _)| endpoint)
+            .collect()
+    }
+
+    /// Returns the names and the contents of the files uploaded so far.
+    pub(crate) fn files(&self) -> Vec<(String, Vec<u8>)> {
+        self.files.lock().unwrap().clone()
+    }
+
+    /// Records the request and returns the future that yields the response.
+    ///
+    /// The response is deserialized when the future is polled, since it may not be `Send`.
+    fn respond<R: Request>(
+        &self,
+        request: R,
+    ) -> BoxFuture<Result<ApiResult<R::Response>, Infallible>> {
+        let request = serde_json::to_value(request).unwrap();
+        let response = (self.handler)(R::ENDPOINT, &request);
+        self.requests
+            .lock()
+            .unwrap()
+            .push((R::ENDPOINT.to_owned(), request));
+        future::ready(response)
+            .map(|response| Ok(serde_json::from_value(response).unwrap()))
+            .boxed()
+    }
+}
+
+impl Client for MockClient {
+    type Error = Infallible;
+
+    fn request<R: Request>(
+        &self,
+        request: R,
+    ) -> BoxFuture<Result<ApiResult<R::Response>, Self::Error>> {
+        self.respond(request)
+    }
+}
+
+impl UploadFileClient for MockClient {
+    fn request_with_file<R, T>(
+        &self,
+        request: R,
+        _type: mime::Mime,
+        file_name: String,
+        mut content: T,
+    ) -> BoxFuture<Result<ApiResult<R::Response>, Self::Error>>
+    where
+        R: UploadFileRequest,
+        T: Read + Send + Sync + 'static,
+    {
+        let mut contents = Vec::new();
+        content.read_to_end(&mut contents).unwrap();
+        self.files.lock().unwrap().push((file_name, contents));
+        self.respond(request)
+    }
+}
+
+/// A streaming client whose channels and captured notes deliver the messages given with
+/// [`send`][`MockStreamingClient::send`].
+///
+/// The connections are identified by the channel names, or `note:<ID>` for the captured notes.
+#[derive(Default)]
+pub(crate) struct MockStreamingClient {
+    connections: Mutex<Vec<(String, UnboundedSender<Value>)>>,
+}
+
+impl MockStreamingClient {
+    pub(crate) fn new() -> Self {
+        MockStreamingClient::default()
+    }
+
+    /// Returns the names of the open connections.
+    pub(crate) fn connections(&self) -> Vec<String> {
+        let mut connections = self.connections.lock().unwrap();
+        connections.retain(|(_, sender)| !sender.is_closed());
+        connections.iter().map(|(name, _)| name.clone()).collect()
+    }
+
+    /// Sends the message in JSON to the connections of `name`.
+    pub(crate) fn send(&self, name: &str, message: Value) {
+        let connections = self.connections.lock().unwrap();
+        for (_, sender) in connections.iter().filter(|(n, _)| n == name) {
+            let _ = sender.unbounded_send(message.clone());
+        }
+    }
+
+    /// Ends the connections of `name`.
+    pub(crate) fn close(&self, name: &str) {
+        self.connections.lock().unwrap().retain(|(n, _)| n != name);
+    }
+
+    fn connect<T: DeserializeOwned>(&self, name: String) -> MockConnection<T> {
+        let (sender, receiver) = mpsc::unbounded();
+        self.connections.lock().unwrap().push((name, sender));
+        MockConnection {
+            receiver,
+            _marker: PhantomData,
+        }
+    }
+}
+
+struct MockConnection<T> {
+    receiver: UnboundedReceiver<Value>,
+    _marker: PhantomData<fn() -> T>,
+}
+
+impl<T: DeserializeOwned> Stream for MockConnection<T> {
+    type Item = Result<T, Infallible>;
+
+    fn poll_next(mut self: Pin<&mut Self>, cx: &mut Context) -> Poll<Option<Self::Item>> {
+        self.receiver
+            .poll_next_unpin(cx)
+            .map(|message| message.map(|m| Ok(serde_json::from_value(m).unwrap())))
+    }
+}
+
+impl<T, O> Sink<O> for MockConnection<T> {
+    type Error = Infallible;
+
+    fn poll_ready(self: Pin<&mut Self>, _cx: &mut Context) -> Poll<Result<(), Infallible>> {
+        Poll::Ready(Ok(()))
+    }
+
+    fn start_send(self: Pin<&mut Self>, _item: O) -> Result<(), Infallible> {
+        Ok(())
+    }
+
+    fn poll_flush(self: Pin<&mut Self>, _cx: &mut Context) -> Poll<Result<(), Infallible>> {
+        Poll::Ready(Ok(()))
+    }
+
+    fn poll_close(self: Pin<&mut Self>, _cx: &mut Context) -> Poll<Result<(), Infallible>> {
+        Poll::Ready(Ok(()))
+    }
+}
+
+impl StreamingClient for MockStreamingClient {
+    type Error = Infallible;
+
+    fn subnote<E: SubNoteEvent>(
+        &self,
+        note_id: String,
+    ) -> BoxFuture<Result<SubNoteStream<E, Infallible>, Infallible>> {
+        let stream: BoxStream<_> = self.connect(format!("note:{}", note_id)).boxed();
+        future::ready(Ok(stream)).boxed()
+    }
+
+    fn channel<R: ConnectChannelRequest>(
+        &self,
+        _request: R,
+    ) -> BoxFuture<Result<ChannelStream<R, Infallible>, Infallible>> {
+        let stream: ChannelStream<R, Infallible> = Box::pin(self.connect(R::NAME.to_owned()));
+        future::ready(Ok(stream)).boxed()
+    }
+
+    fn broadcast<E: BroadcastEvent>(
+        &self,
+    ) -> BoxFuture<Result<BroadcastStream<E, Infallible>, Infallible>> {
+        let stream: BoxStream<_> = self.connect("broadcast".to_owned()).boxed();
+        future::ready(Ok(stream)).boxed()
+    }
+}
+
+/// Returns an API error with `code`.
+pub(crate) fn api_error(code: &str) -> Value {
+    json!({
+        "error": {
+            "id": "00000000-0000-0000-0000-000000000000",
+            "message": code,
+            "code": code,
+            "kind": "client",
+        }
+    })
+}
+
+/// Returns the ID created at `n` milliseconds after 2000-01-01 in the ID format of the
+/// enabled feature.
+pub(crate) fn id<T>(n: u64) -> Id<T> {
+    #[cfg(feature = "aidx")]
+    let id = format!("{}0000{}", radix36(n, 8), radix36(0, 4));
+    #[cfg(feature = "aid")]
+    let id = format!("{}00", radix36(n, 8));
+    #[cfg(feature = "meid")]
+    let id = format!("{:012x}000000000000", 946684800000 + n + 0x800000000000);
+    #[cfg(feature = "ulid")]
+    let id = ulid_crate::Ulid::from_parts(946684800000 + n, 0).to_string();
+    #[cfg(feature = "objectid")]
+    let id = format!("{:08x}{:016x}", 946684800 + n / 1000, n);
+    id.parse().unwrap()
+}
+
+#[cfg(any(feature = "aid", feature = "aidx"))]
+fn radix36(mut x: u64, len: usize) -> String {
+    let mut digits = Vec::new();
+    while x > 0 {
+        digits.push(std::char::from_digit((x % 36) as u32, 36).unwrap());
+        x /= 36;
+    }
+    digits.resize(len.max(digits.len()), '0');
+    digits.into_iter().rev().collect()
+}
+
+/// Returns the date in RFC 3339 at `n` milliseconds after 2000-01-01.
+fn date(n: u64) -> String {
+    let date = chrono::DateTime::parse_from_rfc3339("2000-01-01T00:00:00Z").unwrap()
+        + chrono::Duration::milliseconds(n as i64);
+    date.to_rfc3339()
+}
+
+pub(crate) fn user_json(n: u64, username: &str, host: Option<&str>) -> Value {
+    json!({
+        "id": id::<User>(n),
+        "username": username,
+        "name": null,
+        "host": host,
+        "avatarUrl": null,
+        "isBot": false,
+        "isCat": false,
+    })
+}
+
+pub(crate) fn user(n: u64, username: &str, host: Option<&str>) -> User {
+    serde_json::from_value(user_json(n, username, host)).unwrap()
+}
+
+pub(crate) fn note_json(n: u64, text: &str) -> Value {
+    let mut note = json!({
+        "id": id::<Note>(n),
+        "createdAt": date(n),
+        "text": text,
+        "cw": null,
+        "userId": id::<User>(0),
+        "user": user_json(0, "user", None),
+        "visibility": "public",
+        "fileIds": [],
+        "files": [],
+        "reactions": {},
+        "renoteCount": 0,
+        "repliesCount": 0,
+        "uri": null,
+    });
+    #[cfg(not(feature = "13-0-0"))]
+    {
+        note["emojis"] = json!([]);
+    }
+    #[cfg(feature = "13-2-4")]
+    {
+        note["reactionEmojis"] = json!({});
+    }
+    note
+}
+
+pub(crate) fn note(n: u64, text: &str) -> Note {
+    serde_json::from_value(note_json(n, text)).unwrap()
+}
+
+pub(crate) fn drive_file_json(n: u64, name: &str, md5: &str, folder: Option<u64>) -> Value {
+    json!({
+        "id": id::<()>(n),
+        "createdAt": date(n),
+        "name": name,
+        "type": "image/png",
+        "md5": md5,
+        "size": 0,
+        "url": format!("https://example.com/files/{}", name),
+        "folderId": folder.map(id::<()>),
+        "isSensitive": false,
+        "properties": {},
+    })
+}
+
+/// Creates an empty directory for the test `name` under the temporary directory.
+pub(crate) fn temp_dir(name: &str) -> std::path::PathBuf {
+    let dir = std::env::temp_dir().join(format!("misskey-util-{}-{}", std::process::id(), name));
+    if dir.exists() {
+        std::fs::remove_dir_all(&dir).unwrap();
+    }
+    std::fs::create_dir_all(&dir).unwrap();
+    dir
+}
@@ -3,14 +3,19 @@ use std::ops::{Range, RangeFrom, RangeFull, RangeTo};
 use chrono::{DateTime, Utc};
 use derivative::Derivative;
 use misskey_api::{model::id::Id, Entity};
+use serde::{Deserialize, Serialize};
 
 /// Range in the timeline.
-#[derive(Derivative)]
+///
+/// This can be serialized to save the range of a collection job, for example.
+#[derive(Serialize, Deserialize, Derivative)]
+#[serde(bound = "", rename_all = "camelCase")]
 #[derivative(Debug(bound = ""))]
 #[derivative(PartialEq(bound = ""), Eq(bound = ""))]
 #[derivative(Clone(bound = ""), Copy(bound = ""))]
 pub enum TimelineRange<E> {
     /// Range in the timeline bounded by time.
+    #[serde(rename_all = "camelCase")]
     DateTime {
         /// The lower bound of the range (inclusive), if it exists.
         since_date: Option<DateTime<Utc>>,
@@ -18,6 +23,7 @@ pub enum TimelineRange<E> {
         until_date: Option<DateTime<Utc>>,
     },
     /// Range in the timeline bounded by note IDs.
+    #[serde(rename_all = "camelCase")]
     Id {
         /// The lower bound of the range (inclusive), if it exists.
         since_id: Option<Id<E>>,
@@ -102,7 +108,8 @@ impl_from_range! { RangeFrom, range, Some(range.start), None }
 impl_from_range! { RangeTo, range, None, Some(range.end) }
 
 /// Point on the timeline.
-#[derive(Derivative)]
+#[derive(Serialize, Deserialize, Derivative)]
+#[serde(bound = "", rename_all = "camelCase")]
 #[derivative(Debug(bound = ""))]
 #[derivative(PartialEq(bound = ""), Eq(bound = ""))]
 #[derivative(Clone(bound = ""), Copy(bound = ""))]
@@ -1,29 +1,31 @@
 use std::fmt::{self, Debug};
 use std::sync::Arc;
-use std::time::Duration;
+use std::time::{Duration, Instant};
 
-use crate::channel::{connect_websocket, TrySendError, WebSocketReceiver};
+use crate::channel::{connect, Transport, TrySendError, WebSocketReceiver, WebSocketSender};
 use crate::error::{Error, Result};
-use crate::model::outgoing::OutgoingMessage;
+use crate::model::{incoming::IncomingMessage, outgoing::OutgoingMessage};
+use crate::record::Recorder;
 
 #[cfg(feature = "async-std-runtime")]
 use async_std::task;
 #[cfg(feature = "async-std-runtime")]
 use async_std::task::sleep;
-use async_tungstenite::tungstenite::http::HeaderMap;
 use async_tungstenite::tungstenite::Error as WsError;
-use futures_util::stream::StreamExt;
+use futures_util::{sink::SinkExt, stream::StreamExt};
 use log::{info, warn};
 #[cfg(feature = "tokio-runtime")]
 use tokio::task;
 #[cfg(feature = "tokio-runtime")]
 use tokio::time::sleep;
-use url::Url;
 
 pub mod channel;
 pub mod handler;
 pub mod model;
 
+/// How long the broker waits for the server to reply to the close frame.
+const CLOSE_HANDSHAKE_TIMEOUT: Duration = Duration::from_secs(5);
+
 use channel::{control_channel, ControlReceiver, ControlSender};
 use handler::Handler;
 use model::SharedBrokerState;
@@ -33,8 +35,9 @@ pub(crate) struct Broker {
     broker_rx: ControlReceiver,
     handler: Handler,
     reconnect: ReconnectConfig,
-    url: Url,
-    additional_headers: HeaderMap,
+    heartbeat: HeartbeatConfig,
+    transport: Transport,
+    recorder: Option<Recorder>,
 }
 
 /// Specifies the condition for reconnecting.
@@ -87,6 +90,9 @@ impl ReconnectCondition {
     }
 
     /// Creates a `ReconnectCondition` that reconnects when the connection is lost unexpectedly.
+    ///
+    /// This includes [`Error::HeartbeatTimeout`], which occurs when the connection is silently
+    /// lost (see [`HeartbeatConfig`]).
     pub fn unexpected_reset() -> Self {
         ReconnectCondition {
             inner: ReconnectConditionKind::UnexpectedReset,
@@ -110,6 +116,8 @@ impl ReconnectCondition {
             ReconnectConditionKind::UnexpectedReset => {
                 let ws = match err {
                     Error::WebSocket(ws) => ws,
+                    // the connection is silently lost
+                    Error::HeartbeatTimeout => return true,
                     _ => return false,
                 };
 
@@ -179,11 +187,114 @@ impl Default for ReconnectConfig {
     }
 }
 
+/// Heartbeat configuration.
+///
+/// Heartbeat is used to detect the connection that is silently lost (e.g. by network failure),
+/// which is otherwise left undetected since no error occurs on our side.
+/// Such a connection fails with [`Error::HeartbeatTimeout`], which can be handled by
+/// [`ReconnectCondition`].
+#[derive(Debug, Clone, Default)]
+pub struct HeartbeatConfig {
+    /// Sends a ping frame to the server at this interval, if set.
+    pub ping_interval: Option<Duration>,
+    /// Regards the connection as lost when no message is received from the server for this
+    /// duration, if set.
+    ///
+    /// This should be longer than [`ping_interval`][`HeartbeatConfig::ping_interval`]
+    /// if the both are set, so that the server can reply to the ping in time.
+    pub idle_timeout: Option<Duration>,
+}
+
+impl HeartbeatConfig {
+    /// Creates a `HeartbeatConfig` that disables heartbeat.
+    pub fn none() -> HeartbeatConfig {
+        HeartbeatConfig::default()
+    }
+
+    /// Creates a `HeartbeatConfig` that sends a ping at `ping_interval` and fails the connection
+    /// if nothing is received for `idle_timeout`.
+    pub fn new(ping_interval: Duration, idle_timeout: Duration) -> HeartbeatConfig {
+        HeartbeatConfig {
+            ping_interval: Some(ping_interval),
+            idle_timeout: Some(idle_timeout),
+        }
+    }
+
+    /// Returns when the next heartbeat action should be taken, if any.
+    fn next_deadline(&self, last_ping: Instant, last_received: Instant) -> Option<Instant> {
+        let ping = self.ping_interval.map(|interval| last_ping + interval);
+        let timeout = self.idle_timeout.map(|timeout| last_received + timeout);
+        match (ping, timeout) {
+            (Some(ping), Some(timeout)) => Some(ping.min(timeout)),
+            (ping, timeout) => ping.or(timeout),
+        }
+    }
+}
+
+/// Behavior of a stream when its buffer is full.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum OverflowPolicy {
+    /// Waits until the subscriber consumes a message.
+    ///
+    /// Note that this suspends the whole connection, including the other streams and API calls
+    /// over the same client, until the subscriber catches up.
+    /// Thus the subscriber must not wait for the other streams or requests over the same client
+    /// while leaving the stream unpolled, or it will deadlock.
+    Block,
+    /// Drops the oldest message in the buffer to make room for the new one.
+    DropOldest,
+    /// Drops the new message.
+    DropNewest,
+    /// Terminates the stream with [`Error::BufferFull`].
+    Error,
+}
+
+/// Buffering configuration of a stream.
+///
+/// The number of messages dropped due to the overflow can be obtained from the stream
+/// (e.g. [`SubNote::lagged_count`][`crate::stream::SubNote::lagged_count`]).
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct BufferConfig {
+    /// The maximum number of messages held in the buffer, or `None` for an unbounded buffer.
+    pub capacity: Option<usize>,
+    /// How to handle new messages when the buffer is full.
+    pub policy: OverflowPolicy,
+}
+
+impl BufferConfig {
+    /// Creates a `BufferConfig` with an unbounded buffer.
+    pub fn unbounded() -> BufferConfig {
+        BufferConfig {
+            capacity: None,
+            policy: OverflowPolicy::Block,
+        }
+    }
+
+    /// Creates a `BufferConfig` that holds at most `capacity` messages and handles the rest
+    /// according to `policy`.
+    ///
+    /// `capacity` less than 1 is treated as 1.
+    pub fn bounded(capacity: usize, policy: OverflowPolicy) -> BufferConfig {
+        BufferConfig {
+            capacity: Some(capacity.max(1)),
+            policy,
+        }
+    }
+}
+
+impl Default for BufferConfig {
+    /// Unbounded buffer, as in [`BufferConfig::unbounded`].
+    fn default() -> BufferConfig {
+        BufferConfig::unbounded()
+    }
+}
+
 impl Broker {
     pub async fn spawn(
-        url: Url,
-        additional_headers: HeaderMap,
+        transport: Transport,
         reconnect: ReconnectConfig,
+        heartbeat: HeartbeatConfig,
+        recorder: Option<Recorder>,
     ) -> Result<(ControlSender, SharedBrokerState)> {
         let state = SharedBrokerState::working();
         let shared_state = SharedBrokerState::clone(&state);
@@ -192,10 +303,11 @@ impl Broker {
 
         task::spawn(async move {
             let mut broker = Broker {
-                url,
-                additional_headers,
+                transport,
+                recorder,
                 broker_rx,
                 reconnect,
+                heartbeat,
                 handler: Handler::new(),
             };
 
@@ -228,6 +340,16 @@ impl Broker {
 
             info!("broker: task exited with error: {:?}", err.error);
 
+            if self.handler.is_closing() {
+                info!("broker: connection lost while closing, exiting");
+                return None;
+            }
+
+            if self.transport.is_replay() && is_connection_closed(&err.error) {
+                info!("broker: replay finished");
+                return None;
+            }
+
             if !self.reconnect.condition.should_reconnect(&err.error) {
                 warn!("broker: died with error");
                 return Some(err.error);
@@ -259,6 +381,66 @@ impl Broker {
         Ok(())
     }
 
+    async fn close(
+        &mut self,
+        websocket_tx: &mut WebSocketSender,
+        websocket_rx: &mut WebSocketReceiver,
+    ) -> Result<()> {
+        use futures_util::future::{self, Either};
+
+        info!("broker: closing connection");
+
+        for message in self.handler.close_messages() {
+            websocket_tx.send(&message).await?;
+        }
+
+        websocket_tx.close().await?;
+
+        // wait for the server to reply with a close frame, which ends the receiver
+        let handshake = async { while websocket_rx.next().await.is_some() {} };
+        futures_util::pin_mut!(handshake);
+        let timeout = sleep(CLOSE_HANDSHAKE_TIMEOUT);
+        futures_util::pin_mut!(timeout);
+        if let Either::Right(_) = future::select(handshake, timeout).await {
+            warn!("broker: server did not complete the closing handshake in time");
+        }
+
+        Ok(())
+    }
+
+    /// Handles the incoming message, while keeping sending pings if it is blocked
+    /// by the consumers that do not keep up.
+    async fn handle(
+        &mut self,
+        msg: IncomingMessage,
+        websocket_tx: &mut WebSocketSender,
+        last_ping: &mut Instant,
+    ) -> Result<()> {
+        use futures_util::future::{self, Either};
+
+        let ping_interval = self.heartbeat.ping_interval;
+        let handle = self.handler.handle(msg);
+        futures_util::pin_mut!(handle);
+
+        loop {
+            let ping = match ping_interval {
+                Some(interval) => Either::Left(sleep(
+                    (*last_ping + interval).saturating_duration_since(Instant::now()),
+                )),
+                None => Either::Right(future::pending()),
+            };
+            futures_util::pin_mut!(ping);
+
+            match future::select(handle.as_mut(), ping).await {
+                Either::Left((res, _)) => return res,
+                Either::Right(((), _)) => {
+                    websocket_tx.ping().await?;
+                    *last_ping = Instant::now();
+                }
+            }
+        }
+    }
+
     async fn task(
         &mut self,
         remaining_message: Option<OutgoingMessage>,
@@ -266,7 +448,7 @@ impl Broker {
         use futures_util::future::{self, Either};
 
         let (mut websocket_tx, mut websocket_rx) =
-            match connect_websocket(self.url.clone(), self.additional_headers.clone()).await {
+            match connect(&mut self.transport, self.recorder.as_ref()).await {
                 Ok(x) => x,
                 Err(error) => {
                     // retain `remaining_message` because we've not sent it yet
@@ -287,13 +469,30 @@ impl Broker {
             websocket_tx.try_send(message).await?;
         }
 
+        // Notification handlers are registered only while connected,
+        // so this notifies nobody on the initial connection.
+        self.handler.notify_reconnect();
+
+        let mut last_ping = Instant::now();
+        // The time spent waiting for the consumers is not counted as idle,
+        // since we do not read from the connection in the meantime.
+        let mut idle_since = last_ping;
+
         loop {
+            let last_received = websocket_rx.last_received().max(idle_since);
+            let heartbeat = match self.heartbeat.next_deadline(last_ping, last_received) {
+                Some(deadline) => {
+                    Either::Left(sleep(deadline.saturating_duration_since(Instant::now())))
+                }
+                None => Either::Right(future::pending()),
+            };
+
             let t1 = websocket_rx.recv();
             let t2 = self.broker_rx.next();
 
-            futures_util::pin_mut!(t1, t2);
+            futures_util::pin_mut!(t1, t2, heartbeat);
 
-            match future::select(t1, t2).await {
+            match future::select(t1, future::select(t2, heartbeat)).await {
                 Either::Left((msg, _)) => {
                     while let Some(ctrl) = self.broker_rx.try_recv() {
                         #[cfg(feature = "inspect-contents")]
@@ -304,25 +503,63 @@ impl Broker {
                         }
                     }
 
-                    self.handler.handle(msg?).await?;
+                    // deliver the message even if the close has begun, since it was
+                    // received before the close was requested
+                    self.handle(msg?, &mut websocket_tx, &mut last_ping).await?;
+                    idle_since = Instant::now();
+
+                    if self.handler.is_closing() {
+                        return Ok(self.close(&mut websocket_tx, &mut websocket_rx).await?);
+                    }
                 }
-                Either::Right((Some(ctrl), _)) => {
+                Either::Right((Either::Left((Some(ctrl), _)), _)) => {
                     #[cfg(feature = "inspect-contents")]
                     log::debug!("broker: received control {:?}", ctrl);
 
                     if let Some(out) = self.handler.control(ctrl) {
                         websocket_tx.try_send(out).await?
                     }
+
+                    if self.handler.is_closing() {
+                        return Ok(self.close(&mut websocket_tx, &mut websocket_rx).await?);
+                    }
                 }
-                Either::Right((None, _)) => {
+                Either::Right((Either::Left((None, _)), _)) => {
                     info!("broker: all controls terminated, exiting gracefully");
                     return Ok(self.clean_handler(&mut websocket_rx).await?);
                 }
+                Either::Right((Either::Right(((), _)), _)) => {
+                    let now = Instant::now();
+
+                    if let Some(timeout) = self.heartbeat.idle_timeout {
+                        if now.saturating_duration_since(last_received) >= timeout {
+                            warn!("broker: no message received in {:?}", timeout);
+                            return Err(Error::HeartbeatTimeout.into());
+                        }
+                    }
+
+                    if let Some(interval) = self.heartbeat.ping_interval {
+                        if now.saturating_duration_since(last_ping) >= interval {
+                            websocket_tx.ping().await?;
+                            last_ping = now;
+                        }
+                    }
+                }
             }
         }
     }
 }
 
+fn is_connection_closed(err: &Error) -> bool {
+    match err {
+        Error::WebSocket(ws) => matches!(
+            ws.as_ref(),
+            WsError::ConnectionClosed | WsError::AlreadyClosed
+        ),
+        _ => false,
+    }
+}
+
 #[derive(Debug, Clone)]
 struct TaskError {
     remaining_message: Option<OutgoingMessage>,
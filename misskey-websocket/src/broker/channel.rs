@@ -1,11 +1,15 @@
 mod channel_pong;
+mod close_notify;
 mod control;
+mod reconnect_notify;
 #[cfg(not(feature = "12-111-0"))]
 mod response_oneshot;
 mod response_stream;
 
 pub(crate) use channel_pong::{channel_pong_channel, ChannelPongSender};
+pub(crate) use close_notify::{close_notify_channel, CloseNotifySender};
 pub(crate) use control::{control_channel, ControlReceiver, ControlSender};
+pub(crate) use reconnect_notify::{reconnect_notify_channel, ReconnectNotifySender};
 #[cfg(not(feature = "12-111-0"))]
 pub(crate) use response_oneshot::{response_channel, ResponseSender};
 pub(crate) use response_stream::{
@@ -0,0 +1,24 @@
+use futures_channel::oneshot::{self, Receiver, Sender};
+
+/// Sender channel that broker holds until it exits
+///
+/// This is never sent explicitly. Instead, the broker drops this after the broker state is set
+/// to `Exited` or `Dead`, so that the client can read the final state after it is notified.
+#[derive(Debug)]
+pub(crate) struct CloseNotifySender(#[allow(dead_code)] Sender<()>);
+
+/// Receiver channel that the client uses to wait for the broker to exit
+#[derive(Debug)]
+pub(crate) struct CloseNotifyReceiver(Receiver<()>);
+
+impl CloseNotifyReceiver {
+    pub async fn wait(self) {
+        // `Err(Canceled)` is what we expect here, as the sender is dropped on exit
+        let _ = self.0.await;
+    }
+}
+
+pub(crate) fn close_notify_channel() -> (CloseNotifySender, CloseNotifyReceiver) {
+    let (sender, receiver) = oneshot::channel();
+    (CloseNotifySender(sender), CloseNotifyReceiver(receiver))
+}
@@ -26,6 +26,13 @@ impl ControlSender {
             .dead()
             .expect("broker control channel unexpectedly closed")
     }
+
+    /// send a control without waiting, which is possible because the underlying channel is unbounded
+    pub fn unbounded_send(&self, item: BrokerControl) -> Result<()> {
+        self.inner
+            .unbounded_send(item)
+            .map_err(|e| self.to_error(&e.into_send_error()))
+    }
 }
 
 impl Sink<BrokerControl> for ControlSender {
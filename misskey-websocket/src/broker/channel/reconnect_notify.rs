@@ -0,0 +1,44 @@
+use std::pin::Pin;
+use std::task::{Context, Poll};
+
+use futures_channel::mpsc::{self, UnboundedReceiver, UnboundedSender};
+use futures_util::stream::{FusedStream, Stream, StreamExt};
+
+/// Sender channel that broker uses to notify the client of reconnections
+#[derive(Debug, Clone)]
+pub(crate) struct ReconnectNotifySender(UnboundedSender<()>);
+
+impl ReconnectNotifySender {
+    /// `true` when successfully sent, `false` when the channel is closed
+    pub fn try_send(&mut self) -> bool {
+        self.0.unbounded_send(()).is_ok()
+    }
+}
+
+/// Receiver channel that the client uses to be notified of reconnections
+///
+/// Unlike other response channels, this simply terminates when the broker exits
+/// because missing a notification after that is harmless.
+#[derive(Debug)]
+pub(crate) struct ReconnectNotifyReceiver(UnboundedReceiver<()>);
+
+impl Stream for ReconnectNotifyReceiver {
+    type Item = ();
+    fn poll_next(mut self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<Option<()>> {
+        self.0.poll_next_unpin(cx)
+    }
+}
+
+impl FusedStream for ReconnectNotifyReceiver {
+    fn is_terminated(&self) -> bool {
+        self.0.is_terminated()
+    }
+}
+
+pub(crate) fn reconnect_notify_channel() -> (ReconnectNotifySender, ReconnectNotifyReceiver) {
+    let (sender, receiver) = mpsc::unbounded();
+    (
+        ReconnectNotifySender(sender),
+        ReconnectNotifyReceiver(receiver),
+    )
+}
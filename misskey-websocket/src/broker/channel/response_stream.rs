@@ -1,34 +1,145 @@
+use std::collections::VecDeque;
 use std::fmt::{self, Debug};
 use std::pin::Pin;
-use std::task::{Context, Poll};
+use std::sync::{Arc, Mutex, MutexGuard};
+use std::task::{Context, Poll, Waker};
 
-use crate::broker::model::{ReadBrokerState, SharedBrokerState};
-use crate::error::Result;
+use crate::broker::model::{BrokerState, ReadBrokerState, SharedBrokerState};
+use crate::broker::{BufferConfig, OverflowPolicy};
+use crate::error::{Error, Result};
 
-use futures_channel::mpsc::{self, UnboundedReceiver, UnboundedSender};
-use futures_util::future::FutureExt;
-use futures_util::stream::{FusedStream, Stream, StreamExt};
+use futures_util::future::{self, FutureExt};
+use futures_util::stream::{FusedStream, Stream};
+
+struct Shared<T> {
+    queue: VecDeque<T>,
+    config: BufferConfig,
+    /// number of messages dropped due to the overflow
+    lagged: u64,
+    /// set when the buffer overflowed with `OverflowPolicy::Error`
+    overflowed: bool,
+    sender_dropped: bool,
+    receiver_dropped: bool,
+    sender_waker: Option<Waker>,
+    receiver_waker: Option<Waker>,
+}
+
+impl<T> Shared<T> {
+    fn wake_receiver(&mut self) {
+        if let Some(waker) = self.receiver_waker.take() {
+            waker.wake();
+        }
+    }
+
+    fn wake_sender(&mut self) {
+        if let Some(waker) = self.sender_waker.take() {
+            waker.wake();
+        }
+    }
+}
+
+fn lock<T>(shared: &Mutex<Shared<T>>) -> MutexGuard<'_, Shared<T>> {
+    // nothing panics while holding the lock, but just in case
+    shared.lock().unwrap_or_else(|e| e.into_inner())
+}
 
 /// Sender channel that broker uses to respond to the client
-#[derive(Debug, Clone)]
-pub(crate) struct ResponseStreamSender<T>(UnboundedSender<T>);
+pub(crate) struct ResponseStreamSender<T>(Arc<Mutex<Shared<T>>>);
+
+impl<T> Debug for ResponseStreamSender<T> {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        let shared = lock(&self.0);
+        f.debug_struct("ResponseStreamSender")
+            .field("config", &shared.config)
+            .field("len", &shared.queue.len())
+            .field("lagged", &shared.lagged)
+            .finish()
+    }
+}
 
 impl<T> ResponseStreamSender<T> {
-    /// `Ok(())` when successfully sent, `Err(t)` when the channel is closed
-    pub fn try_send(&mut self, t: T) -> std::result::Result<(), T> {
-        self.0.unbounded_send(t).map_err(|e| e.into_inner())
+    /// `Ok(())` when successfully sent (or dropped as per the overflow policy),
+    /// `Err(t)` when the channel is closed
+    ///
+    /// This waits for the receiver only when the buffer is full with [`OverflowPolicy::Block`].
+    pub async fn send(&mut self, t: T) -> std::result::Result<(), T> {
+        let mut item = Some(t);
+        future::poll_fn(|cx| self.poll_send(cx, &mut item)).await
+    }
+
+    fn poll_send(
+        &mut self,
+        cx: &mut Context<'_>,
+        item: &mut Option<T>,
+    ) -> Poll<std::result::Result<(), T>> {
+        let mut shared = lock(&self.0);
+        let t = item
+            .take()
+            .expect("ResponseStreamSender::send polled after completion");
+
+        if shared.receiver_dropped || shared.overflowed {
+            return Poll::Ready(Err(t));
+        }
+
+        let is_full = match shared.config.capacity {
+            Some(capacity) => shared.queue.len() >= capacity.max(1),
+            None => false,
+        };
+
+        if is_full {
+            match shared.config.policy {
+                OverflowPolicy::Block => {
+                    shared.sender_waker = Some(cx.waker().clone());
+                    item.replace(t);
+                    return Poll::Pending;
+                }
+                OverflowPolicy::DropOldest => {
+                    shared.queue.pop_front();
+                    shared.lagged += 1;
+                }
+                OverflowPolicy::DropNewest => {
+                    shared.lagged += 1;
+                    return Poll::Ready(Ok(()));
+                }
+                OverflowPolicy::Error => {
+                    shared.lagged += 1;
+                    shared.overflowed = true;
+                    shared.wake_receiver();
+                    return Poll::Ready(Err(t));
+                }
+            }
+        }
+
+        shared.queue.push_back(t);
+        shared.wake_receiver();
+        Poll::Ready(Ok(()))
+    }
+}
+
+impl<T> Drop for ResponseStreamSender<T> {
+    fn drop(&mut self) {
+        let mut shared = lock(&self.0);
+        shared.sender_dropped = true;
+        shared.wake_receiver();
     }
 }
 
 /// Receiver channel that the client uses to receive the response from broker
 pub(crate) struct ResponseStreamReceiver<T> {
-    inner: UnboundedReceiver<T>,
+    inner: Arc<Mutex<Shared<T>>>,
     state: SharedBrokerState,
     is_terminated: bool,
     /// when `state_read_fut` is `Some(_)`, this stream is in terminating phase
     state_read_fut: Option<ReadBrokerState>,
 }
 
+impl<T> ResponseStreamReceiver<T> {
+    /// The number of messages dropped so far because the buffer was full.
+    pub fn lagged(&self) -> u64 {
+        lock(&self.inner).lagged
+    }
+}
+
 impl<T> Debug for ResponseStreamReceiver<T> {
     fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
         f.debug_struct("ResponseStreamReceiver")
@@ -46,9 +157,22 @@ impl<T> Stream for ResponseStreamReceiver<T> {
         }
 
         if self.state_read_fut.is_none() {
-            if let Some(x) = futures_util::ready!(self.inner.poll_next_unpin(cx)) {
+            let mut shared = lock(&self.inner);
+            if let Some(x) = shared.queue.pop_front() {
+                shared.wake_sender();
                 return Poll::Ready(Some(Ok(x)));
             }
+
+            if shared.overflowed {
+                drop(shared);
+                self.is_terminated = true;
+                return Poll::Ready(Some(Err(Error::BufferFull)));
+            }
+
+            if !shared.sender_dropped {
+                shared.receiver_waker = Some(cx.waker().clone());
+                return Poll::Pending;
+            }
         }
 
         // broker is unavailable, so...
@@ -62,11 +186,16 @@ impl<T> Stream for ResponseStreamReceiver<T> {
         };
 
         let state = futures_util::ready!(fut.poll_unpin(cx));
+        self.is_terminated = true;
+
+        // the stream simply ends if the client is closed explicitly
+        if let BrokerState::Exited = state {
+            return Poll::Ready(None);
+        }
+
         let err = state
             .dead()
             .expect("broker must be dead after poll_next returned None (ResponseStreamReceiver)");
-
-        self.is_terminated = true;
         Poll::Ready(Some(Err(err)))
     }
 }
@@ -77,17 +206,99 @@ impl<T> FusedStream for ResponseStreamReceiver<T> {
     }
 }
 
+impl<T> Drop for ResponseStreamReceiver<T> {
+    fn drop(&mut self) {
+        let mut shared = lock(&self.inner);
+        shared.receiver_dropped = true;
+        shared.queue.clear();
+        shared.wake_sender();
+    }
+}
+
 pub(crate) fn response_stream_channel<T>(
     state: SharedBrokerState,
+    config: BufferConfig,
 ) -> (ResponseStreamSender<T>, ResponseStreamReceiver<T>) {
-    let (sender, receiver) = mpsc::unbounded();
+    let shared = Arc::new(Mutex::new(Shared {
+        queue: VecDeque::new(),
+        config,
+        lagged: 0,
+        overflowed: false,
+        sender_dropped: false,
+        receiver_dropped: false,
+        sender_waker: None,
+        receiver_waker: None,
+    }));
     (
-        ResponseStreamSender(sender),
+        ResponseStreamSender(Arc::clone(&shared)),
         ResponseStreamReceiver {
-            inner: receiver,
+            inner: shared,
             is_terminated: false,
             state_read_fut: None,
             state,
         },
     )
 }
+
+#[cfg(test)]
+mod tests {
+    use super::response_stream_channel;
+    use crate::broker::{model::SharedBrokerState, BufferConfig, OverflowPolicy};
+    use crate::error::Error;
+
+    use futures_util::{
+        future::FutureExt,
+        stream::{FusedStream, StreamExt},
+    };
+
+    #[test]
+    fn test_drop_oldest() {
+        let config = BufferConfig::bounded(2, OverflowPolicy::DropOldest);
+        let (mut tx, mut rx) = response_stream_channel(SharedBrokerState::working(), config);
+        for i in 0..4 {
+            assert!(tx.send(i).now_or_never().unwrap().is_ok());
+        }
+        assert_eq!(rx.lagged(), 2);
+        assert_eq!(rx.next().now_or_never().unwrap().unwrap().unwrap(), 2);
+        assert_eq!(rx.next().now_or_never().unwrap().unwrap().unwrap(), 3);
+        assert!(rx.next().now_or_never().is_none());
+    }
+
+    #[test]
+    fn test_drop_newest() {
+        let config = BufferConfig::bounded(2, OverflowPolicy::DropNewest);
+        let (mut tx, mut rx) = response_stream_channel(SharedBrokerState::working(), config);
+        for i in 0..4 {
+            assert!(tx.send(i).now_or_never().unwrap().is_ok());
+        }
+        assert_eq!(rx.lagged(), 2);
+        assert_eq!(rx.next().now_or_never().unwrap().unwrap().unwrap(), 0);
+        assert_eq!(rx.next().now_or_never().unwrap().unwrap().unwrap(), 1);
+        assert!(rx.next().now_or_never().is_none());
+    }
+
+    #[test]
+    fn test_error() {
+        let config = BufferConfig::bounded(1, OverflowPolicy::Error);
+        let (mut tx, mut rx) = response_stream_channel(SharedBrokerState::working(), config);
+        assert!(tx.send(0).now_or_never().unwrap().is_ok());
+        assert_eq!(tx.send(1).now_or_never().unwrap(), Err(1));
+        assert_eq!(rx.next().now_or_never().unwrap().unwrap().unwrap(), 0);
+        assert!(matches!(
+            rx.next().now_or_never().unwrap(),
+            Some(Err(Error::BufferFull))
+        ));
+        assert!(rx.is_terminated());
+    }
+
+    #[test]
+    fn test_block() {
+        let config = BufferConfig::bounded(1, OverflowPolicy::Block);
+        let (mut tx, mut rx) = response_stream_channel(SharedBrokerState::working(), config);
+        assert!(tx.send(0).now_or_never().unwrap().is_ok());
+        assert!(tx.send(1).now_or_never().is_none());
+        assert_eq!(rx.next().now_or_never().unwrap().unwrap().unwrap(), 0);
+        assert!(tx.send(1).now_or_never().unwrap().is_ok());
+        assert_eq!(rx.lagged(), 0);
+    }
+}
@@ -3,7 +3,7 @@ use std::collections::HashMap;
 #[cfg(not(feature = "12-111-0"))]
 use crate::broker::channel::ResponseSender;
 use crate::broker::{
-    channel::{ChannelPongSender, ResponseStreamSender},
+    channel::{ChannelPongSender, CloseNotifySender, ReconnectNotifySender, ResponseStreamSender},
     model::{BroadcastId, BrokerControl},
 };
 use crate::error::Result;
@@ -49,6 +49,8 @@ pub(crate) struct Handler {
     sub_note: HashMap<SubNoteId, SubNoteHandler>,
     channel: HashMap<ChannelId, ChannelHandler>,
     broadcast: HashMap<&'static str, HashMap<BroadcastId, ResponseStreamSender<Value>>>,
+    reconnect: Vec<ReconnectNotifySender>,
+    close: Vec<CloseNotifySender>,
 }
 
 impl Handler {
@@ -59,6 +61,8 @@ impl Handler {
             sub_note: HashMap::new(),
             channel: HashMap::new(),
             broadcast: HashMap::new(),
+            reconnect: Vec::new(),
+            close: Vec::new(),
         }
     }
 
@@ -82,6 +86,17 @@ impl Handler {
         messages
     }
 
+    pub fn notify_reconnect(&mut self) {
+        self.reconnect.retain_mut(|sender| {
+            if sender.try_send() {
+                true
+            } else {
+                info!("stale reconnect notification handler, deleted");
+                false
+            }
+        });
+    }
+
     pub fn control(&mut self, ctrl: BrokerControl) -> Option<OutgoingMessage> {
         match ctrl {
             #[cfg(not(feature = "12-111-0"))]
@@ -155,7 +170,37 @@ impl Handler {
                 }
                 None
             }
+            BrokerControl::SubscribeReconnect { sender } => {
+                self.reconnect.push(sender);
+                None
+            }
+            BrokerControl::Close { sender } => {
+                // notified when the handler (and thus the broker) is dropped
+                self.close.push(sender);
+                None
+            }
+        }
+    }
+
+    pub fn is_closing(&self) -> bool {
+        !self.close.is_empty()
+    }
+
+    /// Messages to be sent before closing the connection, which leave the server side cleaned up.
+    ///
+    /// Handlers are kept here so that the streams are terminated after the broker exits.
+    pub fn close_messages(&self) -> Vec<OutgoingMessage> {
+        let mut messages = Vec::new();
+
+        for id in self.sub_note.keys() {
+            messages.push(OutgoingMessage::UnsubNote { id: id.clone() });
+        }
+
+        for id in self.channel.keys() {
+            messages.push(OutgoingMessage::Disconnect { id: *id });
         }
+
+        messages
     }
 
     #[cfg(not(feature = "12-111-0"))]
@@ -196,7 +241,7 @@ impl Handler {
                     }
                 };
 
-                if sender.try_send(message).is_err() {
+                if sender.send(message).await.is_err() {
                     warn!("stale channel handler for {:?}, deleted", id);
                     self.channel.remove(&id);
                 }
@@ -229,7 +274,7 @@ impl Handler {
                     }
                 };
 
-                if sender.try_send(message).is_err() {
+                if sender.send(message).await.is_err() {
                     warn!("stale subnote handler for {:?}, deleted", id);
                     self.sub_note.remove(&id);
                 }
@@ -246,14 +291,16 @@ impl Handler {
                 };
 
                 let body = msg.body;
-                senders.retain(|id, sender| {
-                    if sender.try_send(body.clone()).is_err() {
-                        warn!("stale broadcast handler {}:{:?}, deleted", type_, id);
-                        false
-                    } else {
-                        true
+                let mut stale = Vec::new();
+                for (id, sender) in senders.iter_mut() {
+                    if sender.send(body.clone()).await.is_err() {
+                        stale.push(*id);
                     }
-                });
+                }
+                for id in stale {
+                    warn!("stale broadcast handler {}:{:?}, deleted", type_, id);
+                    senders.remove(&id);
+                }
             }
         }
 
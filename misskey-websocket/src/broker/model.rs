@@ -1,3 +1,4 @@
+use std::borrow::Cow;
 use std::hash::Hash;
 use std::pin::Pin;
 use std::sync::Arc;
@@ -5,7 +6,9 @@ use std::task::{Context, Poll};
 
 #[cfg(not(feature = "12-111-0"))]
 use crate::broker::channel::ResponseSender;
-use crate::broker::channel::{ChannelPongSender, ResponseStreamSender};
+use crate::broker::channel::{
+    ChannelPongSender, CloseNotifySender, ReconnectNotifySender, ResponseStreamSender,
+};
 use crate::error::Error;
 #[cfg(not(feature = "12-111-0"))]
 use crate::model::ApiRequestId;
@@ -38,7 +41,7 @@ pub(crate) enum BrokerControl {
     },
     Connect {
         id: ChannelId,
-        name: &'static str,
+        name: Cow<'static, str>,
         params: Value,
         sender: ResponseStreamSender<Value>,
         pong: ChannelPongSender,
@@ -65,6 +68,12 @@ pub(crate) enum BrokerControl {
     StopBroadcast {
         id: BroadcastId,
     },
+    SubscribeReconnect {
+        sender: ReconnectNotifySender,
+    },
+    Close {
+        sender: CloseNotifySender,
+    },
 }
 
 #[derive(Debug, Clone)]
@@ -81,8 +90,8 @@ impl BrokerState {
     pub fn dead(self) -> Option<Error> {
         match self {
             BrokerState::Working => None,
-            // TODO: clearify the guarantee that no one asks for `BrokerState` after broker is dead
-            BrokerState::Exited => panic!("asked if broker is dead while it is already exited"),
+            // the broker exits while the client is in use only when it is explicitly closed
+            BrokerState::Exited => Some(Error::Closed),
             BrokerState::Dead(e) => Some(e),
         }
     }
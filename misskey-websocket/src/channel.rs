@@ -2,9 +2,11 @@ use std::fmt;
 use std::future::Future;
 use std::pin::Pin;
 use std::task::{Context, Poll};
+use std::time::Instant;
 
 use crate::error::{Error, Result};
 use crate::model::{incoming::IncomingMessage, outgoing::OutgoingMessage};
+use crate::record::{Direction, Recorder, Recording, ReplayConfig};
 
 #[cfg(feature = "async-std-runtime")]
 use async_tungstenite::async_std::{connect_async, ConnectStream};
@@ -19,14 +21,42 @@ use async_tungstenite::tungstenite::{
 use async_tungstenite::WebSocketStream;
 use futures_util::{
     sink::{Sink, SinkExt},
-    stream::{SplitSink, SplitStream, Stream, StreamExt, TryStreamExt},
+    stream::{BoxStream, Stream, StreamExt, TryStreamExt},
 };
 #[cfg(feature = "inspect-contents")]
 use log::debug;
 use url::Url;
 
+mod replay;
+
+use replay::replay_transport;
+
+/// Where the broker connects to
+#[derive(Debug)]
+pub enum Transport {
+    WebSocket {
+        url: Url,
+        additional_headers: HeaderMap,
+    },
+    /// `recording` is taken on the first connection, as it can be replayed only once
+    Replay {
+        recording: Option<Recording>,
+        config: ReplayConfig,
+    },
+}
+
+impl Transport {
+    pub fn is_replay(&self) -> bool {
+        matches!(self, Transport::Replay { .. })
+    }
+}
+
 /// Receiver channel that communicates with Misskey
-pub struct WebSocketReceiver(SplitStream<PingPongWebSocketStream<WebSocketStream<ConnectStream>>>);
+pub struct WebSocketReceiver {
+    inner: BoxStream<'static, WsResult<WsMessage>>,
+    last_received: Instant,
+    recorder: Option<Recorder>,
+}
 
 impl fmt::Debug for WebSocketReceiver {
     fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
@@ -38,7 +68,12 @@ impl Stream for WebSocketReceiver {
     type Item = Result<IncomingMessage>;
 
     fn poll_next(mut self: Pin<&mut Self>, cx: &mut Context) -> Poll<Option<Self::Item>> {
-        let text = match futures_util::ready!(self.0.poll_next_unpin(cx)?) {
+        let msg = futures_util::ready!(self.inner.poll_next_unpin(cx)?);
+        if msg.is_some() {
+            self.last_received = Instant::now();
+        }
+
+        let text = match msg {
             Some(WsMessage::Text(t)) => t,
             Some(WsMessage::Ping(_)) | Some(WsMessage::Pong(_)) => return self.poll_next(cx),
             None | Some(WsMessage::Close(_)) => return Poll::Ready(None),
@@ -48,6 +83,10 @@ impl Stream for WebSocketReceiver {
         #[cfg(feature = "inspect-contents")]
         debug!("received message: {}", text);
 
+        if let Some(recorder) = &self.recorder {
+            recorder.record(Direction::Incoming, &text);
+        }
+
         Poll::Ready(Some(Ok(serde_json::from_str(&text)?)))
     }
 }
@@ -72,12 +111,18 @@ impl WebSocketReceiver {
     pub fn recv(&mut self) -> Recv<'_> {
         Recv { stream: self }
     }
+
+    /// The time when any frame (including ping and pong) is received last from the server.
+    pub fn last_received(&self) -> Instant {
+        self.last_received
+    }
 }
 
 /// Sender channel that communicates with Misskey
-pub struct WebSocketSender(
-    SplitSink<PingPongWebSocketStream<WebSocketStream<ConnectStream>>, WsMessage>,
-);
+pub struct WebSocketSender {
+    inner: Pin<Box<dyn Sink<WsMessage, Error = WsError> + Send>>,
+    recorder: Option<Recorder>,
+}
 
 #[derive(Debug, Clone)]
 pub struct TrySendError {
@@ -96,6 +141,24 @@ impl WebSocketSender {
             error,
         })
     }
+
+    /// send a ping frame to check if the connection is alive
+    pub async fn ping(&mut self) -> Result<()> {
+        #[cfg(feature = "inspect-contents")]
+        debug!("send ping");
+
+        self.inner.send(WsMessage::Ping(Vec::new())).await?;
+        Ok(())
+    }
+
+    /// send a close frame to start the closing handshake
+    pub async fn close(&mut self) -> Result<()> {
+        #[cfg(feature = "inspect-contents")]
+        debug!("send close");
+
+        self.inner.close().await?;
+        Ok(())
+    }
 }
 
 impl fmt::Debug for WebSocketSender {
@@ -108,24 +171,30 @@ impl Sink<&'_ OutgoingMessage> for WebSocketSender {
     type Error = Error;
 
     fn poll_ready(mut self: Pin<&mut Self>, cx: &mut Context) -> Poll<Result<()>> {
-        self.0.poll_ready_unpin(cx).map_err(Into::into)
+        self.inner.poll_ready_unpin(cx).map_err(Into::into)
     }
 
     fn start_send(mut self: Pin<&mut Self>, item: &OutgoingMessage) -> Result<()> {
-        let msg = WsMessage::Text(serde_json::to_string(item)?);
+        let text = serde_json::to_string(item)?;
+
+        if let Some(recorder) = &self.recorder {
+            recorder.record(Direction::Outgoing, &text);
+        }
+
+        let msg = WsMessage::Text(text);
 
         #[cfg(feature = "inspect-contents")]
         debug!("send message: {:?}", msg);
 
-        self.0.start_send_unpin(msg).map_err(Into::into)
+        self.inner.start_send_unpin(msg).map_err(Into::into)
     }
 
     fn poll_flush(mut self: Pin<&mut Self>, cx: &mut Context) -> Poll<Result<()>> {
-        self.0.poll_flush_unpin(cx).map_err(Into::into)
+        self.inner.poll_flush_unpin(cx).map_err(Into::into)
     }
 
     fn poll_close(mut self: Pin<&mut Self>, cx: &mut Context) -> Poll<Result<()>> {
-        self.0.poll_close_unpin(cx).map_err(Into::into)
+        self.inner.poll_close_unpin(cx).map_err(Into::into)
     }
 }
 
@@ -213,13 +282,46 @@ where
     }
 }
 
-pub async fn connect_websocket(
+async fn connect_websocket(
     url: Url,
     additional_headers: HeaderMap,
-) -> Result<(WebSocketSender, WebSocketReceiver)> {
+) -> Result<(
+    impl Sink<WsMessage, Error = WsError>,
+    impl Stream<Item = WsResult<WsMessage>>,
+)> {
     let mut request = url.into_client_request()?;
     request.headers_mut().extend(additional_headers);
-    let (ws, _) = connect_async(request).await?;
-    let (sink, stream) = PingPongWebSocketStream::new(ws).split();
-    Ok((WebSocketSender(sink), WebSocketReceiver(stream)))
+    let (ws, _): (WebSocketStream<ConnectStream>, _) = connect_async(request).await?;
+    Ok(PingPongWebSocketStream::new(ws).split())
+}
+
+pub async fn connect(
+    transport: &mut Transport,
+    recorder: Option<&Recorder>,
+) -> Result<(WebSocketSender, WebSocketReceiver)> {
+    let (sink, stream): (Pin<Box<dyn Sink<_, Error = _> + Send>>, BoxStream<_>) = match transport {
+        Transport::WebSocket {
+            url,
+            additional_headers,
+        } => {
+            let (sink, stream) = connect_websocket(url.clone(), additional_headers.clone()).await?;
+            (Box::pin(sink), stream.boxed())
+        }
+        Transport::Replay { recording, config } => {
+            let recording = recording.take().ok_or(WsError::AlreadyClosed)?;
+            let (sink, stream) = replay_transport(recording, config.clone());
+            (Box::pin(sink), stream.boxed())
+        }
+    };
+
+    let sender = WebSocketSender {
+        inner: sink,
+        recorder: recorder.cloned(),
+    };
+    let receiver = WebSocketReceiver {
+        inner: stream,
+        last_received: Instant::now(),
+        recorder: recorder.cloned(),
+    };
+    Ok((sender, receiver))
 }
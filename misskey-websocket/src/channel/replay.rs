@@ -0,0 +1,284 @@
+use std::collections::{HashMap, VecDeque};
+use std::pin::Pin;
+use std::sync::{Arc, Mutex, MutexGuard};
+use std::task::{Context, Poll, Waker};
+use std::time::Instant;
+
+use crate::record::{Direction, Record, Recording, ReplayConfig};
+
+#[cfg(feature = "async-std-runtime")]
+use async_std::task::sleep;
+use async_tungstenite::tungstenite::{
+    error::Result as WsResult, Error as WsError, Message as WsMessage,
+};
+use chrono::{DateTime, Utc};
+use futures_util::{
+    future::{BoxFuture, FutureExt},
+    sink::Sink,
+    stream::Stream,
+};
+use serde_json::Value;
+#[cfg(feature = "tokio-runtime")]
+use tokio::time::sleep;
+
+/// Outgoing message types that the replay waits for the client to send,
+/// because the following incoming messages are delivered only after them.
+const SYNC_MESSAGE_TYPES: &[&str] = &["api", "connect", "subNote"];
+
+#[derive(Debug, Default)]
+struct Shared {
+    /// messages sent by the client that are not matched with the recorded ones yet
+    sent: VecDeque<Value>,
+    closed: bool,
+    waker: Option<Waker>,
+}
+
+fn lock(shared: &Mutex<Shared>) -> MutexGuard<'_, Shared> {
+    shared.lock().unwrap_or_else(|e| e.into_inner())
+}
+
+fn message_type(message: &Value) -> Option<&str> {
+    message.get("type").and_then(Value::as_str)
+}
+
+fn body_field<'a>(message: &'a Value, field: &str) -> Option<&'a Value> {
+    message.get("body").and_then(|body| body.get(field))
+}
+
+/// Tests if the message sent by the client corresponds to the recorded one.
+fn is_counterpart(recorded: &Value, sent: &Value) -> bool {
+    let type_ = match message_type(recorded) {
+        Some(x) => x,
+        None => return false,
+    };
+    if message_type(sent) != Some(type_) {
+        return false;
+    }
+
+    let key = match type_ {
+        "api" => "endpoint",
+        "connect" => "channel",
+        // captured notes are identified by the note ID, which does not change in replay
+        "subNote" => "id",
+        _ => return true,
+    };
+    body_field(recorded, key) == body_field(sent, key)
+}
+
+/// Sink side of the replay transport, which accepts messages from the broker.
+#[derive(Debug)]
+pub struct ReplaySink {
+    shared: Arc<Mutex<Shared>>,
+}
+
+impl Sink<WsMessage> for ReplaySink {
+    type Error = WsError;
+
+    fn poll_ready(self: Pin<&mut Self>, _cx: &mut Context) -> Poll<WsResult<()>> {
+        Poll::Ready(Ok(()))
+    }
+
+    fn start_send(self: Pin<&mut Self>, item: WsMessage) -> WsResult<()> {
+        let mut shared = lock(&self.shared);
+        if shared.closed {
+            return Err(WsError::AlreadyClosed);
+        }
+
+        match item {
+            WsMessage::Text(text) => {
+                let message: Value = match serde_json::from_str(&text) {
+                    Ok(x) => x,
+                    Err(_) => return Ok(()),
+                };
+                match message_type(&message) {
+                    Some(type_) if SYNC_MESSAGE_TYPES.contains(&type_) => {
+                        shared.sent.push_back(message)
+                    }
+                    _ => return Ok(()),
+                }
+            }
+            WsMessage::Close(_) => shared.closed = true,
+            _ => return Ok(()),
+        }
+
+        if let Some(waker) = shared.waker.take() {
+            waker.wake();
+        }
+        Ok(())
+    }
+
+    fn poll_flush(self: Pin<&mut Self>, _cx: &mut Context) -> Poll<WsResult<()>> {
+        Poll::Ready(Ok(()))
+    }
+
+    fn poll_close(self: Pin<&mut Self>, _cx: &mut Context) -> Poll<WsResult<()>> {
+        let mut shared = lock(&self.shared);
+        shared.closed = true;
+        if let Some(waker) = shared.waker.take() {
+            waker.wake();
+        }
+        Poll::Ready(Ok(()))
+    }
+}
+
+/// Stream side of the replay transport, which yields the recorded incoming messages.
+///
+/// Recorded outgoing messages that open something (see [`SYNC_MESSAGE_TYPES`]) are
+/// synchronization points: the replay waits for the client to send the corresponding message,
+/// and rewrites the IDs in the following incoming messages to the ones the client has chosen.
+pub struct ReplayStream {
+    shared: Arc<Mutex<Shared>>,
+    records: VecDeque<Record>,
+    /// recorded ID -> ID chosen by the client in replay
+    ids: HashMap<String, String>,
+    config: ReplayConfig,
+    /// when the last incoming message is delivered, and its recorded time
+    last_delivered: Option<(Instant, DateTime<Utc>)>,
+    delay: Option<BoxFuture<'static, ()>>,
+}
+
+impl std::fmt::Debug for ReplayStream {
+    fn fmt(&self, f: &mut std::fmt::Formatter) -> std::fmt::Result {
+        f.debug_struct("ReplayStream")
+            .field("remaining", &self.records.len())
+            .field("config", &self.config)
+            .finish()
+    }
+}
+
+impl ReplayStream {
+    /// Returns `false` if the client has not sent the counterpart of `recorded` yet.
+    fn sync(&mut self, recorded: &Value, cx: &mut Context) -> bool {
+        let mut shared = lock(&self.shared);
+        let pos = match shared
+            .sent
+            .iter()
+            .position(|sent| is_counterpart(recorded, sent))
+        {
+            Some(pos) => pos,
+            None => {
+                shared.waker = Some(cx.waker().clone());
+                return false;
+            }
+        };
+        let sent = shared.sent.remove(pos).unwrap();
+        drop(shared);
+
+        let recorded_id = body_field(recorded, "id").and_then(Value::as_str);
+        let sent_id = body_field(&sent, "id").and_then(Value::as_str);
+        if let (Some(recorded_id), Some(sent_id)) = (recorded_id, sent_id) {
+            self.ids.insert(recorded_id.to_owned(), sent_id.to_owned());
+        }
+        true
+    }
+
+    fn rewrite_ids(&self, message: &mut Value) {
+        let map = |id: &str| self.ids.get(id).cloned();
+
+        let type_ = match message.get_mut("type") {
+            Some(Value::String(x)) => x,
+            _ => return,
+        };
+
+        if let Some(id) = type_.strip_prefix("api:").and_then(map) {
+            *type_ = format!("api:{}", id);
+            return;
+        }
+
+        if type_ != "channel" && type_ != "connected" {
+            return;
+        }
+
+        if let Some(Value::String(id)) = message.get_mut("body").and_then(|b| b.get_mut("id")) {
+            if let Some(mapped) = map(id) {
+                *id = mapped;
+            }
+        }
+    }
+
+    /// Returns `false` if the delivery of the incoming message recorded at `time` is delayed.
+    fn wait_until_due(&mut self, time: DateTime<Utc>, cx: &mut Context) -> bool {
+        if !self.config.realtime {
+            return true;
+        }
+
+        if self.delay.is_none() {
+            let (last_instant, last_time) = match self.last_delivered {
+                Some(x) => x,
+                None => return true,
+            };
+            let interval = (time - last_time).to_std().unwrap_or_default();
+            let due = last_instant + interval;
+            let now = Instant::now();
+            if due <= now {
+                return true;
+            }
+            self.delay = Some(sleep(due - now).boxed());
+        }
+
+        match self.delay.as_mut().unwrap().poll_unpin(cx) {
+            Poll::Ready(()) => {
+                self.delay = None;
+                true
+            }
+            Poll::Pending => false,
+        }
+    }
+}
+
+impl Stream for ReplayStream {
+    type Item = WsResult<WsMessage>;
+
+    fn poll_next(mut self: Pin<&mut Self>, cx: &mut Context) -> Poll<Option<Self::Item>> {
+        loop {
+            if lock(&self.shared).closed {
+                return Poll::Ready(None);
+            }
+
+            let (direction, time) = match self.records.front() {
+                Some(record) => (record.direction, record.time),
+                None => return Poll::Ready(None),
+            };
+
+            match direction {
+                Direction::Outgoing => {
+                    let recorded = self.records.front().unwrap().message.clone();
+                    let needs_sync = matches!(
+                        message_type(&recorded),
+                        Some(type_) if SYNC_MESSAGE_TYPES.contains(&type_)
+                    );
+                    if needs_sync && !self.sync(&recorded, cx) {
+                        return Poll::Pending;
+                    }
+                    self.records.pop_front();
+                }
+                Direction::Incoming => {
+                    if !self.wait_until_due(time, cx) {
+                        return Poll::Pending;
+                    }
+
+                    let mut record = self.records.pop_front().unwrap();
+                    self.rewrite_ids(&mut record.message);
+                    self.last_delivered = Some((Instant::now(), record.time));
+                    return Poll::Ready(Some(Ok(WsMessage::Text(record.to_text()))));
+                }
+            }
+        }
+    }
+}
+
+pub fn replay_transport(recording: Recording, config: ReplayConfig) -> (ReplaySink, ReplayStream) {
+    let shared = Arc::new(Mutex::new(Shared::default()));
+    let sink = ReplaySink {
+        shared: Arc::clone(&shared),
+    };
+    let stream = ReplayStream {
+        shared,
+        records: recording.records.into(),
+        ids: HashMap::new(),
+        config,
+        last_delivered: None,
+        delay: None,
+    };
+    (sink, stream)
+}
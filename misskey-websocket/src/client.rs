@@ -1,26 +1,39 @@
 use std::fmt::{self, Debug};
+use std::time::Duration;
 
 #[cfg(not(feature = "12-111-0"))]
-use crate::broker::{channel::response_channel, model::BrokerControl};
-use crate::broker::{channel::ControlSender, model::SharedBrokerState, Broker, ReconnectConfig};
+use crate::broker::channel::response_channel;
+use crate::broker::{
+    channel::{close_notify_channel, reconnect_notify_channel, ControlSender},
+    model::{BrokerControl, BrokerState, SharedBrokerState},
+    Broker, BufferConfig, HeartbeatConfig, ReconnectConfig,
+};
+use crate::channel::Transport;
 use crate::error::{Error, Result};
 #[cfg(not(feature = "12-111-0"))]
 use crate::model::ApiRequestId;
 use crate::model::SubNoteId;
+use crate::record::{Recorder, Recording, ReplayConfig};
 
+#[cfg(feature = "async-std-runtime")]
+use async_std::task::sleep;
 use async_tungstenite::tungstenite::http::HeaderMap;
 #[cfg(not(feature = "12-111-0"))]
 use futures_util::sink::SinkExt;
 use futures_util::{
-    future::{BoxFuture, FutureExt, TryFutureExt},
+    future::{self, BoxFuture, Either, FutureExt, TryFutureExt},
     sink::Sink,
     stream::{BoxStream, Stream, StreamExt},
 };
-use misskey_core::streaming::{BoxStreamSink, StreamingClient};
+use log::info;
+use misskey_core::streaming::{BoxStreamSink, ReconnectionStream, StreamingClient};
 #[cfg(not(feature = "12-111-0"))]
 use misskey_core::{model::ApiResult, Client};
 #[cfg(not(feature = "12-111-0"))]
 use serde_json::value;
+use serde_json::Value;
+#[cfg(feature = "tokio-runtime")]
+use tokio::time::sleep;
 use url::Url;
 
 pub mod builder;
@@ -41,6 +54,7 @@ use stream::{Broadcast, Channel, SubNote};
 pub struct WebSocketClient {
     broker_tx: ControlSender,
     state: SharedBrokerState,
+    buffer: BufferConfig,
 }
 
 impl Debug for WebSocketClient {
@@ -95,8 +109,79 @@ impl WebSocketClient {
         additional_headers: HeaderMap,
         reconnect_config: ReconnectConfig,
     ) -> Result<WebSocketClient> {
-        let (broker_tx, state) = Broker::spawn(url, additional_headers, reconnect_config).await?;
-        Ok(WebSocketClient { broker_tx, state })
+        WebSocketClient::connect_with_heartbeat(
+            url,
+            additional_headers,
+            reconnect_config,
+            HeartbeatConfig::default(),
+        )
+        .await
+    }
+
+    pub(crate) async fn connect_with_heartbeat(
+        url: Url,
+        additional_headers: HeaderMap,
+        reconnect_config: ReconnectConfig,
+        heartbeat_config: HeartbeatConfig,
+    ) -> Result<WebSocketClient> {
+        let transport = Transport::WebSocket {
+            url,
+            additional_headers,
+        };
+        WebSocketClient::connect_with_transport(transport, reconnect_config, heartbeat_config, None)
+            .await
+    }
+
+    /// Replays a recorded session, and returns [`WebSocketClient`] that receives
+    /// messages from it instead of the server.
+    ///
+    /// The client works as if it is connected to the server which sends the recorded messages,
+    /// except that nothing is actually sent.
+    /// Streams on the client end when the recording is exhausted.
+    ///
+    /// The replay waits for the client to connect to the channels (or to capture the notes, etc.)
+    /// in the order they are recorded, and delivers the messages on them to the streams on the
+    /// client. Thus you need to open the streams in the same order as the recorded session,
+    /// or the replay will not proceed.
+    /// See [`record`][`crate::record`] for details.
+    pub async fn replay(recording: Recording, config: ReplayConfig) -> Result<WebSocketClient> {
+        let transport = Transport::Replay {
+            recording: Some(recording),
+            config,
+        };
+        WebSocketClient::connect_with_transport(
+            transport,
+            ReconnectConfig::none(),
+            HeartbeatConfig::none(),
+            None,
+        )
+        .await
+    }
+
+    pub(crate) async fn connect_with_transport(
+        transport: Transport,
+        reconnect_config: ReconnectConfig,
+        heartbeat_config: HeartbeatConfig,
+        recorder: Option<Recorder>,
+    ) -> Result<WebSocketClient> {
+        let (broker_tx, state) =
+            Broker::spawn(transport, reconnect_config, heartbeat_config, recorder).await?;
+        Ok(WebSocketClient {
+            broker_tx,
+            state,
+            buffer: BufferConfig::default(),
+        })
+    }
+
+    /// Returns a client sharing the connection with this one, which creates streams
+    /// with the given buffering configuration by default.
+    ///
+    /// See [`BufferConfig`] for details.
+    pub fn with_buffer(&self, config: BufferConfig) -> WebSocketClient {
+        WebSocketClient {
+            buffer: config,
+            ..self.clone()
+        }
     }
 
     /// Creates a new builder instance with `url`.
@@ -111,6 +196,39 @@ impl WebSocketClient {
         WebSocketClientBuilder::new(url)
     }
 
+    /// Closes the connection gracefully.
+    ///
+    /// This disconnects from all channels and captured notes, sends a close frame to the server,
+    /// and waits up to `timeout` for the connection to be closed.
+    /// After that, every stream created from this client (and its clones) ends with [`None`],
+    /// and further requests fail with [`Error::Closed`].
+    ///
+    /// Returns [`Error::CloseTimeout`] if the connection is not closed within `timeout`,
+    /// in which case the connection will be closed in the background (e.g. after reconnecting).
+    /// If the connection is already closed, or is lost with an error, this returns immediately
+    /// with [`Ok`] or that error respectively.
+    pub async fn close(&self, timeout: Duration) -> Result<()> {
+        let (tx, rx) = close_notify_channel();
+        if self
+            .broker_tx
+            .unbounded_send(BrokerControl::Close { sender: tx })
+            .is_ok()
+        {
+            let wait = rx.wait();
+            let sleep = sleep(timeout);
+            futures_util::pin_mut!(wait, sleep);
+            if let Either::Right(_) = future::select(wait, sleep).await {
+                return Err(Error::CloseTimeout);
+            }
+        }
+
+        match self.state.read().await {
+            BrokerState::Working => unreachable!("broker must be exited after it notified us"),
+            BrokerState::Exited => Ok(()),
+            BrokerState::Dead(err) => Err(err),
+        }
+    }
+
     /// Captures the note specified by `id`.
     ///
     /// The returned [`SubNote`] implements [`Stream`][stream]
@@ -118,6 +236,21 @@ impl WebSocketClient {
     ///
     /// [stream]: futures_util::stream::Stream
     pub fn subnote<E, Id>(&self, note_id: Id) -> BoxFuture<'static, Result<SubNote<E>>>
+    where
+        E: misskey_core::streaming::SubNoteEvent,
+        Id: Into<String>,
+    {
+        self.subnote_with_buffer(note_id, self.buffer)
+    }
+
+    /// Captures the note specified by `id`, buffering the events as configured in `buffer`.
+    ///
+    /// See [`subnote`][`WebSocketClient::subnote`] and [`BufferConfig`] for details.
+    pub fn subnote_with_buffer<E, Id>(
+        &self,
+        note_id: Id,
+        buffer: BufferConfig,
+    ) -> BoxFuture<'static, Result<SubNote<E>>>
     where
         E: misskey_core::streaming::SubNoteEvent,
         Id: Into<String>,
@@ -126,6 +259,7 @@ impl WebSocketClient {
             SubNoteId(note_id.into()),
             self.broker_tx.clone(),
             SharedBrokerState::clone(&self.state),
+            buffer,
         )
         .boxed()
     }
@@ -141,6 +275,20 @@ impl WebSocketClient {
         &self,
         request: R,
     ) -> BoxFuture<'static, Result<Channel<R::Incoming, R::Outgoing>>>
+    where
+        R: misskey_core::streaming::ConnectChannelRequest,
+    {
+        self.channel_with_buffer(request, self.buffer)
+    }
+
+    /// Connects to the channel using `request`, buffering the messages as configured in `buffer`.
+    ///
+    /// See [`channel`][`WebSocketClient::channel`] and [`BufferConfig`] for details.
+    pub fn channel_with_buffer<R>(
+        &self,
+        request: R,
+        buffer: BufferConfig,
+    ) -> BoxFuture<'static, Result<Channel<R::Incoming, R::Outgoing>>>
     where
         R: misskey_core::streaming::ConnectChannelRequest,
     {
@@ -148,6 +296,47 @@ impl WebSocketClient {
             request,
             self.broker_tx.clone(),
             SharedBrokerState::clone(&self.state),
+            buffer,
+        )
+    }
+
+    /// Connects to the channel specified by `name` with `params`.
+    ///
+    /// This is an untyped version of [`channel`][`WebSocketClient::channel`], which is useful
+    /// when the channel is determined at runtime or not modeled in misskey-api.
+    /// The messages exchanged on the returned [`Channel`] are raw JSON values of the form
+    /// `{ "type": ..., "body": ... }`.
+    pub fn dynamic_channel<S>(
+        &self,
+        name: S,
+        params: Value,
+    ) -> BoxFuture<'static, Result<Channel<Value, Value>>>
+    where
+        S: Into<String>,
+    {
+        self.dynamic_channel_with_buffer(name, params, self.buffer)
+    }
+
+    /// Connects to the channel specified by `name` with `params`, buffering the messages as
+    /// configured in `buffer`.
+    ///
+    /// See [`dynamic_channel`][`WebSocketClient::dynamic_channel`] and [`BufferConfig`]
+    /// for details.
+    pub fn dynamic_channel_with_buffer<S>(
+        &self,
+        name: S,
+        params: Value,
+        buffer: BufferConfig,
+    ) -> BoxFuture<'static, Result<Channel<Value, Value>>>
+    where
+        S: Into<String>,
+    {
+        Channel::connect_dynamic(
+            name.into(),
+            params,
+            self.broker_tx.clone(),
+            SharedBrokerState::clone(&self.state),
+            buffer,
         )
     }
 
@@ -158,12 +347,26 @@ impl WebSocketClient {
     ///
     /// [stream]: futures_util::stream::Stream
     pub fn broadcast<E>(&self) -> BoxFuture<'static, Result<Broadcast<E>>>
+    where
+        E: misskey_core::streaming::BroadcastEvent,
+    {
+        self.broadcast_with_buffer(self.buffer)
+    }
+
+    /// Receive messages from the broadcast stream, buffering them as configured in `buffer`.
+    ///
+    /// See [`broadcast`][`WebSocketClient::broadcast`] and [`BufferConfig`] for details.
+    pub fn broadcast_with_buffer<E>(
+        &self,
+        buffer: BufferConfig,
+    ) -> BoxFuture<'static, Result<Broadcast<E>>>
     where
         E: misskey_core::streaming::BroadcastEvent,
     {
         Broadcast::start(
             self.broker_tx.clone(),
             SharedBrokerState::clone(&self.state),
+            buffer,
         )
         .boxed()
     }
@@ -224,6 +427,7 @@ impl StreamingClient for WebSocketClient {
                 SubNoteId(note_id),
                 self.broker_tx.clone(),
                 SharedBrokerState::clone(&self.state),
+                self.buffer,
             )
             .await?
             .boxed())
@@ -241,6 +445,7 @@ impl StreamingClient for WebSocketClient {
             request,
             self.broker_tx.clone(),
             SharedBrokerState::clone(&self.state),
+            self.buffer,
         )
         .map_ok(boxed_stream_sink)
         .boxed()
@@ -254,11 +459,24 @@ impl StreamingClient for WebSocketClient {
             Ok(Broadcast::start(
                 self.broker_tx.clone(),
                 SharedBrokerState::clone(&self.state),
+                self.buffer,
             )
             .await?
             .boxed())
         })
     }
+
+    fn reconnections(&self) -> Option<ReconnectionStream> {
+        let (tx, rx) = reconnect_notify_channel();
+        // If the broker is dead, `rx` just terminates as `tx` is dropped here.
+        if let Err(e) = self
+            .broker_tx
+            .unbounded_send(BrokerControl::SubscribeReconnect { sender: tx })
+        {
+            info!("subscribing to reconnections on dead broker: {:?}", e);
+        }
+        Some(rx.boxed())
+    }
 }
 
 #[cfg(not(feature = "12-111-0"))]
@@ -347,5 +565,189 @@ mod tests {
         .await;
     }
 
+    #[cfg_attr(feature = "tokio-runtime", tokio::test)]
+    #[cfg_attr(feature = "tokio02-runtime", tokio02::test)]
+    #[cfg_attr(feature = "async-std-runtime", async_std::test)]
+    async fn dynamic_channel() {
+        let client = test_client().await;
+
+        let mut channel = client
+            .dynamic_channel("localTimeline", serde_json::json!({}))
+            .await
+            .unwrap();
+
+        futures_util::future::join(
+            async {
+                client
+                    .request(
+                        misskey_api::endpoint::notes::create::Request::builder()
+                            .text("hi")
+                            .build(),
+                    )
+                    .await
+                    .unwrap()
+                    .unwrap()
+            },
+            async {
+                let message = channel.next().await.unwrap().unwrap();
+                assert_eq!(message["type"], "note");
+            },
+        )
+        .await;
+
+        channel.disconnect().await.unwrap();
+    }
+
+    #[cfg_attr(feature = "tokio-runtime", tokio::test)]
+    #[cfg_attr(feature = "tokio02-runtime", tokio02::test)]
+    #[cfg_attr(feature = "async-std-runtime", async_std::test)]
+    async fn close() {
+        let client = test_client().await;
+
+        let mut channel = client
+            .dynamic_channel("localTimeline", serde_json::json!({}))
+            .await
+            .unwrap();
+
+        client
+            .close(std::time::Duration::from_secs(10))
+            .await
+            .unwrap();
+
+        assert!(channel.next().await.is_none());
+        assert!(matches!(
+            client
+                .request(
+                    misskey_api::endpoint::notes::create::Request::builder()
+                        .text("hi")
+                        .build(),
+                )
+                .await,
+            Err(crate::Error::Closed)
+        ));
+        // closing twice is fine
+        client
+            .close(std::time::Duration::from_secs(10))
+            .await
+            .unwrap();
+    }
+
+    #[cfg_attr(feature = "tokio-runtime", tokio::test)]
+    #[cfg_attr(feature = "tokio02-runtime", tokio02::test)]
+    #[cfg_attr(feature = "async-std-runtime", async_std::test)]
+    async fn replay() {
+        use crate::record::{Recording, ReplayConfig};
+
+        let id = "00000000-0000-0000-0000-000000000000";
+        let session = format!(
+            r#"{{"time":"2022-01-01T00:00:00Z","direction":"outgoing","message":{{"type":"connect","body":{{"id":"{id}","channel":"localTimeline","params":{{}},"pong":true}}}}}}
+{{"time":"2022-01-01T00:00:01Z","direction":"incoming","message":{{"type":"connected","body":{{"id":"{id}"}}}}}}
+{{"time":"2022-01-01T00:00:02Z","direction":"incoming","message":{{"type":"channel","body":{{"id":"{id}","type":"note","body":{{}}}}}}}}
+"#,
+            id = id
+        );
+        let recording = Recording::from_reader(session.as_bytes()).unwrap();
+        let client = WebSocketClient::replay(recording, ReplayConfig::default())
+            .await
+            .unwrap();
+
+        let mut channel = client
+            .dynamic_channel("localTimeline", serde_json::json!({}))
+            .await
+            .unwrap();
+
+        let message = channel.next().await.unwrap().unwrap();
+        assert_eq!(message["type"], "note");
+        assert!(channel.next().await.is_none());
+    }
+
+    #[cfg_attr(feature = "tokio-runtime", tokio::test)]
+    #[cfg_attr(feature = "tokio02-runtime", tokio02::test)]
+    #[cfg_attr(feature = "async-std-runtime", async_std::test)]
+    async fn replay_recorded() {
+        use crate::record::{tests::record_to_vec, Direction, Recording, ReplayConfig};
+
+        // the session recorded with another ID and interrupted while writing the last record
+        let id = "00000000-0000-0000-0000-000000000000";
+        let mut session = record_to_vec(|recorder| {
+            let connect = serde_json::json!({
+                "type": "connect",
+                "body": { "id": id, "channel": "localTimeline", "params": {}, "pong": true },
+            });
+            recorder.record(Direction::Outgoing, &connect.to_string());
+            let connected = serde_json::json!({ "type": "connected", "body": { "id": id } });
+            recorder.record(Direction::Incoming, &connected.to_string());
+            let note = serde_json::json!({
+                "type": "channel",
+                "body": { "id": id, "type": "note", "body": { "text": "hi" } },
+            });
+            recorder.record(Direction::Incoming, &note.to_string());
+            recorder.record(Direction::Incoming, &note.to_string());
+        });
+        session.truncate(session.len() - 10);
+
+        let recording = Recording::from_reader(session.as_slice()).unwrap();
+        assert_eq!(recording.records.len(), 3);
+        let client = WebSocketClient::replay(recording, ReplayConfig::default())
+            .await
+            .unwrap();
+
+        let mut channel = client
+            .dynamic_channel("localTimeline", serde_json::json!({}))
+            .await
+            .unwrap();
+
+        let message = channel.next().await.unwrap().unwrap();
+        assert_eq!(message["body"]["text"], "hi");
+        assert!(channel.next().await.is_none());
+    }
+
+    #[cfg(feature = "tokio-runtime")]
+    #[tokio::test]
+    async fn heartbeat_timeout() {
+        use std::time::Duration;
+        use tokio::{io::AsyncReadExt, net::TcpListener};
+
+        // the server accepts the connection but never replies, even to the pings
+        let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let url = format!("ws://{}/streaming", listener.local_addr().unwrap());
+        let (frame_tx, frame_rx) = futures_channel::oneshot::channel();
+        tokio::spawn(async move {
+            let (stream, _) = listener.accept().await.unwrap();
+            let mut websocket = async_tungstenite::tokio::accept_async(stream)
+                .await
+                .unwrap();
+            // read the raw bytes not to let tungstenite reply with a pong
+            let mut header = [0; 2];
+            let stream = websocket.get_mut().get_mut();
+            stream.read_exact(&mut header).await.unwrap();
+            frame_tx.send(header[0]).unwrap();
+            std::future::pending::<()>().await;
+        });
+
+        let client = WebSocketClientBuilder::new(url.as_str())
+            .ping_interval(Duration::from_millis(50))
+            .idle_timeout(Duration::from_millis(300))
+            .auto_reconnect(false)
+            .connect()
+            .await
+            .unwrap();
+
+        // FIN bit and the opcode of ping
+        assert_eq!(frame_rx.await.unwrap(), 0x89);
+
+        tokio::time::sleep(Duration::from_millis(600)).await;
+        assert!(matches!(
+            client
+                .request(
+                    misskey_api::endpoint::notes::create::Request::builder()
+                        .text("hi")
+                        .build(),
+                )
+                .await,
+            Err(crate::Error::HeartbeatTimeout)
+        ));
+    }
+
     // TODO: test of `Broadcast`
 }
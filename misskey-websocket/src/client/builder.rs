@@ -2,9 +2,11 @@ use std::result::Result as StdResult;
 use std::sync::Arc;
 use std::time::Duration;
 
-use crate::broker::{ReconnectCondition, ReconnectConfig};
+use crate::broker::{BufferConfig, HeartbeatConfig, ReconnectCondition, ReconnectConfig};
+use crate::channel::Transport;
 use crate::client::WebSocketClient;
 use crate::error::{Error, Result};
+use crate::record::Recorder;
 
 use async_tungstenite::tungstenite::http::{
     self,
@@ -17,6 +19,9 @@ struct WebSocketClientBuilderInner {
     url: Url,
     additional_headers: HeaderMap,
     reconnect: ReconnectConfig,
+    heartbeat: HeartbeatConfig,
+    buffer: BufferConfig,
+    recorder: Option<Recorder>,
 }
 
 /// Builder for [`WebSocketClient`].
@@ -70,6 +75,9 @@ impl WebSocketClientBuilder {
                 url,
                 additional_headers: HeaderMap::new(),
                 reconnect: ReconnectConfig::default(),
+                heartbeat: HeartbeatConfig::default(),
+                buffer: BufferConfig::default(),
+                recorder: None,
             });
 
         WebSocketClientBuilder { inner }
@@ -187,17 +195,76 @@ impl WebSocketClientBuilder {
         self
     }
 
+    /// Sets an interval duration of sending ping frames to the server.
+    ///
+    /// See [`HeartbeatConfig`] for details.
+    pub fn ping_interval(&mut self, interval: Duration) -> &mut Self {
+        self.inner.and_then_mut(|inner| {
+            inner.heartbeat.ping_interval = Some(interval);
+            Ok(())
+        });
+        self
+    }
+
+    /// Sets a duration after which the connection is regarded as lost if no message is received
+    /// from the server.
+    ///
+    /// It is recommended to use this together with [`ping_interval`][`WebSocketClientBuilder::ping_interval`]
+    /// so that the server keeps sending something (namely pong frames) while the connection is alive.
+    /// See [`HeartbeatConfig`] for details.
+    pub fn idle_timeout(&mut self, timeout: Duration) -> &mut Self {
+        self.inner.and_then_mut(|inner| {
+            inner.heartbeat.idle_timeout = Some(timeout);
+            Ok(())
+        });
+        self
+    }
+
+    /// Sets the default buffering configuration of the streams created from the client.
+    ///
+    /// This can be overridden for each stream, e.g. by
+    /// [`WebSocketClient::subnote_with_buffer`]. See [`BufferConfig`] for details.
+    pub fn buffer(&mut self, config: BufferConfig) -> &mut Self {
+        self.inner.and_then_mut(|inner| {
+            inner.buffer = config;
+            Ok(())
+        });
+        self
+    }
+
+    /// Records all messages exchanged with the server using `recorder`.
+    ///
+    /// The recorded session can be replayed with [`WebSocketClient::replay`].
+    /// See [`record`][`crate::record`] for details.
+    pub fn record(&mut self, recorder: Recorder) -> &mut Self {
+        self.inner.and_then_mut(|inner| {
+            inner.recorder = Some(recorder);
+            Ok(())
+        });
+        self
+    }
+
     /// Finish this builder instance and connect to Misskey using this configuration.
     pub async fn connect(&self) -> Result<WebSocketClient> {
         let WebSocketClientBuilderInner {
             url,
             additional_headers,
             reconnect,
+            heartbeat,
+            buffer,
+            recorder,
         } = match self.inner.clone() {
             Err(e) => return Err(e),
             Ok(inner) => inner,
         };
 
-        WebSocketClient::connect_with_headers_and_config(url, additional_headers, reconnect).await
+        let transport = Transport::WebSocket {
+            url,
+            additional_headers,
+        };
+        let client =
+            WebSocketClient::connect_with_transport(transport, reconnect, heartbeat, recorder)
+                .await?;
+        Ok(client.with_buffer(buffer))
     }
 }
@@ -6,6 +6,7 @@ use std::task::{Context, Poll};
 use crate::broker::{
     channel::{response_stream_channel, ControlSender, ResponseStreamReceiver},
     model::{BroadcastId, BrokerControl, SharedBrokerState},
+    BufferConfig,
 };
 use crate::error::Result;
 
@@ -45,7 +46,12 @@ impl Stream for BroadcastInner {
             return Poll::Ready(None);
         }
 
-        self.response_rx.poll_next_unpin(cx)
+        let item = futures_util::ready!(self.response_rx.poll_next_unpin(cx));
+        if item.is_none() {
+            // the broker has exited, so there is nothing to clean up on drop
+            self.is_terminated = true;
+        }
+        Poll::Ready(item)
     }
 }
 
@@ -78,10 +84,11 @@ impl BroadcastInner {
         mut broker_tx: ControlSender,
         state: SharedBrokerState,
         type_: &'static str,
+        buffer: BufferConfig,
     ) -> Result<BroadcastInner> {
         let id = BroadcastId::new();
 
-        let (response_tx, response_rx) = response_stream_channel(state);
+        let (response_tx, response_rx) = response_stream_channel(state, buffer);
         broker_tx
             .send(BrokerControl::StartBroadcast {
                 id,
@@ -129,8 +136,9 @@ where
     pub(crate) async fn start(
         broker_tx: ControlSender,
         state: SharedBrokerState,
+        buffer: BufferConfig,
     ) -> Result<Broadcast<E>> {
-        BroadcastInner::start(broker_tx, state, E::TYPE)
+        BroadcastInner::start(broker_tx, state, E::TYPE, buffer)
             .await
             .map(|inner| Broadcast {
                 inner,
@@ -148,6 +156,14 @@ impl<E> Broadcast<E> {
     pub async fn stop(&mut self) -> Result<()> {
         self.inner.stop().await
     }
+
+    /// Returns the number of messages dropped so far because the buffer was full.
+    ///
+    /// This is always zero unless the stream is started with a bounded
+    /// [`BufferConfig`][`crate::BufferConfig`] that drops messages on the overflow.
+    pub fn lagged_count(&self) -> u64 {
+        self.inner.response_rx.lagged()
+    }
 }
 
 impl<E> Stream for Broadcast<E>
@@ -1,3 +1,4 @@
+use std::borrow::Cow;
 use std::fmt::{self, Debug};
 use std::marker::PhantomData;
 use std::pin::Pin;
@@ -8,6 +9,7 @@ use crate::broker::{
         channel_pong_channel, response_stream_channel, ControlSender, ResponseStreamReceiver,
     },
     model::{BrokerControl, SharedBrokerState},
+    BufferConfig,
 };
 use crate::error::{Error, Result};
 use crate::model::ChannelId;
@@ -50,7 +52,12 @@ impl Stream for ChannelInner {
             return Poll::Ready(None);
         }
 
-        self.response_rx.poll_next_unpin(cx)
+        let item = futures_util::ready!(self.response_rx.poll_next_unpin(cx));
+        if item.is_none() {
+            // the broker has exited, so there is nothing to clean up on drop
+            self.is_terminated = true;
+        }
+        Poll::Ready(item)
     }
 }
 
@@ -107,14 +114,16 @@ impl Drop for ChannelInner {
 
 impl ChannelInner {
     async fn connect(
-        name: &'static str,
+        name: Cow<'static, str>,
         serialized_req: Value,
         mut broker_tx: ControlSender,
         state: SharedBrokerState,
+        buffer: BufferConfig,
     ) -> Result<ChannelInner> {
         let id = ChannelId::uuid();
 
-        let (response_tx, response_rx) = response_stream_channel(SharedBrokerState::clone(&state));
+        let (response_tx, response_rx) =
+            response_stream_channel(SharedBrokerState::clone(&state), buffer);
         let (pong_tx, pong_rx) = channel_pong_channel(state);
 
         broker_tx
@@ -175,13 +184,33 @@ where
         req: R,
         broker_tx: ControlSender,
         state: SharedBrokerState,
+        buffer: BufferConfig,
     ) -> BoxFuture<'static, Result<Channel<I, O>>>
     where
         R: ConnectChannelRequest<Incoming = I, Outgoing = O>,
     {
         let req = serde_json::to_value(req);
         Box::pin(async move {
-            ChannelInner::connect(R::NAME, req?, broker_tx, state)
+            ChannelInner::connect(Cow::Borrowed(R::NAME), req?, broker_tx, state, buffer)
+                .await
+                .map(|inner| Channel {
+                    inner,
+                    _marker: PhantomData,
+                })
+        })
+    }
+}
+
+impl Channel<Value, Value> {
+    pub(crate) fn connect_dynamic(
+        name: String,
+        params: Value,
+        broker_tx: ControlSender,
+        state: SharedBrokerState,
+        buffer: BufferConfig,
+    ) -> BoxFuture<'static, Result<Channel<Value, Value>>> {
+        Box::pin(async move {
+            ChannelInner::connect(Cow::Owned(name), params, broker_tx, state, buffer)
                 .await
                 .map(|inner| Channel {
                     inner,
@@ -200,6 +229,14 @@ impl<I, O> Channel<I, O> {
     pub async fn disconnect(&mut self) -> Result<()> {
         self.inner.disconnect().await
     }
+
+    /// Returns the number of messages dropped so far because the buffer was full.
+    ///
+    /// This is always zero unless the channel is connected with a bounded
+    /// [`BufferConfig`][`crate::BufferConfig`] that drops messages on the overflow.
+    pub fn lagged_count(&self) -> u64 {
+        self.inner.response_rx.lagged()
+    }
 }
 
 impl<I, O> Stream for Channel<I, O>
@@ -6,6 +6,7 @@ use std::task::{Context, Poll};
 use crate::broker::{
     channel::{response_stream_channel, ControlSender, ResponseStreamReceiver},
     model::{BrokerControl, SharedBrokerState},
+    BufferConfig,
 };
 use crate::error::Result;
 use crate::model::SubNoteId;
@@ -46,7 +47,12 @@ impl Stream for SubNoteInner {
             return Poll::Ready(None);
         }
 
-        self.response_rx.poll_next_unpin(cx)
+        let item = futures_util::ready!(self.response_rx.poll_next_unpin(cx));
+        if item.is_none() {
+            // the broker has exited, so there is nothing to clean up on drop
+            self.is_terminated = true;
+        }
+        Poll::Ready(item)
     }
 }
 
@@ -82,8 +88,9 @@ impl SubNoteInner {
         id: SubNoteId,
         mut broker_tx: ControlSender,
         state: SharedBrokerState,
+        buffer: BufferConfig,
     ) -> Result<SubNoteInner> {
-        let (response_tx, response_rx) = response_stream_channel(state);
+        let (response_tx, response_rx) = response_stream_channel(state, buffer);
         broker_tx
             .send(BrokerControl::SubNote {
                 id: id.clone(),
@@ -130,8 +137,9 @@ impl<E> SubNote<E> {
         id: SubNoteId,
         broker_tx: ControlSender,
         state: SharedBrokerState,
+        buffer: BufferConfig,
     ) -> Result<SubNote<E>> {
-        SubNoteInner::subscribe(id, broker_tx, state)
+        SubNoteInner::subscribe(id, broker_tx, state, buffer)
             .await
             .map(|inner| SubNote {
                 inner,
@@ -147,6 +155,14 @@ impl<E> SubNote<E> {
     pub async fn unsubscribe(&mut self) -> Result<()> {
         self.inner.unsubscribe().await
     }
+
+    /// Returns the number of messages dropped so far because the buffer was full.
+    ///
+    /// This is always zero unless the stream is subscribed with a bounded
+    /// [`BufferConfig`][`crate::BufferConfig`] that drops messages on the overflow.
+    pub fn lagged_count(&self) -> u64 {
+        self.inner.response_rx.lagged()
+    }
 }
 
 impl<E> Stream for SubNote<E>
@@ -22,6 +22,20 @@ pub enum Error {
     /// Invalid header.
     #[error("Invalid header: {0}")]
     InvalidHeader(#[source] Arc<tungstenite::http::Error>),
+    /// No message is received from the server within the configured idle timeout.
+    #[error("websocket heartbeat timed out")]
+    HeartbeatTimeout,
+    /// The buffer of the stream is full and the stream is configured to fail on the overflow
+    /// (see [`OverflowPolicy::Error`][`crate::OverflowPolicy::Error`]).
+    #[error("stream buffer is full")]
+    BufferFull,
+    /// The client is already closed by [`WebSocketClient::close`][`crate::WebSocketClient::close`].
+    #[error("websocket client is closed")]
+    Closed,
+    /// The connection is not closed within the timeout given to
+    /// [`WebSocketClient::close`][`crate::WebSocketClient::close`].
+    #[error("timed out waiting for the websocket connection to close")]
+    CloseTimeout,
 }
 
 impl From<Infallible> for Error {
@@ -11,7 +11,10 @@ mod channel;
 mod client;
 mod error;
 mod model;
+pub mod record;
 
-pub use broker::{ReconnectCondition, ReconnectConfig};
+pub use broker::{
+    BufferConfig, HeartbeatConfig, OverflowPolicy, ReconnectCondition, ReconnectConfig,
+};
 pub use client::{builder::WebSocketClientBuilder, stream, WebSocketClient};
 pub use error::Error;
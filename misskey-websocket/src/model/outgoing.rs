@@ -1,3 +1,5 @@
+use std::borrow::Cow;
+
 #[cfg(not(feature = "12-111-0"))]
 use crate::model::ApiRequestId;
 use crate::model::{ChannelId, SubNoteId};
@@ -16,7 +18,7 @@ pub enum OutgoingMessage {
     },
     Connect {
         id: ChannelId,
-        channel: &'static str,
+        channel: Cow<'static, str>,
         params: Value,
         pong: bool,
     },
@@ -0,0 +1,318 @@
+//! Recording and replaying of raw streaming sessions.
+//!
+//! A session recorded with [`WebSocketClientBuilder::record`][`crate::WebSocketClientBuilder::record`]
+//! is saved as JSON lines, where each line is a [`Record`] of the message exchanged with the server.
+//! The recorded session can be fed back to a client with [`WebSocketClient::replay`][`crate::WebSocketClient::replay`],
+//! which is useful to reproduce problems in stream handling without the server.
+
+use std::fmt::{self, Debug};
+use std::fs::File;
+use std::io::{self, BufRead, BufReader, BufWriter, Read, Write};
+use std::path::Path;
+use std::sync::mpsc::{self, Receiver, Sender};
+use std::thread;
+
+use chrono::{DateTime, Utc};
+use log::warn;
+use serde::{Deserialize, Serialize};
+use serde_json::Value;
+
+/// Direction of a recorded message.
+#[derive(Serialize, Deserialize, Debug, Clone, Copy, PartialEq, Eq)]
+#[serde(rename_all = "camelCase")]
+pub enum Direction {
+    /// The message is received from the server.
+    Incoming,
+    /// The message is sent to the server.
+    Outgoing,
+}
+
+/// A message recorded in a session.
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq)]
+pub struct Record {
+    /// The time when the message is sent or received.
+    pub time: DateTime<Utc>,
+    /// Whether the message is sent or received.
+    pub direction: Direction,
+    /// The message itself.
+    ///
+    /// Messages that are not valid JSON are recorded as JSON strings of their text as is.
+    pub message: Value,
+}
+
+impl Record {
+    pub(crate) fn from_text(direction: Direction, text: &str) -> Record {
+        let message = serde_json::from_str(text).unwrap_or_else(|_| Value::String(text.to_owned()));
+        Record {
+            time: Utc::now(),
+            direction,
+            message,
+        }
+    }
+
+    pub(crate) fn to_text(&self) -> String {
+        match &self.message {
+            Value::String(text) => text.clone(),
+            message => message.to_string(),
+        }
+    }
+}
+
+/// Writer of the messages exchanged in a session, which is passed to
+/// [`WebSocketClientBuilder::record`][`crate::WebSocketClientBuilder::record`].
+///
+/// Each message is written as a line of JSON on a dedicated thread, so that recording does not
+/// block the connection. The writer is flushed whenever there are no more messages to write,
+/// and the thread ends after writing the remaining messages when all the clones of the
+/// `Recorder` are dropped, i.e. when the client is dropped.
+/// Note that the messages not written yet are lost if the process exits before that.
+/// Failures in writing are logged and ignored not to interrupt the session.
+#[derive(Clone)]
+pub struct Recorder {
+    sender: Sender<Record>,
+}
+
+impl Debug for Recorder {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        f.debug_struct("Recorder").finish()
+    }
+}
+
+impl Recorder {
+    /// Creates a `Recorder` that writes to `writer`.
+    pub fn new<W>(writer: W) -> Recorder
+    where
+        W: Write + Send + 'static,
+    {
+        let (sender, receiver) = mpsc::channel();
+        thread::Builder::new()
+            .name("misskey-websocket-recorder".to_owned())
+            .spawn(move || write_records(writer, receiver))
+            .expect("failed to spawn the recorder thread");
+        Recorder { sender }
+    }
+
+    /// Creates a `Recorder` that writes to the file at `path`, truncating it if it exists.
+    pub fn create<P: AsRef<Path>>(path: P) -> io::Result<Recorder> {
+        let file = File::create(path)?;
+        Ok(Recorder::new(BufWriter::new(file)))
+    }
+
+    pub(crate) fn record(&self, direction: Direction, text: &str) {
+        let record = Record::from_text(direction, text);
+        if self.sender.send(record).is_err() {
+            warn!("failed to record a message (ignored): the recorder thread has stopped");
+        }
+    }
+}
+
+fn write_records<W: Write>(mut writer: W, receiver: Receiver<Record>) {
+    let write = |writer: &mut W, record: &Record| {
+        serde_json::to_writer(&mut *writer, record)?;
+        writer.write_all(b"\n")
+    };
+    while let Ok(record) = receiver.recv() {
+        let mut result = write(&mut writer, &record);
+        // write the pending records before flushing
+        for record in receiver.try_iter() {
+            result = result.and_then(|()| write(&mut writer, &record));
+        }
+        if let Err(e) = result.and_then(|()| writer.flush()) {
+            warn!("failed to record a message (ignored): {}", e);
+        }
+    }
+}
+
+/// A recorded session, which is fed back by [`WebSocketClient::replay`][`crate::WebSocketClient::replay`].
+#[derive(Debug, Clone, Default, PartialEq)]
+pub struct Recording {
+    /// Messages in the session, in the order of exchange.
+    pub records: Vec<Record>,
+}
+
+impl Recording {
+    /// Reads a recorded session from `reader`.
+    ///
+    /// Empty lines are skipped. The last line is also skipped if it is incomplete, which
+    /// happens when the recording is interrupted while writing it.
+    /// Other lines that are not valid records result in an error of
+    /// [`io::ErrorKind::InvalidData`].
+    pub fn from_reader<R: Read>(reader: R) -> io::Result<Recording> {
+        let mut reader = BufReader::new(reader);
+        let mut records = Vec::new();
+        let mut line = String::new();
+        for number in 1.. {
+            line.clear();
+            if reader.read_line(&mut line)? == 0 {
+                break;
+            }
+            if line.trim().is_empty() {
+                continue;
+            }
+            match serde_json::from_str(&line) {
+                Ok(record) => records.push(record),
+                Err(_) if !line.ends_with('\n') => {
+                    warn!("skipping the incomplete record at line {}", number);
+                    break;
+                }
+                Err(e) => {
+                    return Err(io::Error::new(
+                        io::ErrorKind::InvalidData,
+                        format!("invalid record at line {}: {}", number, e),
+                    ))
+                }
+            }
+        }
+        Ok(Recording { records })
+    }
+
+    /// Reads a recorded session from the file at `path`.
+    pub fn open<P: AsRef<Path>>(path: P) -> io::Result<Recording> {
+        Recording::from_reader(File::open(path)?)
+    }
+}
+
+impl From<Vec<Record>> for Recording {
+    fn from(records: Vec<Record>) -> Recording {
+        Recording { records }
+    }
+}
+
+/// Replay configuration.
+#[derive(Debug, Clone, Default)]
+pub struct ReplayConfig {
+    /// Delivers the incoming messages at the pace they are recorded, instead of as fast as possible.
+    pub realtime: bool,
+}
+
+impl ReplayConfig {
+    /// Creates a `ReplayConfig` that delivers the messages at the pace they are recorded.
+    pub fn realtime() -> ReplayConfig {
+        ReplayConfig { realtime: true }
+    }
+}
+
+#[cfg(test)]
+pub(crate) mod tests {
+    use super::{Direction, Record, Recorder, Recording};
+
+    use std::io::{self, Write};
+    use std::sync::{mpsc, Arc, Mutex};
+
+    use serde_json::json;
+
+    /// A writer into the shared buffer, which notifies when the recorder thread drops it.
+    struct SharedWriter {
+        buffer: Arc<Mutex<Vec<u8>>>,
+        dropped: mpsc::Sender<()>,
+    }
+
+    impl Write for SharedWriter {
+        fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+            self.buffer.lock().unwrap().write(buf)
+        }
+
+        fn flush(&mut self) -> io::Result<()> {
+            Ok(())
+        }
+    }
+
+    impl Drop for SharedWriter {
+        fn drop(&mut self) {
+            let _ = self.dropped.send(());
+        }
+    }
+
+    /// Records the messages with `f` and returns the written contents after the recorder ends.
+    pub(crate) fn record_to_vec(f: impl FnOnce(&Recorder)) -> Vec<u8> {
+        let buffer = Arc::new(Mutex::new(Vec::new()));
+        let (dropped, wait) = mpsc::channel();
+        let recorder = Recorder::new(SharedWriter {
+            buffer: Arc::clone(&buffer),
+            dropped,
+        });
+        f(&recorder);
+        drop(recorder);
+        wait.recv().unwrap();
+        let contents = buffer.lock().unwrap().clone();
+        contents
+    }
+
+    #[test]
+    fn test_record_format() {
+        let contents = record_to_vec(|recorder| {
+            recorder.record(Direction::Outgoing, r#"{"type":"connect","body":{}}"#);
+            recorder.record(Direction::Incoming, "not json");
+        });
+        let contents = String::from_utf8(contents).unwrap();
+        let lines: Vec<_> = contents.lines().collect();
+        assert_eq!(lines.len(), 2);
+        assert!(contents.ends_with('\n'));
+
+        let first: serde_json::Value = serde_json::from_str(lines[0]).unwrap();
+        assert_eq!(first["direction"], "outgoing");
+        assert_eq!(first["message"], json!({"type": "connect", "body": {}}));
+        assert!(first["time"].is_string());
+        let second: Record = serde_json::from_str(lines[1]).unwrap();
+        assert_eq!(second.direction, Direction::Incoming);
+        assert_eq!(second.message, json!("not json"));
+        assert_eq!(second.to_text(), "not json");
+    }
+
+    #[test]
+    fn test_record_from_recorder() {
+        let contents = record_to_vec(|recorder| {
+            // records from the clones are written in order
+            let cloned = recorder.clone();
+            recorder.record(Direction::Outgoing, r#"{"type":"api"}"#);
+            cloned.record(Direction::Incoming, r#"{"type":"api:x"}"#);
+        });
+        let recording = Recording::from_reader(contents.as_slice()).unwrap();
+        let texts: Vec<_> = recording.records.iter().map(Record::to_text).collect();
+        assert_eq!(texts, [r#"{"type":"api"}"#, r#"{"type":"api:x"}"#]);
+    }
+
+    const RECORD: &str = r#"{"time":"2022-01-01T00:00:00Z","direction":"incoming","message":{}}"#;
+
+    #[test]
+    fn test_recording_empty_lines() {
+        let text = format!("\n{}\n  \n{}\n\n", RECORD, RECORD);
+        let recording = Recording::from_reader(text.as_bytes()).unwrap();
+        assert_eq!(recording.records.len(), 2);
+        assert!(Recording::from_reader(&b""[..]).unwrap().records.is_empty());
+    }
+
+    #[test]
+    fn test_recording_truncated() {
+        let truncated = &RECORD[..RECORD.len() - 10];
+        let text = format!("{}\n{}\n{}", RECORD, RECORD, truncated);
+        let recording = Recording::from_reader(text.as_bytes()).unwrap();
+        assert_eq!(recording.records.len(), 2);
+
+        // the complete last line without the line break is read
+        let text = format!("{}\n{}", RECORD, RECORD);
+        let recording = Recording::from_reader(text.as_bytes()).unwrap();
+        assert_eq!(recording.records.len(), 2);
+    }
+
+    #[test]
+    fn test_recording_corrupt() {
+        for corrupt in [
+            &RECORD[..RECORD.len() - 10],
+            "not json",
+            r#"{"time":"2022-01-01T00:00:00Z","direction":"sideways","message":{}}"#,
+            r#"{"direction":"incoming","message":{}}"#,
+        ] {
+            let text = format!("{}\n{}\n{}\n", RECORD, corrupt, RECORD);
+            let err = Recording::from_reader(text.as_bytes()).unwrap_err();
+            assert_eq!(err.kind(), io::ErrorKind::InvalidData, "{}", corrupt);
+            assert!(err.to_string().contains("line 2"), "{}", err);
+        }
+    }
+
+    #[test]
+    fn test_recording_not_utf8() {
+        let err = Recording::from_reader(&b"\xff\xfe\n"[..]).unwrap_err();
+        assert_eq!(err.kind(), io::ErrorKind::InvalidData);
+    }
+}
@@ -90,6 +90,8 @@
 //! - `meid`: Assume that the `meid` ID generation method is used in the targeted Misskey instance.
 //! - `ulid`: Assume that the `ulid` ID generation method is used in the targeted Misskey instance.
 //! - `objectid`: Assume that the `objectid` ID generation method is used in the targeted Misskey instance.
+//! - `emoji-pack`: Enables the `emoji_pack` module to import and export custom emoji packs,
+//!   which requires the `12-102-0` version flag.
 //! - and version flags, as described in [version flags section](#specifying-misskey-version).
 //!
 //! ## Specifying Misskey version
@@ -394,7 +396,19 @@ pub mod websocket {
 #[cfg_attr(docsrs, doc(cfg(feature = "websocket-client")))]
 pub use websocket::WebSocketClient;
 
-pub use misskey_util::{builder, pager, Error, TimelineCursor, TimelineRange};
+#[cfg(all(feature = "12-102-0", feature = "emoji-pack"))]
+#[cfg_attr(docsrs, doc(cfg(all(feature = "12-102-0", feature = "emoji-pack"))))]
+pub use misskey_util::emoji_pack;
+#[cfg(feature = "13-0-0")]
+#[cfg_attr(docsrs, doc(cfg(feature = "13-0-0")))]
+pub use misskey_util::role;
+pub use misskey_util::{
+    analysis, antenna, archive, bot, builder, drive, graph, mfm, pager, schedule, split,
+    BackfillError, Error, TimelineCursor, TimelineRange,
+};
+pub use misskey_util::{
+    BusEvent, EventBus, EventBusHandle, Subscription, TimelineSource,
+};
 pub use misskey_util::{ClientExt, StreamingClientExt, UploadFileClientExt};
 
 /// Prelude for crates using `misskey-rs`.